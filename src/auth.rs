@@ -1,5 +1,10 @@
+use std::{collections::HashMap, path::Path};
+
+use anyhow::{bail, Context, Result};
 use base64::{engine::general_purpose::STANDARD, Engine as _};
 
+use crate::viewer::host_alias;
+
 /// Auth trait
 pub trait Auth {
     /// create header value
@@ -30,7 +35,7 @@ impl Auth for BasicAuth {
     fn create_header(&self) -> String {
         format!(
             "Basic {}",
-            STANDARD.encode(&format!("{}:{}", self.username, self.password))
+            STANDARD.encode(format!("{}:{}", self.username, self.password))
         )
     }
 
@@ -77,3 +82,173 @@ impl Auth for EmptyAuth {
         "".to_string()
     }
 }
+
+/// Credentials loaded from a netrc file
+#[derive(Debug, Clone)]
+pub enum NetrcAuth {
+    Basic(BasicAuth),
+    Bearer(BearerAuth),
+}
+
+impl Auth for NetrcAuth {
+    fn create_header(&self) -> String {
+        match self {
+            NetrcAuth::Basic(auth) => auth.create_header(),
+            NetrcAuth::Bearer(auth) => auth.create_header(),
+        }
+    }
+
+    fn get_header_value(&self) -> String {
+        match self {
+            NetrcAuth::Basic(auth) => auth.get_header_value(),
+            NetrcAuth::Bearer(auth) => auth.get_header_value(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct NetrcEntry {
+    login: Option<String>,
+    password: Option<String>,
+}
+
+impl NetrcEntry {
+    /// login and password is basic auth, a password alone is a bearer token
+    fn auth(&self) -> Option<NetrcAuth> {
+        match (&self.login, &self.password) {
+            (Some(login), Some(password)) => {
+                Some(NetrcAuth::Basic(BasicAuth::new(login, password)))
+            }
+            (None, Some(token)) => Some(NetrcAuth::Bearer(BearerAuth::new(token))),
+            _ => None,
+        }
+    }
+}
+
+/// A `.netrc` style credentials file keyed by host
+#[derive(Debug, Clone, Default)]
+pub struct Netrc {
+    machines: HashMap<String, NetrcEntry>,
+    default: Option<NetrcEntry>,
+}
+
+impl Netrc {
+    /// parse the content of a netrc file
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut netrc = Netrc::default();
+        // the machine the following tokens belong to, `None` for `default`
+        let mut current: Option<Option<String>> = None;
+        let mut in_macdef = false;
+
+        for line in text.lines() {
+            if in_macdef {
+                // a macro definition ends with an empty line
+                in_macdef = !line.trim().is_empty();
+                continue;
+            }
+            let mut tokens = line.split_whitespace();
+            while let Some(token) = tokens.next() {
+                if token.starts_with('#') {
+                    break;
+                }
+                let mut value = || {
+                    tokens
+                        .next()
+                        .with_context(|| format!("Missing value after `{}` in netrc", token))
+                };
+                match token {
+                    "machine" => {
+                        let host = value()?.to_string();
+                        netrc.machines.entry(host.clone()).or_default();
+                        current = Some(Some(host));
+                    }
+                    "default" => {
+                        netrc.default.get_or_insert_with(NetrcEntry::default);
+                        current = Some(None);
+                    }
+                    "login" | "password" | "account" => {
+                        let value = value()?.to_string();
+                        let entry = match &current {
+                            Some(Some(host)) => netrc.machines.get_mut(host),
+                            Some(None) => netrc.default.as_mut(),
+                            None => bail!("`{}` appears before any machine in netrc", token),
+                        }
+                        .context("Unknown netrc entry")?;
+                        match token {
+                            "login" => entry.login = Some(value),
+                            "password" => entry.password = Some(value),
+                            _ => {}
+                        }
+                    }
+                    "macdef" => {
+                        in_macdef = true;
+                        break;
+                    }
+                    _ => bail!("Unknown netrc token: {}", token),
+                }
+            }
+        }
+
+        Ok(netrc)
+    }
+
+    /// load a netrc file
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read netrc: {}", path.display()))?;
+        Self::parse(&text)
+    }
+
+    /// the credentials for the host, with or without a leading `www.`,
+    /// falling back to the `default` entry
+    pub fn auth(&self, host: &str) -> Option<NetrcAuth> {
+        self.machines
+            .get(host)
+            .or_else(|| self.machines.get(&host_alias(host)))
+            .or(self.default.as_ref())
+            .and_then(|entry| entry.auth())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_netrc_selects_credentials_by_host() -> Result<()> {
+        let netrc = Netrc::parse(
+            "# logins
+machine shonenjumpplus.com login user password secret
+machine comic-days.com
+    password token123
+machine www.sunday-webry.com login webry password sunday
+
+macdef init
+machine ignored.com login nobody password nothing
+
+default login anonymous password guest
+",
+        )?;
+
+        let auth = netrc.auth("shonenjumpplus.com").unwrap();
+        assert!(matches!(auth, NetrcAuth::Basic(_)));
+        assert_eq!(auth.get_header_value(), "user:secret");
+
+        let auth = netrc.auth("www.comic-days.com").unwrap();
+        assert!(matches!(auth, NetrcAuth::Bearer(_)));
+        assert_eq!(auth.create_header(), "Bearer token123");
+
+        // the alias works the other way too
+        let auth = netrc.auth("sunday-webry.com").unwrap();
+        assert_eq!(auth.get_header_value(), "webry:sunday");
+
+        assert_eq!(
+            netrc.auth("ignored.com").unwrap().get_header_value(),
+            "anonymous:guest"
+        );
+        assert!(Netrc::parse("login user").is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,258 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use anyhow::{anyhow, Result};
+use sha2::{Digest, Sha256};
+use url::Url;
+
+use crate::utils::Bytes;
+
+/// Suffix of the files being written, never read as cache entries
+const TEMP_SUFFIX: &str = ".tmp";
+
+/// Image bodies cached on disk by url, so downloading an episode again doesn't fetch the
+/// images again. The least recently used entries are evicted to stay under `max_bytes`.
+///
+/// Clones share the same cache, and concurrent tasks can use it at once.
+#[derive(Debug, Clone)]
+pub struct ImageCache {
+    dir: PathBuf,
+    max_bytes: u64,
+    index: Arc<Mutex<CacheIndex>>,
+}
+
+#[derive(Debug, Default)]
+struct CacheIndex {
+    /// File name to the size and the last use of the entry
+    entries: HashMap<String, (u64, u64)>,
+    total_bytes: u64,
+    /// Incremented on every use, the smallest last use is evicted first
+    clock: u64,
+}
+
+impl CacheIndex {
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+
+    fn insert(&mut self, name: String, size: u64) {
+        let used = self.tick();
+        if let Some((old_size, _)) = self.entries.insert(name, (size, used)) {
+            self.total_bytes -= old_size;
+        }
+        self.total_bytes += size;
+    }
+
+    fn remove(&mut self, name: &str) -> bool {
+        match self.entries.remove(name) {
+            Some((size, _)) => {
+                self.total_bytes -= size;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The least recently used entry
+    fn oldest(&self) -> Option<String> {
+        self.entries
+            .iter()
+            .min_by_key(|(_, (_, used))| *used)
+            .map(|(name, _)| name.clone())
+    }
+}
+
+impl ImageCache {
+    /// Open the cache in `dir`, keeping the entries of earlier runs
+    pub fn open<P: AsRef<Path>>(dir: P, max_bytes: u64) -> Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        std::fs::create_dir_all(&dir)?;
+
+        // the entries of earlier runs were last used when they were last written or read
+        let mut entries = Vec::new();
+        for entry in std::fs::read_dir(&dir)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            let metadata = entry.metadata()?;
+            if !metadata.is_file() {
+                continue;
+            }
+            if name.ends_with(TEMP_SUFFIX) {
+                // left by an interrupted run
+                let _ = std::fs::remove_file(entry.path());
+                continue;
+            }
+            entries.push((metadata.modified()?, name, metadata.len()));
+        }
+        entries.sort();
+
+        let mut index = CacheIndex::default();
+        for (_, name, size) in entries {
+            index.insert(name, size);
+        }
+        let cache = ImageCache {
+            dir,
+            max_bytes,
+            index: Arc::new(Mutex::new(index)),
+        };
+        for name in cache.evicted()? {
+            let _ = std::fs::remove_file(cache.dir.join(name));
+        }
+        Ok(cache)
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn max_bytes(&self) -> u64 {
+        self.max_bytes
+    }
+
+    /// Total size of the cached images
+    pub fn total_bytes(&self) -> Result<u64> {
+        Ok(self.lock()?.total_bytes)
+    }
+
+    /// The cached body of the url
+    pub async fn get(&self, url: &Url) -> Result<Option<Bytes>> {
+        let name = Self::entry_name(url);
+        if !self.lock()?.entries.contains_key(&name) {
+            return Ok(None);
+        }
+        let path = self.dir.join(&name);
+        let bytes = match tokio::fs::read(&path).await {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                // removed from the disk behind our back
+                self.lock()?.remove(&name);
+                return Ok(None);
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        {
+            let mut index = self.lock()?;
+            let used = index.tick();
+            if let Some((_, last_used)) = index.entries.get_mut(&name) {
+                *last_used = used;
+            }
+        }
+        // keep the order of use for the next run, off the async workers as tokio::fs does
+        let touch = tokio::task::spawn_blocking(move || {
+            if let Ok(file) = std::fs::File::options().write(true).open(&path) {
+                let _ = file.set_modified(SystemTime::now());
+            }
+        });
+        let _ = touch.await;
+        Ok(Some(bytes.into()))
+    }
+
+    /// Store the body of the url, evicting the least recently used entries over the limit.
+    /// A body larger than the whole cache is not stored.
+    pub async fn put(&self, url: &Url, bytes: &[u8]) -> Result<()> {
+        let size = bytes.len() as u64;
+        if size > self.max_bytes {
+            return Ok(());
+        }
+        let name = Self::entry_name(url);
+        // a temp file of its own, so tasks storing the same url don't mix their bytes
+        let temp = {
+            let mut index = self.lock()?;
+            self.dir
+                .join(format!("{}.{}{}", name, index.tick(), TEMP_SUFFIX))
+        };
+        tokio::fs::write(&temp, bytes).await?;
+        tokio::fs::rename(&temp, self.dir.join(&name)).await?;
+
+        self.lock()?.insert(name, size);
+        self.evict().await
+    }
+
+    /// Forget the body of the url, e.g. when it turned out to be broken
+    pub async fn remove(&self, url: &Url) -> Result<()> {
+        let name = Self::entry_name(url);
+        if self.lock()?.remove(&name) {
+            match tokio::fs::remove_file(self.dir.join(name)).await {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove the least recently used entries until the cache fits in the limit
+    async fn evict(&self) -> Result<()> {
+        // the files are removed once the index is unlocked
+        for name in self.evicted()? {
+            let _ = tokio::fs::remove_file(self.dir.join(name)).await;
+        }
+        Ok(())
+    }
+
+    /// Drop the least recently used entries from the index until the cache fits in the
+    /// limit, returning their file names
+    fn evicted(&self) -> Result<Vec<String>> {
+        let mut index = self.lock()?;
+        let mut evicted = Vec::new();
+        while index.total_bytes > self.max_bytes {
+            let Some(name) = index.oldest() else {
+                break;
+            };
+            index.remove(&name);
+            evicted.push(name);
+        }
+        Ok(evicted)
+    }
+
+    /// File name of the entry of the url, the SHA-256 of the url in hex
+    fn entry_name(url: &Url) -> String {
+        format!("{:x}", Sha256::digest(url.as_str().as_bytes()))
+    }
+
+    fn lock(&self) -> Result<std::sync::MutexGuard<'_, CacheIndex>> {
+        self.index
+            .lock()
+            .map_err(|_| anyhow!("The image cache index is poisoned"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_least_recently_used_is_evicted() -> Result<()> {
+        let dir = Path::new("playground/output/image_cache_lru");
+        let _ = std::fs::remove_dir_all(dir);
+        let cache = ImageCache::open(dir, 25)?;
+        let url = |i: usize| Url::parse(&format!("https://example.com/page/{}", i));
+
+        cache.put(&url(0)?, &[0; 10]).await?;
+        cache.put(&url(1)?, &[1; 10]).await?;
+        // the first page is used again, so the second one is the oldest
+        assert!(cache.get(&url(0)?).await?.is_some());
+        cache.put(&url(2)?, &[2; 10]).await?;
+
+        assert_eq!(cache.get(&url(0)?).await?.as_deref(), Some(&[0; 10][..]));
+        assert!(cache.get(&url(1)?).await?.is_none());
+        assert!(!dir.join(ImageCache::entry_name(&url(1)?)).exists());
+        assert!(cache.get(&url(2)?).await?.is_some());
+        assert_eq!(cache.total_bytes()?, 20);
+        // too large for the whole cache
+        cache.put(&url(3)?, &[3; 30]).await?;
+        assert!(cache.get(&url(3)?).await?.is_none());
+
+        // the entries are kept for the next run
+        let reopened = ImageCache::open(dir, 25)?;
+        assert_eq!(reopened.total_bytes()?, 20);
+        assert!(reopened.get(&url(2)?).await?.is_some());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,130 @@
+use std::io::Cursor;
+
+use anyhow::{anyhow, Result};
+use image::{DynamicImage, ImageDecoder, ImageReader};
+use moxcms::{ColorProfile, DataColorSpace, Layout, TransformOptions};
+
+/// Read the embedded ICC profile of an encoded image
+pub fn icc_profile<B: AsRef<[u8]>>(bytes: B) -> Result<Option<Vec<u8>>> {
+    let mut decoder = ImageReader::new(Cursor::new(bytes.as_ref()))
+        .with_guessed_format()?
+        .into_decoder()?;
+    Ok(decoder.icc_profile()?)
+}
+
+/// Whether the ICC profile describes RGB colors
+pub fn is_rgb_profile(icc: &[u8]) -> bool {
+    ColorProfile::new_from_slice(icc)
+        .is_ok_and(|profile| profile.color_space == DataColorSpace::Rgb)
+}
+
+/// Decode an image and convert it to sRGB when it embeds an ICC profile
+pub fn load_as_srgb<B: AsRef<[u8]>>(bytes: B) -> Result<DynamicImage> {
+    let mut decoder = ImageReader::new(Cursor::new(bytes.as_ref()))
+        .with_guessed_format()?
+        .into_decoder()?;
+    let icc = decoder.icc_profile()?;
+    let image = DynamicImage::from_decoder(decoder)?;
+
+    match icc {
+        Some(icc) => convert_to_srgb(image, &icc),
+        None => Ok(image),
+    }
+}
+
+/// Convert the pixels of an image from the given ICC profile to sRGB.
+///
+/// Only RGB and grayscale profiles are converted, other images are returned unchanged.
+pub fn convert_to_srgb(image: DynamicImage, icc: &[u8]) -> Result<DynamicImage> {
+    let source = ColorProfile::new_from_slice(icc).map_err(|e| anyhow!("{:?}", e))?;
+    let srgb = ColorProfile::new_srgb();
+    let transform = |source_layout: Layout, layout: Layout, pixels: &[u8], out: &mut [u8]| {
+        source
+            .create_transform_8bit(source_layout, &srgb, layout, TransformOptions::default())
+            .and_then(|transform| transform.transform(pixels, out))
+            .map_err(|e| anyhow!("{:?}", e))
+    };
+
+    match source.color_space {
+        DataColorSpace::Rgb if image.color().has_alpha() => {
+            let pixels = image.to_rgba8();
+            let mut out = pixels.clone();
+            transform(Layout::Rgba, Layout::Rgba, &pixels, &mut out)?;
+            Ok(DynamicImage::ImageRgba8(out))
+        }
+        DataColorSpace::Rgb => {
+            let pixels = image.to_rgb8();
+            let mut out = pixels.clone();
+            transform(Layout::Rgb, Layout::Rgb, &pixels, &mut out)?;
+            Ok(DynamicImage::ImageRgb8(out))
+        }
+        DataColorSpace::Gray => {
+            // keep grayscale pages grayscale after mapping the tone curve
+            let pixels = image.to_luma8();
+            let mut out = image::RgbImage::new(pixels.width(), pixels.height());
+            transform(Layout::Gray, Layout::Rgb, &pixels, &mut out)?;
+            Ok(DynamicImage::ImageLuma8(
+                DynamicImage::ImageRgb8(out).to_luma8(),
+            ))
+        }
+        color_space => {
+            tracing::debug!("Skipped color conversion from {:?}", color_space);
+            Ok(image)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use image::{codecs::png::PngEncoder, GenericImageView, ImageEncoder, Rgb, RgbImage};
+
+    use super::*;
+
+    fn encode_png_with_profile(image: &RgbImage, icc: Option<Vec<u8>>) -> Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        let mut encoder = PngEncoder::new(&mut bytes);
+        if let Some(icc) = icc {
+            encoder.set_icc_profile(icc)?;
+        }
+        encoder.write_image(
+            image.as_raw(),
+            image.width(),
+            image.height(),
+            image::ExtendedColorType::Rgb8,
+        )?;
+        Ok(bytes)
+    }
+
+    #[test]
+    fn test_convert_display_p3_to_srgb() -> Result<()> {
+        let icc = ColorProfile::new_display_p3()
+            .encode()
+            .map_err(|e| anyhow!("{:?}", e))?;
+        let image = RgbImage::from_pixel(4, 4, Rgb([200, 100, 50]));
+        let bytes = encode_png_with_profile(&image, Some(icc))?;
+
+        assert!(icc_profile(&bytes)?.is_some());
+        let converted = load_as_srgb(&bytes)?;
+        assert_eq!(converted.dimensions(), (4, 4));
+
+        // the same color is more saturated in the smaller sRGB gamut
+        let [r, g, b, _] = converted.get_pixel(0, 0).0;
+        assert!(r > 200, "red {} should increase", r);
+        assert!(g < 100, "green {} should decrease", g);
+        assert!(b < 50, "blue {} should decrease", b);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_keep_image_without_profile() -> Result<()> {
+        let image = RgbImage::from_pixel(4, 4, Rgb([200, 100, 50]));
+        let bytes = encode_png_with_profile(&image, None)?;
+
+        assert!(icc_profile(&bytes)?.is_none());
+        let loaded = load_as_srgb(&bytes)?;
+        assert_eq!(loaded.get_pixel(0, 0).0, [200, 100, 50, 255]);
+
+        Ok(())
+    }
+}
@@ -1,4 +1,4 @@
-use anyhow::Result;
+use anyhow::{bail, Result};
 use url::Url;
 
 /// A manga is a collection of series
@@ -8,6 +8,23 @@ pub trait MangaPage {
 
     /// Check if the page is an image
     fn is_image(&self) -> bool;
+
+    /// (width, height) of the page from the metadata, without decoding the image
+    fn dimensions(&self) -> Option<(u32, u32)> {
+        None
+    }
+
+    /// Absolute url of the page image
+    fn url(&self) -> Result<Url> {
+        bail!("Page has no absolute url")
+    }
+
+    /// Url of the page image, resolving a path relative to `base` such as an image CDN.
+    /// Pages with an absolute url ignore `base`.
+    fn resolved_url(&self, base: &Url) -> Result<Url> {
+        let _ = base;
+        self.url()
+    }
 }
 
 /// An episode is a single chapter or part of a series
@@ -23,6 +40,11 @@ pub trait MangaEpisode<P: MangaPage> {
 
     /// Get the pages of the episode
     fn pages(&self) -> Vec<P>;
+
+    /// Get the url of the episode on the website
+    fn url(&self) -> Option<Url> {
+        None
+    }
 }
 
 /// A series is a collection of episodes
@@ -0,0 +1,251 @@
+use std::path::PathBuf;
+
+use anyhow::{bail, Context, Result};
+use url::Url;
+
+#[cfg(feature = "fuz")]
+use crate::viewer::fuz;
+#[cfg(feature = "giga")]
+use crate::viewer::{giga, ViewerWebsite};
+use crate::{
+    pipeline::{DynPipeline, EpisodePipelineBuilder, SaveFormat, WriterConifg},
+    progress::ProgressConfig,
+    viewer::{ViewerClient, ViewerConfigBuilder, ViewerType},
+};
+
+/// Download an episode without choosing the viewer and the pipeline by hand.
+///
+/// The viewer is picked from the host of the url.
+///
+/// ```no_run
+/// # async fn run() -> anyhow::Result<()> {
+/// manga::Download::from_url("https://shonenjumpplus.com/episode/3269754496649675685".parse()?)
+///     .format(manga::pipeline::SaveFormat::Raw)
+///     .image(image::ImageFormat::Png)
+///     .output("downloads")
+///     .run()
+///     .await
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Download {
+    url: Url,
+    save_format: SaveFormat,
+    image_format: image::ImageFormat,
+    output: PathBuf,
+    progress: ProgressConfig,
+    viewer: Option<ViewerType>,
+    base_url: Option<Url>,
+}
+
+impl Download {
+    pub fn from_url(url: Url) -> Self {
+        Download {
+            url,
+            save_format: SaveFormat::Raw,
+            image_format: image::ImageFormat::Png,
+            output: PathBuf::from("."),
+            progress: ProgressConfig::default(),
+            viewer: None,
+            base_url: None,
+        }
+    }
+
+    /// How to save the episode, raw images by default
+    pub fn format(self, save_format: SaveFormat) -> Self {
+        Download {
+            save_format,
+            ..self
+        }
+    }
+
+    /// Format of the saved images, PNG by default
+    pub fn image(self, image_format: image::ImageFormat) -> Self {
+        Download {
+            image_format,
+            ..self
+        }
+    }
+
+    /// Directory the episode is saved in, the current directory by default
+    pub fn output<P: Into<PathBuf>>(self, output: P) -> Self {
+        Download {
+            output: output.into(),
+            ..self
+        }
+    }
+
+    pub fn progress(self, progress: ProgressConfig) -> Self {
+        Download { progress, ..self }
+    }
+
+    /// Use this viewer instead of looking up the host of the url
+    pub fn viewer(self, viewer: ViewerType) -> Self {
+        Download {
+            viewer: Some(viewer),
+            ..self
+        }
+    }
+
+    /// Send the viewer requests to this url instead of the website, e.g. a mirror
+    pub fn base_url(self, base_url: Url) -> Self {
+        Download {
+            base_url: Some(base_url),
+            ..self
+        }
+    }
+
+    /// Resolve the viewer, build its pipeline and download into the output directory
+    pub async fn run(self) -> Result<()> {
+        let pipe = self.pipeline()?;
+        tokio::fs::create_dir_all(&self.output).await?;
+        pipe.download_in(&self.url, &self.output).await
+    }
+
+    /// Resolve the viewer and build its pipeline, boxed as the viewer is only known at runtime
+    pub fn pipeline(&self) -> Result<Box<dyn DynPipeline>> {
+        let host = self.url.host_str().context("Url must have host")?;
+        let viewer = match self.viewer {
+            Some(viewer) => viewer,
+            None => ViewerType::lookup(host)
+                .with_context(|| format!("Website not supported: {}", host))?,
+        };
+        let writer_config = WriterConifg::new(self.save_format.clone(), self.image_format);
+
+        match viewer {
+            #[cfg(feature = "giga")]
+            ViewerType::Giga => {
+                let website = giga::viewer::Website::lookup(host)
+                    .unwrap_or_else(|| giga::viewer::Website::Custom(host.to_string()));
+                let mut pipe = giga::pipeline::Pipeline::default()
+                    .set_website(website)
+                    .set_progress(self.progress.clone())
+                    .set_writer_config(writer_config);
+                if let Some(base_url) = &self.base_url {
+                    let config = giga::viewer::ConfigBuilder::custom(base_url.to_string())?.build();
+                    pipe = pipe.with_client(giga::viewer::Client::new(config));
+                }
+                Ok(Box::new(pipe))
+            }
+            #[cfg(feature = "fuz")]
+            ViewerType::Fuz => {
+                let mut pipe = fuz::pipeline::Pipeline::default()
+                    .set_website(fuz::viewer::Website::ComicFuz)
+                    .set_progress(self.progress.clone())
+                    .set_writer_config(writer_config);
+                if let Some(base_url) = &self.base_url {
+                    let base_url = base_url.to_string();
+                    let config = fuz::viewer::ConfigBuilder::custom(
+                        base_url.clone(),
+                        base_url.clone(),
+                        base_url,
+                    )?
+                    .build();
+                    pipe = pipe.with_client(fuz::viewer::Client::new(config));
+                }
+                Ok(Box::new(pipe))
+            }
+            #[cfg(not(feature = "giga"))]
+            ViewerType::Giga => bail!("The giga viewer needs the giga feature"),
+            ViewerType::Ichijin => bail!("The Ichijin viewer is not supported yet"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #[cfg(feature = "giga")]
+    use crate::testing;
+
+    use super::*;
+
+    #[cfg(feature = "giga")]
+    #[tokio::test]
+    async fn test_download_recorded_giga_episode() -> Result<()> {
+        let server = testing::giga_server().await?;
+        let url = Url::parse(&format!(
+            "{}/episode/{}",
+            server.uri(),
+            testing::GIGA_EPISODE_ID
+        ))?;
+        let output = PathBuf::from("playground/output/download_builder");
+        let _ = std::fs::remove_dir_all(&output);
+
+        Download::from_url(url)
+            .format(SaveFormat::Zip {
+                compression_method: zip::CompressionMethod::Stored,
+                extension: None,
+            })
+            .image(image::ImageFormat::Png)
+            .output(&output)
+            .progress(ProgressConfig::disabled())
+            .viewer(ViewerType::Giga)
+            .base_url(Url::parse(&server.uri())?)
+            .run()
+            .await?;
+
+        let files = std::fs::read_dir(&output)?.collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(files.len(), 1);
+        let archive = zip::ZipArchive::new(std::fs::File::open(files[0].path())?)?;
+        assert_eq!(archive.len(), 3);
+
+        Ok(())
+    }
+
+    #[cfg(feature = "giga")]
+    #[tokio::test]
+    async fn test_download_through_boxed_pipelines() -> Result<()> {
+        let giga_server = testing::giga_server().await?;
+        let giga_url = Url::parse(&format!(
+            "{}/episode/{}",
+            giga_server.uri(),
+            testing::GIGA_EPISODE_ID
+        ))?;
+        #[cfg_attr(not(feature = "fuz"), allow(unused_mut))]
+        let mut downloads = vec![(
+            Download::from_url(giga_url.clone())
+                .viewer(ViewerType::Giga)
+                .base_url(Url::parse(&giga_server.uri())?),
+            giga_url,
+            PathBuf::from("playground/output/boxed_giga"),
+        )];
+        #[cfg(feature = "fuz")]
+        let fuz_server = testing::fuz_server().await?;
+        #[cfg(feature = "fuz")]
+        {
+            let fuz_url = Url::parse(&format!(
+                "{}/manga/viewer/{}",
+                fuz_server.uri(),
+                testing::FUZ_CHAPTER_ID
+            ))?;
+            downloads.push((
+                Download::from_url(fuz_url.clone())
+                    .viewer(ViewerType::Fuz)
+                    .base_url(Url::parse(&fuz_server.uri())?),
+                fuz_url,
+                PathBuf::from("playground/output/boxed_fuz"),
+            ));
+        }
+
+        for (download, url, path) in downloads {
+            let _ = std::fs::remove_dir_all(&path);
+            let pipe: Box<dyn DynPipeline> =
+                download.progress(ProgressConfig::disabled()).pipeline()?;
+
+            pipe.download(&url, &path).await?;
+
+            assert_eq!(std::fs::read_dir(&path)?.count(), 3);
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_unknown_host_is_rejected() {
+        let url = Url::parse("https://example.com/episode/1").unwrap();
+
+        let result = Download::from_url(url).run().await;
+
+        assert!(result.is_err());
+    }
+}
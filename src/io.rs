@@ -1,17 +1,178 @@
-use std::{future::Future, path::Path};
+use std::{
+    future::Future,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
 use anyhow::Result;
+use futures::{Stream, TryStreamExt};
 use image::DynamicImage;
+use tokio::io::AsyncWrite;
+use url::Url;
 
+use crate::{
+    limit::BytePermit,
+    solver::SolvedImage,
+    utils::{self, Bytes},
+};
+
+pub mod animation;
+pub mod atomic;
+pub mod contact_sheet;
 #[cfg(feature = "pdf")]
 pub mod pdf;
+#[cfg(feature = "pdf")]
+pub mod pdf_reader;
 pub mod raw;
+#[cfg(feature = "sevenz")]
+pub mod sevenz;
+pub mod tiff;
+pub mod webtoon;
 pub mod zip;
 
+/// Compression of TIFF pages
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TiffCompression {
+    #[default]
+    None,
+    Lzw,
+    Deflate,
+}
+
+/// A page handed to a writer as soon as it is solved.
+///
+/// It holds the reservation of its fetched bytes, if any, until it is written. The pages
+/// a page is split into share the reservation.
+#[derive(Debug)]
+pub struct StreamedPage<T> {
+    /// Number the page is named by
+    pub page: usize,
+    pub image: T,
+    permit: Option<Arc<BytePermit>>,
+}
+
+impl<T> StreamedPage<T> {
+    pub fn new(page: usize, image: T) -> Self {
+        StreamedPage {
+            page,
+            image,
+            permit: None,
+        }
+    }
+
+    /// Hold the reservation until the page is written
+    pub fn with_permit(self, permit: Option<Arc<BytePermit>>) -> Self {
+        StreamedPage { permit, ..self }
+    }
+
+    /// The image, releasing the reservation
+    pub fn into_image(self) -> T {
+        self.image
+    }
+
+    /// The page number, the image and the reservation to drop once it is written
+    pub fn into_parts(self) -> (usize, T, Option<Arc<BytePermit>>) {
+        (self.page, self.image, self.permit)
+    }
+}
+
+/// The path with `extension` appended to its file name, unless it already ends in it.
+/// Unlike [`Path::with_extension`] a dot in the title, e.g. in `Vol.2`, is kept.
+pub(crate) fn with_extension(path: &Path, extension: &str) -> PathBuf {
+    let has_extension = path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case(extension));
+    match (has_extension, path.file_name()) {
+        (false, Some(name)) => {
+            path.with_file_name(format!("{}.{}", name.to_string_lossy(), extension))
+        }
+        _ => path.to_path_buf(),
+    }
+}
+
+/// The images as pages in order, each numbered by `page_index` of its position
+pub(crate) fn indexed<T, F>(
+    images: Vec<T>,
+    page_index: F,
+) -> impl Stream<Item = Result<StreamedPage<T>>>
+where
+    F: Fn(usize) -> usize,
+{
+    let pages = images
+        .into_iter()
+        .enumerate()
+        .map(|(i, image)| Ok(StreamedPage::new(page_index(i), image)))
+        .collect::<Vec<_>>();
+    futures::stream::iter(pages)
+}
+
+/// An image a writer takes, decoded or as the bytes to write
+pub trait PageImage: Send + Sized + 'static {
+    /// The bytes to write, encoding a decoded image in the format
+    fn into_bytes(
+        self,
+        image_format: image::ImageFormat,
+        tiff_compression: TiffCompression,
+    ) -> Result<Bytes>;
+
+    /// Save the images with the method of the writer for this kind of image
+    fn write_with<W: EpisodeWriter + ?Sized, P: AsRef<Path>>(
+        writer: &W,
+        images: Vec<Self>,
+        path: P,
+    ) -> impl Future<Output = Result<()>>;
+}
+
+impl PageImage for Bytes {
+    fn into_bytes(self, _: image::ImageFormat, _: TiffCompression) -> Result<Bytes> {
+        Ok(self)
+    }
+
+    fn write_with<W: EpisodeWriter + ?Sized, P: AsRef<Path>>(
+        writer: &W,
+        images: Vec<Self>,
+        path: P,
+    ) -> impl Future<Output = Result<()>> {
+        writer.write(images, path)
+    }
+}
+
+impl PageImage for SolvedImage {
+    fn into_bytes(self, _: image::ImageFormat, _: TiffCompression) -> Result<Bytes> {
+        Ok(SolvedImage::into_bytes(self))
+    }
+
+    fn write_with<W: EpisodeWriter + ?Sized, P: AsRef<Path>>(
+        writer: &W,
+        images: Vec<Self>,
+        path: P,
+    ) -> impl Future<Output = Result<()>> {
+        writer.write_solved(images, path)
+    }
+}
+
+impl PageImage for DynamicImage {
+    fn into_bytes(
+        self,
+        image_format: image::ImageFormat,
+        tiff_compression: TiffCompression,
+    ) -> Result<Bytes> {
+        utils::encode_image_with(&self, image_format, tiff_compression)
+    }
+
+    fn write_with<W: EpisodeWriter + ?Sized, P: AsRef<Path>>(
+        writer: &W,
+        images: Vec<Self>,
+        path: P,
+    ) -> impl Future<Output = Result<()>> {
+        writer.write_images(images, path)
+    }
+}
+
 /// A trait for saving manga to disk.
 pub trait EpisodeWriter {
     /// Save images from bytes
-    fn write<P: AsRef<Path>, B: AsRef<[u8]>>(
+    fn write<P: AsRef<Path>, B: Into<Bytes>>(
         &self,
         images: Vec<B>,
         path: P,
@@ -23,4 +184,161 @@ pub trait EpisodeWriter {
         images: Vec<DynamicImage>,
         path: P,
     ) -> impl Future<Output = Result<()>>;
+
+    /// Save solved images. Writers that need the dimensions can use the known ones
+    /// instead of probing the bytes again.
+    fn write_solved<P: AsRef<Path>>(
+        &self,
+        images: Vec<SolvedImage>,
+        path: P,
+    ) -> impl Future<Output = Result<()>> {
+        let images = images
+            .into_iter()
+            .map(|image| image.into_bytes())
+            .collect::<Vec<_>>();
+        self.write(images, path)
+    }
+
+    /// Save the pages as they arrive in the order they are written, so the episode is not
+    /// held in memory at once. Each page is released once it is written, and an error in
+    /// the stream fails the write.
+    ///
+    /// Writers that need every page before writing one collect them first, releasing each
+    /// page as it arrives.
+    fn write_stream<P, T, S>(
+        &self,
+        pages: S,
+        _total_pages: usize,
+        path: P,
+    ) -> impl Future<Output = Result<()>>
+    where
+        P: AsRef<Path>,
+        T: PageImage,
+        S: Stream<Item = Result<StreamedPage<T>>> + Send,
+    {
+        async move {
+            let images = pages
+                .map_ok(StreamedPage::into_image)
+                .try_collect::<Vec<_>>()
+                .await?;
+            T::write_with(self, images, path).await
+        }
+    }
+}
+
+/// A trait for saving manga as a single file into any sink, e.g. stdout or a buffer.
+///
+/// The whole file is built in memory before any of it is written, as the formats go
+/// back to earlier parts of the file once the pages are in, e.g. the entry headers of a
+/// zip or the object offsets of a PDF. Expect the file on top of the images in memory,
+/// and nothing in the sink until it is complete. Write large episodes to a path with
+/// [`EpisodeWriter`] instead.
+pub trait EpisodeSinkWriter {
+    /// Save images from bytes into the sink
+    fn write_to<W: AsyncWrite + Unpin + Send, B: Into<Bytes>>(
+        &self,
+        images: Vec<B>,
+        sink: &mut W,
+    ) -> impl Future<Output = Result<()>>;
+
+    /// Save images into the sink
+    fn write_images_to<W: AsyncWrite + Unpin + Send>(
+        &self,
+        images: Vec<DynamicImage>,
+        sink: &mut W,
+    ) -> impl Future<Output = Result<()>>;
+}
+
+/// A writer selected by the save format
+#[derive(Debug, Clone)]
+pub enum Writer {
+    Raw(raw::RawWriter),
+    Zip(zip::ZipWriter),
+    Gif(animation::GifWriter),
+    Tiff(tiff::TiffWriter),
+    #[cfg(feature = "pdf")]
+    Pdf(pdf::PdfWriter),
+    #[cfg(feature = "sevenz")]
+    SevenZip(sevenz::SevenZipWriter),
+}
+
+impl Writer {
+    /// Record the url the episode was downloaded from in the formats that have a place
+    /// for it, the zip comment and the PDF subject
+    pub fn with_source_url(self, source_url: Option<Url>) -> Self {
+        match self {
+            Writer::Zip(writer) => Writer::Zip(writer.with_source_url(source_url)),
+            #[cfg(feature = "pdf")]
+            Writer::Pdf(writer) => Writer::Pdf(writer.with_source_url(source_url)),
+            writer => writer,
+        }
+    }
+
+    /// Name the pages and zip entries by these page indices of the episode
+    pub fn with_page_indices(self, page_indices: Option<Vec<usize>>) -> Self {
+        match self {
+            Writer::Raw(writer) => Writer::Raw(writer.with_page_indices(page_indices)),
+            Writer::Zip(writer) => Writer::Zip(writer.with_page_indices(page_indices)),
+            writer => writer,
+        }
+    }
+}
+
+impl EpisodeWriter for Writer {
+    async fn write<P: AsRef<Path>, B: Into<Bytes>>(&self, images: Vec<B>, path: P) -> Result<()> {
+        match self {
+            Writer::Raw(writer) => writer.write(images, path).await,
+            Writer::Zip(writer) => writer.write(images, path).await,
+            Writer::Gif(writer) => writer.write(images, path).await,
+            Writer::Tiff(writer) => writer.write(images, path).await,
+            #[cfg(feature = "pdf")]
+            Writer::Pdf(writer) => writer.write(images, path).await,
+            #[cfg(feature = "sevenz")]
+            Writer::SevenZip(writer) => writer.write(images, path).await,
+        }
+    }
+
+    async fn write_images<P: AsRef<Path>>(&self, images: Vec<DynamicImage>, path: P) -> Result<()> {
+        match self {
+            Writer::Raw(writer) => writer.write_images(images, path).await,
+            Writer::Zip(writer) => writer.write_images(images, path).await,
+            Writer::Gif(writer) => writer.write_images(images, path).await,
+            Writer::Tiff(writer) => writer.write_images(images, path).await,
+            #[cfg(feature = "pdf")]
+            Writer::Pdf(writer) => writer.write_images(images, path).await,
+            #[cfg(feature = "sevenz")]
+            Writer::SevenZip(writer) => writer.write_images(images, path).await,
+        }
+    }
+
+    async fn write_solved<P: AsRef<Path>>(&self, images: Vec<SolvedImage>, path: P) -> Result<()> {
+        match self {
+            Writer::Raw(writer) => writer.write_solved(images, path).await,
+            Writer::Zip(writer) => writer.write_solved(images, path).await,
+            Writer::Gif(writer) => writer.write_solved(images, path).await,
+            Writer::Tiff(writer) => writer.write_solved(images, path).await,
+            #[cfg(feature = "pdf")]
+            Writer::Pdf(writer) => writer.write_solved(images, path).await,
+            #[cfg(feature = "sevenz")]
+            Writer::SevenZip(writer) => writer.write_solved(images, path).await,
+        }
+    }
+
+    async fn write_stream<P, T, S>(&self, pages: S, total_pages: usize, path: P) -> Result<()>
+    where
+        P: AsRef<Path>,
+        T: PageImage,
+        S: Stream<Item = Result<StreamedPage<T>>> + Send,
+    {
+        match self {
+            Writer::Raw(writer) => writer.write_stream(pages, total_pages, path).await,
+            Writer::Zip(writer) => writer.write_stream(pages, total_pages, path).await,
+            Writer::Gif(writer) => writer.write_stream(pages, total_pages, path).await,
+            Writer::Tiff(writer) => writer.write_stream(pages, total_pages, path).await,
+            #[cfg(feature = "pdf")]
+            Writer::Pdf(writer) => writer.write_stream(pages, total_pages, path).await,
+            #[cfg(feature = "sevenz")]
+            Writer::SevenZip(writer) => writer.write_stream(pages, total_pages, path).await,
+        }
+    }
 }
@@ -0,0 +1,169 @@
+use std::{fs::File, io::Write, path::Path, time::Duration};
+
+use anyhow::Result;
+use image::{
+    codecs::gif::{GifEncoder, Repeat},
+    Delay, DynamicImage, Frame,
+};
+use indicatif::ProgressBar;
+
+use crate::{
+    progress::ProgressConfig,
+    utils::{self, Bytes},
+};
+
+use super::{atomic::AtomicFile, EpisodeWriter};
+
+/// Frame delay used by default, 10 frames per second
+pub const DEFAULT_FRAME_DELAY: Duration = Duration::from_millis(100);
+
+/// Save the pages as the frames of a looping animated GIF, for motion comic episodes.
+///
+/// Every frame is shown for `frame_delay`. The viewers don't flag which episodes or pages
+/// are animated, so the caller picks the frames: [`SaveFormat::Gif`] writes every page of
+/// an episode as one animation, and a run of frames can be given to this writer directly.
+///
+/// [`SaveFormat::Gif`]: crate::pipeline::SaveFormat::Gif
+#[derive(Debug, Clone)]
+pub struct GifWriter {
+    frame_delay: Duration,
+    progress: ProgressConfig,
+    temp_file: bool,
+}
+
+impl Default for GifWriter {
+    fn default() -> Self {
+        GifWriter {
+            frame_delay: DEFAULT_FRAME_DELAY,
+            progress: ProgressConfig::default(),
+            temp_file: true,
+        }
+    }
+}
+
+impl GifWriter {
+    pub fn new(frame_delay: Duration, progress: ProgressConfig) -> Self {
+        GifWriter {
+            frame_delay,
+            progress,
+            temp_file: true,
+        }
+    }
+
+    /// Write `<path>.part` and rename it into place when the animation is complete
+    pub fn with_temp_file(self, temp_file: bool) -> Self {
+        GifWriter { temp_file, ..self }
+    }
+
+    /// Encode the images as the frames of the animation, advancing `pb` by each frame
+    pub fn encode<W: Write>(
+        &self,
+        images: &[DynamicImage],
+        inner: W,
+        pb: &ProgressBar,
+    ) -> Result<()> {
+        let delay = Delay::from_saturating_duration(self.frame_delay);
+        let mut encoder = GifEncoder::new(inner);
+        encoder.set_repeat(Repeat::Infinite)?;
+        for image in images {
+            encoder.encode_frame(Frame::from_parts(image.to_rgba8(), 0, 0, delay))?;
+            pb.inc(1);
+        }
+        Ok(())
+    }
+
+    fn save(&self, images: &[DynamicImage], path: &Path, pb: &ProgressBar) -> Result<()> {
+        let path = super::with_extension(path, "gif");
+        if !self.temp_file {
+            return self.encode(images, File::create(path)?, pb);
+        }
+        let mut file = AtomicFile::create(path)?;
+        self.encode(images, &mut file, pb)?;
+        file.persist()
+    }
+}
+
+impl EpisodeWriter for GifWriter {
+    async fn write<P: AsRef<Path>, B: Into<Bytes>>(&self, images: Vec<B>, path: P) -> Result<()> {
+        let images = images
+            .into_iter()
+            .map(|bytes| image::load_from_memory(&bytes.into()))
+            .collect::<Result<Vec<_>, _>>()?;
+        self.write_images(images, path).await
+    }
+
+    async fn write_images<P: AsRef<Path>>(&self, images: Vec<DynamicImage>, path: P) -> Result<()> {
+        let pb = self
+            .progress
+            .build_with_message(images.len(), "Encoding the GIF...")?;
+        let writer = self.clone();
+        let path = path.as_ref().to_path_buf();
+
+        let frames = pb.clone();
+        let task = tokio::task::spawn_blocking(move || writer.save(&images, &path, &frames));
+        utils::join(task, "Encoding the GIF panicked").await?;
+        pb.finish();
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use image::{codecs::gif::GifDecoder, AnimationDecoder, Rgb, RgbImage};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_three_frame_gif() -> Result<()> {
+        let frames = [[255, 0, 0], [0, 255, 0], [0, 0, 255]]
+            .into_iter()
+            .map(|color| DynamicImage::ImageRgb8(RgbImage::from_pixel(16, 24, Rgb(color))))
+            .collect::<Vec<_>>();
+        let path = "playground/output/animation.gif";
+
+        let writer = GifWriter::new(Duration::from_millis(200), ProgressConfig::disabled());
+        writer.write_images(frames, path).await?;
+
+        let decoder = GifDecoder::new(std::io::BufReader::new(File::open(path)?))?;
+        let frames = decoder.into_frames().collect_frames()?;
+        assert_eq!(frames.len(), 3);
+        assert_eq!(
+            frames[0].delay(),
+            Delay::from_saturating_duration(Duration::from_millis(200))
+        );
+        assert_eq!(frames[2].buffer().get_pixel(0, 0).0, [0, 0, 255, 255]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_each_frame_advances_the_bar() -> Result<()> {
+        let frames = vec![DynamicImage::ImageRgb8(RgbImage::new(8, 16)); 3];
+        let pb = ProgressBar::hidden();
+
+        GifWriter::default().encode(&frames, Vec::new(), &pb)?;
+        assert_eq!(pb.position(), 3);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_dotted_title_keeps_its_name() -> Result<()> {
+        let dir = Path::new("playground/output/gif_dotted");
+        let _ = std::fs::remove_dir_all(dir);
+        std::fs::create_dir_all(dir)?;
+        let frames = vec![DynamicImage::ImageRgb8(RgbImage::new(8, 16))];
+
+        let writer = GifWriter::new(DEFAULT_FRAME_DELAY, ProgressConfig::disabled());
+        writer
+            .write_images(frames.clone(), dir.join("Vol.2"))
+            .await?;
+        assert!(dir.join("Vol.2.gif").exists());
+        writer.write_images(frames, dir.join("Vol.3.gif")).await?;
+        assert!(dir.join("Vol.3.gif").exists());
+        assert!(!dir.join("Vol.gif").exists());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,186 @@
+use std::{
+    fs::File,
+    io::{Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+
+/// Path of the file or directory written before it is renamed to `path`
+pub fn part_path<P: AsRef<Path>>(path: P) -> Result<PathBuf> {
+    let path = path.as_ref();
+    let name = path.file_name().context("Output path has no file name")?;
+    Ok(path.with_file_name(format!("{}.part", name.to_string_lossy())))
+}
+
+/// A file written as `<path>.part` and renamed to `path` once complete.
+///
+/// An interrupted run never leaves a truncated file at the expected name.
+/// Dropping it without persisting removes the partial file.
+#[derive(Debug)]
+pub struct AtomicFile {
+    file: File,
+    part_path: PathBuf,
+    path: PathBuf,
+    persisted: bool,
+}
+
+impl AtomicFile {
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let part_path = part_path(&path)?;
+        let file = File::create(&part_path)?;
+
+        Ok(AtomicFile {
+            file,
+            part_path,
+            path,
+            persisted: false,
+        })
+    }
+
+    /// Flush the contents to disk and rename the file into place
+    pub fn persist(mut self) -> Result<()> {
+        self.file.sync_all()?;
+        std::fs::rename(&self.part_path, &self.path)?;
+        self.persisted = true;
+        Ok(())
+    }
+}
+
+impl Write for AtomicFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl Seek for AtomicFile {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.file.seek(pos)
+    }
+}
+
+impl Drop for AtomicFile {
+    fn drop(&mut self) {
+        if !self.persisted {
+            let _ = std::fs::remove_file(&self.part_path);
+        }
+    }
+}
+
+/// A directory written as `<path>.part` and moved to `path` once complete.
+///
+/// When `path` already exists, the written files are moved into it one by one
+/// so other files in the directory are kept.
+#[derive(Debug)]
+pub struct AtomicDir {
+    part_path: PathBuf,
+    path: PathBuf,
+    persisted: bool,
+}
+
+impl AtomicDir {
+    pub async fn create<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let part_path = part_path(&path)?;
+        // left over from an interrupted run
+        if tokio::fs::try_exists(&part_path).await? {
+            tokio::fs::remove_dir_all(&part_path).await?;
+        }
+        tokio::fs::create_dir_all(&part_path).await?;
+
+        Ok(AtomicDir {
+            part_path,
+            path,
+            persisted: false,
+        })
+    }
+
+    /// Directory to write the files into
+    pub fn part_path(&self) -> &Path {
+        &self.part_path
+    }
+
+    /// Move the written files into place
+    pub async fn persist(mut self) -> Result<()> {
+        if !tokio::fs::try_exists(&self.path).await? {
+            tokio::fs::rename(&self.part_path, &self.path).await?;
+        } else {
+            let mut entries = tokio::fs::read_dir(&self.part_path).await?;
+            while let Some(entry) = entries.next_entry().await? {
+                tokio::fs::rename(entry.path(), self.path.join(entry.file_name())).await?;
+            }
+            tokio::fs::remove_dir(&self.part_path).await?;
+        }
+        self.persisted = true;
+        Ok(())
+    }
+}
+
+impl Drop for AtomicDir {
+    fn drop(&mut self) {
+        if !self.persisted {
+            let _ = std::fs::remove_dir_all(&self.part_path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn entries(dir: &Path) -> Result<Vec<PathBuf>> {
+        let mut entries = std::fs::read_dir(dir)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<Vec<_>, _>>()?;
+        entries.sort();
+        Ok(entries)
+    }
+
+    #[test]
+    fn test_persist_and_discard_file() -> Result<()> {
+        let dir = Path::new("playground/output/atomic_file");
+        let _ = std::fs::remove_dir_all(dir);
+        std::fs::create_dir_all(dir)?;
+        let path = dir.join("episode.zip");
+
+        let mut file = AtomicFile::create(&path)?;
+        file.write_all(b"partial")?;
+        assert_eq!(entries(dir)?, vec![dir.join("episode.zip.part")]);
+        drop(file);
+        assert!(entries(dir)?.is_empty());
+
+        let mut file = AtomicFile::create(&path)?;
+        file.write_all(b"complete")?;
+        file.persist()?;
+        assert_eq!(entries(dir)?, vec![path.clone()]);
+        assert_eq!(std::fs::read(&path)?, b"complete");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_persist_dir_into_existing() -> Result<()> {
+        let dir = Path::new("playground/output/atomic_dir");
+        let _ = std::fs::remove_dir_all(dir);
+        std::fs::create_dir_all(dir)?;
+        std::fs::write(dir.join("cover.png"), b"kept")?;
+
+        let part = AtomicDir::create(dir).await?;
+        std::fs::write(part.part_path().join("0.png"), b"page")?;
+        assert!(!dir.join("0.png").exists());
+        part.persist().await?;
+
+        assert_eq!(
+            entries(dir)?,
+            vec![dir.join("0.png"), dir.join("cover.png")]
+        );
+        assert!(!part_path(dir)?.exists());
+
+        Ok(())
+    }
+}
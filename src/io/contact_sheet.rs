@@ -0,0 +1,191 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Result};
+use image::{imageops, DynamicImage, Rgb, RgbImage};
+
+use crate::{
+    data::ScrollDirection,
+    utils::{self, Bytes},
+};
+
+use super::EpisodeWriter;
+
+/// Save a single contact sheet image, a grid of thumbnails of all pages.
+///
+/// The sheet is written next to the episode as `<path>.sheet.<ext>`.
+/// Pages of a right-to-left episode are laid out from the right of each row.
+#[derive(Debug, Clone)]
+pub struct ContactSheetWriter {
+    columns: u32,
+    thumbnail_size: (u32, u32),
+    direction: ScrollDirection,
+    image_format: image::ImageFormat,
+}
+
+impl Default for ContactSheetWriter {
+    fn default() -> Self {
+        ContactSheetWriter {
+            columns: 4,
+            thumbnail_size: (256, 362),
+            direction: ScrollDirection::Unknown,
+            image_format: image::ImageFormat::Png,
+        }
+    }
+}
+
+impl ContactSheetWriter {
+    /// `thumbnail_size` is the (width, height) of each cell in the grid
+    pub fn new(columns: u32, thumbnail_size: (u32, u32), image_format: image::ImageFormat) -> Self {
+        ContactSheetWriter {
+            columns,
+            thumbnail_size,
+            direction: ScrollDirection::Unknown,
+            image_format,
+        }
+    }
+
+    pub fn thumbnail_size(&self) -> (u32, u32) {
+        self.thumbnail_size
+    }
+
+    /// Set the reading direction of the episode
+    pub fn with_direction(self, direction: ScrollDirection) -> Self {
+        ContactSheetWriter { direction, ..self }
+    }
+
+    /// Path of the sheet written for the episode at `path`.
+    /// The suffix is appended, so a dot in the title is not taken as an extension.
+    pub fn sheet_path<P: AsRef<Path>>(&self, path: P) -> PathBuf {
+        let mut sheet_path = path.as_ref().as_os_str().to_os_string();
+        sheet_path.push(format!(".sheet.{}", self.image_format.extensions_str()[0]));
+        sheet_path.into()
+    }
+
+    /// Compose the pages into a grid
+    pub fn compose(&self, images: &[DynamicImage]) -> Result<DynamicImage> {
+        let (cell_width, cell_height) = self.thumbnail_size;
+        if self.columns == 0 || cell_width == 0 || cell_height == 0 {
+            bail!("Contact sheet columns and thumbnail size must be positive");
+        }
+        let columns = self.columns.min(images.len().max(1) as u32);
+        let rows = (images.len() as u32).div_ceil(columns).max(1);
+
+        let mut sheet = RgbImage::from_pixel(
+            columns * cell_width,
+            rows * cell_height,
+            Rgb([255, 255, 255]),
+        );
+        for (i, image) in images.iter().enumerate() {
+            let (row, column) = (i as u32 / columns, i as u32 % columns);
+            let column = match self.direction {
+                ScrollDirection::RightToLeft => columns - 1 - column,
+                _ => column,
+            };
+
+            // keep the aspect ratio and center the page in its cell
+            let thumbnail = image.thumbnail(cell_width, cell_height).to_rgb8();
+            let x = column * cell_width + (cell_width - thumbnail.width()) / 2;
+            let y = row * cell_height + (cell_height - thumbnail.height()) / 2;
+            imageops::overlay(&mut sheet, &thumbnail, x.into(), y.into());
+        }
+
+        Ok(DynamicImage::ImageRgb8(sheet))
+    }
+
+    /// Compose the pages and save the sheet for the episode at `path`
+    pub fn write_sheet<P: AsRef<Path>>(&self, images: &[DynamicImage], path: P) -> Result<()> {
+        let sheet = self.compose(images)?;
+        let path = self.sheet_path(path);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        sheet.save_with_format(path, self.image_format)?;
+        Ok(())
+    }
+
+    /// Compose and save the sheet off the async executor, handing the pages back
+    /// for writing the episode itself
+    pub async fn write_sheet_owned<P: AsRef<Path>>(
+        &self,
+        images: Vec<DynamicImage>,
+        path: P,
+    ) -> Result<Vec<DynamicImage>> {
+        let writer = self.clone();
+        let path = path.as_ref().to_path_buf();
+
+        let task = tokio::task::spawn_blocking(move || {
+            writer.write_sheet(&images, path)?;
+            Result::<_>::Ok(images)
+        });
+        utils::join(task, "Composing the contact sheet panicked").await
+    }
+
+    async fn save<P: AsRef<Path>>(&self, images: Vec<DynamicImage>, path: P) -> Result<()> {
+        self.write_sheet_owned(images, path).await?;
+        Ok(())
+    }
+}
+
+impl EpisodeWriter for ContactSheetWriter {
+    async fn write<P: AsRef<Path>, B: Into<Bytes>>(&self, images: Vec<B>, path: P) -> Result<()> {
+        let images = images
+            .into_iter()
+            .map(|bytes| image::load_from_memory(&bytes.into()))
+            .collect::<Result<Vec<_>, _>>()?;
+        self.save(images, path).await
+    }
+
+    async fn write_images<P: AsRef<Path>>(&self, images: Vec<DynamicImage>, path: P) -> Result<()> {
+        self.save(images, path).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use image::GenericImageView;
+
+    use super::*;
+
+    fn pages() -> Vec<DynamicImage> {
+        [[255, 0, 0], [0, 255, 0], [0, 0, 255], [0, 0, 0]]
+            .into_iter()
+            .map(|color| DynamicImage::ImageRgb8(RgbImage::from_pixel(80, 120, Rgb(color))))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_write_2x2_sheet() -> Result<()> {
+        let writer = ContactSheetWriter::new(2, (40, 60), image::ImageFormat::Png);
+        let path = "playground/output/contact_sheet";
+
+        writer.write_images(pages(), path).await?;
+
+        let sheet = image::open(writer.sheet_path(path))?;
+        assert_eq!(sheet.dimensions(), (80, 120));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sheet_path_keeps_dotted_title() {
+        let writer = ContactSheetWriter::default();
+        assert_eq!(
+            writer.sheet_path("out/Vol.2 第3話"),
+            PathBuf::from("out/Vol.2 第3話.sheet.png")
+        );
+    }
+
+    #[test]
+    fn test_right_to_left_order() -> Result<()> {
+        let sheet = ContactSheetWriter::new(2, (40, 60), image::ImageFormat::Png)
+            .with_direction(ScrollDirection::RightToLeft)
+            .compose(&pages())?;
+
+        // the first page is at the top right
+        assert_eq!(sheet.get_pixel(60, 30).0, [255, 0, 0, 255]);
+        assert_eq!(sheet.get_pixel(20, 30).0, [0, 255, 0, 255]);
+        assert_eq!(sheet.get_pixel(60, 90).0, [0, 0, 255, 255]);
+
+        Ok(())
+    }
+}
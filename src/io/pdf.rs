@@ -1,22 +1,60 @@
 use std::{
-    io::{BufReader, Cursor, Read},
+    collections::HashMap,
+    io::{BufReader, Cursor, Read, Write},
     path::Path,
 };
 
-use anyhow::Result;
+use anyhow::{bail, Result};
 use flate2::{bufread::ZlibEncoder, Compression};
 use image::{GenericImageView, ImageFormat, ImageReader};
 use indicatif::{ParallelProgressIterator, ProgressIterator};
-use pdf_writer::{Content, Finish, Name, Pdf, Rect, Ref};
+use pdf_writer::{
+    types::NumberingStyle, writers::PageLabel, Content, Finish, Name, Pdf, Rect, Ref, TextStr,
+};
 use rayon::iter::{IntoParallelIterator, ParallelIterator};
-use tokio::{fs::File, io::AsyncWriteExt};
+use tokio::{
+    fs::File,
+    io::{AsyncWrite, AsyncWriteExt},
+};
+use url::Url;
 
 use crate::{
+    color,
     progress::ProgressConfig,
+    solver::SolvedImage,
     utils::{self, Bytes},
 };
 
-use super::EpisodeWriter;
+use super::{atomic::AtomicFile, EpisodeSinkWriter, EpisodeWriter};
+
+/// Labels shown by PDF viewers instead of the page indices, e.g. `p1`, `p2`, ...
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PageLabels {
+    prefix: String,
+    start: i32,
+}
+
+impl PageLabels {
+    /// Number the pages from `start`, which must be 1 or more, after the prefix
+    pub fn new(prefix: &str, start: i32) -> Result<Self> {
+        if start < 1 {
+            bail!("Page labels must start at 1 or more, got {}", start);
+        }
+        Ok(PageLabels {
+            prefix: prefix.to_string(),
+            start,
+        })
+    }
+}
+
+impl Default for PageLabels {
+    fn default() -> Self {
+        PageLabels {
+            prefix: String::new(),
+            start: 1,
+        }
+    }
+}
 
 /// Save as a zip file.
 #[derive(Debug, Clone)]
@@ -24,6 +62,19 @@ pub struct PdfWriter {
     // num_threads: usize,
     progress: ProgressConfig,
     image_format: image::ImageFormat,
+    temp_file: bool,
+    source_url: Option<Url>,
+    page_labels: Option<PageLabels>,
+    icc_profiles: bool,
+}
+
+/// An encoded page ready to be embedded
+struct EncodedPage {
+    bytes: Bytes,
+    width: u32,
+    height: u32,
+    /// RGB ICC profile of the page, if it is embedded
+    icc_profile: Option<Vec<u8>>,
 }
 
 impl PdfWriter {
@@ -31,13 +82,62 @@ impl PdfWriter {
         PdfWriter {
             progress,
             image_format,
+            temp_file: true,
+            source_url: None,
+            page_labels: None,
+            icc_profiles: false,
+        }
+    }
+
+    /// Fail for page formats that can't be embedded in a PDF
+    pub fn check_image_format(image_format: ImageFormat) -> Result<()> {
+        match image_format {
+            ImageFormat::Tiff | ImageFormat::Bmp => bail!(
+                "{:?} pages can't be embedded in a PDF, use JPEG or PNG instead",
+                image_format
+            ),
+            _ => Ok(()),
         }
     }
 
-    pub fn default() -> Self {
+    /// Write `<path>.part` and rename it into place when the PDF is complete
+    pub fn with_temp_file(self, temp_file: bool) -> Self {
+        PdfWriter { temp_file, ..self }
+    }
+
+    /// Record the url the episode was downloaded from as the subject of the document
+    pub fn with_source_url(self, source_url: Option<Url>) -> Self {
+        PdfWriter { source_url, ..self }
+    }
+
+    /// Label the pages in the catalog's `PageLabels` tree
+    pub fn with_page_labels(self, page_labels: Option<PageLabels>) -> Self {
+        PdfWriter {
+            page_labels,
+            ..self
+        }
+    }
+
+    /// Embed the ICC profile of the source images as an `ICCBased` color space, so
+    /// wide-gamut pages keep their colors. Pages without a profile stay `DeviceRGB`.
+    /// Only the bytes written as they are carry a profile, decoded images don't.
+    pub fn with_icc_profiles(self, icc_profiles: bool) -> Self {
+        PdfWriter {
+            icc_profiles,
+            ..self
+        }
+    }
+}
+
+impl Default for PdfWriter {
+    fn default() -> Self {
         PdfWriter {
             progress: ProgressConfig::default(),
             image_format: image::ImageFormat::Jpeg,
+            temp_file: true,
+            source_url: None,
+            page_labels: None,
+            icc_profiles: false,
         }
     }
 }
@@ -47,8 +147,8 @@ impl PdfWriter {
     pub fn new_pdf() -> (Pdf, Ref, Ref) {
         let mut pdf = Pdf::new();
         let mut ref_id = Ref::new(1);
-        let catalog_id = ref_id.bump().clone();
-        let page_tree_id = ref_id.bump().clone();
+        let catalog_id = ref_id.bump();
+        let page_tree_id = ref_id.bump();
 
         // required
         pdf.catalog(catalog_id).pages(page_tree_id);
@@ -56,6 +156,41 @@ impl PdfWriter {
         (pdf, ref_id, page_tree_id)
     }
 
+    /// Create a new PDF instance with the page labels in the catalog, if there are any
+    fn new_labeled_pdf(&self) -> (Pdf, Ref, Ref) {
+        let Some(page_labels) = &self.page_labels else {
+            return Self::new_pdf();
+        };
+        let mut pdf = Pdf::new();
+        let mut ref_id = Ref::new(1);
+        let catalog_id = ref_id.bump();
+        let page_tree_id = ref_id.bump();
+        let label_id = ref_id.bump();
+
+        let mut catalog = pdf.catalog(catalog_id);
+        catalog.pages(page_tree_id);
+        catalog.page_labels().nums().insert(0, label_id);
+        catalog.finish();
+        let mut label = pdf.indirect(label_id).start::<PageLabel>();
+        label
+            .style(NumberingStyle::Arabic)
+            .offset(page_labels.start);
+        if !page_labels.prefix.is_empty() {
+            label.prefix(TextStr(&page_labels.prefix));
+        }
+        label.finish();
+
+        (pdf, ref_id, page_tree_id)
+    }
+
+    /// Write the document information with the source url, if there is one
+    fn write_info(&self, pdf: &mut Pdf, ref_id: &mut Ref) {
+        if let Some(source_url) = &self.source_url {
+            pdf.document_info(ref_id.bump())
+                .subject(TextStr(source_url.as_str()));
+        }
+    }
+
     /// Get the image decoder based on the image format.
     fn get_image_decoder(&self) -> pdf_writer::Filter {
         match self.image_format {
@@ -64,48 +199,78 @@ impl PdfWriter {
         }
     }
 
-    fn compress_image_bytes_if_needed<B: AsRef<[u8]>>(&self, bytes: B) -> Result<Bytes> {
+    fn compress_image_bytes_if_needed(&self, bytes: Bytes) -> Result<Bytes> {
         match self.image_format {
-            ImageFormat::Jpeg => Ok(bytes.as_ref().into()),
+            ImageFormat::Jpeg => Ok(bytes),
             _ => {
                 let mut compressed = Vec::new();
                 let reader = BufReader::new(bytes.as_ref());
                 let mut encoder = ZlibEncoder::new(reader, Compression::default());
                 encoder.read_to_end(&mut compressed)?;
-                Ok(compressed)
+                Ok(compressed.into())
+            }
+        }
+    }
+
+    /// The RGB ICC profile embedded in the image bytes, if profiles are embedded at all
+    fn read_icc_profile(&self, bytes: &Bytes) -> Option<Vec<u8>> {
+        if !self.icc_profiles {
+            return None;
+        }
+        match color::icc_profile(bytes) {
+            // the pages are RGB, a profile of another color space doesn't apply
+            Ok(Some(icc)) if color::is_rgb_profile(&icc) => Some(icc),
+            Ok(_) => None,
+            Err(e) => {
+                tracing::debug!("Failed to read the ICC profile: {:#}", e);
+                None
             }
         }
     }
 
     fn add_image_page(
         &self,
-        image_bytes: Bytes,
-        image_width: u32,
-        image_height: u32,
+        page: EncodedPage,
         pdf: &mut Pdf,
         ref_id: &mut Ref,
         page_tree_id: &Ref,
+        icc_ids: &mut HashMap<Vec<u8>, Ref>,
     ) -> Ref {
-        let width = image_width as f32;
-        let height = image_height as f32;
+        let width = page.width as f32;
+        let height = page.height as f32;
+
+        // pages with the same profile share one stream
+        let icc_id = page.icc_profile.map(|icc| {
+            *icc_ids.entry(icc).or_insert_with_key(|icc| {
+                let icc_id = ref_id.bump();
+                let mut profile = pdf.icc_profile(icc_id, icc);
+                profile.n(3);
+                profile.alternate().device_rgb();
+                profile.finish();
+                icc_id
+            })
+        });
 
-        let image_id = ref_id.bump().clone();
+        let image_id = ref_id.bump();
         {
-            let width = image_width as i32;
-            let height = image_height as i32;
+            let width = page.width as i32;
+            let height = page.height as i32;
 
-            let mut image = pdf.image_xobject(image_id, &image_bytes);
+            let mut image = pdf.image_xobject(image_id, &page.bytes);
             image.filter(self.get_image_decoder());
             image.width(width);
             image.height(height);
-            image.color_space().device_rgb();
+            match icc_id {
+                Some(icc_id) => image.color_space().icc_based(icc_id),
+                None => image.color_space().device_rgb(),
+            }
             image.bits_per_component(8);
             image.finish();
         }
 
         // create blank page
-        let page_id = ref_id.bump().clone();
-        let content_id = ref_id.bump().clone();
+        let page_id = ref_id.bump();
+        let content_id = ref_id.bump();
         let image_name = format!("Image{}", image_id.get());
         let image_name = Name(image_name.as_bytes());
         {
@@ -113,7 +278,7 @@ impl PdfWriter {
             let area = Rect::new(0.0, 0.0, width, height);
             // let area = Rect::new(0.0, 0.0, 2400., 2400.);
             page.media_box(area);
-            page.parent(page_tree_id.clone());
+            page.parent(*page_tree_id);
             page.contents(content_id);
             page.resources().x_objects().pair(image_name, image_id);
             page.finish();
@@ -128,18 +293,16 @@ impl PdfWriter {
             pdf.stream(content_id, &content.finish());
         }
 
-        page_id.clone()
+        page_id
     }
 }
 
-impl EpisodeWriter for PdfWriter {
-    async fn write<P: AsRef<Path>, B: AsRef<[u8]>>(&self, images: Vec<B>, path: P) -> Result<()> {
-        let (mut pdf, mut ref_id, page_tree_id) = Self::new_pdf();
+impl PdfWriter {
+    /// Build a PDF from solved images. The dimensions are probed only when they are unknown.
+    fn build_solved(&self, images: Vec<SolvedImage>) -> Result<Vec<u8>> {
+        Self::check_image_format(self.image_format)?;
+        let (mut pdf, mut ref_id, page_tree_id) = self.new_labeled_pdf();
 
-        let images: Vec<Bytes> = images
-            .into_iter()
-            .map(|bytes| bytes.as_ref().into())
-            .collect();
         let images_len = images.len();
         let encoded = images
             .into_par_iter()
@@ -148,48 +311,50 @@ impl EpisodeWriter for PdfWriter {
                     .build_with_message(images_len, "Encoding images...")?,
             )
             .map(|image| {
-                // get width and height without full decode
-                let reader = ImageReader::new(Cursor::new(image.clone())).with_guessed_format()?;
-                let (width, height) = reader.into_dimensions()?;
-                let image_bytes = self.compress_image_bytes_if_needed(image)?;
-                Result::<_>::Ok((image_bytes, width, height))
+                let (width, height) = match image.dimensions() {
+                    Some(dimensions) => dimensions,
+                    None => {
+                        // get width and height without full decode
+                        let reader =
+                            ImageReader::new(Cursor::new(image.bytes())).with_guessed_format()?;
+                        reader.into_dimensions()?
+                    }
+                };
+                let icc_profile = self.read_icc_profile(image.bytes());
+                let bytes = self.compress_image_bytes_if_needed(image.into_bytes())?;
+                Result::<_>::Ok(EncodedPage {
+                    bytes,
+                    width,
+                    height,
+                    icc_profile,
+                })
             })
-            .map(|pair| pair.unwrap())
-            .collect::<Vec<_>>();
+            .collect::<Result<Vec<_>>>()?;
 
+        let mut icc_ids = HashMap::new();
         let page_ids = encoded
             .into_iter()
             .progress_with(
                 self.progress
                     .build_with_message(images_len, "Building a PDF...")?,
             )
-            .map(|(bytes, width, height)| {
-                self.add_image_page(bytes, width, height, &mut pdf, &mut ref_id, &page_tree_id)
+            .map(|page| {
+                self.add_image_page(page, &mut pdf, &mut ref_id, &page_tree_id, &mut icc_ids)
             })
             .collect::<Vec<_>>();
 
         pdf.pages(page_tree_id)
             .count(page_ids.len() as i32)
             .kids(page_ids);
+        self.write_info(&mut pdf, &mut ref_id);
 
-        // save
-        let mut file = File::options()
-            .write(true)
-            .create(true)
-            .truncate(true)
-            .open(path)
-            .await?;
-        file.write_all(pdf.finish().as_ref()).await?;
-
-        Ok(())
+        Ok(pdf.finish())
     }
 
-    async fn write_images<P: AsRef<Path>>(
-        &self,
-        images: Vec<image::DynamicImage>,
-        path: P,
-    ) -> Result<()> {
-        let (mut pdf, mut ref_id, page_tree_id) = Self::new_pdf();
+    /// Build a PDF from images
+    fn build_images(&self, images: Vec<image::DynamicImage>) -> Result<Vec<u8>> {
+        Self::check_image_format(self.image_format)?;
+        let (mut pdf, mut ref_id, page_tree_id) = self.new_labeled_pdf();
 
         let image_format = self.image_format;
 
@@ -203,34 +368,119 @@ impl EpisodeWriter for PdfWriter {
             .map(|image| {
                 let (width, height) = image.dimensions();
                 let bytes = utils::encode_image(&image, image_format)?;
-                Result::<_>::Ok((bytes, width, height))
+                let bytes = self.compress_image_bytes_if_needed(bytes)?;
+                Result::<_>::Ok(EncodedPage {
+                    bytes,
+                    width,
+                    height,
+                    icc_profile: None,
+                })
             })
-            .map(|pair| pair.unwrap())
-            .collect::<Vec<_>>();
+            .collect::<Result<Vec<_>>>()?;
 
+        let mut icc_ids = HashMap::new();
         let page_ids = encoded
             .into_iter()
             .progress_with(
                 self.progress
                     .build_with_message(images_len, "Building a PDF...")?,
             )
-            .map(|(bytes, width, height)| {
-                self.add_image_page(bytes, width, height, &mut pdf, &mut ref_id, &page_tree_id)
+            .map(|page| {
+                self.add_image_page(page, &mut pdf, &mut ref_id, &page_tree_id, &mut icc_ids)
             })
             .collect::<Vec<_>>();
 
         pdf.pages(page_tree_id)
             .count(page_ids.len() as i32)
             .kids(page_ids);
+        self.write_info(&mut pdf, &mut ref_id);
 
-        // save
+        Ok(pdf.finish())
+    }
+
+    /// Save pages whose `(bytes, width, height)` are already known, e.g. from the page
+    /// metadata, without probing the bytes for the dimensions
+    pub async fn write_with_dimensions<P: AsRef<Path>>(
+        &self,
+        images: Vec<(Bytes, u32, u32)>,
+        path: P,
+    ) -> Result<()> {
+        let images = images
+            .into_iter()
+            .map(|(bytes, width, height)| SolvedImage::new(bytes, Some((width, height))))
+            .collect();
+        self.write_solved(images, path).await
+    }
+
+    async fn save<P: AsRef<Path>>(&self, path: P, pdf: &[u8]) -> Result<()> {
+        if self.temp_file {
+            let mut file = AtomicFile::create(path)?;
+            file.write_all(pdf)?;
+            return file.persist();
+        }
         let mut file = File::options()
             .write(true)
             .create(true)
             .truncate(true)
             .open(path)
             .await?;
-        file.write_all(pdf.finish().as_ref()).await?;
+        file.write_all(pdf).await?;
+
+        Ok(())
+    }
+}
+
+impl EpisodeWriter for PdfWriter {
+    async fn write<P: AsRef<Path>, B: Into<Bytes>>(&self, images: Vec<B>, path: P) -> Result<()> {
+        let images = images
+            .into_iter()
+            .map(|bytes| SolvedImage::new(bytes.into(), None))
+            .collect::<Vec<_>>();
+
+        self.write_solved(images, path).await
+    }
+
+    /// Save solved images. The dimensions are probed only when they are unknown.
+    async fn write_solved<P: AsRef<Path>>(&self, images: Vec<SolvedImage>, path: P) -> Result<()> {
+        let pdf = self.build_solved(images)?;
+        self.save(path, &pdf).await
+    }
+
+    async fn write_images<P: AsRef<Path>>(
+        &self,
+        images: Vec<image::DynamicImage>,
+        path: P,
+    ) -> Result<()> {
+        let pdf = self.build_images(images)?;
+        self.save(path, &pdf).await
+    }
+}
+
+impl EpisodeSinkWriter for PdfWriter {
+    async fn write_to<W: AsyncWrite + Unpin + Send, B: Into<Bytes>>(
+        &self,
+        images: Vec<B>,
+        sink: &mut W,
+    ) -> Result<()> {
+        let images = images
+            .into_iter()
+            .map(|bytes| SolvedImage::new(bytes.into(), None))
+            .collect::<Vec<_>>();
+        let pdf = self.build_solved(images)?;
+        sink.write_all(&pdf).await?;
+        sink.flush().await?;
+
+        Ok(())
+    }
+
+    async fn write_images_to<W: AsyncWrite + Unpin + Send>(
+        &self,
+        images: Vec<image::DynamicImage>,
+        sink: &mut W,
+    ) -> Result<()> {
+        let pdf = self.build_images(images)?;
+        sink.write_all(&pdf).await?;
+        sink.flush().await?;
 
         Ok(())
     }
@@ -238,17 +488,155 @@ impl EpisodeWriter for PdfWriter {
 
 #[cfg(test)]
 mod test {
-    use image::GenericImageView;
+    use image::{GenericImageView, ImageEncoder};
     use pdf_writer::{Content, Filter, Finish, Name, Pdf, Rect, Ref};
 
     use super::*;
 
+    #[tokio::test]
+    async fn test_write_to_memory() -> Result<()> {
+        let image =
+            image::load_from_memory(&std::fs::read("playground/assets/giga-original.jpg")?)?;
+        let writer = PdfWriter::new(ProgressConfig::disabled(), ImageFormat::Jpeg);
+
+        let mut buffer = Vec::new();
+        writer.write_images_to(vec![image], &mut buffer).await?;
+
+        assert!(buffer.starts_with(b"%PDF-"));
+        assert!(String::from_utf8_lossy(&buffer).contains("/Count 1"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_source_url_is_the_subject() -> Result<()> {
+        let image = SolvedImage::new(vec![0xff, 0xd8, 0x00, 0x01].into(), Some((843, 1200)));
+        let url = Url::parse("https://shonenjumpplus.com/episode/1")?;
+        let writer = PdfWriter::new(ProgressConfig::disabled(), ImageFormat::Jpeg)
+            .with_source_url(Some(url));
+
+        let path = "playground/output/source_url.pdf";
+        writer.write_solved(vec![image], path).await?;
+
+        let pdf = tokio::fs::read(path).await?;
+        let pdf = String::from_utf8_lossy(&pdf);
+        assert!(pdf.contains("/Subject (https://shonenjumpplus.com/episode/1)"));
+        assert!(pdf.contains("/Info"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_page_labels_start_at_the_configured_value() -> Result<()> {
+        let image = SolvedImage::new(vec![0xff, 0xd8, 0x00, 0x01].into(), Some((843, 1200)));
+        let writer = PdfWriter::new(ProgressConfig::disabled(), ImageFormat::Jpeg)
+            .with_page_labels(Some(PageLabels::new("p", 5)?));
+
+        let path = "playground/output/page_labels.pdf";
+        writer.write_solved(vec![image], path).await?;
+
+        let pdf = tokio::fs::read(path).await?;
+        let pdf = String::from_utf8_lossy(&pdf);
+        assert!(pdf.contains("/PageLabels"));
+        assert!(pdf.contains("/Type /PageLabel"));
+        assert!(pdf.contains("/S /D"));
+        assert!(pdf.contains("/St 5"));
+        assert!(pdf.contains("/P (p)"));
+
+        assert!(PageLabels::new("p", 0).is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_write_solved_uses_known_dimensions() -> Result<()> {
+        // not a decodable image, so probing would fail
+        let image = SolvedImage::new(vec![0xff, 0xd8, 0x00, 0x01].into(), Some((843, 1200)));
+        let path = "playground/output/known_dimensions.pdf";
+
+        let writer = PdfWriter::new(ProgressConfig::disabled(), ImageFormat::Jpeg);
+        writer.write_solved(vec![image], path).await?;
+
+        let pdf = tokio::fs::read(path).await?;
+        let pdf = String::from_utf8_lossy(&pdf);
+        assert!(pdf.contains("/MediaBox [0 0 843 1200]"));
+        assert!(pdf.contains("/Width 843"));
+        assert!(pdf.contains("/Height 1200"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_known_dimensions_match_probing() -> Result<()> {
+        let bytes = Bytes::from(std::fs::read("playground/assets/giga-original.jpg")?);
+        let (width, height) = image::load_from_memory(&bytes)?.dimensions();
+        let probed = "playground/output/dimensions_probed.pdf";
+        let known = "playground/output/dimensions_known.pdf";
+
+        let writer = PdfWriter::new(ProgressConfig::disabled(), ImageFormat::Jpeg);
+        writer
+            .write(vec![bytes.clone(), bytes.clone()], probed)
+            .await?;
+        writer
+            .write_with_dimensions(
+                vec![(bytes.clone(), width, height), (bytes, width, height)],
+                known,
+            )
+            .await?;
+
+        assert_eq!(std::fs::read(probed)?, std::fs::read(known)?);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_embed_icc_profile() -> Result<()> {
+        let icc = moxcms::ColorProfile::new_display_p3()
+            .encode()
+            .map_err(|e| anyhow::anyhow!("{:?}", e))?;
+        let image = image::RgbImage::from_pixel(8, 8, image::Rgb([200, 100, 50]));
+        let encode = |icc: Option<Vec<u8>>| {
+            let mut bytes = Vec::new();
+            let mut encoder = image::codecs::jpeg::JpegEncoder::new(&mut bytes);
+            if let Some(icc) = icc {
+                encoder.set_icc_profile(icc)?;
+            }
+            encoder.write_image(image.as_raw(), 8, 8, image::ExtendedColorType::Rgb8)?;
+            Result::<_>::Ok(Bytes::from(bytes))
+        };
+        let with_profile = encode(Some(icc))?;
+        let without_profile = encode(None)?;
+
+        let writer =
+            PdfWriter::new(ProgressConfig::disabled(), ImageFormat::Jpeg).with_icc_profiles(true);
+        let mut pdf = Vec::new();
+        writer
+            .write_to(
+                vec![with_profile.clone(), with_profile.clone(), without_profile],
+                &mut pdf,
+            )
+            .await?;
+        let pdf = String::from_utf8_lossy(&pdf);
+        // the two pages with the profile share one stream
+        assert_eq!(pdf.matches("/ICCBased").count(), 2);
+        assert_eq!(pdf.matches("/N 3").count(), 1);
+        assert_eq!(pdf.matches("/ColorSpace /DeviceRGB").count(), 1);
+
+        // off by default
+        let writer = PdfWriter::new(ProgressConfig::disabled(), ImageFormat::Jpeg);
+        let mut pdf = Vec::new();
+        writer.write_to(vec![with_profile], &mut pdf).await?;
+        assert!(!String::from_utf8_lossy(&pdf).contains("/ICCBased"));
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_pdf_blank_5_pages() -> Result<()> {
         let mut pdf = Pdf::new();
         let mut ref_id = Ref::new(1);
-        let catalog_id = ref_id.bump().clone();
-        let page_tree_id = ref_id.bump().clone();
+        let catalog_id = ref_id.bump();
+        let page_tree_id = ref_id.bump();
         assert_ne!(catalog_id, page_tree_id);
 
         // catalog
@@ -258,8 +646,8 @@ mod test {
 
         // create new page
         for _ in 0..5 {
-            let page_id = ref_id.bump().clone();
-            let content_id = ref_id.bump().clone();
+            let page_id = ref_id.bump();
+            let content_id = ref_id.bump();
             let mut page = pdf.page(page_id);
 
             // create blank page
@@ -294,8 +682,8 @@ mod test {
         let mut pdf = Pdf::new();
 
         let mut ref_id = Ref::new(1);
-        let catalog_id = ref_id.bump().clone();
-        let page_tree_id = ref_id.bump().clone();
+        let catalog_id = ref_id.bump();
+        let page_tree_id = ref_id.bump();
         assert_ne!(catalog_id, page_tree_id);
 
         // catalog
@@ -304,7 +692,7 @@ mod test {
         let mut page_ids = vec![];
 
         // load the image
-        let image_id = ref_id.bump().clone();
+        let image_id = ref_id.bump();
         let data = std::fs::read("playground/assets/giga-original.jpg")?;
         let dynamic = image::load_from_memory(&data)?;
 
@@ -319,8 +707,8 @@ mod test {
         image.finish();
 
         // create new page
-        let page_id = ref_id.bump().clone();
-        let content_id = ref_id.bump().clone();
+        let page_id = ref_id.bump();
+        let content_id = ref_id.bump();
         let mut page = pdf.page(page_id);
         let (width, height) = dynamic.dimensions();
         let area = Rect::new(0.0, 0.0, width as f32, height as f32);
@@ -349,4 +737,17 @@ mod test {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_reject_tiff_pages() -> Result<()> {
+        let writer = PdfWriter::new(ProgressConfig::disabled(), ImageFormat::Tiff);
+        let images = vec![image::DynamicImage::new_rgb8(8, 8)];
+
+        let mut pdf = Vec::new();
+        let error = writer.write_images_to(images, &mut pdf).await.unwrap_err();
+        assert!(error.to_string().contains("Tiff"), "{}", error);
+        assert!(pdf.is_empty());
+
+        Ok(())
+    }
 }
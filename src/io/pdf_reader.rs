@@ -0,0 +1,128 @@
+use std::{io::Read, path::Path};
+
+use anyhow::{bail, Context, Result};
+use flate2::read::ZlibDecoder;
+use image::{DynamicImage, GrayImage, RgbImage};
+use lopdf::{xobject::PdfImage, Document};
+
+use crate::utils::{self, Bytes};
+
+/// Read the page images back from a PDF written by [`super::pdf::PdfWriter`].
+///
+/// Both `DCTDecode` (JPEG) and `FlateDecode` image streams are supported.
+pub struct PdfReader {
+    document: Document,
+}
+
+impl PdfReader {
+    pub fn load_mem<B: AsRef<[u8]>>(bytes: B) -> Result<Self> {
+        let document = Document::load_mem(bytes.as_ref())?;
+        Ok(PdfReader { document })
+    }
+
+    pub async fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let bytes = tokio::fs::read(path).await?;
+        Self::load_mem(bytes)
+    }
+
+    /// Encoded images in page order
+    pub fn extract_bytes(&self) -> Result<Vec<Bytes>> {
+        let mut images = Vec::new();
+        for (_, page_id) in self.document.get_pages() {
+            for image in self.document.get_page_images(page_id)? {
+                images.push(Self::extract_image(&image)?);
+            }
+        }
+        Ok(images)
+    }
+
+    /// Decoded images in page order
+    pub fn extract_images(&self) -> Result<Vec<DynamicImage>> {
+        self.extract_bytes()?
+            .iter()
+            .map(|bytes| Ok(image::load_from_memory(bytes)?))
+            .collect()
+    }
+
+    fn extract_image(image: &PdfImage) -> Result<Bytes> {
+        let filters = image.filters.clone().unwrap_or_default();
+        match filters.iter().map(|f| f.as_str()).collect::<Vec<_>>()[..] {
+            ["DCTDecode"] => Ok(Bytes::copy_from_slice(image.content)),
+            // older versions stored encoded images without compressing them
+            ["FlateDecode"] if image::guess_format(image.content).is_ok() => {
+                Ok(Bytes::copy_from_slice(image.content))
+            }
+            ["FlateDecode"] => {
+                let mut inflated = Vec::new();
+                ZlibDecoder::new(image.content).read_to_end(&mut inflated)?;
+
+                // this crate stores encoded images, other writers store raw samples
+                if image::guess_format(&inflated).is_ok() {
+                    return Ok(inflated.into());
+                }
+                let raw = Self::raw_samples(image, inflated)?;
+                utils::encode_image(&raw, image::ImageFormat::Png)
+            }
+            _ => bail!("Unsupported image filters: {:?}", filters),
+        }
+    }
+
+    fn raw_samples(image: &PdfImage, samples: Vec<u8>) -> Result<DynamicImage> {
+        let width = u32::try_from(image.width)?;
+        let height = u32::try_from(image.height)?;
+        if image.bits_per_component != Some(8) {
+            bail!(
+                "Unsupported bits per component: {:?}",
+                image.bits_per_component
+            );
+        }
+
+        match image.color_space.as_deref() {
+            Some("DeviceRGB") => RgbImage::from_raw(width, height, samples)
+                .map(DynamicImage::ImageRgb8)
+                .context("Image data is shorter than its dimensions"),
+            Some("DeviceGray") => GrayImage::from_raw(width, height, samples)
+                .map(DynamicImage::ImageLuma8)
+                .context("Image data is shorter than its dimensions"),
+            color_space => bail!("Unsupported color space: {:?}", color_space),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use image::GenericImageView;
+
+    use crate::{
+        io::{pdf::PdfWriter, EpisodeSinkWriter},
+        progress::ProgressConfig,
+    };
+
+    use super::*;
+
+    fn pages() -> Vec<DynamicImage> {
+        [(40, 60), (60, 40), (32, 32)]
+            .into_iter()
+            .map(|(w, h)| DynamicImage::ImageRgb8(RgbImage::new(w, h)))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_round_trip() -> Result<()> {
+        for format in [image::ImageFormat::Jpeg, image::ImageFormat::Png] {
+            let writer = PdfWriter::new(ProgressConfig::disabled(), format);
+            let mut pdf = Vec::new();
+            writer.write_images_to(pages(), &mut pdf).await?;
+
+            let images = PdfReader::load_mem(&pdf)?.extract_images()?;
+            assert_eq!(
+                images.iter().map(|i| i.dimensions()).collect::<Vec<_>>(),
+                pages().iter().map(|i| i.dimensions()).collect::<Vec<_>>(),
+                "{:?}",
+                format
+            );
+        }
+
+        Ok(())
+    }
+}
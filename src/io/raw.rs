@@ -1,21 +1,35 @@
 use std::{path::Path, sync::Arc};
 
 use anyhow::Result;
-use futures::StreamExt;
+use futures::{Stream, StreamExt};
+use sha2::{Digest, Sha256};
 use tokio::{
     fs::File,
     io::{AsyncWriteExt, BufWriter},
 };
 
-use crate::{progress::ProgressConfig, utils};
+use crate::{
+    progress::ProgressConfig,
+    utils::{self, Bytes},
+};
+
+use super::{atomic::AtomicDir, EpisodeWriter, PageImage, StreamedPage, TiffCompression};
 
-use super::EpisodeWriter;
+/// Sidecar listing the page file names in page order when naming by content
+pub const INDEX_NAME: &str = "index.json";
 
 #[derive(Debug, Clone)]
 pub struct RawWriter {
     progress: ProgressConfig,
     image_format: image::ImageFormat,
     num_threads: usize,
+    temp_file: bool,
+    tiff_compression: TiffCompression,
+    content_addressed: bool,
+    skip_unchanged: bool,
+    extension_from_content: bool,
+    /// Index in the episode of each written page
+    page_indices: Option<Vec<usize>>,
 }
 
 impl RawWriter {
@@ -28,104 +42,455 @@ impl RawWriter {
             progress,
             image_format,
             num_threads,
+            temp_file: true,
+            tiff_compression: TiffCompression::None,
+            content_addressed: false,
+            skip_unchanged: false,
+            extension_from_content: false,
+            page_indices: None,
+        }
+    }
+
+    /// Write into `<path>.part` and move the pages into place when all are written
+    pub fn with_temp_file(self, temp_file: bool) -> Self {
+        RawWriter { temp_file, ..self }
+    }
+
+    /// Compression of the pages when saving as TIFF
+    pub fn with_tiff_compression(self, tiff_compression: TiffCompression) -> Self {
+        RawWriter {
+            tiff_compression,
+            ..self
+        }
+    }
+
+    /// Name the pages by the SHA-256 of their bytes instead of the page number
+    /// and list the names in page order in `index.json`
+    pub fn with_content_addressed(self, content_addressed: bool) -> Self {
+        RawWriter {
+            content_addressed,
+            ..self
+        }
+    }
+
+    /// Leave the pages whose file already has the same SHA-256 untouched and log how many
+    /// changed, e.g. when downloading again to pick up corrected pages. The pages are
+    /// written in place then, without the temporary directory.
+    pub fn with_skip_unchanged(self, skip_unchanged: bool) -> Self {
+        RawWriter {
+            skip_unchanged,
+            ..self
+        }
+    }
+
+    /// Name the pages written as bytes by the format detected from their bytes, e.g. `0.jpg`
+    /// for a JPEG page even when saving as PNG. Pages of an unknown format keep the
+    /// configured extension.
+    pub fn with_extension_from_content(self, extension_from_content: bool) -> Self {
+        RawWriter {
+            extension_from_content,
+            ..self
         }
     }
 
-    pub fn default() -> Self {
+    /// Name the pages by these indices in the episode instead of their position,
+    /// e.g. when failed pages were left out
+    pub fn with_page_indices(self, page_indices: Option<Vec<usize>>) -> Self {
+        RawWriter {
+            page_indices,
+            ..self
+        }
+    }
+
+    /// Index in the episode of the `i`th written page
+    fn page_index(&self, i: usize) -> usize {
+        self.page_indices
+            .as_ref()
+            .and_then(|indices| indices.get(i).copied())
+            .unwrap_or(i)
+    }
+}
+
+impl Default for RawWriter {
+    fn default() -> Self {
         RawWriter {
             progress: ProgressConfig::default(),
             image_format: image::ImageFormat::Png,
             num_threads: num_cpus::get(),
+            temp_file: true,
+            tiff_compression: TiffCompression::None,
+            content_addressed: false,
+            skip_unchanged: false,
+            extension_from_content: false,
+            page_indices: None,
         }
     }
 }
 
-impl EpisodeWriter for RawWriter {
-    async fn write<P: AsRef<Path>, B: AsRef<[u8]>>(&self, images: Vec<B>, path: P) -> Result<()> {
+impl RawWriter {
+    /// Write the pages as they arrive, releasing each once it is written
+    async fn write_pages_into<P, T, S>(&self, pages: S, total_pages: usize, path: P) -> Result<()>
+    where
+        P: AsRef<Path>,
+        T: PageImage,
+        S: Stream<Item = Result<StreamedPage<T>>> + Send,
+    {
         let image_format = self.image_format;
+        let tiff_compression = self.tiff_compression;
+        let content_addressed = self.content_addressed;
+        let skip_unchanged = self.skip_unchanged;
+        let extension_from_content = self.extension_from_content;
 
         tokio::fs::create_dir_all(path.as_ref()).await?;
         let path = Arc::new(path.as_ref().to_path_buf());
 
-        let images = images
-            .into_iter()
-            .map(|bytes| bytes.as_ref().to_vec())
-            .collect::<Vec<_>>();
-
-        self.progress
-            .build_with_message(images.len(), "Writing images...")?
-            .wrap_stream(futures::stream::iter(images))
+        let names = self
+            .progress
+            .build_with_message(total_pages, "Writing images...")?
+            // the progress bar only wraps Unpin streams
+            .wrap_stream(Box::pin(pages))
             .enumerate()
-            .map(|pair| {
+            .map(|(i, page)| async move {
+                let (page, image, permit) = page?.into_parts();
+                let task = tokio::task::spawn_blocking(move || {
+                    let bytes = image.into_bytes(image_format, tiff_compression)?;
+                    Result::<_>::Ok((i, page, bytes, permit))
+                });
+                utils::join(task, format!("Encoding page {} panicked", i)).await
+            })
+            .buffer_unordered(self.num_threads)
+            .map(|encoded| {
                 let path = path.clone();
-                tokio::spawn(async move {
-                    let (i, bytes) = pair;
-                    let image_name = format!("{}.{}", i, image_format.extensions_str()[0]);
-
-                    let mut file = BufWriter::new(
-                        File::options()
-                            .create(true)
-                            .write(true)
-                            .truncate(true)
-                            .open(path.join(image_name))
-                            .await?,
-                    );
-                    file.write_all(&bytes.as_ref()).await?;
-
-                    Result::<_>::Ok(())
-                })
+                async move {
+                    let (i, page, bytes, permit) = encoded?;
+                    let task = tokio::spawn(async move {
+                        let image_format = match extension_from_content {
+                            true => image::guess_format(&bytes).unwrap_or(image_format),
+                            false => image_format,
+                        };
+                        let image_name =
+                            Self::image_name(page, &bytes, image_format, content_addressed);
+
+                        let written =
+                            Self::write_page_if(&path.join(&image_name), &bytes, skip_unchanged)
+                                .await?;
+                        // the page is on disk, its bytes are no longer held
+                        drop(permit);
+
+                        Result::<_>::Ok((i, image_name, written))
+                    });
+                    utils::join(task, format!("Writing page {} panicked", i)).await
+                }
             })
             .buffer_unordered(self.num_threads)
             .collect::<Vec<_>>()
-            .await;
+            .await
+            .into_iter()
+            .collect::<Result<Vec<_>>>()?;
+
+        self.finish(names, &path).await
+    }
+
+    /// Log the changed pages and write the index, given `(i, name, written)` of every page
+    async fn finish(&self, pages: Vec<(usize, String, bool)>, path: &Path) -> Result<()> {
+        if self.skip_unchanged {
+            let changed = pages.iter().filter(|(_, _, written)| *written).count();
+            tracing::info!("{} of {} pages changed", changed, pages.len());
+        }
+        let names = pages.into_iter().map(|(i, name, _)| (i, name)).collect();
+        self.write_index(names, path).await
+    }
+
+    /// Write the page, unless `skip_unchanged` and the file already has the same SHA-256.
+    /// Returns whether the page was written.
+    async fn write_page_if(path: &Path, bytes: &[u8], skip_unchanged: bool) -> Result<bool> {
+        if skip_unchanged {
+            if let Ok(existing) = tokio::fs::read(path).await {
+                if Sha256::digest(&existing) == Sha256::digest(bytes) {
+                    return Ok(false);
+                }
+            }
+        }
+        Self::write_page(path, bytes).await?;
+        Ok(true)
+    }
 
+    /// Write one page and flush it, so a failed write is reported instead of dropped
+    async fn write_page(path: &Path, bytes: &[u8]) -> Result<()> {
+        let mut file = BufWriter::new(File::create(path).await?);
+        file.write_all(bytes).await?;
+        file.flush().await?;
         Ok(())
     }
 
+    /// `<page>.<ext>`, or `<sha256>.<ext>` when naming by content
+    fn image_name(
+        page: usize,
+        bytes: &[u8],
+        image_format: image::ImageFormat,
+        content_addressed: bool,
+    ) -> String {
+        let extension = image_format.extensions_str()[0];
+        match content_addressed {
+            true => format!("{:x}.{}", Sha256::digest(bytes), extension),
+            false => format!("{}.{}", page, extension),
+        }
+    }
+
+    /// Write `index.json` mapping the page numbers to the content addressed names
+    async fn write_index(&self, mut names: Vec<(usize, String)>, path: &Path) -> Result<()> {
+        if !self.content_addressed {
+            return Ok(());
+        }
+        names.sort_by_key(|(i, _)| *i);
+        let names = names.into_iter().map(|(_, name)| name).collect::<Vec<_>>();
+        tokio::fs::write(path.join(INDEX_NAME), serde_json::to_vec_pretty(&names)?).await?;
+        Ok(())
+    }
+}
+
+impl EpisodeWriter for RawWriter {
+    async fn write<P: AsRef<Path>, B: Into<Bytes>>(&self, images: Vec<B>, path: P) -> Result<()> {
+        let images = images.into_iter().map(Into::into).collect::<Vec<Bytes>>();
+        let total_pages = images.len();
+        self.write_stream(
+            super::indexed(images, |i| self.page_index(i)),
+            total_pages,
+            path,
+        )
+        .await
+    }
+
     async fn write_images<P: AsRef<Path>>(
         &self,
         images: Vec<image::DynamicImage>,
         path: P,
     ) -> Result<()> {
-        let image_format = self.image_format;
+        let total_pages = images.len();
+        self.write_stream(
+            super::indexed(images, |i| self.page_index(i)),
+            total_pages,
+            path,
+        )
+        .await
+    }
 
-        tokio::fs::create_dir_all(path.as_ref()).await?;
-        let path = Arc::new(path.as_ref().to_path_buf());
+    /// Write each page as it arrives, named by its page number
+    async fn write_stream<P, T, S>(&self, pages: S, total_pages: usize, path: P) -> Result<()>
+    where
+        P: AsRef<Path>,
+        T: PageImage,
+        S: Stream<Item = Result<StreamedPage<T>>> + Send,
+    {
+        if !self.temp_file || self.skip_unchanged {
+            return self.write_pages_into(pages, total_pages, path).await;
+        }
+        let dir = AtomicDir::create(path).await?;
+        self.write_pages_into(pages, total_pages, dir.part_path())
+            .await?;
+        dir.persist().await
+    }
+}
 
-        self.progress
-            .build_with_message(images.len(), "Writing images...")?
-            .wrap_stream(futures::stream::iter(images))
-            .enumerate()
-            .map(|(i, image)| {
-                tokio::task::spawn_blocking(move || {
-                    let bytes = utils::encode_image(&image, image_format)?;
-                    Result::<_>::Ok((i, bytes))
-                })
-            })
-            .buffer_unordered(self.num_threads)
-            .map(|pair| pair?)
-            .map(|pair| {
-                let path = path.clone();
-                tokio::spawn(async move {
-                    let (i, bytes) = pair?;
-                    let image_name = format!("{}.{}", i, image_format.extensions_str()[0]);
-
-                    let mut file = BufWriter::new(
-                        File::options()
-                            .create(true)
-                            .write(true)
-                            .truncate(true)
-                            .open(path.join(image_name))
-                            .await?,
-                    );
-                    file.write_all(&bytes).await?;
-
-                    Result::<_>::Ok(())
-                })
-            })
-            .buffer_unordered(self.num_threads)
-            .collect::<Vec<_>>()
-            .await;
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use image::{DynamicImage, RgbImage, Rgba32FImage};
+
+    use crate::{io::atomic::part_path, limit::ByteLimiter};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_failed_write_leaves_no_directory() -> Result<()> {
+        let path = Path::new("playground/output/raw_temp_dir");
+        let _ = std::fs::remove_dir_all(path);
+        // PNG can't store 32-bit float pixels, so the second page fails to encode
+        let images = vec![
+            DynamicImage::ImageRgb8(RgbImage::new(10, 20)),
+            DynamicImage::ImageRgba32F(Rgba32FImage::new(10, 20)),
+        ];
+        let writer = RawWriter::new(ProgressConfig::disabled(), image::ImageFormat::Png, 1);
+
+        assert!(writer.write_images(images, path).await.is_err());
+        assert!(!path.exists());
+        assert!(!part_path(path)?.exists());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_streamed_pages_are_released_once_written() -> Result<()> {
+        let path = Path::new("playground/output/raw_streamed_pages");
+        let _ = std::fs::remove_dir_all(path);
+        // room for one page, so each page waits for the one before it to be written
+        let limiter = ByteLimiter::new(10);
+        let pages = futures::stream::iter(0..4).then(|page| {
+            let limiter = limiter.clone();
+            async move {
+                let permit = limiter.acquire(10).await?;
+                if page > 0 {
+                    assert!(path.join(format!("{}.png", page - 1)).exists());
+                }
+                Ok(StreamedPage::new(page, Bytes::from_static(b"page"))
+                    .with_permit(Some(Arc::new(permit))))
+            }
+        });
+        let writer = RawWriter::new(ProgressConfig::disabled(), image::ImageFormat::Png, 2)
+            .with_temp_file(false);
+
+        tokio::time::timeout(Duration::from_secs(5), writer.write_stream(pages, 4, path)).await??;
+        assert_eq!(std::fs::read_dir(path)?.count(), 4);
+        assert_eq!(limiter.high_water_mark(), 10);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_failed_page_write_is_returned() -> Result<()> {
+        let path = Path::new("playground/output/raw_failed_page");
+        let _ = std::fs::remove_dir_all(path);
+        // a directory in the way of the second page
+        std::fs::create_dir_all(path.join("1.png"))?;
+        let pages = vec![Bytes::from_static(b"first"), Bytes::from_static(b"second")];
+        let writer = RawWriter::new(ProgressConfig::disabled(), image::ImageFormat::Png, 2)
+            .with_temp_file(false);
+
+        assert!(writer.write(pages, path).await.is_err());
+        // the first page is complete, not left in the write buffer
+        assert_eq!(std::fs::read(path.join("0.png"))?, b"first");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_pages_are_named_by_page_indices() -> Result<()> {
+        let path = Path::new("playground/output/raw_page_indices");
+        let _ = std::fs::remove_dir_all(path);
+        let pages = vec![
+            Bytes::from_static(b"first"),
+            Bytes::from_static(b"third"),
+            Bytes::from_static(b"fourth"),
+        ];
+        let writer = RawWriter::new(ProgressConfig::disabled(), image::ImageFormat::Png, 2)
+            .with_page_indices(Some(vec![0, 2, 3]));
+
+        writer.write(pages, path).await?;
+
+        assert_eq!(std::fs::read(path.join("0.png"))?, b"first");
+        assert!(!path.join("1.png").exists());
+        assert_eq!(std::fs::read(path.join("2.png"))?, b"third");
+        assert_eq!(std::fs::read(path.join("3.png"))?, b"fourth");
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_read_only_directory_is_an_error() -> Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = Path::new("playground/output/raw_read_only");
+        let _ = std::fs::remove_dir_all(path);
+        std::fs::create_dir_all(path)?;
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o555))?;
+        // root ignores the permissions, nothing to check then
+        if std::fs::write(path.join("probe"), b"").is_ok() {
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o755))?;
+            return Ok(());
+        }
+        let writer = RawWriter::new(ProgressConfig::disabled(), image::ImageFormat::Png, 2)
+            .with_temp_file(false);
+
+        let result = writer.write(vec![Bytes::from_static(b"page")], path).await;
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o755))?;
+
+        assert!(result.is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_unchanged_pages_are_not_rewritten() -> Result<()> {
+        let path = Path::new("playground/output/raw_skip_unchanged");
+        let _ = std::fs::remove_dir_all(path);
+        let pages = vec![
+            Bytes::from_static(b"first"),
+            Bytes::from_static(b"second"),
+            Bytes::from_static(b"third"),
+        ];
+        let writer = RawWriter::new(ProgressConfig::disabled(), image::ImageFormat::Png, 2)
+            .with_skip_unchanged(true);
+        writer.write(pages.clone(), path).await?;
+        let modified = |i: usize| std::fs::metadata(path.join(format!("{}.png", i)))?.modified();
+        let before = (0..3).map(modified).collect::<Result<Vec<_>, _>>()?;
+
+        let mut corrected = pages;
+        corrected[1] = Bytes::from_static(b"second, corrected");
+        writer.write(corrected, path).await?;
+
+        assert_eq!(std::fs::read(path.join("1.png"))?, b"second, corrected");
+        assert_eq!(modified(0)?, before[0]);
+        assert_eq!(modified(2)?, before[2]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_extension_from_content() -> Result<()> {
+        let path = Path::new("playground/output/raw_extension_from_content");
+        let _ = std::fs::remove_dir_all(path);
+        let jpeg = utils::encode_image(
+            &DynamicImage::ImageRgb8(RgbImage::new(10, 20)),
+            image::ImageFormat::Jpeg,
+        )?;
+        let pages = vec![jpeg, Bytes::from_static(b"not an image")];
+        let writer = RawWriter::new(ProgressConfig::disabled(), image::ImageFormat::Png, 2)
+            .with_extension_from_content(true);
+
+        writer.write(pages, path).await?;
+
+        assert!(path.join("0.jpg").is_file());
+        // unknown bytes keep the configured extension
+        assert!(path.join("1.png").is_file());
+        assert!(!path.join("0.png").exists());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_content_addressed_names() -> Result<()> {
+        let path = Path::new("playground/output/raw_content_addressed");
+        let _ = std::fs::remove_dir_all(path);
+        let pages = vec![
+            Bytes::from_static(b"first page"),
+            Bytes::from_static(b"second page"),
+            Bytes::from_static(b"first page"),
+        ];
+        let writer = RawWriter::new(ProgressConfig::disabled(), image::ImageFormat::Png, 2)
+            .with_content_addressed(true);
+
+        writer.write(pages.clone(), path).await?;
+
+        let index: Vec<String> = serde_json::from_slice(&std::fs::read(path.join(INDEX_NAME))?)?;
+        let expected = pages
+            .iter()
+            .map(|bytes| format!("{:x}.png", Sha256::digest(bytes)))
+            .collect::<Vec<_>>();
+        assert_eq!(index, expected);
+        assert_eq!(
+            index[0],
+            "845bb60fe5c91b77a0b634e351b296a9222c94d686371b0ad741dff73c95edbb.png"
+        );
+        // the repeated page is stored once
+        let mut files = std::fs::read_dir(path)?
+            .map(|entry| Ok(entry?.file_name().to_string_lossy().to_string()))
+            .collect::<Result<Vec<_>>>()?;
+        files.sort();
+        let mut expected = vec![index[0].clone(), index[1].clone(), INDEX_NAME.to_string()];
+        expected.sort();
+        assert_eq!(files, expected);
 
         Ok(())
     }
@@ -0,0 +1,219 @@
+use std::{
+    fs::File,
+    io::{Seek, Write},
+    path::Path,
+};
+
+use anyhow::Result;
+use futures::StreamExt;
+use image::DynamicImage;
+use sevenz_rust::{
+    lzma::LZMA2Options, SevenZArchiveEntry, SevenZMethod, SevenZMethodConfiguration, SevenZWriter,
+};
+
+use crate::{
+    progress::ProgressConfig,
+    utils::{self, Bytes},
+};
+
+use super::{atomic::AtomicFile, EpisodeWriter, TiffCompression};
+
+/// LZMA2 preset used by default. Pages are already compressed images,
+/// so a higher preset costs time without making the archive much smaller.
+const FAST_PRESET: u32 = 1;
+
+/// Save as a 7z file.
+///
+/// Each page is stored as its own (non-solid) entry. Solid compression gives little
+/// benefit for precompressed images, so the fast LZMA2 preset is used by default.
+#[derive(Debug, Clone)]
+pub struct SevenZipWriter {
+    image_format: image::ImageFormat,
+    preset: u32,
+    progress: ProgressConfig,
+    num_threads: usize,
+    temp_file: bool,
+    tiff_compression: TiffCompression,
+}
+
+impl Default for SevenZipWriter {
+    fn default() -> Self {
+        SevenZipWriter {
+            image_format: image::ImageFormat::Png,
+            preset: FAST_PRESET,
+            progress: ProgressConfig::default(),
+            num_threads: num_cpus::get(),
+            temp_file: true,
+            tiff_compression: TiffCompression::None,
+        }
+    }
+}
+
+impl SevenZipWriter {
+    pub fn new(
+        image_format: image::ImageFormat,
+        preset: Option<u32>,
+        num_threads: usize,
+        progress: ProgressConfig,
+    ) -> Self {
+        SevenZipWriter {
+            image_format,
+            preset: preset.unwrap_or(FAST_PRESET),
+            progress,
+            num_threads,
+            temp_file: true,
+            tiff_compression: TiffCompression::None,
+        }
+    }
+
+    /// Write `<path>.part` and rename it into place when the archive is complete
+    pub fn with_temp_file(self, temp_file: bool) -> Self {
+        SevenZipWriter { temp_file, ..self }
+    }
+
+    /// Compression of the pages when saving as TIFF
+    pub fn with_tiff_compression(self, tiff_compression: TiffCompression) -> Self {
+        SevenZipWriter {
+            tiff_compression,
+            ..self
+        }
+    }
+
+    /// Write the encoded pages in order as the entries of a new archive
+    async fn write_entries<P: AsRef<Path>>(&self, images: Vec<Bytes>, path: P) -> Result<()> {
+        let path = super::with_extension(path.as_ref(), "7z");
+        let extension = self.image_format.extensions_str()[0];
+        let preset = self.preset;
+        let temp_file = self.temp_file;
+
+        let task = tokio::task::spawn_blocking(move || {
+            if !temp_file {
+                Self::archive(images, extension, preset, File::create(path)?)?;
+                return Ok(());
+            }
+            let file = AtomicFile::create(path)?;
+            Self::archive(images, extension, preset, file)?.persist()
+        });
+        utils::join(task, "Writing the 7z panicked").await
+    }
+
+    fn archive<W: Write + Seek>(
+        images: Vec<Bytes>,
+        extension: &str,
+        preset: u32,
+        inner: W,
+    ) -> Result<W> {
+        let mut archive = SevenZWriter::new(inner)?;
+        archive.set_content_methods(vec![SevenZMethodConfiguration::new(SevenZMethod::LZMA2)
+            .with_options(LZMA2Options::with_preset(preset).into())]);
+
+        for (i, bytes) in images.into_iter().enumerate() {
+            let mut entry = SevenZArchiveEntry::new();
+            entry.name = format!("{}.{}", i, extension);
+            entry.has_stream = true;
+            archive.push_archive_entry(entry, Some(bytes.as_ref()))?;
+        }
+
+        Ok(archive.finish()?)
+    }
+}
+
+impl EpisodeWriter for SevenZipWriter {
+    async fn write<P: AsRef<Path>, B: Into<Bytes>>(&self, images: Vec<B>, path: P) -> Result<()> {
+        let images = images.into_iter().map(Into::into).collect::<Vec<_>>();
+        let pb = self
+            .progress
+            .build_with_message(images.len(), "Writing the 7z...")?;
+
+        self.write_entries(images, path).await?;
+        pb.finish();
+
+        Ok(())
+    }
+
+    async fn write_images<P: AsRef<Path>>(&self, images: Vec<DynamicImage>, path: P) -> Result<()> {
+        let image_format = self.image_format;
+        let tiff_compression = self.tiff_compression;
+
+        let mut encoded = self
+            .progress
+            .build_with_message(images.len(), "Encoding images...")?
+            .wrap_stream(futures::stream::iter(images))
+            .enumerate()
+            .map(|(i, image)| {
+                let task = tokio::task::spawn_blocking(move || {
+                    let bytes = utils::encode_image_with(&image, image_format, tiff_compression)?;
+                    Result::<_>::Ok((i, bytes))
+                });
+                utils::join(task, format!("Encoding page {} panicked", i))
+            })
+            .buffer_unordered(self.num_threads)
+            .collect::<Vec<_>>()
+            .await
+            .into_iter()
+            .collect::<Result<Vec<_>>>()?;
+        encoded.sort_by_key(|(i, _)| *i);
+        let encoded = encoded.into_iter().map(|(_, bytes)| bytes).collect();
+
+        self.write_entries(encoded, path).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use image::{GenericImageView, RgbImage};
+    use sevenz_rust::{Password, SevenZReader};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_write_and_reopen() -> Result<()> {
+        let images = (0..3)
+            .map(|i| DynamicImage::ImageRgb8(RgbImage::new(8 + i, 16)))
+            .collect::<Vec<_>>();
+        let path = "playground/output/sevenz_writer.7z";
+
+        let writer =
+            SevenZipWriter::new(image::ImageFormat::Png, None, 2, ProgressConfig::disabled());
+        writer.write_images(images, path).await?;
+
+        let mut reader = SevenZReader::open(path, Password::empty())?;
+        let mut entries = Vec::new();
+        reader.for_each_entries(|entry, data| {
+            let mut bytes = Vec::new();
+            data.read_to_end(&mut bytes)?;
+            entries.push((entry.name().to_string(), bytes));
+            Ok(true)
+        })?;
+
+        assert_eq!(entries.len(), 3);
+        for (i, (name, bytes)) in entries.iter().enumerate() {
+            assert_eq!(name, &format!("{}.png", i));
+            let image = image::load_from_memory(bytes)?;
+            assert_eq!(image.dimensions(), (8 + i as u32, 16));
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_dotted_title_keeps_its_name() -> Result<()> {
+        let dir = Path::new("playground/output/sevenz_dotted");
+        let _ = std::fs::remove_dir_all(dir);
+        std::fs::create_dir_all(dir)?;
+        let images = vec![DynamicImage::ImageRgb8(RgbImage::new(8, 16))];
+
+        let writer =
+            SevenZipWriter::new(image::ImageFormat::Png, None, 1, ProgressConfig::disabled());
+        writer
+            .write_images(images.clone(), dir.join("Vol.2"))
+            .await?;
+        assert!(dir.join("Vol.2.7z").exists());
+        // a path already ending in the extension is kept as it is
+        writer.write_images(images, dir.join("Vol.3.7z")).await?;
+        assert!(dir.join("Vol.3.7z").exists());
+        assert!(!dir.join("Vol.7z").exists());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,174 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Seek, Write},
+    path::Path,
+};
+
+use anyhow::Result;
+use image::DynamicImage;
+use indicatif::ProgressBar;
+
+use crate::{
+    progress::ProgressConfig,
+    utils::{self, Bytes},
+};
+
+use super::{atomic::AtomicFile, EpisodeWriter, TiffCompression};
+
+/// Save the pages as the directories of a single multi-page TIFF, in page order.
+///
+/// Every page is compressed with `compression`.
+#[derive(Debug, Clone)]
+pub struct TiffWriter {
+    compression: TiffCompression,
+    progress: ProgressConfig,
+    temp_file: bool,
+}
+
+impl Default for TiffWriter {
+    fn default() -> Self {
+        TiffWriter {
+            compression: TiffCompression::Lzw,
+            progress: ProgressConfig::default(),
+            temp_file: true,
+        }
+    }
+}
+
+impl TiffWriter {
+    pub fn new(compression: TiffCompression, progress: ProgressConfig) -> Self {
+        TiffWriter {
+            compression,
+            progress,
+            temp_file: true,
+        }
+    }
+
+    /// Write `<path>.part` and rename it into place when the TIFF is complete
+    pub fn with_temp_file(self, temp_file: bool) -> Self {
+        TiffWriter { temp_file, ..self }
+    }
+
+    /// Encode the images as the pages of the TIFF, advancing `pb` by each page
+    pub fn encode<W: Write + Seek>(
+        &self,
+        images: &[DynamicImage],
+        inner: W,
+        pb: &ProgressBar,
+    ) -> Result<()> {
+        let mut encoder = utils::tiff_encoder(inner, self.compression)?;
+        for image in images {
+            utils::write_tiff_page(&mut encoder, image)?;
+            pb.inc(1);
+        }
+        Ok(())
+    }
+
+    fn save(&self, images: &[DynamicImage], path: &Path, pb: &ProgressBar) -> Result<()> {
+        let path = super::with_extension(path, "tiff");
+        if !self.temp_file {
+            let mut file = BufWriter::new(File::create(path)?);
+            self.encode(images, &mut file, pb)?;
+            file.flush()?;
+            return Ok(());
+        }
+        let mut file = AtomicFile::create(path)?;
+        self.encode(images, &mut file, pb)?;
+        file.persist()
+    }
+}
+
+impl EpisodeWriter for TiffWriter {
+    async fn write<P: AsRef<Path>, B: Into<Bytes>>(&self, images: Vec<B>, path: P) -> Result<()> {
+        let images = images
+            .into_iter()
+            .map(|bytes| image::load_from_memory(&bytes.into()))
+            .collect::<Result<Vec<_>, _>>()?;
+        self.write_images(images, path).await
+    }
+
+    async fn write_images<P: AsRef<Path>>(&self, images: Vec<DynamicImage>, path: P) -> Result<()> {
+        let pb = self
+            .progress
+            .build_with_message(images.len(), "Encoding the TIFF...")?;
+        let writer = self.clone();
+        let path = path.as_ref().to_path_buf();
+
+        let pages = pb.clone();
+        let task = tokio::task::spawn_blocking(move || writer.save(&images, &path, &pages));
+        utils::join(task, "Encoding the TIFF panicked").await?;
+        pb.finish();
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use image::{Rgb, RgbImage};
+    use tiff::decoder::{Decoder, DecodingResult};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_one_directory_per_page() -> Result<()> {
+        let pages = [[255, 0, 0], [0, 255, 0], [0, 0, 255]]
+            .into_iter()
+            .map(|color| DynamicImage::ImageRgb8(RgbImage::from_pixel(16, 24, Rgb(color))))
+            .collect::<Vec<_>>();
+        let path = "playground/output/multipage.tiff";
+
+        let writer = TiffWriter::new(TiffCompression::Deflate, ProgressConfig::disabled());
+        writer.write_images(pages, path).await?;
+
+        let mut decoder = Decoder::new(std::io::BufReader::new(File::open(path)?))?;
+        let mut firsts = Vec::new();
+        loop {
+            assert_eq!(decoder.dimensions()?, (16, 24));
+            let DecodingResult::U8(pixels) = decoder.read_image()? else {
+                panic!("Pages should be 8 bit");
+            };
+            firsts.push(pixels[..3].to_vec());
+            if !decoder.more_images() {
+                break;
+            }
+            decoder.next_image()?;
+        }
+        assert_eq!(
+            firsts,
+            vec![vec![255, 0, 0], vec![0, 255, 0], vec![0, 0, 255]]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_each_page_advances_the_bar() -> Result<()> {
+        let pages = vec![DynamicImage::ImageRgb8(RgbImage::new(8, 16)); 3];
+        let pb = ProgressBar::hidden();
+
+        TiffWriter::default().encode(&pages, std::io::Cursor::new(Vec::new()), &pb)?;
+        assert_eq!(pb.position(), 3);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_dotted_title_keeps_its_name() -> Result<()> {
+        let dir = Path::new("playground/output/tiff_dotted");
+        let _ = std::fs::remove_dir_all(dir);
+        std::fs::create_dir_all(dir)?;
+        let pages = vec![DynamicImage::ImageRgb8(RgbImage::new(8, 16))];
+
+        let writer = TiffWriter::new(TiffCompression::Deflate, ProgressConfig::disabled());
+        writer
+            .write_images(pages.clone(), dir.join("Vol.2"))
+            .await?;
+        assert!(dir.join("Vol.2.tiff").exists());
+        writer.write_images(pages, dir.join("Vol.3.tiff")).await?;
+        assert!(dir.join("Vol.3.tiff").exists());
+        assert!(!dir.join("Vol.tiff").exists());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,145 @@
+use std::path::Path;
+
+use anyhow::{bail, Result};
+use image::{imageops, DynamicImage, Rgb, RgbImage};
+
+use crate::{progress::ProgressConfig, utils::Bytes};
+
+use super::{raw::RawWriter, EpisodeWriter};
+
+/// Save the pages of a vertical scroll episode joined into long strips.
+///
+/// Pages are stacked top to bottom until the next one would make the strip taller than
+/// `max_height`, so a page is never cut. A page taller than the limit is a strip of its own.
+/// The strips are written into a directory as `0.png`, `1.png`, ...
+#[derive(Debug, Clone)]
+pub struct WebtoonWriter {
+    progress: ProgressConfig,
+    max_height: u32,
+    image_format: image::ImageFormat,
+    num_threads: usize,
+}
+
+impl Default for WebtoonWriter {
+    fn default() -> Self {
+        WebtoonWriter {
+            progress: ProgressConfig::default(),
+            max_height: 16000,
+            image_format: image::ImageFormat::Png,
+            num_threads: num_cpus::get(),
+        }
+    }
+}
+
+impl WebtoonWriter {
+    pub fn new(
+        progress: ProgressConfig,
+        max_height: u32,
+        image_format: image::ImageFormat,
+        num_threads: usize,
+    ) -> Self {
+        WebtoonWriter {
+            progress,
+            max_height,
+            image_format,
+            num_threads,
+        }
+    }
+
+    pub fn max_height(&self) -> u32 {
+        self.max_height
+    }
+
+    /// Join the pages into strips in page order
+    pub fn stitch(&self, images: &[DynamicImage]) -> Result<Vec<DynamicImage>> {
+        if self.max_height == 0 {
+            bail!("Webtoon strip height must be positive");
+        }
+        let mut strips = Vec::new();
+        let mut strip = Vec::new();
+        let mut height = 0;
+        for image in images {
+            if !strip.is_empty() && height + image.height() > self.max_height {
+                strips.push(Self::join(&strip));
+                strip.clear();
+                height = 0;
+            }
+            height += image.height();
+            strip.push(image);
+        }
+        if !strip.is_empty() {
+            strips.push(Self::join(&strip));
+        }
+        Ok(strips)
+    }
+
+    /// Stack the pages on a white strip as wide as the widest one, each centered
+    fn join(pages: &[&DynamicImage]) -> DynamicImage {
+        let width = pages.iter().map(|page| page.width()).max().unwrap_or(0);
+        let height = pages.iter().map(|page| page.height()).sum();
+
+        let mut strip = RgbImage::from_pixel(width, height, Rgb([255, 255, 255]));
+        let mut y = 0;
+        for page in pages {
+            let x = (width - page.width()) / 2;
+            imageops::overlay(&mut strip, &page.to_rgb8(), x.into(), y.into());
+            y += page.height();
+        }
+        DynamicImage::ImageRgb8(strip)
+    }
+
+    async fn save<P: AsRef<Path>>(&self, images: Vec<DynamicImage>, path: P) -> Result<()> {
+        let strips = self.stitch(&images)?;
+        RawWriter::new(self.progress.clone(), self.image_format, self.num_threads)
+            .write_images(strips, path)
+            .await
+    }
+}
+
+impl EpisodeWriter for WebtoonWriter {
+    async fn write<P: AsRef<Path>, B: Into<Bytes>>(&self, images: Vec<B>, path: P) -> Result<()> {
+        let images = images
+            .into_iter()
+            .map(|bytes| image::load_from_memory(&bytes.into()))
+            .collect::<Result<Vec<_>, _>>()?;
+        self.save(images, path).await
+    }
+
+    async fn write_images<P: AsRef<Path>>(&self, images: Vec<DynamicImage>, path: P) -> Result<()> {
+        self.save(images, path).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use image::GenericImageView;
+
+    use super::*;
+
+    fn page(height: u32, color: [u8; 3]) -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_pixel(40, height, Rgb(color)))
+    }
+
+    #[test]
+    fn test_pages_are_not_cut() -> Result<()> {
+        let pages = vec![
+            page(60, [255, 0, 0]),
+            page(60, [0, 255, 0]),
+            page(60, [0, 0, 255]),
+            page(200, [0, 0, 0]),
+        ];
+        let strips =
+            WebtoonWriter::new(ProgressConfig::disabled(), 150, image::ImageFormat::Png, 1)
+                .stitch(&pages)?;
+
+        let heights = strips
+            .iter()
+            .map(|strip| strip.height())
+            .collect::<Vec<_>>();
+        assert_eq!(heights, vec![120, 60, 200]);
+        // the second page is right below the first one
+        assert_eq!(strips[0].get_pixel(20, 90).0, [0, 255, 0, 255]);
+
+        Ok(())
+    }
+}
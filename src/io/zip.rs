@@ -1,17 +1,98 @@
-use std::{io::Write, path::Path, sync::Arc};
+use std::{
+    io::{Cursor, Seek, Write},
+    path::Path,
+    str::FromStr,
+    sync::{Arc, LazyLock},
+};
 
-use anyhow::Result;
-use futures::StreamExt;
+use anyhow::{anyhow, bail, Result};
+use futures::{Stream, StreamExt};
 use image::DynamicImage;
-use tokio::sync::Mutex;
+use regex::{Captures, Regex};
+use tokio::{
+    io::{AsyncWrite, AsyncWriteExt},
+    sync::Mutex,
+};
+use url::Url;
 use zip::{
     write::{ExtendedFileOptions, FileOptions},
     CompressionMethod,
 };
 
-use crate::{progress::ProgressConfig, utils};
+use crate::{
+    progress::ProgressConfig,
+    utils::{self, Bytes},
+};
+
+use super::{
+    atomic::AtomicFile, EpisodeSinkWriter, EpisodeWriter, PageImage, StreamedPage, TiffCompression,
+};
+
+/// Name of the cover entry without the extension
+pub const COVER_NAME: &str = "cover";
+
+/// `{index}`, `{page}` or `{number}` with an optional zero padded width, e.g. `{page:03}`
+static PLACEHOLDER_PATTERN: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\{(index|page|number)(?::(\d+))?\}").unwrap());
+
+/// How the page entries of the archive are named, before the extension
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum EntryNaming {
+    /// Position of the page in the archive, `0`, `1`, ...
+    #[default]
+    Index,
+    /// Index of the page in the episode, which keeps the gaps of the pages left out
+    PageIndex,
+    /// A template of `{index}` (the position), `{page}` (the page index) and `{number}`
+    /// (the page index from 1), each with an optional width, e.g. `p{number:03}`
+    Template(String),
+}
+
+impl EntryNaming {
+    pub fn template(template: &str) -> Result<Self> {
+        if !PLACEHOLDER_PATTERN.is_match(template) {
+            bail!(
+                "Entry name template needs {{index}}, {{page}} or {{number}}: {}",
+                template
+            );
+        }
+        Ok(EntryNaming::Template(template.to_string()))
+    }
+
+    /// Name of the entry at `position` of the archive, the page `page` of the episode
+    pub fn name(&self, position: usize, page: usize) -> String {
+        match self {
+            EntryNaming::Index => position.to_string(),
+            EntryNaming::PageIndex => page.to_string(),
+            EntryNaming::Template(template) => PLACEHOLDER_PATTERN
+                .replace_all(template, |captures: &Captures| {
+                    let value = match &captures[1] {
+                        "index" => position,
+                        "page" => page,
+                        _ => page + 1,
+                    };
+                    let width = captures
+                        .get(2)
+                        .map_or(0, |width| width.as_str().parse().unwrap_or(0));
+                    format!("{:0width$}", value, width = width)
+                })
+                .into_owned(),
+        }
+    }
+}
+
+impl FromStr for EntryNaming {
+    type Err = anyhow::Error;
 
-use super::EpisodeWriter;
+    /// `index`, `page`, or a template
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "index" => Ok(EntryNaming::Index),
+            "page" => Ok(EntryNaming::PageIndex),
+            template => EntryNaming::template(template),
+        }
+    }
+}
 
 /// Save as a zip file.
 #[derive(Debug, Clone)]
@@ -21,20 +102,40 @@ pub struct ZipWriter {
     extension: Option<String>,
     progress: ProgressConfig,
     num_threads: usize,
+    temp_file: bool,
     // writer: Arc<Mutex<zip::ZipWriter<std::fs::File>>>,
+    tiff_compression: TiffCompression,
+    cover: bool,
+    /// Directory of the page entries inside the archive, `None` for the root
+    image_dir: Option<String>,
+    source_url: Option<Url>,
+    entry_naming: EntryNaming,
+    /// Index in the episode of each page, `None` when the pages are the whole episode
+    page_indices: Option<Vec<usize>>,
+    extension_from_content: bool,
 }
 
-impl ZipWriter {
-    pub fn default() -> Self {
+impl Default for ZipWriter {
+    fn default() -> Self {
         ZipWriter {
             compression_method: CompressionMethod::Zstd,
             image_format: image::ImageFormat::Png,
             extension: Some("zip".to_string()),
             num_threads: num_cpus::get(),
             progress: ProgressConfig::default(),
+            temp_file: true,
+            tiff_compression: TiffCompression::None,
+            cover: false,
+            image_dir: None,
+            source_url: None,
+            entry_naming: EntryNaming::default(),
+            page_indices: None,
+            extension_from_content: false,
         }
     }
+}
 
+impl ZipWriter {
     pub fn new(
         compression_method: CompressionMethod,
         image_format: image::ImageFormat,
@@ -48,6 +149,109 @@ impl ZipWriter {
             extension,
             num_threads,
             progress,
+            temp_file: true,
+            tiff_compression: TiffCompression::None,
+            cover: false,
+            image_dir: None,
+            source_url: None,
+            entry_naming: EntryNaming::default(),
+            page_indices: None,
+            extension_from_content: false,
+        }
+    }
+
+    /// Write `<path>.part` and rename it into place when the archive is complete
+    pub fn with_temp_file(self, temp_file: bool) -> Self {
+        ZipWriter { temp_file, ..self }
+    }
+
+    /// Compression of the pages when saving as TIFF
+    pub fn with_tiff_compression(self, tiff_compression: TiffCompression) -> Self {
+        ZipWriter {
+            tiff_compression,
+            ..self
+        }
+    }
+
+    /// Also store the first page as `cover.<ext>` for readers that look for a cover entry.
+    ///
+    /// The numbered page entries are unchanged and the cover is written after them.
+    pub fn with_cover(self, cover: bool) -> Self {
+        ZipWriter { cover, ..self }
+    }
+
+    /// Store the pages under this directory of the archive, e.g. `images/0.png`,
+    /// for readers that expect them there. The cover stays at the root.
+    pub fn with_image_dir(self, image_dir: Option<String>) -> Self {
+        ZipWriter { image_dir, ..self }
+    }
+
+    /// Record the url the episode was downloaded from as the archive comment
+    pub fn with_source_url(self, source_url: Option<Url>) -> Self {
+        ZipWriter { source_url, ..self }
+    }
+
+    /// Name the page entries with this strategy instead of their position
+    pub fn with_entry_naming(self, entry_naming: EntryNaming) -> Self {
+        ZipWriter {
+            entry_naming,
+            ..self
+        }
+    }
+
+    /// Index in the episode of each written page, e.g. when failed pages were left out
+    pub fn with_page_indices(self, page_indices: Option<Vec<usize>>) -> Self {
+        ZipWriter {
+            page_indices,
+            ..self
+        }
+    }
+
+    /// Name the entries by the format of their bytes, e.g. `.jpg` for JPEG pages written
+    /// without re-encoding. Unknown bytes keep the extension of the image format.
+    pub fn with_extension_from_content(self, extension_from_content: bool) -> Self {
+        ZipWriter {
+            extension_from_content,
+            ..self
+        }
+    }
+
+    /// Start an archive on `inner`, with the source url as the comment if there is one
+    fn new_zip<W: Write + Seek>(&self, inner: W) -> zip::ZipWriter<W> {
+        let mut zip = zip::ZipWriter::new(inner);
+        if let Some(source_url) = &self.source_url {
+            zip.set_comment(source_url.as_str());
+        }
+        zip
+    }
+
+    /// Extension of the entry of the bytes
+    fn entry_extension(&self, bytes: &[u8]) -> &'static str {
+        let image_format = match self.extension_from_content {
+            true => image::guess_format(bytes).unwrap_or(self.image_format),
+            false => self.image_format,
+        };
+        image_format.extensions_str()[0]
+    }
+
+    /// Index in the episode of the `i`th written page
+    fn page_index(&self, i: usize) -> usize {
+        self.page_indices
+            .as_ref()
+            .and_then(|indices| indices.get(i).copied())
+            .unwrap_or(i)
+    }
+
+    /// Entry name of the page at `position`, inside the image directory if there is one
+    fn page_name(&self, position: usize, page: usize, bytes: &[u8]) -> String {
+        let name = format!(
+            "{}.{}",
+            self.entry_naming.name(position, page),
+            self.entry_extension(bytes)
+        );
+        match self.image_dir.as_deref().map(|dir| dir.trim_matches('/')) {
+            Some(dir) if !dir.is_empty() => format!("{}/{}", dir, name),
+            _ => name,
         }
     }
 
@@ -60,81 +264,446 @@ impl ZipWriter {
     }
 }
 
-impl EpisodeWriter for ZipWriter {
-    async fn write<P: AsRef<Path>, B: AsRef<[u8]>>(&self, images: Vec<B>, path: P) -> Result<()> {
-        let file = std::fs::File::create(path.as_ref().with_extension(self.extension()))?;
-        let zip = Arc::new(Mutex::new(zip::ZipWriter::new(file)));
-
+impl ZipWriter {
+    /// Write the pages into a zip archive built on `inner` as they arrive, releasing
+    /// each once it is written.
+    async fn zip_pages<W, T, S>(&self, pages: S, total_pages: usize, inner: W) -> Result<W>
+    where
+        W: Write + Seek + Send + 'static,
+        T: PageImage,
+        S: Stream<Item = Result<StreamedPage<T>>> + Send,
+    {
+        let zip = Arc::new(Mutex::new(self.new_zip(inner)));
         let image_format = self.image_format;
+        let tiff_compression = self.tiff_compression;
         let compression_method = self.compression_method;
-        let images = images
-            .into_iter()
-            .map(|bytes| bytes.as_ref().to_vec())
-            .collect::<Vec<_>>();
+        let cover = self.cover;
 
-        self.progress
-            .build_with_message(images.len(), "Writing the zip...")?
-            .wrap_stream(futures::stream::iter(images))
+        let written = self
+            .progress
+            .build_with_message(total_pages, "Writing the zip...")?
+            // the progress bar only wraps Unpin streams
+            .wrap_stream(Box::pin(pages))
             .enumerate()
-            .map(|pair| {
+            .map(|(i, page)| async move {
+                let (page, image, permit) = page?.into_parts();
+                let task = tokio::task::spawn_blocking(move || {
+                    let bytes = image.into_bytes(image_format, tiff_compression)?;
+                    Result::<_>::Ok((i, page, bytes, permit))
+                });
+                utils::join(task, format!("Encoding page {} panicked", i)).await
+            })
+            .buffer_unordered(self.num_threads)
+            .map(|encoded| {
                 let zip = zip.clone();
                 let options = FileOptions::<ExtendedFileOptions>::default()
                     .compression_method(compression_method);
-                tokio::spawn(async move {
-                    let (i, bytes) = pair;
-                    let mut zip = zip.lock().await;
-                    zip.start_file(
-                        format!("{}.{}", i, image_format.extensions_str()[0]),
-                        options,
-                    )?;
-                    zip.write_all(&bytes)?;
-                    Result::<_>::Ok(())
-                })
+                async move {
+                    let (i, page, bytes, permit) = encoded?;
+                    let name = self.page_name(i, page, &bytes);
+                    let task = tokio::spawn(async move {
+                        let mut zip = zip.lock().await;
+                        zip.start_file(name, options)?;
+                        zip.write_all(&bytes)?;
+                        // the page is in the archive, its bytes are no longer held
+                        drop(permit);
+                        // keep the encoded first page for the cover
+                        Result::<_>::Ok((i == 0 && cover).then_some(bytes))
+                    });
+                    utils::join(task, format!("Writing page {} panicked", i)).await
+                }
             })
             .buffer_unordered(self.num_threads)
             .collect::<Vec<_>>()
-            .await;
+            .await
+            .into_iter()
+            .collect::<Result<Vec<_>>>()?;
+
+        if let Some(cover) = written.into_iter().flatten().next() {
+            self.write_cover(&zip, &cover).await?;
+        }
+        Self::finish(zip)
+    }
 
+    /// Write images from bytes into a zip archive built on `inner`.
+    async fn zip_bytes<W: Write + Seek + Send + 'static, B: Into<Bytes>>(
+        &self,
+        images: Vec<B>,
+        inner: W,
+    ) -> Result<W> {
+        let images = images.into_iter().map(Into::into).collect::<Vec<Bytes>>();
+        let total_pages = images.len();
+        self.zip_pages(
+            super::indexed(images, |i| self.page_index(i)),
+            total_pages,
+            inner,
+        )
+        .await
+    }
+
+    /// Encode images and write them into a zip archive built on `inner`.
+    async fn zip_images<W: Write + Seek + Send + 'static>(
+        &self,
+        images: Vec<DynamicImage>,
+        inner: W,
+    ) -> Result<W> {
+        let total_pages = images.len();
+        self.zip_pages(
+            super::indexed(images, |i| self.page_index(i)),
+            total_pages,
+            inner,
+        )
+        .await
+    }
+
+    /// Write the `cover.<ext>` entry
+    async fn write_cover<W: Write + Seek>(
+        &self,
+        zip: &Mutex<zip::ZipWriter<W>>,
+        bytes: &[u8],
+    ) -> Result<()> {
+        let options = FileOptions::<ExtendedFileOptions>::default()
+            .compression_method(self.compression_method);
+        let mut zip = zip.lock().await;
+        zip.start_file(
+            format!("{}.{}", COVER_NAME, self.entry_extension(bytes)),
+            options,
+        )?;
+        zip.write_all(bytes)?;
         Ok(())
     }
 
+    /// Sync the archive written straight to `path`, or remove it when a page failed
+    fn keep_if_complete(written: Result<std::fs::File>, path: &Path) -> Result<()> {
+        match written.and_then(|file| Ok(file.sync_all()?)) {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                let _ = std::fs::remove_file(path);
+                Err(e)
+            }
+        }
+    }
+
+    /// Finish the archive once all writing tasks are done and return the inner writer.
+    fn finish<W: Write + Seek>(zip: Arc<Mutex<zip::ZipWriter<W>>>) -> Result<W> {
+        let zip = Arc::try_unwrap(zip)
+            .map_err(|_| anyhow!("The zip is still used by a writing task"))?
+            .into_inner();
+        Ok(zip.finish()?)
+    }
+}
+
+impl EpisodeWriter for ZipWriter {
+    async fn write<P: AsRef<Path>, B: Into<Bytes>>(&self, images: Vec<B>, path: P) -> Result<()> {
+        let images = images.into_iter().map(Into::into).collect::<Vec<Bytes>>();
+        let total_pages = images.len();
+        self.write_stream(
+            super::indexed(images, |i| self.page_index(i)),
+            total_pages,
+            path,
+        )
+        .await
+    }
+
     /// Save images as a zip file.
     async fn write_images<P: AsRef<Path>>(&self, images: Vec<DynamicImage>, path: P) -> Result<()> {
-        let file = std::fs::File::create(path.as_ref().with_extension(self.extension()))?;
-        let zip = Arc::new(Mutex::new(zip::ZipWriter::new(file)));
-        let image_format = self.image_format;
-        let compression_method = self.compression_method;
+        let total_pages = images.len();
+        self.write_stream(
+            super::indexed(images, |i| self.page_index(i)),
+            total_pages,
+            path,
+        )
+        .await
+    }
 
-        self.progress
-            .build_with_message(images.len(), "Writing the zip...")?
-            .wrap_stream(futures::stream::iter(images))
-            .enumerate()
-            .map(|(i, image)| {
-                tokio::task::spawn_blocking(move || {
-                    let bytes = utils::encode_image(&image, image_format)?;
-                    Result::<_>::Ok((i, bytes))
-                })
-            })
-            .buffer_unordered(self.num_threads)
-            .map(|pair| pair?)
-            .map(|pair| {
-                let zip = zip.clone();
-                let options = FileOptions::<ExtendedFileOptions>::default()
-                    .compression_method(compression_method);
-                tokio::spawn(async move {
-                    let (i, bytes) = pair?;
-                    let mut zip = zip.lock().await;
-                    zip.start_file(
-                        format!("{}.{}", i, image_format.extensions_str()[0]),
-                        options,
-                    )?;
-                    zip.write_all(&bytes)?;
-                    Result::<_>::Ok(())
-                })
-            })
-            .buffer_unordered(self.num_threads)
-            .collect::<Vec<_>>()
-            .await;
+    /// Save the pages as a zip file, writing each entry as the page arrives
+    async fn write_stream<P, T, S>(&self, pages: S, total_pages: usize, path: P) -> Result<()>
+    where
+        P: AsRef<Path>,
+        T: PageImage,
+        S: Stream<Item = Result<StreamedPage<T>>> + Send,
+    {
+        let path = super::with_extension(path.as_ref(), &self.extension());
+        if self.temp_file {
+            let file = AtomicFile::create(path)?;
+            return self.zip_pages(pages, total_pages, file).await?.persist();
+        }
+        let file = std::fs::File::create(&path)?;
+        let written = self.zip_pages(pages, total_pages, file).await;
+        Self::keep_if_complete(written, &path)
+    }
+}
+
+impl EpisodeSinkWriter for ZipWriter {
+    async fn write_to<W: AsyncWrite + Unpin + Send, B: Into<Bytes>>(
+        &self,
+        images: Vec<B>,
+        sink: &mut W,
+    ) -> Result<()> {
+        // the zip writer seeks back to finish each entry header, so it can't write to
+        // the sink directly
+        let buffer = self.zip_bytes(images, Cursor::new(Vec::new())).await?;
+        sink.write_all(buffer.get_ref()).await?;
+        sink.flush().await?;
+
+        Ok(())
+    }
+
+    async fn write_images_to<W: AsyncWrite + Unpin + Send>(
+        &self,
+        images: Vec<DynamicImage>,
+        sink: &mut W,
+    ) -> Result<()> {
+        let buffer = self.zip_images(images, Cursor::new(Vec::new())).await?;
+        sink.write_all(buffer.get_ref()).await?;
+        sink.flush().await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Read;
+
+    use image::{GenericImageView, RgbImage, Rgba32FImage};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_write_to_memory() -> Result<()> {
+        let images = (0..3)
+            .map(|i| DynamicImage::ImageRgb8(RgbImage::new(10 + i, 20)))
+            .collect::<Vec<_>>();
+        let writer = ZipWriter::new(
+            CompressionMethod::Deflated,
+            image::ImageFormat::Png,
+            None,
+            2,
+            ProgressConfig::disabled(),
+        );
+
+        let mut buffer = Vec::new();
+        writer.write_images_to(images, &mut buffer).await?;
+
+        let mut archive = zip::ZipArchive::new(Cursor::new(buffer))?;
+        assert_eq!(archive.len(), 3);
+        for i in 0..3 {
+            let mut entry = archive.by_name(&format!("{}.png", i))?;
+            let mut bytes = Vec::new();
+            entry.read_to_end(&mut bytes)?;
+            let image = image::load_from_memory(&bytes)?;
+            assert_eq!(image.dimensions(), (10 + i as u32, 20));
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_pages_under_image_dir() -> Result<()> {
+        let images = (0..2)
+            .map(|_| DynamicImage::ImageRgb8(RgbImage::new(10, 20)))
+            .collect::<Vec<_>>();
+        let writer = ZipWriter::new(
+            CompressionMethod::Stored,
+            image::ImageFormat::Png,
+            None,
+            2,
+            ProgressConfig::disabled(),
+        )
+        .with_cover(true)
+        .with_image_dir(Some("images/".to_string()));
+
+        let mut buffer = Vec::new();
+        writer.write_images_to(images, &mut buffer).await?;
+
+        let archive = zip::ZipArchive::new(Cursor::new(buffer))?;
+        let mut names = archive.file_names().collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, vec!["cover.png", "images/0.png", "images/1.png"]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_entry_naming() -> Result<()> {
+        let names = |naming: EntryNaming| async move {
+            let images = (0..3)
+                .map(|_| DynamicImage::ImageRgb8(RgbImage::new(10, 20)))
+                .collect::<Vec<_>>();
+            let writer = ZipWriter::new(
+                CompressionMethod::Stored,
+                image::ImageFormat::Png,
+                None,
+                2,
+                ProgressConfig::disabled(),
+            )
+            .with_entry_naming(naming)
+            // the second page failed
+            .with_page_indices(Some(vec![0, 2, 3]));
+            let mut buffer = Vec::new();
+            writer.write_images_to(images, &mut buffer).await?;
+            let archive = zip::ZipArchive::new(Cursor::new(buffer))?;
+            let mut names = archive.file_names().map(String::from).collect::<Vec<_>>();
+            names.sort();
+            Result::<_>::Ok(names)
+        };
+
+        assert_eq!(
+            names(EntryNaming::Index).await?,
+            ["0.png", "1.png", "2.png"]
+        );
+        assert_eq!(
+            names(EntryNaming::PageIndex).await?,
+            ["0.png", "2.png", "3.png"]
+        );
+        assert_eq!(
+            names("p{number:03}-{index}".parse()?).await?,
+            ["p001-0.png", "p003-1.png", "p004-2.png"]
+        );
+        assert!("page-name".parse::<EntryNaming>().is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_failed_write_leaves_no_partial_file() -> Result<()> {
+        let dir = Path::new("playground/output/zip_temp_file");
+        let _ = std::fs::remove_dir_all(dir);
+        std::fs::create_dir_all(dir)?;
+        // PNG can't store 32-bit float pixels, so the second page fails to encode
+        let images = vec![
+            DynamicImage::ImageRgb8(RgbImage::new(10, 20)),
+            DynamicImage::ImageRgba32F(Rgba32FImage::new(10, 20)),
+            DynamicImage::ImageRgb8(RgbImage::new(10, 20)),
+        ];
+        let writer = ZipWriter::new(
+            CompressionMethod::Deflated,
+            image::ImageFormat::Png,
+            None,
+            1,
+            ProgressConfig::disabled(),
+        )
+        .with_temp_file(true);
+
+        assert!(writer
+            .write_images(images, dir.join("episode"))
+            .await
+            .is_err());
+        assert_eq!(std::fs::read_dir(dir)?.count(), 0);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_encode_error_is_returned_without_temp_file() -> Result<()> {
+        let dir = Path::new("playground/output/zip_encode_error");
+        let _ = std::fs::remove_dir_all(dir);
+        std::fs::create_dir_all(dir)?;
+        let images = vec![
+            DynamicImage::ImageRgb8(RgbImage::new(10, 20)),
+            DynamicImage::ImageRgba32F(Rgba32FImage::new(10, 20)),
+        ];
+        let writer = ZipWriter::new(
+            CompressionMethod::Deflated,
+            image::ImageFormat::Png,
+            None,
+            2,
+            ProgressConfig::disabled(),
+        )
+        .with_temp_file(false);
+
+        let result = writer.write_images(images, dir.join("episode")).await;
+
+        assert!(result.is_err());
+        // no half-written archive is left behind
+        assert_eq!(std::fs::read_dir(dir)?.count(), 0);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_extension_from_content() -> Result<()> {
+        let jpeg = utils::encode_image(
+            &DynamicImage::ImageRgb8(RgbImage::new(10, 20)),
+            image::ImageFormat::Jpeg,
+        )?;
+        let writer = ZipWriter::new(
+            CompressionMethod::Stored,
+            image::ImageFormat::Png,
+            None,
+            2,
+            ProgressConfig::disabled(),
+        )
+        .with_cover(true)
+        .with_extension_from_content(true);
+
+        let mut buffer = Vec::new();
+        writer
+            .write_to(vec![jpeg, Bytes::from_static(b"not an image")], &mut buffer)
+            .await?;
+
+        let archive = zip::ZipArchive::new(Cursor::new(buffer))?;
+        let mut names = archive.file_names().collect::<Vec<_>>();
+        names.sort();
+        // unknown bytes keep the configured extension
+        assert_eq!(names, ["0.jpg", "1.png", "cover.jpg"]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_dotted_title_keeps_its_name() -> Result<()> {
+        let dir = Path::new("playground/output/zip_dotted");
+        let _ = std::fs::remove_dir_all(dir);
+        std::fs::create_dir_all(dir)?;
+        let images = vec![DynamicImage::ImageRgb8(RgbImage::new(10, 20))];
+        let writer = ZipWriter::new(
+            CompressionMethod::Stored,
+            image::ImageFormat::Png,
+            Some("cbz".to_string()),
+            1,
+            ProgressConfig::disabled(),
+        );
+
+        writer
+            .write_images(images.clone(), dir.join("Vol.2"))
+            .await?;
+        assert!(dir.join("Vol.2.cbz").exists());
+        writer.write_images(images, dir.join("Vol.3.cbz")).await?;
+        assert!(dir.join("Vol.3.cbz").exists());
+        assert!(!dir.join("Vol.cbz").exists());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_write_cover_entry() -> Result<()> {
+        let images = (0..3)
+            .map(|i| DynamicImage::ImageRgb8(RgbImage::new(10 + i, 20)))
+            .collect::<Vec<_>>();
+        let writer = ZipWriter::new(
+            CompressionMethod::Stored,
+            image::ImageFormat::Png,
+            None,
+            2,
+            ProgressConfig::disabled(),
+        )
+        .with_cover(true);
+
+        let mut buffer = Vec::new();
+        writer.write_images_to(images, &mut buffer).await?;
+
+        let mut archive = zip::ZipArchive::new(Cursor::new(buffer))?;
+        assert_eq!(archive.len(), 4);
+        // the pages keep their names and the cover comes last
+        assert_eq!(archive.name_for_index(3), Some("cover.png"));
+        let mut bytes = Vec::new();
+        archive.by_name("cover.png")?.read_to_end(&mut bytes)?;
+        assert_eq!(image::load_from_memory(&bytes)?.dimensions(), (10, 20));
+        for i in 0..3 {
+            archive.by_name(&format!("{}.png", i))?;
+        }
 
         Ok(())
     }
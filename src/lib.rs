@@ -1,9 +1,22 @@
 pub mod auth;
+pub mod cache;
+pub mod color;
 pub mod data;
+pub mod download;
 pub mod io;
+pub mod limit;
 pub mod parser;
 pub mod pipeline;
 pub mod progress;
+pub mod retry;
 pub mod solver;
+#[cfg(test)]
+pub(crate) mod testing;
+pub mod transform;
 pub(crate) mod utils;
 pub mod viewer;
+
+/// Page data shared between the pipeline stages without copying
+pub use bytes::Bytes;
+pub use download::Download;
+pub use pipeline::{Defaults, SizeEstimate};
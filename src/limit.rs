@@ -0,0 +1,602 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    future::Future,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, MutexGuard, PoisonError,
+    },
+    time::Duration,
+};
+
+use anyhow::Result;
+use bytes::BytesMut;
+use reqwest::Response;
+use tokio::{
+    sync::{Notify, OwnedSemaphorePermit, Semaphore},
+    time::Instant,
+};
+use url::Url;
+
+use crate::{cache::ImageCache, utils::Bytes};
+
+/// Size assumed for a response without `Content-Length`
+const ESTIMATED_PAGE_SIZE: usize = 1024 * 1024;
+
+/// Limits the total bytes of pages held in flight at once.
+///
+/// A pipeline holds the reservation of a page from fetching it until it is written.
+/// Pages collected before writing, e.g. for a contact sheet, release it once solved.
+#[derive(Debug, Clone)]
+pub struct ByteLimiter {
+    semaphore: Arc<Semaphore>,
+    max_bytes: usize,
+    in_flight: Arc<AtomicUsize>,
+    high_water_mark: Arc<AtomicUsize>,
+}
+
+/// Bytes reserved from a [`ByteLimiter`]. They are released on drop.
+#[derive(Debug)]
+pub struct BytePermit {
+    _permit: OwnedSemaphorePermit,
+    size: usize,
+    in_flight: Arc<AtomicUsize>,
+}
+
+impl ByteLimiter {
+    pub fn new(max_bytes: usize) -> Self {
+        let max_bytes = max_bytes.clamp(1, u32::MAX as usize);
+        ByteLimiter {
+            semaphore: Arc::new(Semaphore::new(max_bytes)),
+            max_bytes,
+            in_flight: Arc::new(AtomicUsize::new(0)),
+            high_water_mark: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    pub fn max_bytes(&self) -> usize {
+        self.max_bytes
+    }
+
+    /// Wait until `size` bytes are available and reserve them.
+    /// A page larger than the limit waits for the whole budget instead of blocking forever.
+    pub async fn acquire(&self, size: usize) -> Result<BytePermit> {
+        let size = size.clamp(1, self.max_bytes);
+        let permit = self
+            .semaphore
+            .clone()
+            .acquire_many_owned(size as u32)
+            .await?;
+
+        let in_flight = self.in_flight.fetch_add(size, Ordering::SeqCst) + size;
+        self.high_water_mark.fetch_max(in_flight, Ordering::SeqCst);
+
+        Ok(BytePermit {
+            _permit: permit,
+            size,
+            in_flight: self.in_flight.clone(),
+        })
+    }
+
+    /// Reserve the size of the response body, estimated when `Content-Length` is missing.
+    pub async fn acquire_response(&self, res: &Response) -> Result<BytePermit> {
+        self.acquire(response_size(res)).await
+    }
+
+    /// The largest number of bytes that were reserved at the same time
+    pub fn high_water_mark(&self) -> usize {
+        self.high_water_mark.load(Ordering::SeqCst)
+    }
+}
+
+/// Size of the response body, estimated when `Content-Length` is missing
+fn response_size(res: &Response) -> usize {
+    res.content_length()
+        .map(|len| len as usize)
+        .unwrap_or(ESTIMATED_PAGE_SIZE)
+}
+
+impl Drop for BytePermit {
+    fn drop(&mut self) {
+        self.in_flight.fetch_sub(self.size, Ordering::SeqCst);
+    }
+}
+
+/// The turn of a page to reserve its bytes, right after the page before it.
+///
+/// Pages written in order reserve their bytes in that order, so the page the writer waits
+/// for never waits for the bytes of the pages after it, which the writer can't release.
+/// A turn dropped before its page reserved anything is skipped once the pages before it
+/// are done.
+#[derive(Debug)]
+pub struct Turn {
+    index: usize,
+    turns: Arc<Turns>,
+    passed: bool,
+}
+
+/// The turn being taken, and the turns after it already given up
+#[derive(Debug, Default)]
+struct Turns {
+    state: Mutex<(usize, BTreeSet<usize>)>,
+    notify: Notify,
+}
+
+impl Turn {
+    /// The turns of `count` pages, one after another
+    pub fn chain(count: usize) -> Vec<Turn> {
+        let turns = Arc::new(Turns::default());
+        (0..count)
+            .map(|index| Turn {
+                index,
+                turns: turns.clone(),
+                passed: false,
+            })
+            .collect()
+    }
+
+    /// Wait until the pages before have reserved their bytes or failed
+    async fn wait(&self) {
+        loop {
+            // registered before the check, so a pass in between is not missed
+            let notified = self.turns.notify.notified();
+            if self.turns.state().0 == self.index {
+                return;
+            }
+            notified.await;
+        }
+    }
+
+    /// Let the page after reserve its bytes
+    fn pass(&mut self) {
+        if self.passed {
+            return;
+        }
+        self.passed = true;
+        {
+            let mut state = self.turns.state();
+            let (current, given_up) = &mut *state;
+            if *current != self.index {
+                given_up.insert(self.index);
+                return;
+            }
+            *current += 1;
+            while given_up.remove(current) {
+                *current += 1;
+            }
+        }
+        self.turns.notify.notify_waiters();
+    }
+}
+
+impl Turns {
+    /// The counters are left consistent by every holder, so a poisoned lock is still usable
+    fn state(&self) -> MutexGuard<'_, (usize, BTreeSet<usize>)> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Drop for Turn {
+    fn drop(&mut self) {
+        self.pass();
+    }
+}
+
+/// Limits the concurrent requests to each host, keyed by the origin of the url.
+///
+/// The pages of an episode usually come from one CDN, which may drop the connections
+/// beyond a few, whatever the number of pages fetched at once.
+#[derive(Debug, Clone)]
+pub struct HostLimiter {
+    max_per_host: usize,
+    hosts: Arc<Mutex<HashMap<String, Arc<Semaphore>>>>,
+}
+
+impl HostLimiter {
+    pub fn new(max_per_host: usize) -> Self {
+        HostLimiter {
+            max_per_host: max_per_host.max(1),
+            hosts: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn max_per_host(&self) -> usize {
+        self.max_per_host
+    }
+
+    /// Wait until another request to the host of the url may start.
+    /// The request slot is released when the permit is dropped.
+    pub async fn acquire(&self, url: &Url) -> Result<OwnedSemaphorePermit> {
+        let semaphore = self
+            .hosts
+            .lock()
+            .map_err(|_| anyhow::anyhow!("Host limiter lock poisoned"))?
+            .entry(url.origin().ascii_serialization())
+            .or_insert_with(|| Arc::new(Semaphore::new(self.max_per_host)))
+            .clone();
+        Ok(semaphore.acquire_owned().await?)
+    }
+}
+
+/// Caps the bytes read per second by every fetch sharing it.
+///
+/// Clones share the same budget, so one limiter caps the total bandwidth of all the
+/// pages and episodes downloaded at once.
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    bytes_per_sec: u64,
+    /// When the bytes read so far are paid off at the rate
+    paid_until: Arc<Mutex<Instant>>,
+}
+
+impl RateLimiter {
+    pub fn new(bytes_per_sec: u64) -> Self {
+        RateLimiter {
+            bytes_per_sec: bytes_per_sec.max(1),
+            paid_until: Arc::new(Mutex::new(Instant::now())),
+        }
+    }
+
+    pub fn bytes_per_sec(&self) -> u64 {
+        self.bytes_per_sec
+    }
+
+    /// Account for `size` bytes read, waiting until the rate allows them.
+    /// Idle time is not saved up, so there is no burst after a pause.
+    pub async fn consume(&self, size: usize) -> Result<()> {
+        let until = {
+            let mut paid_until = self
+                .paid_until
+                .lock()
+                .map_err(|_| anyhow::anyhow!("Rate limiter lock poisoned"))?;
+            let start = (*paid_until).max(Instant::now());
+            *paid_until = start + Duration::from_secs_f64(size as f64 / self.bytes_per_sec as f64);
+            *paid_until
+        };
+        tokio::time::sleep_until(until).await;
+        Ok(())
+    }
+
+    /// Read the body of the response chunk by chunk, at most at the rate
+    pub async fn read(&self, mut res: Response) -> Result<Bytes> {
+        let mut body = BytesMut::with_capacity(res.content_length().unwrap_or(0) as usize);
+        while let Some(chunk) = res.chunk().await? {
+            self.consume(chunk.len()).await?;
+            body.extend_from_slice(&chunk);
+        }
+        Ok(body.freeze())
+    }
+}
+
+/// Fetches the images of a pipeline through its image cache and limiters, all optional
+#[derive(Debug, Clone, Default)]
+pub struct PageFetcher {
+    image_cache: Option<ImageCache>,
+    byte_limiter: Option<ByteLimiter>,
+    host_limiter: Option<HostLimiter>,
+    rate_limiter: Option<RateLimiter>,
+}
+
+impl PageFetcher {
+    pub fn with_image_cache(self, image_cache: Option<ImageCache>) -> Self {
+        Self {
+            image_cache,
+            ..self
+        }
+    }
+
+    pub fn with_byte_limiter(self, byte_limiter: Option<ByteLimiter>) -> Self {
+        Self {
+            byte_limiter,
+            ..self
+        }
+    }
+
+    pub fn with_host_limiter(self, host_limiter: Option<HostLimiter>) -> Self {
+        Self {
+            host_limiter,
+            ..self
+        }
+    }
+
+    pub fn with_rate_limiter(self, rate_limiter: Option<RateLimiter>) -> Self {
+        Self {
+            rate_limiter,
+            ..self
+        }
+    }
+
+    pub fn byte_limiter(&self) -> Option<&ByteLimiter> {
+        self.byte_limiter.as_ref()
+    }
+
+    /// Wait for a free request slot on the host of the url, if the hosts are limited
+    pub async fn acquire_host(&self, url: &Url) -> Result<Option<OwnedSemaphorePermit>> {
+        match &self.host_limiter {
+            Some(limiter) => Ok(Some(limiter.acquire(url).await?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Fetch the image of the url with `get`, or read it from the cache, and reserve its
+    /// size from the in-flight bytes limit.
+    ///
+    /// The page is held within the limit until the permit is dropped.
+    pub async fn fetch<F, Fut>(&self, url: Url, get: F) -> Result<(Bytes, Option<BytePermit>)>
+    where
+        F: FnOnce(Url) -> Fut,
+        Fut: Future<Output = Result<Response>>,
+    {
+        self.fetch_with(url, self.byte_limiter.as_ref(), get, None)
+            .await
+    }
+
+    /// [`PageFetcher::fetch`], reserving the size only in the turn of the page.
+    /// The requests are still sent at once, only the reservations wait for each other,
+    /// unless the hosts are limited. Then each request also waits for the turn, so the
+    /// pages after hold no host slot the pages before need.
+    pub async fn fetch_in_turn<F, Fut>(
+        &self,
+        url: Url,
+        get: F,
+        mut turn: Turn,
+    ) -> Result<(Bytes, Option<BytePermit>)>
+    where
+        F: FnOnce(Url) -> Fut,
+        Fut: Future<Output = Result<Response>>,
+    {
+        // a page that fails before its turn gives it up when the turn is dropped
+        self.fetch_with(url, self.byte_limiter.as_ref(), get, Some(&mut turn))
+            .await
+    }
+
+    /// Fetch the image again from the network, past a cached body that failed to solve
+    pub async fn refetch<F, Fut>(&self, url: Url, get: F) -> Result<Bytes>
+    where
+        F: FnOnce(Url) -> Fut,
+        Fut: Future<Output = Result<Response>>,
+    {
+        if let Some(cache) = &self.image_cache {
+            cache.remove(&url).await?;
+        }
+        // the body replaces the failed one, which still holds the reservation of the page
+        let (bytes, _) = self.fetch_with(url, None, get, None).await?;
+        Ok(bytes)
+    }
+
+    async fn fetch_with<F, Fut>(
+        &self,
+        url: Url,
+        byte_limiter: Option<&ByteLimiter>,
+        get: F,
+        turn: Option<&mut Turn>,
+    ) -> Result<(Bytes, Option<BytePermit>)>
+    where
+        F: FnOnce(Url) -> Fut,
+        Fut: Future<Output = Result<Response>>,
+    {
+        if let Some(bytes) = self.cached(&url).await {
+            let permit = Self::reserve(byte_limiter, bytes.len(), turn).await?;
+            return Ok((bytes, permit));
+        }
+        tracing::debug!("Fetching page {}", url);
+        // a page waiting for its turn must not hold the host slot a page before it needs
+        if let (Some(_), Some(turn)) = (&self.host_limiter, turn.as_deref()) {
+            turn.wait().await;
+        }
+        let _host_permit = self.acquire_host(&url).await?;
+        let res = get(url.clone()).await?;
+        let permit = Self::reserve(byte_limiter, response_size(&res), turn).await?;
+        let bytes = self.read(res).await?;
+        self.cache(&url, &bytes).await;
+
+        Ok((bytes, permit))
+    }
+
+    /// Reserve the size from the limiter if there is one, in the turn of the page if it has one
+    async fn reserve(
+        byte_limiter: Option<&ByteLimiter>,
+        size: usize,
+        turn: Option<&mut Turn>,
+    ) -> Result<Option<BytePermit>> {
+        let Some(limiter) = byte_limiter else {
+            return Ok(None);
+        };
+        match turn {
+            Some(turn) => {
+                turn.wait().await;
+                let permit = limiter.acquire(size).await;
+                turn.pass();
+                Ok(Some(permit?))
+            }
+            None => Ok(Some(limiter.acquire(size).await?)),
+        }
+    }
+
+    /// Read the fetched image, at most at the rate of the limiter if there is one
+    async fn read(&self, res: Response) -> Result<Bytes> {
+        match &self.rate_limiter {
+            Some(limiter) => limiter.read(res).await,
+            None => Ok(res.bytes().await?),
+        }
+    }
+
+    /// The cached image of the url. A cache that can't be read is only a miss.
+    async fn cached(&self, url: &Url) -> Option<Bytes> {
+        let cache = self.image_cache.as_ref()?;
+        cache.get(url).await.unwrap_or_else(|e| {
+            tracing::warn!("Failed to read {} from the image cache: {:#}", url, e);
+            None
+        })
+    }
+
+    /// Store the fetched image in the cache, if there is one
+    async fn cache(&self, url: &Url, bytes: &Bytes) {
+        if let Some(cache) = &self.image_cache {
+            if let Err(e) = cache.put(url, bytes).await {
+                tracing::warn!("Failed to cache {}: {:#}", url, e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use futures::{stream, StreamExt};
+    use wiremock::{
+        matchers::{method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_high_water_mark_under_limit() -> Result<()> {
+        let limiter = ByteLimiter::new(1000);
+        let sizes = vec![300, 800, 50, 400, 999, 120, 640, 10, 700, 250];
+
+        stream::iter(sizes)
+            .map(|size| {
+                let limiter = limiter.clone();
+                async move {
+                    let _permit = limiter.acquire(size).await?;
+                    tokio::time::sleep(Duration::from_millis(5)).await;
+                    Result::<_>::Ok(())
+                }
+            })
+            .buffer_unordered(8)
+            .collect::<Vec<_>>()
+            .await
+            .into_iter()
+            .collect::<Result<Vec<_>>>()?;
+
+        assert!(limiter.high_water_mark() <= 1000);
+        assert!(limiter.high_water_mark() >= 999);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_hosts_are_limited_separately() -> Result<()> {
+        let limiter = HostLimiter::new(2);
+        let a = Url::parse("https://a.example.com/page/1.jpg")?;
+        let b = Url::parse("https://b.example.com/page/1.jpg")?;
+
+        let _first = limiter.acquire(&a).await?;
+        let _second = limiter.acquire(&a).await?;
+        // another host is not held up by the full one
+        let _other = limiter.acquire(&b).await?;
+        let third = tokio::time::timeout(Duration::from_millis(20), limiter.acquire(&a)).await;
+        assert!(third.is_err());
+
+        drop(_first);
+        let _third = limiter.acquire(&a).await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_oversized_page_does_not_block() -> Result<()> {
+        let limiter = ByteLimiter::new(100);
+        let permit = limiter.acquire(5000).await?;
+        assert_eq!(limiter.high_water_mark(), 100);
+        drop(permit);
+
+        let _permit = limiter.acquire(10).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_bytes_are_reserved_in_turn() -> Result<()> {
+        let limiter = ByteLimiter::new(100);
+        let [mut first, second, third, fourth]: [Turn; 4] = Turn::chain(4)
+            .try_into()
+            .map_err(|_| anyhow::anyhow!("Not 4 turns"))?;
+        let reserve = |size: usize, mut turn: Turn| {
+            let limiter = limiter.clone();
+            tokio::spawn(async move {
+                PageFetcher::reserve(Some(&limiter), size, Some(&mut turn)).await
+            })
+        };
+
+        // the third page failed, the pages after it still wait for the ones before it
+        drop(third);
+        let last = reserve(10, fourth);
+        let next = reserve(100, second);
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!last.is_finished());
+        assert!(!next.is_finished());
+
+        let permit = PageFetcher::reserve(Some(&limiter), 60, Some(&mut first)).await?;
+        assert!(permit.is_some());
+        drop(permit);
+        let permit = next.await??;
+        assert!(permit.is_some());
+        drop(permit);
+        assert!(last.await??.is_some());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_turns_do_not_hold_host_slots() -> Result<()> {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(b"jpeg".to_vec()))
+            .mount(&server)
+            .await;
+        // one request at a time and room for one page
+        let fetcher = PageFetcher::default()
+            .with_host_limiter(Some(HostLimiter::new(1)))
+            .with_byte_limiter(Some(ByteLimiter::new(4)));
+        let get = |url: Url| async move { Ok(reqwest::get(url).await?) };
+
+        // the last page asks for the host slot first
+        let fetches = Turn::chain(3)
+            .into_iter()
+            .enumerate()
+            .rev()
+            .map(|(i, turn)| {
+                let fetcher = fetcher.clone();
+                let url = Url::parse(&format!("{}/page/{}.jpg", server.uri(), i));
+                async move {
+                    let (bytes, _permit) = fetcher.fetch_in_turn(url?, get, turn).await?;
+                    Result::<_>::Ok(bytes)
+                }
+            });
+        let fetched = tokio::time::timeout(
+            Duration::from_secs(5),
+            futures::future::try_join_all(fetches),
+        )
+        .await??;
+        assert_eq!(fetched.len(), 3);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_refetch_skips_the_cache() -> Result<()> {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/page.jpg"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(b"jpeg".to_vec()))
+            .expect(2)
+            .mount(&server)
+            .await;
+        let dir = std::path::Path::new("playground/output/page_fetcher_cache");
+        let _ = std::fs::remove_dir_all(dir);
+        let fetcher = PageFetcher::default()
+            .with_image_cache(Some(ImageCache::open(dir, 1024)?))
+            .with_byte_limiter(Some(ByteLimiter::new(100)));
+        let url = Url::parse(&format!("{}/page.jpg", server.uri()))?;
+        let get = |url: Url| async move { Ok(reqwest::get(url).await?) };
+
+        let (bytes, permit) = fetcher.fetch(url.clone(), get).await?;
+        assert_eq!(bytes, &b"jpeg"[..]);
+        assert!(permit.is_some());
+        // the second fetch is read from the cache
+        fetcher.fetch(url.clone(), get).await?;
+        assert_eq!(fetcher.refetch(url, get).await?, &b"jpeg"[..]);
+
+        Ok(())
+    }
+}
@@ -1,27 +1,229 @@
+use std::{future::Future, path::Path, time::Duration};
+
 use anyhow::{bail, Context, Result};
-use manga::pipeline::{EpisodePipeline, EpisodePipelineBuilder, WriterConifg};
+use manga::pipeline::{EpisodePipeline, EpisodePipelineBuilder, ErrorPolicy, WriterConifg};
 #[cfg(feature = "fuz")]
 use manga::viewer::fuz::{self, pipeline::Pipeline as FuzPipeline};
 use manga::viewer::giga::{self, pipeline::Pipeline as GigaPipeline};
-use manga::{progress::ProgressConfig, viewer::ViewerWebsite};
+use manga::{
+    auth::Netrc,
+    cache::ImageCache,
+    io::{contact_sheet::ContactSheetWriter, zip::EntryNaming},
+    limit::RateLimiter,
+    progress::ProgressConfig,
+    viewer::ViewerWebsite,
+    Defaults,
+};
 
-use clap::{Parser, Subcommand, ValueEnum};
+use clap::{ArgAction, Parser, Subcommand, ValueEnum};
+use futures::{stream, StreamExt};
+use indicatif::MultiProgress;
+use tracing::level_filters::LevelFilter;
 use url::Url;
 
 #[derive(Debug, Clone, Parser)]
 struct Cli {
     #[command(subcommand)]
     command: Source,
+
+    /// Hide progress bars and print errors only
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Show more logs. `-v` for info, `-vv` for debug and `-vvv` for trace
+    #[arg(short, long, global = true, action = ArgAction::Count)]
+    verbose: u8,
+
+    /// Number of threads solving and writing the pages. Defaults to the number of CPUs
+    #[arg(long, global = true, value_parser = clap::value_parser!(u32).range(1..))]
+    threads: Option<u32>,
+
+    /// Number of concurrent requests per episode. Defaults to the preset of the website
+    #[arg(long, global = true, value_parser = clap::value_parser!(u32).range(1..))]
+    connections: Option<u32>,
+
+    /// netrc file with the credentials of the sites, looked up by their host
+    #[arg(long, global = true)]
+    netrc: Option<String>,
+}
+
+impl Cli {
+    fn log_level(&self) -> LevelFilter {
+        if self.quiet {
+            return LevelFilter::ERROR;
+        }
+        match self.verbose {
+            0 => LevelFilter::WARN,
+            1 => LevelFilter::INFO,
+            2 => LevelFilter::DEBUG,
+            _ => LevelFilter::TRACE,
+        }
+    }
+
+    fn progress(&self) -> ProgressConfig {
+        if self.quiet {
+            ProgressConfig::disabled()
+        } else {
+            ProgressConfig::default()
+        }
+    }
+
+    /// Settings of every pipeline built for the command
+    fn defaults(&self) -> Result<Defaults> {
+        let mut defaults = Defaults::default().with_progress(self.progress());
+        if let Some(threads) = self.threads {
+            defaults = defaults.with_num_threads(threads as usize);
+        }
+        if let Some(connections) = self.connections {
+            defaults = defaults.with_num_connections(connections as usize);
+        }
+        if let Some(netrc) = &self.netrc {
+            defaults = defaults.with_netrc(Netrc::load(netrc)?);
+        }
+        Ok(defaults)
+    }
 }
 
 #[derive(Debug, Clone, Subcommand)]
 enum Source {
     Episode {
         /// Episode URL of the manga
-        url: Url,
+        #[arg(required_unless_present = "input")]
+        url: Option<Url>,
+
+        /// File with newline-separated episode URLs, or `-` to read them from stdin.
+        /// Blank lines and lines starting with `#` are ignored.
+        #[arg(short, long, conflicts_with = "url")]
+        input: Option<String>,
+
+        /// Number of episodes downloaded at once when reading from `--input`
+        #[arg(short, long, default_value_t = 2)]
+        jobs: usize,
 
         /// Output directory.
         /// New directory or file will be created in this directory.
+        /// A path ending in a file extension such as `.cbz` or `.pdf` is used as the output file.
+        #[arg(short, long, required_unless_present = "list_pages")]
+        output_dir: Option<String>,
+
+        /// Save as. `auto` picks the format from the extension of the output path
+        #[arg(short, long, default_value = "auto")]
+        save_as: SaveFormat,
+
+        /// Image format
+        #[arg(short, long, default_value = "png")]
+        format: ImageFormat,
+
+        /// Milliseconds each frame is shown when saving as GIF
+        #[arg(long, default_value_t = 100)]
+        frame_delay: u64,
+
+        /// Compression of TIFF pages
+        #[arg(long, default_value = "none")]
+        tiff_compression: TiffCompression,
+
+        /// Also store the first page as `cover.<ext>` in zip and cbz archives
+        #[arg(long)]
+        cover: bool,
+
+        /// Store the pages under this directory inside zip and cbz archives, e.g. `images`
+        #[arg(long)]
+        zip_image_dir: Option<String>,
+
+        /// Names of the pages in zip and cbz archives: `index`, `page` for the index in the
+        /// episode, or a template like `p{number:03}`
+        #[arg(long, default_value = "index")]
+        zip_entry_names: EntryNaming,
+
+        /// Record the episode url in the zip comment or the PDF subject
+        #[arg(long)]
+        source_url: bool,
+
+        /// Name raw pages by the SHA-256 of their bytes and list the page order in `index.json`
+        #[arg(long)]
+        content_addressed: bool,
+
+        /// Only rewrite the raw pages whose content changed since the last download
+        #[arg(long)]
+        skip_unchanged: bool,
+
+        /// Name raw pages and zip entries by the format of their bytes, e.g. `.jpg` for JPEG
+        /// pages written without re-encoding
+        #[arg(long)]
+        extension_from_content: bool,
+
+        /// Spell the episode titles in the output names in ASCII
+        #[arg(long)]
+        transliterate: bool,
+
+        /// Name of the output of an episode without a title
+        #[arg(long, default_value = "id")]
+        missing_title: MissingTitle,
+
+        /// Write the pages in reverse order, e.g. when they come out backwards in your reader
+        #[arg(long)]
+        reverse_pages: bool,
+
+        /// Convert pages embedding a color profile to sRGB
+        #[arg(long)]
+        srgb: bool,
+
+        /// Also save a contact sheet with this many thumbnails per row
+        #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+        contact_sheet: Option<u32>,
+
+        /// Write the pages that succeeded and list the failed ones in a report
+        #[arg(long)]
+        best_effort: bool,
+
+        /// Attempt every page before failing and report all the failed pages together
+        #[arg(long)]
+        collect_errors: bool,
+
+        /// Fetch the pages one at a time in page order
+        #[arg(long)]
+        sequential: bool,
+
+        /// Keep the fetched images in this directory and read them from it on later runs
+        #[arg(long)]
+        image_cache: Option<String>,
+
+        /// Size limit of the image cache in MB, the least recently used images are evicted
+        #[arg(long, default_value_t = 1024, requires = "image_cache")]
+        image_cache_size: u64,
+
+        /// Cap the download bandwidth of all the episodes together, in bytes per second
+        /// such as `500K`, `2MiB` or `1.5MB`
+        #[arg(long, value_parser = parse_byte_size)]
+        max_rate: Option<u64>,
+
+        /// Write the fetched pages without solving them, for debugging the solvers.
+        /// The pages are still scrambled or encrypted and cannot be viewed.
+        #[arg(long)]
+        no_solve: bool,
+
+        /// Print the index and image URL of every page, separated by a tab, without downloading
+        #[arg(long, conflicts_with = "input")]
+        list_pages: bool,
+
+        /// Warn about giga pages that still look scrambled after solving
+        #[arg(long)]
+        verify_solved: bool,
+
+        /// Override the number of tiles per side when solving giga pages
+        #[arg(long, hide = true, value_parser = clap::value_parser!(u32).range(1..))]
+        giga_num_cells: Option<u32>,
+
+        /// Override the multiple the giga tile size is rounded down to
+        #[arg(long, hide = true, value_parser = clap::value_parser!(u32).range(1..))]
+        giga_divisible_with: Option<u32>,
+    },
+    /// Download the episodes of a series not downloaded yet, the oldest first
+    Series {
+        /// Series URL of the manga
+        url: Url,
+
+        /// Output directory. Every episode is saved in it, along with the state of the series
         #[arg(short, long)]
         output_dir: String,
 
@@ -32,28 +234,95 @@ enum Source {
         /// Image format
         #[arg(short, long, default_value = "png")]
         format: ImageFormat,
+
+        /// Download every episode again, ignoring the episodes recorded as done
+        #[arg(long)]
+        force: bool,
+
+        /// Layout of the episodes, `komga` saves them as `<series>/Chapter 001.<ext>`
+        #[arg(long, default_value = "flat")]
+        layout: SeriesLayout,
+
+        /// Package every N episodes into one volume instead of saving each episode on its own
+        #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+        volume_size: Option<u32>,
     },
 }
 
 #[derive(Debug, Clone, ValueEnum)]
 enum ImageFormat {
+    /// Keep the bytes served by the site where possible, e.g. the decrypted fuz JPEGs.
+    /// Giga pages are descrambled, so they are encoded once more as JPEG
+    Original,
     Png,
     #[value(alias = "jpg")]
     Jpeg,
     Webp,
+    #[value(alias = "tif")]
+    Tiff,
+    Bmp,
+}
+
+#[derive(Debug, Clone, ValueEnum)]
+enum SeriesLayout {
+    Flat,
+    Komga,
 }
 
 #[derive(Debug, Clone, ValueEnum)]
+enum MissingTitle {
+    Fail,
+    Id,
+    Index,
+}
+
+#[derive(Debug, Clone, ValueEnum)]
+enum TiffCompression {
+    None,
+    Lzw,
+    Deflate,
+}
+
+#[derive(Debug, Clone, PartialEq, ValueEnum)]
 enum SaveFormat {
+    Auto,
     Raw,
     Zip,
     Cbz,
+    /// Animated GIF, for motion comics
+    Gif,
+    /// A single multi-page TIFF
+    Tiff,
     #[cfg(feature = "pdf")]
     Pdf,
+    #[cfg(feature = "sevenz")]
+    #[value(name = "7z")]
+    SevenZip,
 }
 
-fn get_save_format(save: SaveFormat) -> manga::pipeline::SaveFormat {
+/// Infer the save format from the extension of the output path, raw for directories
+fn infer_save_format(path: &Path) -> SaveFormat {
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_lowercase());
+
+    match extension.as_deref() {
+        Some("zip") => SaveFormat::Zip,
+        Some("cbz") => SaveFormat::Cbz,
+        Some("gif") => SaveFormat::Gif,
+        Some("tif" | "tiff") => SaveFormat::Tiff,
+        #[cfg(feature = "pdf")]
+        Some("pdf") => SaveFormat::Pdf,
+        #[cfg(feature = "sevenz")]
+        Some("7z") => SaveFormat::SevenZip,
+        _ => SaveFormat::Raw,
+    }
+}
+
+fn get_save_format(save: SaveFormat, output: &Path) -> manga::pipeline::SaveFormat {
     match save {
+        SaveFormat::Auto => get_save_format(infer_save_format(output), output),
         SaveFormat::Raw => manga::pipeline::SaveFormat::Raw,
         SaveFormat::Zip => manga::pipeline::SaveFormat::Zip {
             compression_method: zip::CompressionMethod::Zstd,
@@ -63,65 +332,683 @@ fn get_save_format(save: SaveFormat) -> manga::pipeline::SaveFormat {
             compression_method: zip::CompressionMethod::Zstd,
             extension: Some("cbz".to_string()),
         },
+        SaveFormat::Gif => manga::pipeline::SaveFormat::Gif {
+            frame_delay: manga::io::animation::DEFAULT_FRAME_DELAY,
+        },
+        SaveFormat::Tiff => manga::pipeline::SaveFormat::Tiff,
         #[cfg(feature = "pdf")]
         SaveFormat::Pdf => manga::pipeline::SaveFormat::Pdf,
+        #[cfg(feature = "sevenz")]
+        SaveFormat::SevenZip => manga::pipeline::SaveFormat::SevenZip { preset: None },
     }
 }
 
 fn get_image_format(format: ImageFormat) -> image::ImageFormat {
     match format {
+        // the sites serve JPEG, and pages that must be encoded are encoded as such
+        ImageFormat::Original => image::ImageFormat::Jpeg,
         ImageFormat::Png => image::ImageFormat::Png,
         ImageFormat::Jpeg => image::ImageFormat::Jpeg,
         ImageFormat::Webp => image::ImageFormat::WebP,
+        ImageFormat::Tiff => image::ImageFormat::Tiff,
+        ImageFormat::Bmp => image::ImageFormat::Bmp,
+    }
+}
+
+#[cfg(feature = "giga")]
+fn get_series_layout(layout: SeriesLayout) -> manga::pipeline::SeriesLayout {
+    match layout {
+        SeriesLayout::Flat => manga::pipeline::SeriesLayout::Flat,
+        SeriesLayout::Komga => manga::pipeline::SeriesLayout::Komga,
+    }
+}
+
+fn get_missing_title(missing_title: MissingTitle) -> manga::pipeline::MissingTitle {
+    match missing_title {
+        MissingTitle::Fail => manga::pipeline::MissingTitle::Fail,
+        MissingTitle::Id => manga::pipeline::MissingTitle::Id,
+        MissingTitle::Index => manga::pipeline::MissingTitle::Index,
+    }
+}
+
+fn get_tiff_compression(compression: TiffCompression) -> manga::io::TiffCompression {
+    match compression {
+        TiffCompression::None => manga::io::TiffCompression::None,
+        TiffCompression::Lzw => manga::io::TiffCompression::Lzw,
+        TiffCompression::Deflate => manga::io::TiffCompression::Deflate,
+    }
+}
+
+/// Parse a size in bytes like `500K`, `2MiB` or `1.5MB`, with an optional `/s`.
+/// `K`, `M` and `G` alone or with `iB` are powers of 1024, with `B` powers of 1000.
+fn parse_byte_size(text: &str) -> Result<u64> {
+    let text = text.trim();
+    let text = text.strip_suffix("/s").unwrap_or(text);
+    let split = text
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let number = number
+        .parse::<f64>()
+        .with_context(|| format!("Invalid size: {}", text))?;
+    let multiplier = match unit.trim() {
+        "" | "B" => 1.0,
+        "K" | "KiB" => 1024.0,
+        "M" | "MiB" => 1024.0 * 1024.0,
+        "G" | "GiB" => 1024.0 * 1024.0 * 1024.0,
+        "KB" => 1e3,
+        "MB" => 1e6,
+        "GB" => 1e9,
+        unit => bail!("Unknown size unit: {}", unit),
+    };
+    let size = (number * multiplier) as u64;
+    if size == 0 {
+        bail!("Size must be at least 1 byte");
+    }
+    Ok(size)
+}
+
+/// Parse newline-separated URLs, skipping blank lines and `#` comments
+fn parse_url_list(text: &str) -> Result<Vec<Url>> {
+    text.lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| Url::parse(line).with_context(|| format!("Invalid URL: {}", line)))
+        .collect()
+}
+
+fn read_url_list(input: &str) -> Result<Vec<Url>> {
+    let text = if input == "-" {
+        std::io::read_to_string(std::io::stdin())?
+    } else {
+        std::fs::read_to_string(input).with_context(|| format!("Failed to read {}", input))?
+    };
+    parse_url_list(&text)
+}
+
+/// Download every URL with at most `jobs` at once, keeping the result of each
+async fn download_batch<F, Fut>(urls: Vec<Url>, jobs: usize, download: F) -> Vec<(Url, Result<()>)>
+where
+    F: Fn(Url) -> Fut,
+    Fut: Future<Output = Result<()>>,
+{
+    stream::iter(urls)
+        .map(|url| {
+            let task = download(url.clone());
+            async move { (url, task.await) }
+        })
+        .buffered(jobs.max(1))
+        .collect()
+        .await
+}
+
+/// Options shared by every downloaded episode
+#[derive(Debug, Clone)]
+struct DownloadOptions {
+    save_as: SaveFormat,
+    format: ImageFormat,
+    tiff_compression: TiffCompression,
+    frame_delay: u64,
+    cover: bool,
+    zip_image_dir: Option<String>,
+    zip_entry_names: EntryNaming,
+    source_url: bool,
+    content_addressed: bool,
+    skip_unchanged: bool,
+    extension_from_content: bool,
+    transliterate: bool,
+    missing_title: MissingTitle,
+    reverse_pages: bool,
+    defaults: Defaults,
+    srgb: bool,
+    contact_sheet: Option<u32>,
+    best_effort: bool,
+    collect_errors: bool,
+    sequential: bool,
+    image_cache: Option<ImageCache>,
+    rate_limiter: Option<RateLimiter>,
+    no_solve: bool,
+    giga_solver: giga::solver::Solver,
+    verify_solved: bool,
+}
+
+async fn download_episode(url: &Url, output: &Path, options: DownloadOptions) -> Result<()> {
+    let host = url.host_str().context("Url must have host")?;
+
+    let DownloadOptions {
+        save_as,
+        format,
+        tiff_compression,
+        frame_delay,
+        cover,
+        zip_image_dir,
+        zip_entry_names,
+        source_url,
+        content_addressed,
+        skip_unchanged,
+        extension_from_content,
+        transliterate,
+        missing_title,
+        reverse_pages,
+        defaults,
+        srgb,
+        contact_sheet,
+        best_effort,
+        collect_errors,
+        sequential,
+        image_cache,
+        rate_limiter,
+        no_solve,
+        giga_solver,
+        verify_solved,
+    } = options;
+    let save_format = match get_save_format(save_as, output) {
+        manga::pipeline::SaveFormat::Gif { .. } => manga::pipeline::SaveFormat::Gif {
+            frame_delay: Duration::from_millis(frame_delay),
+        },
+        save_format => save_format,
+    };
+    // the served bytes are kept, so name the pages by their format
+    let keep_original = matches!(format, ImageFormat::Original);
+    let extension_from_content = extension_from_content || keep_original;
+    let image_format = get_image_format(format);
+    #[cfg(feature = "pdf")]
+    if let manga::pipeline::SaveFormat::Pdf = save_format {
+        manga::io::pdf::PdfWriter::check_image_format(image_format)?;
+    }
+    let writer_config = WriterConifg::new(save_format, image_format)
+        .with_tiff_compression(get_tiff_compression(tiff_compression))
+        .with_cover(cover)
+        .with_zip_image_dir(zip_image_dir)
+        .with_zip_entry_naming(zip_entry_names)
+        .with_source_url(source_url)
+        .with_content_addressed(content_addressed)
+        .with_skip_unchanged(skip_unchanged)
+        .with_extension_from_content(extension_from_content)
+        .with_keep_original(keep_original)
+        .with_transliterate(transliterate)
+        .with_missing_title(get_missing_title(missing_title))
+        .with_reverse_pages(reverse_pages);
+    let defaults = defaults.with_writer_config(writer_config);
+    let contact_sheet = contact_sheet.map(|columns| {
+        ContactSheetWriter::new(
+            columns,
+            ContactSheetWriter::default().thumbnail_size(),
+            image_format,
+        )
+    });
+    let error_policy = match collect_errors {
+        true => ErrorPolicy::Collect,
+        false => ErrorPolicy::FailFast,
+    };
+    // a path with a known archive extension is the output file itself
+    let into_file = infer_save_format(output) != SaveFormat::Raw;
+
+    if let Some(website) = giga::viewer::Website::lookup(host) {
+        let pipe = GigaPipeline::from_defaults(website, &defaults)?
+            .set_convert_to_srgb(srgb)
+            .set_contact_sheet(contact_sheet.clone())
+            .set_best_effort(best_effort)
+            .set_error_policy(error_policy)
+            .set_sequential(sequential)
+            .set_image_cache(image_cache.clone())
+            .set_rate_limiter(rate_limiter.clone())
+            .set_no_solve(no_solve)
+            .set_solver(giga_solver)
+            .set_verify_solved(verify_solved);
+
+        if into_file {
+            pipe.download(url, output).await?;
+        } else {
+            pipe.download_in(url, output).await?;
+        }
+
+        return Ok(());
+    }
+
+    #[cfg(feature = "fuz")]
+    if let Some(website) = fuz::viewer::Website::lookup(host) {
+        let pipe = FuzPipeline::from_defaults(website, &defaults)?
+            .set_convert_to_srgb(srgb)
+            .set_contact_sheet(contact_sheet.clone())
+            .set_best_effort(best_effort)
+            .set_error_policy(error_policy)
+            .set_sequential(sequential)
+            .set_image_cache(image_cache)
+            .set_rate_limiter(rate_limiter)
+            .set_no_solve(no_solve);
+
+        if into_file {
+            pipe.download(url, output).await?;
+        } else {
+            pipe.download_in(url, output).await?;
+        }
+
+        return Ok(());
     }
+
+    bail!("Website not supported: {}", host);
+}
+
+#[cfg(feature = "giga")]
+async fn download_series(
+    url: &Url,
+    output: &Path,
+    defaults: Defaults,
+    force: bool,
+    layout: SeriesLayout,
+    volume_size: Option<u32>,
+) -> Result<()> {
+    let host = url.host_str().context("Url must have host")?;
+    let Some(website) = giga::viewer::Website::lookup(host) else {
+        bail!("Series are not supported for {}", host);
+    };
+
+    GigaPipeline::from_defaults(website, &defaults)?
+        .set_force(force)
+        .set_series_layout(get_series_layout(layout))
+        .set_volume_size(volume_size.map(|size| size as usize))
+        .download_series_in(url, output)
+        .await
+}
+
+/// `(index, url)` of the image pages of the episode, fetched with the same settings
+/// as a download
+async fn list_episode_pages(url: &Url, defaults: &Defaults) -> Result<Vec<(usize, Url)>> {
+    let host = url.host_str().context("Url must have host")?;
+
+    if let Some(website) = giga::viewer::Website::lookup(host) {
+        return GigaPipeline::from_defaults(website, defaults)?
+            .list_pages(url)
+            .await;
+    }
+
+    #[cfg(feature = "fuz")]
+    if let Some(website) = fuz::viewer::Website::lookup(host) {
+        return FuzPipeline::from_defaults(website, defaults)?
+            .list_pages(url)
+            .await;
+    }
+
+    bail!("Website not supported: {}", host);
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let cli = Cli::parse();
 
-    println!("{:?}", cli);
+    tracing_subscriber::fmt()
+        .with_max_level(cli.log_level())
+        .with_writer(std::io::stderr)
+        .init();
+    tracing::debug!("{:?}", cli);
 
-    let progress = ProgressConfig::default();
+    let defaults = cli.defaults()?;
+    let quiet = cli.quiet;
 
     match cli.command {
         Source::Episode {
             url,
+            input,
+            jobs,
             output_dir,
             save_as,
             format,
+            tiff_compression,
+            frame_delay,
+            cover,
+            zip_image_dir,
+            zip_entry_names,
+            source_url,
+            content_addressed,
+            skip_unchanged,
+            extension_from_content,
+            transliterate,
+            missing_title,
+            reverse_pages,
+            srgb,
+            contact_sheet,
+            best_effort,
+            collect_errors,
+            sequential,
+            image_cache,
+            image_cache_size,
+            max_rate,
+            no_solve,
+            list_pages,
+            verify_solved,
+            giga_num_cells,
+            giga_divisible_with,
         } => {
-            let host = url.host_str().context("Url must have host")?;
+            if list_pages {
+                let url = url.context("Episode URL is required")?;
+                for (index, page_url) in list_episode_pages(&url, &defaults).await? {
+                    println!("{}\t{}", index, page_url);
+                }
+                return Ok(());
+            }
 
-            let save_format = get_save_format(save_as);
-            let image_format = get_image_format(format);
+            let output_dir = output_dir.context("Output directory is required")?;
+            let output = Path::new(&output_dir);
+            let default_solver = giga::solver::Solver::default();
+            let options = DownloadOptions {
+                save_as,
+                format,
+                tiff_compression,
+                frame_delay,
+                cover,
+                zip_image_dir,
+                zip_entry_names,
+                source_url,
+                content_addressed,
+                skip_unchanged,
+                extension_from_content,
+                transliterate,
+                missing_title,
+                reverse_pages,
+                defaults,
+                srgb,
+                contact_sheet,
+                best_effort,
+                collect_errors,
+                sequential,
+                // opened once, so the episodes downloaded at once share the cache
+                image_cache: image_cache
+                    .map(|dir| ImageCache::open(dir, image_cache_size * 1024 * 1024))
+                    .transpose()?,
+                rate_limiter: max_rate.map(RateLimiter::new),
+                no_solve,
+                giga_solver: giga::solver::Solver::new(
+                    giga_num_cells.unwrap_or(default_solver.num_cells()),
+                    giga_divisible_with.unwrap_or(default_solver.divisible_with()),
+                ),
+                verify_solved,
+            };
 
-            if let Some(website) = giga::viewer::Website::lookup(host) {
-                let pipe = GigaPipeline::default()
-                    .set_website(website)
-                    .set_progress(progress)
-                    .set_writer_config(WriterConifg::new(save_format, image_format));
+            let Some(input) = input else {
+                let url = url.context("Episode URL is required")?;
+                return download_episode(&url, output, options).await;
+            };
 
-                pipe.download_in(&url, output_dir).await?;
+            if infer_save_format(output) != SaveFormat::Raw {
+                bail!("Output must be a directory when downloading from --input");
+            }
+            let urls = read_url_list(&input)?;
+            // episodes downloaded at once draw their bars under one parent
+            let options = DownloadOptions {
+                defaults: options.defaults.clone().with_progress(
+                    options
+                        .defaults
+                        .progress()
+                        .with_multi_progress(MultiProgress::new()),
+                ),
+                ..options
+            };
+            let results = download_batch(urls, jobs, |url| {
+                let options = options.clone();
+                async move { download_episode(&url, output, options).await }
+            })
+            .await;
 
-                return Ok(());
+            let mut failed = 0;
+            for (url, result) in &results {
+                match result {
+                    Ok(()) => {
+                        if !quiet {
+                            println!("OK     {}", url);
+                        }
+                    }
+                    Err(e) => {
+                        failed += 1;
+                        eprintln!("FAILED {}: {:#}", url, e);
+                    }
+                }
+            }
+            if failed > 0 {
+                bail!("{} of {} episodes failed", failed, results.len());
             }
 
-            #[cfg(feature = "fuz")]
-            if let Some(website) = fuz::viewer::Website::lookup(host) {
-                let pipe = FuzPipeline::default()
-                    .set_website(website)
-                    .set_progress(progress)
-                    .set_writer_config(WriterConifg::new(save_format, image_format));
+            Ok(())
+        }
+        #[cfg(feature = "giga")]
+        Source::Series {
+            url,
+            output_dir,
+            save_as,
+            format,
+            force,
+            layout,
+            volume_size,
+        } => {
+            let output = Path::new(&output_dir);
+            let original = matches!(format, ImageFormat::Original);
+            let writer_config =
+                WriterConifg::new(get_save_format(save_as, output), get_image_format(format))
+                    .with_extension_from_content(original)
+                    .with_keep_original(original);
+            let defaults = defaults.with_writer_config(writer_config);
+            download_series(&url, output, defaults, force, layout, volume_size).await
+        }
+        #[cfg(not(feature = "giga"))]
+        Source::Series { .. } => bail!("Series need the giga feature"),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_infer_save_format() {
+        assert_eq!(
+            infer_save_format(Path::new("out/book.cbz")),
+            SaveFormat::Cbz
+        );
+        assert_eq!(
+            infer_save_format(Path::new("out/book.ZIP")),
+            SaveFormat::Zip
+        );
+        #[cfg(feature = "pdf")]
+        assert_eq!(
+            infer_save_format(Path::new("out/book.pdf")),
+            SaveFormat::Pdf
+        );
+        assert_eq!(
+            infer_save_format(Path::new("out/book.tif")),
+            SaveFormat::Tiff
+        );
+        assert_eq!(infer_save_format(Path::new("out/books")), SaveFormat::Raw);
+        assert_eq!(infer_save_format(Path::new("out/books/")), SaveFormat::Raw);
+    }
 
-                pipe.download_in(&url, output_dir).await?;
+    #[test]
+    fn test_explicit_save_as_overrides() {
+        let format = get_save_format(SaveFormat::Raw, Path::new("out/book.cbz"));
+        assert!(matches!(format, manga::pipeline::SaveFormat::Raw));
 
-                return Ok(());
-            }
+        let format = get_save_format(SaveFormat::Auto, Path::new("out/book.cbz"));
+        assert_eq!(format.extension().as_deref(), Some("cbz"));
+    }
+
+    #[test]
+    fn test_original_image_format() -> Result<()> {
+        let cli = Cli::try_parse_from([
+            "manga",
+            "episode",
+            "-i",
+            "-",
+            "-o",
+            "out",
+            "--save-as",
+            "cbz",
+            "--format",
+            "original",
+        ])?;
+        let Source::Episode { format, .. } = cli.command else {
+            panic!("Not an episode command");
+        };
+        assert!(matches!(format, ImageFormat::Original));
+        // pages that must be encoded are encoded as the sites serve them
+        assert_eq!(get_image_format(format), image::ImageFormat::Jpeg);
 
-            bail!("Website not supported: {}", host);
+        Ok(())
+    }
+
+    #[test]
+    fn test_quiet_and_verbose_flags() -> Result<()> {
+        let cli = Cli::try_parse_from(["manga", "episode", "-i", "-", "-o", "out"])?;
+        assert_eq!(cli.log_level(), LevelFilter::WARN);
+        assert!(cli.progress().is_enabled());
+
+        let cli = Cli::try_parse_from(["manga", "--quiet", "episode", "-i", "-", "-o", "out"])?;
+        assert_eq!(cli.log_level(), LevelFilter::ERROR);
+        assert!(!cli.progress().is_enabled());
+
+        let cli = Cli::try_parse_from(["manga", "episode", "-vv", "-i", "-", "-o", "out"])?;
+        assert_eq!(cli.log_level(), LevelFilter::DEBUG);
+        assert!(cli.progress().is_enabled());
+
+        assert!(
+            Cli::try_parse_from(["manga", "-q", "-v", "episode", "-i", "-", "-o", "out"]).is_err()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_threads_and_connections_flags() -> Result<()> {
+        let cli = Cli::try_parse_from(["manga", "episode", "-i", "-", "-o", "out"])?;
+        let defaults = cli.defaults()?;
+        assert_eq!(defaults.num_threads(), num_cpus::get());
+        assert_eq!(defaults.num_connections(), None);
+
+        let cli = Cli::try_parse_from([
+            "manga",
+            "--threads",
+            "3",
+            "episode",
+            "-i",
+            "-",
+            "-o",
+            "out",
+            "--connections",
+            "2",
+        ])?;
+        let defaults = cli.defaults()?;
+        assert_eq!(defaults.num_threads(), 3);
+        assert_eq!(defaults.num_connections(), Some(2));
+        let pipe = GigaPipeline::from_defaults(giga::viewer::Website::ShonenJumpPlus, &defaults)?;
+        assert_eq!(pipe.num_connections(), 2);
+
+        for flag in ["--threads", "--connections"] {
+            assert!(
+                Cli::try_parse_from(["manga", flag, "0", "episode", "-i", "-", "-o", "out"])
+                    .is_err()
+            );
         }
-    };
 
-    Ok(())
+        Ok(())
+    }
+
+    #[test]
+    fn test_netrc_flag() -> Result<()> {
+        let path = "playground/output/netrc";
+        std::fs::create_dir_all("playground/output")?;
+        std::fs::write(
+            path,
+            "machine shonenjumpplus.com login user password secret\n",
+        )?;
+
+        let cli =
+            Cli::try_parse_from(["manga", "--netrc", path, "episode", "-i", "-", "-o", "out"])?;
+        let defaults = cli.defaults()?;
+        let auth = defaults
+            .netrc()
+            .and_then(|netrc| netrc.auth("www.shonenjumpplus.com"));
+        assert!(auth.is_some());
+
+        let cli = Cli::try_parse_from([
+            "manga",
+            "--netrc",
+            "playground/output/missing-netrc",
+            "episode",
+            "-i",
+            "-",
+            "-o",
+            "out",
+        ])?;
+        assert!(cli.defaults().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_list_pages_needs_no_output() -> Result<()> {
+        let url = "https://shonenjumpplus.com/episode/1";
+        assert!(Cli::try_parse_from(["manga", "episode", url, "--list-pages"]).is_ok());
+        assert!(Cli::try_parse_from(["manga", "episode", url]).is_err());
+        assert!(Cli::try_parse_from(["manga", "episode", "-i", "-", "--list-pages"]).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_byte_size() -> Result<()> {
+        assert_eq!(parse_byte_size("2MiB")?, 2 * 1024 * 1024);
+        assert_eq!(parse_byte_size("500K")?, 500 * 1024);
+        assert_eq!(parse_byte_size("1.5MB/s")?, 1_500_000);
+        assert_eq!(parse_byte_size("4096")?, 4096);
+        assert!(parse_byte_size("0").is_err());
+        assert!(parse_byte_size("2 parsecs").is_err());
+        assert!(parse_byte_size("fast").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_url_list() -> Result<()> {
+        let urls = parse_url_list(
+            "# comics to read\n\
+             https://shonenjumpplus.com/episode/1\n\
+             \n\
+             \thttps://comic-days.com/episode/2  \n",
+        )?;
+        assert_eq!(
+            urls.iter().map(|u| u.as_str()).collect::<Vec<_>>(),
+            [
+                "https://shonenjumpplus.com/episode/1",
+                "https://comic-days.com/episode/2"
+            ]
+        );
+
+        assert!(parse_url_list("not a url").is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_download_batch() -> Result<()> {
+        let urls = parse_url_list(
+            "https://example.com/ok/1\nhttps://example.com/fail/2\nhttps://example.com/ok/3",
+        )?;
+
+        let results = download_batch(urls, 2, |url| async move {
+            if url.path().starts_with("/fail") {
+                bail!("not found");
+            }
+            Ok(())
+        })
+        .await;
+
+        let summary = results
+            .iter()
+            .map(|(url, result)| (url.path(), result.is_ok()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            [("/ok/1", true), ("/fail/2", false), ("/ok/3", true)]
+        );
+
+        Ok(())
+    }
 }
@@ -1,13 +1,40 @@
-use std::{future::Future, path::Path};
+use std::{
+    collections::BTreeSet,
+    fmt::{self, Write},
+    future::Future,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
-use anyhow::Result;
+use anyhow::{anyhow, bail, Context, Result};
+use futures::{future::BoxFuture, stream, FutureExt, Stream, StreamExt};
 use image::DynamicImage;
+use serde::{Deserialize, Serialize};
 use url::Url;
 
+#[cfg(feature = "pdf")]
+use crate::io::pdf::PdfWriter;
+#[cfg(feature = "sevenz")]
+use crate::io::sevenz::SevenZipWriter;
 use crate::{
+    auth::Netrc,
+    cache::ImageCache,
     data::{MangaEpisode, MangaPage},
+    io::{
+        animation::GifWriter,
+        contact_sheet::ContactSheetWriter,
+        raw::RawWriter,
+        tiff::TiffWriter,
+        zip::{EntryNaming, ZipWriter},
+        StreamedPage, TiffCompression, Writer,
+    },
+    limit::{BytePermit, RateLimiter},
     progress::ProgressConfig,
-    utils::Bytes,
+    solver::SolvedImage,
+    transform::Transforms,
+    utils::{self, Bytes},
+    viewer::parse_proxy,
 };
 
 /// How to save the manga
@@ -18,8 +45,63 @@ pub enum SaveFormat {
         compression_method: zip::CompressionMethod,
         extension: Option<String>,
     },
+    /// Animated GIF with every page as a frame shown for `frame_delay`
+    Gif {
+        frame_delay: Duration,
+    },
+    /// A single multi-page TIFF, compressed with the TIFF compression of the writer config
+    Tiff,
     #[cfg(feature = "pdf")]
     Pdf,
+    /// 7z archive. `preset` is the LZMA2 preset level, fast (1) when `None`.
+    #[cfg(feature = "sevenz")]
+    SevenZip {
+        preset: Option<u32>,
+    },
+}
+
+impl SaveFormat {
+    /// File extension of the output, `None` for a directory
+    pub fn extension(&self) -> Option<String> {
+        match self {
+            SaveFormat::Raw => None,
+            SaveFormat::Zip { extension, .. } => {
+                Some(extension.clone().unwrap_or("zip".to_string()))
+            }
+            SaveFormat::Gif { .. } => Some("gif".to_string()),
+            SaveFormat::Tiff => Some("tiff".to_string()),
+            #[cfg(feature = "pdf")]
+            SaveFormat::Pdf => Some("pdf".to_string()),
+            #[cfg(feature = "sevenz")]
+            SaveFormat::SevenZip { .. } => Some("7z".to_string()),
+        }
+    }
+
+    /// Whether the pages are stored as image files, so they can be written as they are.
+    /// The other formats decode the pages, or embed them in the image format only.
+    pub fn stores_image_files(&self) -> bool {
+        match self {
+            SaveFormat::Raw | SaveFormat::Zip { .. } => true,
+            #[cfg(feature = "sevenz")]
+            SaveFormat::SevenZip { .. } => true,
+            _ => false,
+        }
+    }
+}
+
+/// Longest file name most file systems allow, in bytes
+pub const DEFAULT_MAX_FILE_NAME_BYTES: usize = 255;
+
+/// How to name the output of an episode without a title
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MissingTitle {
+    /// Fail the download
+    Fail,
+    /// Name it by the episode id
+    #[default]
+    Id,
+    /// Name it by the episode index
+    Index,
 }
 
 /// Configuration for the writer
@@ -27,6 +109,21 @@ pub enum SaveFormat {
 pub struct WriterConifg {
     save_format: SaveFormat,
     image_format: image::ImageFormat,
+    temp_file: bool,
+    tiff_compression: TiffCompression,
+    cover: bool,
+    zip_image_dir: Option<String>,
+    zip_entry_naming: EntryNaming,
+    content_addressed: bool,
+    skip_unchanged: bool,
+    extension_from_content: bool,
+    /// Write the solved pages as they are instead of in the image format
+    keep_original: bool,
+    transliterate: bool,
+    max_file_name_bytes: usize,
+    source_url: bool,
+    missing_title: MissingTitle,
+    reverse_pages: bool,
 }
 
 impl WriterConifg {
@@ -34,6 +131,171 @@ impl WriterConifg {
         WriterConifg {
             save_format,
             image_format,
+            temp_file: true,
+            tiff_compression: TiffCompression::None,
+            cover: false,
+            zip_image_dir: None,
+            zip_entry_naming: EntryNaming::default(),
+            content_addressed: false,
+            skip_unchanged: false,
+            extension_from_content: false,
+            keep_original: false,
+            transliterate: false,
+            max_file_name_bytes: DEFAULT_MAX_FILE_NAME_BYTES,
+            source_url: false,
+            missing_title: MissingTitle::default(),
+            reverse_pages: false,
+        }
+    }
+
+    /// Write the output under a `.part` name and rename it into place once complete.
+    /// On by default, so an interrupted run never leaves a truncated output.
+    pub fn with_temp_file(self, temp_file: bool) -> Self {
+        WriterConifg { temp_file, ..self }
+    }
+
+    /// Compression of the pages when saving as TIFF
+    pub fn with_tiff_compression(self, tiff_compression: TiffCompression) -> Self {
+        WriterConifg {
+            tiff_compression,
+            ..self
+        }
+    }
+
+    /// Also store the first page as `cover.<ext>` in zip archives
+    pub fn with_cover(self, cover: bool) -> Self {
+        WriterConifg { cover, ..self }
+    }
+
+    /// Store the pages under this directory inside zip archives instead of the root
+    pub fn with_zip_image_dir(self, zip_image_dir: Option<String>) -> Self {
+        WriterConifg {
+            zip_image_dir,
+            ..self
+        }
+    }
+
+    /// Name the page entries of zip archives with this strategy
+    pub fn with_zip_entry_naming(self, zip_entry_naming: EntryNaming) -> Self {
+        WriterConifg {
+            zip_entry_naming,
+            ..self
+        }
+    }
+
+    /// Name raw pages by the SHA-256 of their bytes, with an `index.json` of the page order
+    pub fn with_content_addressed(self, content_addressed: bool) -> Self {
+        WriterConifg {
+            content_addressed,
+            ..self
+        }
+    }
+
+    /// Leave the raw pages that did not change since the last download untouched
+    pub fn with_skip_unchanged(self, skip_unchanged: bool) -> Self {
+        WriterConifg {
+            skip_unchanged,
+            ..self
+        }
+    }
+
+    /// Name raw pages and zip entries by the format detected from their bytes instead of
+    /// the image format
+    pub fn with_extension_from_content(self, extension_from_content: bool) -> Self {
+        WriterConifg {
+            extension_from_content,
+            ..self
+        }
+    }
+
+    /// Write the solved pages as they are where the viewer doesn't have to encode them
+    /// again, whatever the image format. The image format is still used for the pages
+    /// that are encoded, and for the save formats that don't store image files.
+    pub fn with_keep_original(self, keep_original: bool) -> Self {
+        WriterConifg {
+            keep_original,
+            ..self
+        }
+    }
+
+    /// Spell the episode titles in the output names in ASCII
+    pub fn with_transliterate(self, transliterate: bool) -> Self {
+        WriterConifg {
+            transliterate,
+            ..self
+        }
+    }
+
+    /// Longest name of an output file or directory in bytes, 255 by default.
+    /// Long titles are cut to fit along with the extension and the `.part` suffix.
+    pub fn with_max_file_name_bytes(self, max_file_name_bytes: usize) -> Self {
+        WriterConifg {
+            max_file_name_bytes,
+            ..self
+        }
+    }
+
+    /// Record the episode url in the zip comment or the PDF subject
+    pub fn with_source_url(self, source_url: bool) -> Self {
+        WriterConifg { source_url, ..self }
+    }
+
+    /// Name episodes without a title by their id (the default), their index,
+    /// or fail them
+    pub fn with_missing_title(self, missing_title: MissingTitle) -> Self {
+        WriterConifg {
+            missing_title,
+            ..self
+        }
+    }
+
+    /// Write the pages in reverse order, whatever the reading direction of the episode,
+    /// for readers expecting the other direction
+    pub fn with_reverse_pages(self, reverse_pages: bool) -> Self {
+        WriterConifg {
+            reverse_pages,
+            ..self
+        }
+    }
+
+    /// The pages, or their page indices, in the order they are written
+    pub fn page_order<T>(&self, mut pages: Vec<T>) -> Vec<T> {
+        if self.reverse_pages {
+            pages.reverse();
+        }
+        pages
+    }
+
+    /// Name of the output of an episode with this title, without the extension
+    pub fn file_name(&self, title: &str) -> String {
+        let extension = self
+            .save_format
+            .extension()
+            .map_or(0, |extension| extension.len() + 1);
+        let reserved = extension + ".part".len();
+        let max_bytes = self.max_file_name_bytes.saturating_sub(reserved);
+        utils::sanitize_file_name(title, self.transliterate, max_bytes)
+    }
+
+    /// Name of the output of the episode without the extension. A missing or blank
+    /// title is replaced as configured with [`WriterConifg::with_missing_title`].
+    pub fn episode_name<P: MangaPage, E: MangaEpisode<P>>(&self, episode: &E) -> Result<String> {
+        let title = episode.title().filter(|title| !title.trim().is_empty());
+        let title = match (title, self.missing_title) {
+            (Some(title), _) => title,
+            (None, MissingTitle::Fail) => bail!("Episode title not found"),
+            (None, MissingTitle::Id) => episode.id(),
+            (None, MissingTitle::Index) => episode.index().to_string(),
+        };
+        Ok(self.file_name(&title))
+    }
+
+    /// `name` with the extension of the save format appended. A dot in the name,
+    /// e.g. in `Vol.2`, is not taken as the start of an extension.
+    pub fn with_extension(&self, name: &str) -> String {
+        match self.save_format.extension() {
+            Some(extension) => format!("{}.{}", name, extension),
+            None => name.to_string(),
         }
     }
 
@@ -42,7 +304,272 @@ impl WriterConifg {
     }
 
     pub fn image_format(&self) -> image::ImageFormat {
-        self.image_format.clone()
+        self.image_format
+    }
+
+    /// Whether the solved pages may be written as they are
+    pub fn keep_original(&self) -> bool {
+        self.keep_original && self.save_format.stores_image_files()
+    }
+
+    /// Create the writer for the save format, recording the url of the episode
+    /// when the source url is on
+    pub fn episode_writer<P: MangaPage, E: MangaEpisode<P>>(
+        &self,
+        episode: &E,
+        progress: ProgressConfig,
+        num_threads: usize,
+    ) -> Writer {
+        let source_url = self.source_url.then(|| episode.url()).flatten();
+        self.writer(progress, num_threads)
+            .with_source_url(source_url)
+    }
+
+    /// Create the writer for the save format
+    pub fn writer(&self, progress: ProgressConfig, num_threads: usize) -> Writer {
+        match self.save_format() {
+            SaveFormat::Raw => Writer::Raw(
+                RawWriter::new(progress, self.image_format, num_threads)
+                    .with_temp_file(self.temp_file)
+                    .with_tiff_compression(self.tiff_compression)
+                    .with_content_addressed(self.content_addressed)
+                    .with_skip_unchanged(self.skip_unchanged)
+                    .with_extension_from_content(self.extension_from_content),
+            ),
+            SaveFormat::Zip {
+                compression_method,
+                extension,
+            } => Writer::Zip(
+                ZipWriter::new(
+                    compression_method,
+                    self.image_format,
+                    extension,
+                    num_threads,
+                    progress,
+                )
+                .with_temp_file(self.temp_file)
+                .with_tiff_compression(self.tiff_compression)
+                .with_cover(self.cover)
+                .with_image_dir(self.zip_image_dir.clone())
+                .with_entry_naming(self.zip_entry_naming.clone())
+                .with_extension_from_content(self.extension_from_content),
+            ),
+            SaveFormat::Gif { frame_delay } => {
+                Writer::Gif(GifWriter::new(frame_delay, progress).with_temp_file(self.temp_file))
+            }
+            SaveFormat::Tiff => Writer::Tiff(
+                TiffWriter::new(self.tiff_compression, progress).with_temp_file(self.temp_file),
+            ),
+            #[cfg(feature = "pdf")]
+            SaveFormat::Pdf => Writer::Pdf(
+                PdfWriter::new(progress, self.image_format).with_temp_file(self.temp_file),
+            ),
+            #[cfg(feature = "sevenz")]
+            SaveFormat::SevenZip { preset } => Writer::SevenZip(
+                SevenZipWriter::new(self.image_format, preset, num_threads, progress)
+                    .with_temp_file(self.temp_file)
+                    .with_tiff_compression(self.tiff_compression),
+            ),
+        }
+    }
+}
+
+/// Settings shared by many downloads, set once and used to build every pipeline.
+///
+/// ```no_run
+/// # fn run() -> anyhow::Result<()> {
+/// use manga::viewer::giga;
+///
+/// let defaults = manga::Defaults::default()
+///     .with_num_connections(4)
+///     .with_user_agent("my-app/1.0")
+///     .with_proxy("socks5://127.0.0.1:1080")?;
+/// let jump = giga::pipeline::Pipeline::from_defaults(giga::viewer::Website::ShonenJumpPlus, &defaults)?;
+/// let days = giga::pipeline::Pipeline::from_defaults(giga::viewer::Website::ComicDays, &defaults)?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Defaults {
+    progress: ProgressConfig,
+    writer_config: WriterConifg,
+    num_threads: usize,
+    /// `None` uses the site preset
+    num_connections: Option<usize>,
+    user_agent: Option<String>,
+    proxy: Option<Url>,
+    /// Credentials looked up by the host of each site
+    netrc: Option<Netrc>,
+}
+
+impl Default for Defaults {
+    fn default() -> Self {
+        Defaults {
+            progress: ProgressConfig::default(),
+            writer_config: WriterConifg::new(SaveFormat::Raw, image::ImageFormat::Png),
+            num_threads: num_cpus::get(),
+            num_connections: None,
+            user_agent: None,
+            proxy: None,
+            netrc: None,
+        }
+    }
+}
+
+impl Defaults {
+    pub fn with_progress(self, progress: ProgressConfig) -> Self {
+        Defaults { progress, ..self }
+    }
+
+    pub fn with_writer_config(self, writer_config: WriterConifg) -> Self {
+        Defaults {
+            writer_config,
+            ..self
+        }
+    }
+
+    pub fn with_num_threads(self, num_threads: usize) -> Self {
+        Defaults {
+            num_threads,
+            ..self
+        }
+    }
+
+    /// Concurrent requests per episode instead of the site preset
+    pub fn with_num_connections(self, num_connections: usize) -> Self {
+        Defaults {
+            num_connections: Some(num_connections),
+            ..self
+        }
+    }
+
+    /// Send requests with this `User-Agent` instead of the default one
+    pub fn with_user_agent(self, user_agent: &str) -> Self {
+        Defaults {
+            user_agent: Some(user_agent.to_string()),
+            ..self
+        }
+    }
+
+    /// Send every request through the proxy
+    pub fn with_proxy(self, proxy: &str) -> Result<Self> {
+        Ok(Defaults {
+            proxy: Some(parse_proxy(proxy)?),
+            ..self
+        })
+    }
+
+    /// Authenticate each site with its credentials in the netrc
+    pub fn with_netrc(self, netrc: Netrc) -> Self {
+        Defaults {
+            netrc: Some(netrc),
+            ..self
+        }
+    }
+
+    pub fn progress(&self) -> ProgressConfig {
+        self.progress.clone()
+    }
+
+    pub fn writer_config(&self) -> WriterConifg {
+        self.writer_config.clone()
+    }
+
+    pub fn num_threads(&self) -> usize {
+        self.num_threads
+    }
+
+    pub fn num_connections(&self) -> Option<usize> {
+        self.num_connections
+    }
+
+    pub fn user_agent(&self) -> Option<&str> {
+        self.user_agent.as_deref()
+    }
+
+    pub fn proxy(&self) -> Option<&Url> {
+        self.proxy.as_ref()
+    }
+
+    pub fn netrc(&self) -> Option<&Netrc> {
+        self.netrc.as_ref()
+    }
+}
+
+/// The episode has no image page to download, e.g. a paywalled or web view only chapter
+#[derive(Debug, Clone, PartialEq)]
+pub struct NoImagePages {
+    pub id: String,
+}
+
+impl NoImagePages {
+    /// Fail with `NoImagePages` unless the episode has an image page
+    pub fn ensure<P: MangaPage, E: MangaEpisode<P>>(episode: &E) -> Result<(), NoImagePages> {
+        match episode.pages().iter().any(|page| page.is_image()) {
+            true => Ok(()),
+            false => Err(NoImagePages { id: episode.id() }),
+        }
+    }
+}
+
+impl fmt::Display for NoImagePages {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Episode {} has no image pages", self.id)
+    }
+}
+
+impl std::error::Error for NoImagePages {}
+
+/// Metadata of the episode passed to the output hook
+#[derive(Debug, Clone, PartialEq)]
+pub struct EpisodeInfo {
+    pub id: String,
+    pub index: usize,
+    pub title: Option<String>,
+}
+
+impl EpisodeInfo {
+    pub fn new<P: MangaPage, E: MangaEpisode<P>>(episode: &E) -> Self {
+        EpisodeInfo {
+            id: episode.id(),
+            index: episode.index(),
+            title: episode.title(),
+        }
+    }
+}
+
+type OutputHookFn = dyn Fn(PathBuf, &EpisodeInfo) -> Result<PathBuf> + Send + Sync;
+
+/// Called with the output path of an episode before it is written.
+///
+/// The returned path is written instead, and the hook may add its own files next to it.
+#[derive(Clone)]
+pub struct OutputHook(Arc<OutputHookFn>);
+
+impl OutputHook {
+    pub fn new<F>(hook: F) -> Self
+    where
+        F: Fn(PathBuf, &EpisodeInfo) -> Result<PathBuf> + Send + Sync + 'static,
+    {
+        OutputHook(Arc::new(hook))
+    }
+
+    /// Output path of the episode, from the hook if there is one
+    pub fn output_path<P: MangaPage, E: MangaEpisode<P>>(
+        hook: Option<&OutputHook>,
+        episode: &E,
+        path: &Path,
+    ) -> Result<PathBuf> {
+        match hook {
+            Some(OutputHook(hook)) => hook(path.to_path_buf(), &EpisodeInfo::new(episode)),
+            None => Ok(path.to_path_buf()),
+        }
+    }
+}
+
+impl fmt::Debug for OutputHook {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("OutputHook")
     }
 }
 
@@ -55,15 +582,462 @@ pub trait EpisodePipelineBuilder<W, A: MangaPage, B: MangaEpisode<A>, P: Episode
     fn set_writer_config(self, writer_config: WriterConifg) -> Self;
     fn set_num_threads(self, num_threads: usize) -> Self;
     fn set_num_connections(self, num_connections: usize) -> Self;
+    /// Limit the total bytes of fetched pages not yet written. The pages are then handed
+    /// to the writer in order as they are solved, and the raw and zip writers release each
+    /// page once it is written. The other formats and the contact sheet need every page
+    /// first, so there the limit only covers the pages until they are solved.
+    fn set_max_in_flight_bytes(self, max_in_flight_bytes: Option<usize>) -> Self;
+    /// Limit the concurrent requests to a single host, below the number of connections
+    fn set_max_connections_per_host(self, max_connections_per_host: Option<usize>) -> Self;
+    /// Fetch a page again up to this many times when solving it fails
+    fn set_max_solve_retries(self, max_solve_retries: usize) -> Self;
+    /// Convert pages embedding an ICC profile to sRGB
+    fn set_convert_to_srgb(self, convert_to_srgb: bool) -> Self;
+    /// Also write a contact sheet of all pages next to the episode
+    fn set_contact_sheet(self, contact_sheet: Option<ContactSheetWriter>) -> Self;
+    /// Write the pages that succeeded and report the failed ones instead of failing the episode
+    fn set_best_effort(self, best_effort: bool) -> Self;
+    /// Stop at the first failed page or attempt all of them before failing.
+    /// Best effort downloads attempt every page either way.
+    fn set_error_policy(self, error_policy: ErrorPolicy) -> Self;
+    /// Run these transforms in order on every solved page before writing it
+    fn set_transforms(self, transforms: Transforms) -> Self;
+    /// Fetch and solve one page at a time in page order
+    fn set_sequential(self, sequential: bool) -> Self;
+    /// Write the fetched pages as they are, skipping the solve step and the transforms.
+    /// For debugging the solvers: the output is not viewable, the pages are still
+    /// scrambled or encrypted.
+    fn set_no_solve(self, no_solve: bool) -> Self;
+    /// Retries allowed for all pages of an episode together, `None` for no limit
+    fn set_retry_budget(self, retry_budget: Option<usize>) -> Self;
+    /// Adjust the output path of each episode before it is written
+    fn set_output_hook(self, output_hook: Option<OutputHook>) -> Self;
+    /// Keep the fetched images in this cache and read them from it on later downloads
+    fn set_image_cache(self, image_cache: Option<ImageCache>) -> Self;
+    /// Cap the bytes of images read per second, shared with every clone of the limiter
+    fn set_rate_limiter(self, rate_limiter: Option<RateLimiter>) -> Self;
+}
+
+/// What to do when a page of an episode fails
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ErrorPolicy {
+    /// Stop at the first failed page
+    #[default]
+    FailFast,
+    /// Attempt every page, then fail with all the failed pages together
+    Collect,
+}
+
+/// What a viewer does to the fetched images before they can be read
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SolveKind {
+    /// The images are served as they are
+    None,
+    /// The image bytes are decrypted, the decoded pixels are untouched
+    Bytes,
+    /// The pixels are descrambled, so the image is decoded and encoded again
+    Pixel,
+}
+
+impl SolveKind {
+    /// Whether the solved bytes can be written as they are, without encoding the image again
+    pub fn is_lossless_passthrough(&self) -> bool {
+        !matches!(self, SolveKind::Pixel)
+    }
+}
+
+/// A page that could not be downloaded
+#[derive(Debug, Clone, PartialEq)]
+pub struct PageFailure {
+    pub index: usize,
+    pub error: String,
+}
+
+/// Pages that failed in a best-effort download
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DownloadReport {
+    pub total_pages: usize,
+    pub failures: Vec<PageFailure>,
+    /// Retries spent from the episode budget
+    pub retries: usize,
+}
+
+impl DownloadReport {
+    pub fn is_complete(&self) -> bool {
+        self.failures.is_empty()
+    }
+
+    /// Path of the report written for the episode at `path`, named after the whole
+    /// file name so that a dot in the title, e.g. in `Vol.2`, is kept
+    pub fn report_path<P: AsRef<Path>>(path: P) -> Result<PathBuf> {
+        let path = path.as_ref();
+        let name = path.file_name().context("Output path has no file name")?;
+        Ok(path.with_file_name(format!("{}.failed.txt", name.to_string_lossy())))
+    }
+
+    /// The failed pages, one per line after a summary
+    pub fn summary(&self) -> Result<String> {
+        let mut report = format!(
+            "{} of {} pages failed\n",
+            self.failures.len(),
+            self.total_pages
+        );
+        if self.retries > 0 {
+            writeln!(report, "{} retries spent", self.retries)?;
+        }
+        for failure in &self.failures {
+            writeln!(report, "page {}: {}", failure.index, failure.error)?;
+        }
+        Ok(report)
+    }
+
+    /// Index in the episode of each of the `written` pages when failed pages were left
+    /// out, `None` when nothing is missing or the written pages aren't one per page
+    pub fn page_indices(&self, written: usize) -> Option<Vec<usize>> {
+        if self.is_complete() {
+            return None;
+        }
+        let indices = (0..self.total_pages)
+            .filter(|index| !self.failures.iter().any(|failure| failure.index == *index))
+            .collect::<Vec<_>>();
+        (indices.len() == written).then_some(indices)
+    }
+
+    /// Write the failed pages next to the episode
+    pub async fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let report = self.summary()?;
+        let path = Self::report_path(path)?;
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        tokio::fs::write(path, report).await?;
+        Ok(())
+    }
+}
+
+/// Pages sampled to estimate the size of an episode
+pub const SIZE_SAMPLE_PAGES: usize = 5;
+
+/// Rough size of an episode, from the `Content-Length` of a few of its pages
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct SizeEstimate {
+    pub pages: usize,
+    /// Pages whose size was known
+    pub sampled: usize,
+    /// `None` when no sampled page told its size
+    pub estimated_bytes: Option<u64>,
+}
+
+impl SizeEstimate {
+    fn from_samples(pages: usize, sizes: &[u64]) -> Self {
+        let estimated_bytes = match sizes.len() {
+            0 => None,
+            n => Some(sizes.iter().sum::<u64>() * pages as u64 / n as u64),
+        };
+        Self {
+            pages,
+            sampled: sizes.len(),
+            estimated_bytes,
+        }
+    }
+
+    pub fn estimated_mb(&self) -> Option<f64> {
+        self.estimated_bytes
+            .map(|bytes| bytes as f64 / (1024.0 * 1024.0))
+    }
+}
+
+/// How the episodes of a series are laid out in the output directory
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SeriesLayout {
+    /// Every episode in the directory, named by its title
+    #[default]
+    Flat,
+    /// `<series>/Chapter 001.cbz`, the layout Komga and Mihon read as a series
+    Komga,
+}
+
+impl SeriesLayout {
+    /// Digits the chapter numbers are zero padded to
+    pub const CHAPTER_DIGITS: usize = 3;
+    /// Digits the volume numbers are zero padded to
+    pub const VOLUME_DIGITS: usize = 2;
+
+    /// Path of the episode of the series, with the extension of the save format
+    pub fn episode_path<P: MangaPage, E: MangaEpisode<P>>(
+        &self,
+        dir: &Path,
+        series_title: &str,
+        episode: &E,
+        writer_config: &WriterConifg,
+    ) -> Result<PathBuf> {
+        let (dir, name) = match self {
+            SeriesLayout::Flat => (dir.to_path_buf(), writer_config.episode_name(episode)?),
+            SeriesLayout::Komga => (
+                dir.join(writer_config.file_name(series_title)),
+                format!(
+                    "Chapter {:0width$}",
+                    episode.index(),
+                    width = Self::CHAPTER_DIGITS
+                ),
+            ),
+        };
+        Ok(dir.join(writer_config.with_extension(&name)))
+    }
+
+    /// Path of the volume `number` of the series, counting from 1, with the extension
+    /// of the save format
+    pub fn volume_path(
+        &self,
+        dir: &Path,
+        series_title: &str,
+        number: usize,
+        writer_config: &WriterConifg,
+    ) -> PathBuf {
+        let series_title = writer_config.file_name(series_title);
+        let (dir, name) = match self {
+            SeriesLayout::Flat => (
+                dir.to_path_buf(),
+                format!(
+                    "{} Vol. {:0width$}",
+                    series_title,
+                    number,
+                    width = Self::VOLUME_DIGITS
+                ),
+            ),
+            SeriesLayout::Komga => (
+                dir.join(&series_title),
+                format!(
+                    "{} v{:0width$}",
+                    series_title,
+                    number,
+                    width = Self::VOLUME_DIGITS
+                ),
+            ),
+        };
+        dir.join(writer_config.with_extension(&name))
+    }
+}
+
+/// Episodes of a series already downloaded into a directory, so a re-run only
+/// fetches the new ones
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SeriesState {
+    completed: BTreeSet<String>,
+    /// Episode ids of every volume in reading order, the first volume first
+    #[serde(default)]
+    volumes: Vec<Vec<String>>,
+}
+
+impl SeriesState {
+    /// Name of the state file in the series directory
+    pub const FILE_NAME: &'static str = "series-state.json";
+
+    /// Path of the state file of the series downloaded into `dir`
+    pub fn state_path<P: AsRef<Path>>(dir: P) -> PathBuf {
+        dir.as_ref().join(Self::FILE_NAME)
+    }
+
+    /// Read the state in `dir`, empty when no episode was downloaded yet
+    pub async fn load<P: AsRef<Path>>(dir: P) -> Result<Self> {
+        let path = Self::state_path(dir);
+        match tokio::fs::read(&path).await {
+            Ok(bytes) => Ok(serde_json::from_slice(&bytes)
+                .with_context(|| format!("Invalid series state {}", path.display()))?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn is_completed(&self, episode_id: &str) -> bool {
+        self.completed.contains(episode_id)
+    }
+
+    pub fn complete(&mut self, episode_id: &str) {
+        self.completed.insert(episode_id.to_string());
+    }
+
+    /// Number of the volume the episode is packaged into, counting from 1.
+    ///
+    /// A new episode goes into the last volume until it has `volume_size` episodes, so the
+    /// volumes keep their numbers when the feed no longer lists the first episodes.
+    pub fn assign_volume(&mut self, episode_id: &str, volume_size: usize) -> usize {
+        if let Some(i) = self
+            .volumes
+            .iter()
+            .position(|volume| volume.iter().any(|id| id == episode_id))
+        {
+            return i + 1;
+        }
+        match self.volumes.last_mut() {
+            Some(volume) if volume.len() < volume_size => volume.push(episode_id.to_string()),
+            _ => self.volumes.push(vec![episode_id.to_string()]),
+        }
+        self.volumes.len()
+    }
+
+    /// Episode ids of the volume `number`, counting from 1
+    pub fn volume(&self, number: usize) -> &[String] {
+        number
+            .checked_sub(1)
+            .and_then(|i| self.volumes.get(i))
+            .map_or(&[], Vec::as_slice)
+    }
+
+    pub async fn save<P: AsRef<Path>>(&self, dir: P) -> Result<()> {
+        tokio::fs::create_dir_all(dir.as_ref()).await?;
+        tokio::fs::write(Self::state_path(dir), serde_json::to_vec_pretty(self)?).await?;
+        Ok(())
+    }
+}
+
+/// Collect the solved pages in order. The first failure aborts the episode
+/// unless `best_effort` is set, in which case it is kept in the report.
+/// With [`ErrorPolicy::Collect`] every page is attempted and the failures are returned together.
+pub async fn collect_pages<T, S>(
+    results: S,
+    total_pages: usize,
+    best_effort: bool,
+    error_policy: ErrorPolicy,
+) -> Result<(Vec<T>, DownloadReport)>
+where
+    S: Stream<Item = (usize, Result<T>)>,
+{
+    let mut results = std::pin::pin!(results);
+    let mut pages = Vec::with_capacity(total_pages);
+    let mut report = DownloadReport {
+        total_pages,
+        failures: Vec::new(),
+        retries: 0,
+    };
+
+    while let Some((index, result)) = results.next().await {
+        match result {
+            Ok(page) => pages.push((index, page)),
+            Err(e) if best_effort || error_policy == ErrorPolicy::Collect => {
+                tracing::warn!("Failed to download page {}: {:#}", index, e);
+                report.failures.push(PageFailure {
+                    index,
+                    error: format!("{:#}", e),
+                });
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    report.failures.sort_by_key(|failure| failure.index);
+    if !best_effort && !report.is_complete() {
+        bail!(report.summary()?);
+    }
+    if pages.is_empty() && !report.is_complete() {
+        bail!("All {} pages failed", total_pages);
+    }
+
+    pages.sort_by_key(|(index, _)| *index);
+    let pages = pages.into_iter().map(|(_, page)| page).collect();
+
+    Ok((pages, report))
+}
+
+/// A solved page with its index and the reservation of its fetched bytes
+pub type HeldPage<T> = (usize, Result<(T, Option<BytePermit>)>);
+
+/// Hand the solved pages to the writer as they arrive, given in the order they are written
+/// with the reservation of their bytes.
+///
+/// Each image is numbered by its position past the pages that failed before it, which
+/// are handled as in [`collect_pages`] and kept in `report`. A failure the episode can't
+/// go on with ends the stream with the error, failing the write.
+pub fn stream_pages<'a, T, S>(
+    results: S,
+    best_effort: bool,
+    error_policy: ErrorPolicy,
+    report: &'a Mutex<DownloadReport>,
+) -> impl Stream<Item = Result<StreamedPage<T>>> + 'a
+where
+    T: 'a,
+    S: Stream<Item = HeldPage<Vec<T>>> + 'a,
+{
+    // (results, images written, pages failed, whether the stream has ended)
+    let state = (Box::pin(results), 0, 0, false);
+    stream::unfold(
+        state,
+        move |(mut results, mut written, mut failed, ended)| async move {
+            if ended {
+                return None;
+            }
+            let end = loop {
+                match results.next().await {
+                    Some((_, Ok((images, permit)))) => {
+                        // the pages a page is split into hold its bytes until all are written
+                        let permit = permit.map(Arc::new);
+                        let pages = images
+                            .into_iter()
+                            .map(|image| {
+                                let page = StreamedPage::new(written + failed, image)
+                                    .with_permit(permit.clone());
+                                written += 1;
+                                Ok(page)
+                            })
+                            .collect::<Vec<_>>();
+                        return Some((stream::iter(pages), (results, written, failed, false)));
+                    }
+                    Some((index, Err(e)))
+                        if best_effort || error_policy == ErrorPolicy::Collect =>
+                    {
+                        tracing::warn!("Failed to download page {}: {:#}", index, e);
+                        let failure = PageFailure {
+                            index,
+                            error: format!("{:#}", e),
+                        };
+                        match report.lock() {
+                            Ok(mut report) => report.failures.push(failure),
+                            Err(_) => break Some(anyhow!("Download report lock poisoned")),
+                        }
+                        failed += 1;
+                    }
+                    Some((_, Err(e))) => break Some(e),
+                    None => break finish_report(report, best_effort, written).err(),
+                }
+            };
+            end.map(|e| (stream::iter(vec![Err(e)]), (results, written, failed, true)))
+        },
+    )
+    .flatten()
+}
+
+/// Sort the failures of the streamed pages, failing as [`collect_pages`] does
+fn finish_report(report: &Mutex<DownloadReport>, best_effort: bool, written: usize) -> Result<()> {
+    let mut report = report
+        .lock()
+        .map_err(|_| anyhow!("Download report lock poisoned"))?;
+    report.failures.sort_by_key(|failure| failure.index);
+    if !best_effort && !report.is_complete() {
+        bail!(report.summary()?);
+    }
+    if written == 0 && !report.is_complete() {
+        bail!("All {} pages failed", report.total_pages);
+    }
+    Ok(())
 }
 
 /// Pipeline to download manga
 pub trait EpisodePipeline<P: MangaPage, E: MangaEpisode<P>> {
     fn parse_episode_id(&self, url: &Url) -> Result<String>;
 
+    /// What solving does to the images of this viewer. By default the pixels are
+    /// assumed to change, so the solved images are always encoded again.
+    fn solve_kind(&self) -> SolveKind {
+        SolveKind::Pixel
+    }
+
     /// Fetch the Episode
     fn fetch_episode(&self, episode_id: &str) -> impl Future<Output = Result<E>> + Send;
 
+    /// Url the image of the page is fetched from, the absolute url of the page by default
+    fn page_url(&self, page: &P) -> Result<Url> {
+        page.url()
+    }
+
     /// Fetch an image
     fn fetch_image(&self, page: &P) -> impl Future<Output = Result<Bytes>> + Send;
 
@@ -81,6 +1055,17 @@ pub trait EpisodePipeline<P: MangaPage, E: MangaEpisode<P>> {
         page: Option<P>,
     ) -> impl Future<Output = Result<DynamicImage>> + Send;
 
+    /// Solve the obfuscation and keep the image dimensions if known, by default read from
+    /// the header of the solved image
+    fn solve_image_with_dimensions(
+        &self,
+        image: Bytes,
+        page: Option<P>,
+    ) -> impl Future<Output = Result<SolvedImage>> + Send {
+        self.solve_image_bytes(image, page)
+            .map(|solved| Ok(SolvedImage::from_bytes(solved?)))
+    }
+
     fn write_image_bytes<T: AsRef<Path>>(
         &self,
         images: Vec<Bytes>,
@@ -93,9 +1078,173 @@ pub trait EpisodePipeline<P: MangaPage, E: MangaEpisode<P>> {
         path: T,
     ) -> impl Future<Output = Result<()>>;
 
+    /// Write the solved images, by default as bytes without their dimensions
+    fn write_solved_images<T: AsRef<Path>>(
+        &self,
+        images: Vec<SolvedImage>,
+        path: T,
+    ) -> impl Future<Output = Result<()>> {
+        let images = images.into_iter().map(SolvedImage::into_bytes).collect();
+        self.write_image_bytes(images, path)
+    }
+
+    /// Fetch and solve the pages of the episode concurrently, yielding `(index, image)` as
+    /// each page is done. Pages complete out of order, so sort by the index when it matters.
+    /// The transforms run on every page, and the pages split from one share its index.
+    ///
+    /// By default the image pages are fetched and solved one at a time in page order,
+    /// with no transforms.
+    fn page_stream<'a>(
+        &'a self,
+        episode: &E,
+    ) -> Result<impl Stream<Item = Result<(usize, DynamicImage)>> + use<'a, Self, P, E>> {
+        let pages = episode
+            .pages()
+            .into_iter()
+            .filter(|page| page.is_image())
+            .collect::<Vec<_>>();
+        Ok(stream::iter(pages).then(move |page| async move {
+            let index = page.index()?;
+            let bytes = self.fetch_image(&page).await?;
+            Ok((index, self.solve_image(bytes, Some(page)).await?))
+        }))
+    }
+
+    /// `(index, url)` of every image page of the episode, without downloading the images
+    fn list_pages(&self, url: &Url) -> impl Future<Output = Result<Vec<(usize, Url)>>> {
+        async move {
+            let episode_id = self.parse_episode_id(url)?;
+            let episode = self.fetch_episode(&episode_id).await?;
+            episode
+                .pages()
+                .iter()
+                .filter(|page| page.is_image())
+                .map(|page| Ok((page.index()?, self.page_url(page)?)))
+                .collect()
+        }
+    }
+
+    /// Fetch and solve only the image page at `index`, e.g. to fix a broken page, and
+    /// save it as a single image in the format of the extension of `path`
+    fn download_page<T: AsRef<Path>>(
+        &self,
+        url: &Url,
+        index: usize,
+        path: T,
+    ) -> impl Future<Output = Result<()>> {
+        async move {
+            let path = path.as_ref();
+            let format = image::ImageFormat::from_path(path)
+                .with_context(|| format!("Unknown image format of {}", path.display()))?;
+            let episode_id = self.parse_episode_id(url)?;
+            let episode = self.fetch_episode(&episode_id).await?;
+            let page = episode
+                .pages()
+                .into_iter()
+                .filter(|page| page.is_image())
+                .find(|page| page.index().ok() == Some(index))
+                .with_context(|| format!("Page {} not found in episode {}", index, episode_id))?;
+
+            let bytes = self.fetch_image(&page).await?;
+            let image = self.solve_image(bytes, Some(page)).await?;
+            let bytes = utils::encode_image(&image, format)?;
+            if let Some(parent) = path.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+            tokio::fs::write(path, bytes).await?;
+            Ok(())
+        }
+    }
+
+    /// Size of the image at `url` without downloading it, `None` if unknown, as it is by
+    /// default
+    fn fetch_image_size(&self, url: &Url) -> impl Future<Output = Result<Option<u64>>> {
+        let _ = url;
+        async { Ok(None) }
+    }
+
+    /// Estimate the size of the episode from a few evenly spread pages, without
+    /// downloading any image
+    fn estimate_size(&self, url: &Url) -> impl Future<Output = Result<SizeEstimate>> {
+        async move {
+            let pages = self.list_pages(url).await?;
+            let step = pages.len().div_ceil(SIZE_SAMPLE_PAGES).max(1);
+            let mut sizes = Vec::new();
+            for (_, url) in pages.iter().step_by(step) {
+                if let Some(size) = self.fetch_image_size(url).await? {
+                    sizes.push(size);
+                }
+            }
+            Ok(SizeEstimate::from_samples(pages.len(), &sizes))
+        }
+    }
+
     /// Just download in the specified path
     fn download<T: AsRef<Path>>(&self, url: &Url, path: T) -> impl Future<Output = Result<()>>;
 
     /// Download with a new folder or file in the specified directory
     fn download_in<T: AsRef<Path>>(&self, url: &Url, dir: T) -> impl Future<Output = Result<()>>;
 }
+
+/// Object-safe part of [`EpisodePipeline`], so a pipeline of a viewer chosen at runtime
+/// can be held as `Box<dyn DynPipeline>`
+pub trait DynPipeline: Send + Sync {
+    /// Just download in the specified path
+    fn download<'a>(&'a self, url: &'a Url, path: &'a Path) -> BoxFuture<'a, Result<()>>;
+
+    /// Download with a new folder or file in the specified directory
+    fn download_in<'a>(&'a self, url: &'a Url, dir: &'a Path) -> BoxFuture<'a, Result<()>>;
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_long_title_fits_with_the_extension() -> Result<()> {
+        let config = WriterConifg::new(
+            SaveFormat::Zip {
+                compression_method: zip::CompressionMethod::Stored,
+                extension: Some("cbz".to_string()),
+            },
+            image::ImageFormat::Png,
+        );
+        let title = "[第1話]".to_string() + &"長いタイトル".repeat(40);
+
+        let name = config.file_name(&title);
+
+        let path = PathBuf::from(&name).with_extension("cbz");
+        let part = crate::io::atomic::part_path(&path)?;
+        let part_name = part.file_name().context("No file name")?;
+        assert!(part_name.len() <= DEFAULT_MAX_FILE_NAME_BYTES);
+        assert!(title.starts_with(&name));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_report_path_keeps_dotted_titles() -> Result<()> {
+        let dir = PathBuf::from("out");
+
+        // each episode of the volume has its own report
+        assert_eq!(
+            DownloadReport::report_path(dir.join("Vol.2 第3話"))?,
+            dir.join("Vol.2 第3話.failed.txt")
+        );
+        assert_eq!(
+            DownloadReport::report_path(dir.join("Vol.2 第4話"))?,
+            dir.join("Vol.2 第4話.failed.txt")
+        );
+        // the archive formats don't share a report
+        assert_ne!(
+            DownloadReport::report_path(dir.join("ep.zip"))?,
+            DownloadReport::report_path(dir.join("ep.pdf"))?
+        );
+        assert_eq!(
+            DownloadReport::report_path(dir.join("ep.zip"))?,
+            dir.join("ep.zip.failed.txt")
+        );
+
+        Ok(())
+    }
+}
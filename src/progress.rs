@@ -1,28 +1,34 @@
-use std::borrow::Cow;
+use std::{borrow::Cow, sync::Arc};
 
 use anyhow::{anyhow, Result};
-use indicatif::{ProgressBar, ProgressStyle};
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
 
 #[derive(Debug, Clone)]
 pub struct ProgressConfig {
     is_enabled: bool,
     template: String,
+    /// Shared by the clones, so bars of concurrent downloads are drawn together
+    multi: Option<Arc<MultiProgress>>,
 }
 
-impl ProgressConfig {
-    pub fn new(is_enabled: bool, template: String) -> Self {
-        ProgressConfig {
-            is_enabled,
-            template,
-        }
-    }
-
-    pub fn default() -> Self {
+impl Default for ProgressConfig {
+    fn default() -> Self {
         ProgressConfig {
             is_enabled: true,
             template:
                 "{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} ({eta}) {msg}"
                     .to_string(),
+            multi: None,
+        }
+    }
+}
+
+impl ProgressConfig {
+    pub fn new(is_enabled: bool, template: String) -> Self {
+        ProgressConfig {
+            is_enabled,
+            template,
+            multi: None,
         }
     }
 
@@ -30,6 +36,7 @@ impl ProgressConfig {
         ProgressConfig {
             is_enabled: false,
             template: "".to_string(),
+            multi: None,
         }
     }
 
@@ -37,6 +44,27 @@ impl ProgressConfig {
         self.is_enabled
     }
 
+    /// Add the bars built from this config and its clones to `multi` as children,
+    /// so the bars of episodes downloaded at once don't clobber each other
+    pub fn with_multi_progress(self, multi: MultiProgress) -> Self {
+        ProgressConfig {
+            multi: Some(Arc::new(multi)),
+            ..self
+        }
+    }
+
+    pub fn multi_progress(&self) -> Option<&MultiProgress> {
+        self.multi.as_deref()
+    }
+
+    /// Draw the bar under the shared parent if there is one
+    fn attach(&self, pb: ProgressBar) -> ProgressBar {
+        match &self.multi {
+            Some(multi) => multi.add(pb),
+            None => pb,
+        }
+    }
+
     pub fn style(&self) -> Result<ProgressStyle> {
         Ok(ProgressStyle::default_bar().template(&self.template)?)
     }
@@ -52,7 +80,7 @@ impl ProgressConfig {
         );
         pb.set_style(self.style()?);
 
-        Ok(pb)
+        Ok(self.attach(pb))
     }
 
     pub fn build_with_message<T: TryInto<u64>>(
@@ -71,6 +99,38 @@ impl ProgressConfig {
         pb.set_style(self.style()?);
         pb.set_message(message);
 
-        Ok(pb)
+        Ok(self.attach(pb))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use indicatif::ProgressDrawTarget;
+
+    use super::*;
+
+    #[test]
+    fn test_child_bars_share_the_parent() -> Result<()> {
+        let multi = MultiProgress::with_draw_target(ProgressDrawTarget::hidden());
+        let config = ProgressConfig::default().with_multi_progress(multi);
+
+        let children = (0..3).map(|_| config.clone()).collect::<Vec<_>>();
+        let bars = children
+            .iter()
+            .map(|child| child.build_with_message(10, "Downloading..."))
+            .collect::<Result<Vec<_>>>()?;
+        for bar in &bars {
+            bar.inc(5);
+            bar.finish();
+        }
+
+        let parent = config.multi_progress().unwrap();
+        for child in &children {
+            assert!(std::ptr::eq(child.multi_progress().unwrap(), parent));
+        }
+        assert!(bars.iter().all(|bar| bar.is_finished()));
+        assert!(ProgressConfig::default().multi_progress().is_none());
+
+        Ok(())
     }
 }
@@ -0,0 +1,184 @@
+use std::{
+    future::Future,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use anyhow::Result;
+
+use crate::utils::Bytes;
+
+/// Retries shared by all pages of an episode, so per-page retries can't add up without bound.
+///
+/// Clones share the same budget.
+#[derive(Debug, Clone, Default)]
+pub struct RetryBudget {
+    limit: Option<usize>,
+    spent: Arc<AtomicUsize>,
+}
+
+impl RetryBudget {
+    /// `None` allows any number of retries
+    pub fn new(limit: Option<usize>) -> Self {
+        RetryBudget {
+            limit,
+            spent: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    pub fn unlimited() -> Self {
+        Self::new(None)
+    }
+
+    /// Take one retry from the budget, `false` once it is used up
+    pub fn try_spend(&self) -> bool {
+        let limit = self.limit.unwrap_or(usize::MAX);
+        self.spent
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |spent| {
+                (spent < limit).then_some(spent + 1)
+            })
+            .is_ok()
+    }
+
+    /// Number of retries taken so far
+    pub fn spent(&self) -> usize {
+        self.spent.load(Ordering::SeqCst)
+    }
+
+    pub fn limit(&self) -> Option<usize> {
+        self.limit
+    }
+}
+
+/// Solve the fetched bytes of a page, fetching the page again when solving fails.
+///
+/// A connection dropped mid-transfer leaves truncated bytes that fail to decode,
+/// so a solve error is retried with freshly fetched bytes up to `max_retries` times,
+/// as long as the episode-wide `budget` allows.
+pub async fn solve_with_refetch<T, F, FFut, S, SFut>(
+    bytes: Bytes,
+    max_retries: usize,
+    budget: &RetryBudget,
+    mut refetch: F,
+    mut solve: S,
+) -> Result<T>
+where
+    F: FnMut() -> FFut,
+    FFut: Future<Output = Result<Bytes>>,
+    S: FnMut(Bytes) -> SFut,
+    SFut: Future<Output = Result<T>>,
+{
+    let mut bytes = bytes;
+    let mut retries = 0;
+
+    loop {
+        match solve(bytes).await {
+            Ok(solved) => return Ok(solved),
+            Err(e) if retries < max_retries => {
+                if !budget.try_spend() {
+                    return Err(e.context("The retry budget of the episode is used up"));
+                }
+                retries += 1;
+                tracing::warn!(
+                    "Failed to solve the page, fetching it again ({}/{}): {:#}",
+                    retries,
+                    max_retries,
+                    e
+                );
+                bytes = refetch().await?;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use image::{DynamicImage, GenericImageView};
+
+    use super::*;
+
+    async fn decode(bytes: Bytes) -> Result<DynamicImage> {
+        Ok(image::load_from_memory(&bytes)?)
+    }
+
+    #[tokio::test]
+    async fn test_refetch_truncated_page() -> Result<()> {
+        // truncated JPEGs still decode, so use a PNG that fails to load
+        let mut bytes = Vec::new();
+        DynamicImage::new_rgb8(32, 48).write_to(
+            &mut std::io::Cursor::new(&mut bytes),
+            image::ImageFormat::Png,
+        )?;
+        let bytes = Bytes::from(bytes);
+        let truncated = bytes.slice(..bytes.len() / 2);
+        let fetches = AtomicUsize::new(0);
+
+        let image = solve_with_refetch(
+            truncated,
+            2,
+            &RetryBudget::unlimited(),
+            || async {
+                fetches.fetch_add(1, Ordering::SeqCst);
+                Ok(bytes.clone())
+            },
+            decode,
+        )
+        .await?;
+
+        assert_eq!(fetches.load(Ordering::SeqCst), 1);
+        assert_eq!(image.dimensions(), (32, 48));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_give_up_after_max_retries() -> Result<()> {
+        let fetches = AtomicUsize::new(0);
+
+        let result = solve_with_refetch(
+            Bytes::from_static(&[0; 16]),
+            2,
+            &RetryBudget::unlimited(),
+            || async {
+                fetches.fetch_add(1, Ordering::SeqCst);
+                Ok(Bytes::from_static(&[0; 16]))
+            },
+            decode,
+        )
+        .await;
+
+        assert!(result.is_err());
+        assert_eq!(fetches.load(Ordering::SeqCst), 2);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_budget_is_shared() -> Result<()> {
+        let budget = RetryBudget::new(Some(3));
+        let fetches = AtomicUsize::new(0);
+
+        for _ in 0..4 {
+            let result = solve_with_refetch(
+                Bytes::from_static(&[0; 16]),
+                2,
+                &budget,
+                || async {
+                    fetches.fetch_add(1, Ordering::SeqCst);
+                    Ok(Bytes::from_static(&[0; 16]))
+                },
+                decode,
+            )
+            .await;
+            assert!(result.is_err());
+        }
+
+        assert_eq!(fetches.load(Ordering::SeqCst), 3);
+        assert_eq!(budget.spent(), 3);
+
+        Ok(())
+    }
+}
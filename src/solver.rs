@@ -1,12 +1,102 @@
+use std::io::Cursor;
+
 use anyhow::Result;
-use image::DynamicImage;
+use image::{DynamicImage, ImageReader};
 
 use crate::utils::Bytes;
 
+/// Solved image bytes with the dimensions when they are already known.
+#[derive(Debug, Clone)]
+pub struct SolvedImage {
+    bytes: Bytes,
+    dimensions: Option<(u32, u32)>,
+}
+
+impl SolvedImage {
+    pub fn new(bytes: Bytes, dimensions: Option<(u32, u32)>) -> Self {
+        SolvedImage { bytes, dimensions }
+    }
+
+    /// The solved bytes with the dimensions read from the image header, without decoding
+    /// the pixels. The dimensions are unknown when the header can't be read.
+    pub fn from_bytes(bytes: Bytes) -> Self {
+        let dimensions = ImageReader::new(Cursor::new(&bytes))
+            .with_guessed_format()
+            .ok()
+            .and_then(|reader| reader.into_dimensions().ok());
+        SolvedImage { bytes, dimensions }
+    }
+
+    pub fn bytes(&self) -> &Bytes {
+        &self.bytes
+    }
+
+    /// (width, height) of the image if known
+    pub fn dimensions(&self) -> Option<(u32, u32)> {
+        self.dimensions
+    }
+
+    pub fn into_bytes(self) -> Bytes {
+        self.bytes
+    }
+}
+
 /// A trait for solving image obfuscation.
 pub trait ImageSolver {
     /// Solve the obfuscated bytes.
     fn solve<T: AsRef<[u8]>>(&self, bytes: T) -> Result<Bytes>;
     /// Solve the obfuscated bytes to an image.
     fn solve_from_bytes<B: AsRef<[u8]>>(&self, bytes: B) -> Result<DynamicImage>;
+    /// Solve the obfuscated bytes and keep the dimensions known while solving.
+    /// By default they are read from the header of the solved image.
+    fn solve_with_dimensions<T: AsRef<[u8]>>(&self, bytes: T) -> Result<SolvedImage> {
+        Ok(SolvedImage::from_bytes(self.solve(bytes)?))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_clone_shares_bytes() {
+        let image = SolvedImage::new(Bytes::from(vec![0u8; 1024]), Some((16, 16)));
+        let cloned = image.clone();
+        assert_eq!(cloned.bytes().as_ptr(), image.bytes().as_ptr());
+
+        let page = image.into_bytes();
+        let slice = page.slice(512..);
+        assert_eq!(slice.as_ptr(), page[512..].as_ptr());
+    }
+
+    /// Serves the bytes as they are, with no dimensions of its own
+    struct Passthrough;
+
+    impl ImageSolver for Passthrough {
+        fn solve<T: AsRef<[u8]>>(&self, bytes: T) -> Result<Bytes> {
+            Ok(Bytes::from(bytes.as_ref().to_vec()))
+        }
+
+        fn solve_from_bytes<B: AsRef<[u8]>>(&self, bytes: B) -> Result<DynamicImage> {
+            Ok(image::load_from_memory(bytes.as_ref())?)
+        }
+    }
+
+    #[test]
+    fn test_default_dimensions_are_read_from_the_header() -> Result<()> {
+        let png =
+            crate::utils::encode_image(&DynamicImage::new_rgb8(30, 40), image::ImageFormat::Png)?;
+        let solved = Passthrough.solve_with_dimensions(&png)?;
+        assert_eq!(solved.dimensions(), Some((30, 40)));
+        assert_eq!(solved.bytes(), &png);
+
+        assert_eq!(
+            Passthrough
+                .solve_with_dimensions(b"not an image")?
+                .dimensions(),
+            None
+        );
+
+        Ok(())
+    }
 }
@@ -0,0 +1,172 @@
+//! Recorded viewer responses replayed by a mock server, so the viewers and
+//! pipelines can be tested without the live sites.
+
+use anyhow::{bail, Result};
+use image::{DynamicImage, GenericImageView};
+use wiremock::{
+    matchers::{method, path, path_regex},
+    Mock, MockServer, ResponseTemplate,
+};
+
+/// Recorded `/episode/<id>.json` of shonenjumpplus.com with 3 image pages
+pub(crate) const GIGA_EPISODE: &str = "playground/assets/fixtures/giga-episode.json";
+pub(crate) const GIGA_EPISODE_ID: &str = "9324103625676410700";
+/// The same episode next to other top-level keys
+pub(crate) const GIGA_EPISODE_EXTRA_KEYS: &str =
+    "playground/assets/fixtures/giga-episode-extra-keys.json";
+/// The same episode wrapped in `episode` instead of `readableProduct`
+pub(crate) const GIGA_EPISODE_WRAPPED: &str =
+    "playground/assets/fixtures/giga-episode-wrapped.json";
+/// The same episode without a wrapper
+pub(crate) const GIGA_EPISODE_BARE: &str = "playground/assets/fixtures/giga-episode-bare.json";
+/// RSS feed of a series with 3 episodes, the recorded one last
+#[cfg(feature = "giga")]
+pub(crate) const GIGA_SERIES: &str = "playground/assets/fixtures/giga-series.rss";
+#[cfg(feature = "giga")]
+pub(crate) const GIGA_SERIES_ID: &str = "13933686331624733447";
+/// Host of the recorded giga page urls, replaced with the mock server
+pub(crate) const GIGA_IMAGE_HOST: &str = "https://cdn-ak-img.shonenjumpplus.com";
+/// Scrambled page served for every giga page, 595x842
+pub(crate) const GIGA_PAGE: &str = "playground/assets/giga-swapped.jpg";
+
+/// Recorded `/v1/web_manga_viewer` response of comic-fuz.com with 3 image pages,
+/// a web view and the last page
+#[cfg(feature = "fuz")]
+pub(crate) const FUZ_EPISODE: &str = "playground/assets/fixtures/fuz-web-manga-viewer.pb";
+#[cfg(feature = "fuz")]
+pub(crate) const FUZ_CHAPTER_ID: &str = "2443";
+/// Encrypted page served for every fuz page, 1350x1920
+#[cfg(feature = "fuz")]
+pub(crate) const FUZ_PAGE: &str = "playground/assets/fuz-encrypted.jpeg";
+
+/// Scrambled giga sample page, 595x842
+pub(crate) const GIGA_SAMPLE: &str = "playground/assets/giga-original.jpg";
+/// The sample with its tiles swapped back by hand, the reference of the solved page
+pub(crate) const GIGA_SAMPLE_SOLVED: &str = "playground/assets/giga-swapped.jpg";
+
+/// Largest difference between the channels of the same pixel in the two images,
+/// 0 when they are the same
+pub(crate) fn max_pixel_difference(a: &DynamicImage, b: &DynamicImage) -> Result<u8> {
+    if a.dimensions() != b.dimensions() {
+        bail!(
+            "Images differ in size: {:?} and {:?}",
+            a.dimensions(),
+            b.dimensions()
+        );
+    }
+    let (a, b) = (a.to_rgba8(), b.to_rgba8());
+    Ok(a.as_raw()
+        .iter()
+        .zip(b.as_raw())
+        .map(|(a, b)| a.abs_diff(*b))
+        .max()
+        .unwrap_or(0))
+}
+
+/// Serve the recorded giga episode with the page urls pointing at the server
+pub(crate) async fn giga_server() -> Result<MockServer> {
+    let server = MockServer::start().await;
+    let episode = std::fs::read_to_string(GIGA_EPISODE)?.replace(GIGA_IMAGE_HOST, &server.uri());
+
+    Mock::given(method("GET"))
+        .and(path(format!("/episode/{}.json", GIGA_EPISODE_ID)))
+        .respond_with(ResponseTemplate::new(200).set_body_raw(episode, "application/json"))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path_regex("^/public/page/"))
+        .respond_with(ResponseTemplate::new(200).set_body_bytes(std::fs::read(GIGA_PAGE)?))
+        .mount(&server)
+        .await;
+
+    Ok(server)
+}
+
+/// Id of the episode `number` of the series served by [`giga_series_server`],
+/// the recorded episode is the first one
+#[cfg(feature = "giga")]
+pub(crate) fn giga_series_episode_id(number: usize) -> String {
+    let first = GIGA_EPISODE_ID.parse::<u64>().unwrap();
+    (first + number as u64 - 1).to_string()
+}
+
+/// Serve a series listing the episodes `numbers` in its feed, the newest first, each of
+/// them a copy of the recorded giga episode
+#[cfg(feature = "giga")]
+pub(crate) async fn giga_series_server(
+    numbers: std::ops::RangeInclusive<usize>,
+) -> Result<MockServer> {
+    let server = giga_server().await?;
+    let recorded = std::fs::read_to_string(GIGA_EPISODE)?.replace(GIGA_IMAGE_HOST, &server.uri());
+
+    let mut items = String::new();
+    for number in numbers.rev() {
+        let id = giga_series_episode_id(number);
+        items.push_str(&format!(
+            "<item><title>[第{}話]Mock Series</title>\
+             <link>https://shonenjumpplus.com/episode/{}</link></item>",
+            number, id
+        ));
+        let episode = recorded
+            .replace(GIGA_EPISODE_ID, &id)
+            .replace("\"number\": 1,", &format!("\"number\": {},", number));
+        Mock::given(method("GET"))
+            .and(path(format!("/episode/{}.json", id)))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(episode, "application/json"))
+            .mount(&server)
+            .await;
+    }
+    let rss = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\
+         <rss version=\"2.0\"><channel><title>Mock Series</title>{}</channel></rss>",
+        items
+    );
+    Mock::given(method("GET"))
+        .and(path(format!("/rss/series/{}", GIGA_SERIES_ID)))
+        .respond_with(ResponseTemplate::new(200).set_body_raw(rss, "application/rss+xml"))
+        .mount(&server)
+        .await;
+
+    Ok(server)
+}
+
+/// Encrypt the page as comic-fuz.com serves it, AES-256-CBC with PKCS#7 padding
+#[cfg(feature = "fuz")]
+pub(crate) fn encrypt_fuz_page(plain: &[u8], key_hex: &str, iv_hex: &str) -> Result<Vec<u8>> {
+    use aes::cipher::{generic_array::GenericArray, BlockEncryptMut, BlockSizeUser, KeyIvInit};
+
+    let mut encrypter = cbc::Encryptor::<aes::Aes256Enc>::new(
+        GenericArray::from_slice(&hex::decode(key_hex)?),
+        GenericArray::from_slice(&hex::decode(iv_hex)?),
+    );
+    let block_size = aes::Aes256Enc::block_size();
+    let padding = block_size - plain.len() % block_size;
+    let mut encrypted = plain.to_vec();
+    encrypted.extend(std::iter::repeat_n(padding as u8, padding));
+    for block in encrypted.chunks_exact_mut(block_size) {
+        encrypter.encrypt_block_mut(GenericArray::from_mut_slice(block));
+    }
+    Ok(encrypted)
+}
+
+/// Serve the recorded fuz episode as the API, the image CDN and the website
+#[cfg(feature = "fuz")]
+pub(crate) async fn fuz_server() -> Result<MockServer> {
+    let server = MockServer::start().await;
+
+    Mock::given(method("POST"))
+        .and(path("/v1/web_manga_viewer"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_raw(std::fs::read(FUZ_EPISODE)?, "application/protobuf"),
+        )
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path_regex(r"\.jpeg\.enc$"))
+        .respond_with(ResponseTemplate::new(200).set_body_bytes(std::fs::read(FUZ_PAGE)?))
+        .mount(&server)
+        .await;
+
+    Ok(server)
+}
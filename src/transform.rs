@@ -0,0 +1,231 @@
+use std::{fmt::Debug, sync::Arc};
+
+use anyhow::{bail, Result};
+use image::{imageops::FilterType, DynamicImage, GenericImageView};
+
+use crate::utils;
+
+/// A per-image step run on every solved page before it is written.
+///
+/// A transform may turn one page into several, e.g. splitting a spread.
+pub trait Transform: Debug + Send + Sync {
+    fn apply(&self, image: DynamicImage) -> Result<Vec<DynamicImage>>;
+}
+
+/// Ordered list of transforms shared by the pipelines
+pub type Transforms = Vec<Arc<dyn Transform>>;
+
+/// Run the transforms in order, feeding every output of a step into the next one
+pub fn apply_all(
+    transforms: &[Arc<dyn Transform>],
+    image: DynamicImage,
+) -> Result<Vec<DynamicImage>> {
+    let mut images = vec![image];
+    for transform in transforms {
+        images = images
+            .into_iter()
+            .map(|image| transform.apply(image))
+            .collect::<Result<Vec<_>>>()?
+            .into_iter()
+            .flatten()
+            .collect();
+    }
+    Ok(images)
+}
+
+/// [`apply_all`] on the blocking pool, keeping the page as is when there is nothing to run
+pub async fn apply_all_blocking(
+    transforms: &[Arc<dyn Transform>],
+    image: DynamicImage,
+) -> Result<Vec<DynamicImage>> {
+    if transforms.is_empty() {
+        return Ok(vec![image]);
+    }
+    let transforms = transforms.to_vec();
+    let task = tokio::task::spawn_blocking(move || apply_all(&transforms, image));
+    utils::join(task, "Transforming a page panicked").await
+}
+
+/// Convert pages to 8-bit grayscale
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Grayscale;
+
+impl Transform for Grayscale {
+    fn apply(&self, image: DynamicImage) -> Result<Vec<DynamicImage>> {
+        Ok(vec![DynamicImage::ImageLuma8(image.to_luma8())])
+    }
+}
+
+/// Reduce every channel to `2^bits` evenly spaced levels, e.g. 4 bits for 16 grays.
+///
+/// Grayscale pages posterized to 1, 2 or 4 bits are saved as PNG with that bit depth.
+#[derive(Debug, Clone, Copy)]
+pub struct Posterize {
+    bits: u8,
+}
+
+impl Posterize {
+    pub fn new(bits: u8) -> Self {
+        Posterize { bits }
+    }
+
+    fn quantize(&self, value: u8) -> u8 {
+        let max_level = (1u32 << self.bits) - 1;
+        let level = (value as u32 * max_level + 127) / 255;
+        (level * 255 / max_level) as u8
+    }
+}
+
+impl Transform for Posterize {
+    fn apply(&self, image: DynamicImage) -> Result<Vec<DynamicImage>> {
+        if !(1..=8).contains(&self.bits) {
+            bail!("Posterize bits must be between 1 and 8");
+        }
+        let image = match image {
+            DynamicImage::ImageLuma8(mut gray) => {
+                gray.pixels_mut()
+                    .for_each(|pixel| pixel.0[0] = self.quantize(pixel.0[0]));
+                DynamicImage::ImageLuma8(gray)
+            }
+            image => {
+                let mut rgb = image.to_rgb8();
+                rgb.pixels_mut()
+                    .for_each(|pixel| pixel.0 = pixel.0.map(|value| self.quantize(value)));
+                DynamicImage::ImageRgb8(rgb)
+            }
+        };
+        Ok(vec![image])
+    }
+}
+
+/// Shrink pages to fit within `max_width` x `max_height`, keeping the aspect ratio.
+/// Smaller pages are left as they are.
+#[derive(Debug, Clone, Copy)]
+pub struct Resize {
+    max_width: u32,
+    max_height: u32,
+    filter: FilterType,
+}
+
+impl Resize {
+    pub fn new(max_width: u32, max_height: u32) -> Self {
+        Resize {
+            max_width,
+            max_height,
+            filter: FilterType::Lanczos3,
+        }
+    }
+
+    pub fn with_filter(self, filter: FilterType) -> Self {
+        Resize { filter, ..self }
+    }
+}
+
+impl Transform for Resize {
+    fn apply(&self, image: DynamicImage) -> Result<Vec<DynamicImage>> {
+        if self.max_width == 0 || self.max_height == 0 {
+            bail!("Resize dimensions must be positive");
+        }
+        let (width, height) = image.dimensions();
+        if width <= self.max_width && height <= self.max_height {
+            return Ok(vec![image]);
+        }
+        Ok(vec![image.resize(
+            self.max_width,
+            self.max_height,
+            self.filter,
+        )])
+    }
+}
+
+/// Split landscape pages (spreads) into two pages.
+///
+/// The right half comes first for right-to-left episodes.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SplitSpread {
+    right_to_left: bool,
+}
+
+impl SplitSpread {
+    pub fn new(right_to_left: bool) -> Self {
+        SplitSpread { right_to_left }
+    }
+}
+
+impl Transform for SplitSpread {
+    fn apply(&self, image: DynamicImage) -> Result<Vec<DynamicImage>> {
+        let (width, height) = image.dimensions();
+        if width <= height {
+            return Ok(vec![image]);
+        }
+        let half = width / 2;
+        let left = image.crop_imm(0, 0, half, height);
+        let right = image.crop_imm(half, 0, width - half, height);
+        match self.right_to_left {
+            true => Ok(vec![right, left]),
+            false => Ok(vec![left, right]),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+
+    use image::{ColorType, GrayImage, Luma, Rgb, RgbImage};
+
+    use super::*;
+
+    #[test]
+    fn test_grayscale_then_resize() -> Result<()> {
+        let image = DynamicImage::ImageRgb8(RgbImage::from_pixel(400, 600, Rgb([255, 0, 0])));
+        let transforms: Transforms = vec![Arc::new(Grayscale), Arc::new(Resize::new(200, 200))];
+
+        let images = apply_all(&transforms, image)?;
+
+        assert_eq!(images.len(), 1);
+        assert_eq!(images[0].color(), ColorType::L8);
+        assert_eq!(images[0].dimensions(), (133, 200));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_posterize_to_4_bits() -> Result<()> {
+        let gradient = GrayImage::from_fn(256, 1, |x, _| Luma([x as u8]));
+        let transforms: Transforms = vec![Arc::new(Posterize::new(4))];
+
+        let images = apply_all(&transforms, DynamicImage::ImageLuma8(gradient))?;
+
+        let levels = images[0]
+            .to_luma8()
+            .pixels()
+            .map(|pixel| pixel.0[0])
+            .collect::<HashSet<_>>();
+        assert_eq!(levels.len(), 16);
+        assert!(levels.iter().all(|level| level % 17 == 0));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_split_spread_right_to_left() -> Result<()> {
+        let mut spread = RgbImage::new(80, 60);
+        for (x, _, pixel) in spread.enumerate_pixels_mut() {
+            *pixel = if x < 40 {
+                Rgb([255, 0, 0])
+            } else {
+                Rgb([0, 0, 255])
+            };
+        }
+        let transforms: Transforms = vec![Arc::new(SplitSpread::new(true))];
+
+        let pages = apply_all(&transforms, DynamicImage::ImageRgb8(spread))?;
+
+        assert_eq!(pages.len(), 2);
+        assert_eq!(pages[0].get_pixel(0, 0).0, [0, 0, 255, 255]);
+        assert_eq!(pages[1].get_pixel(0, 0).0, [255, 0, 0, 255]);
+
+        Ok(())
+    }
+}
@@ -19,16 +19,270 @@ macro_rules! include_proto {
         include!(concat!(env!("OUT_DIR"), "/", $name, ".rs"));
     };
 }
-use std::io::Cursor;
+use std::{
+    fmt::Display,
+    io::{Cursor, Seek, Write},
+};
 
-use anyhow::Result;
-use image::{DynamicImage, ImageFormat};
+use anyhow::{Context, Result};
+use image::{DynamicImage, GrayImage, ImageFormat};
 pub(crate) use include_proto;
+use tiff::encoder::{colortype, Compression, TiffEncoder};
+use unicode_normalization::UnicodeNormalization;
 
-pub(crate) type Bytes = Vec<u8>;
+use crate::io::TiffCompression;
+
+pub(crate) use bytes::Bytes;
+
+/// Turn an episode title into a file name that is the same on every platform.
+///
+/// The title is NFC normalized, so composed and decomposed kana map to one name,
+/// and characters that are not allowed in file names are replaced with `_`.
+/// `transliterate` also spells the title in ASCII, e.g. `マリア` as `maria`.
+/// Longer names are cut to `max_bytes` of UTF-8 at a character boundary.
+pub(crate) fn sanitize_file_name(title: &str, transliterate: bool, max_bytes: usize) -> String {
+    let title = title.nfc().collect::<String>();
+    let title = match transliterate {
+        true => deunicode::deunicode(&title),
+        false => title,
+    };
+    let name = title
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect::<String>();
+    let name = truncate_bytes(&name, max_bytes);
+    // Windows drops trailing dots and spaces
+    let name = name.trim().trim_end_matches('.');
+    match name.is_empty() {
+        true => "_".to_string(),
+        false => name.to_string(),
+    }
+}
+
+/// The longest prefix of `text` within `max_bytes` that doesn't split a character
+fn truncate_bytes(text: &str, max_bytes: usize) -> &str {
+    let end = text
+        .char_indices()
+        .map(|(i, c)| i + c.len_utf8())
+        .take_while(|&end| end <= max_bytes)
+        .last()
+        .unwrap_or(0);
+    &text[..end]
+}
+
+/// Characters of the body quoted in a JSON parse error
+const JSON_SNIPPET_CHARS: usize = 200;
+
+/// Parse a JSON response body, skipping a UTF-8 byte order mark and leading whitespace.
+/// A body that doesn't parse is quoted at the start of the error.
+pub(crate) fn parse_json<T: serde::de::DeserializeOwned>(body: &[u8]) -> Result<T> {
+    let body = body.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(body);
+    let body = body.trim_ascii_start();
+    serde_json::from_slice(body).with_context(|| {
+        let snippet = String::from_utf8_lossy(body)
+            .chars()
+            .take(JSON_SNIPPET_CHARS)
+            .collect::<String>();
+        format!("Body is not the expected JSON: {:?}", snippet)
+    })
+}
+
+/// Wait for a spawned task, returning a panic in it as an error with `context`
+pub(crate) async fn join<T, C>(task: tokio::task::JoinHandle<Result<T>>, context: C) -> Result<T>
+where
+    C: Display + Send + Sync + 'static,
+{
+    task.await.context(context)?
+}
 
 pub(crate) fn encode_image(image: &DynamicImage, format: ImageFormat) -> Result<Bytes> {
+    if let (ImageFormat::Png, DynamicImage::ImageLuma8(gray)) = (format, image) {
+        if let Some(bits) = gray_bit_depth(gray) {
+            return encode_gray_png(gray, bits);
+        }
+    }
+
     let mut buffer = Vec::new();
     image.write_to(&mut Cursor::new(&mut buffer), format)?;
-    Ok(buffer)
+    Ok(buffer.into())
+}
+
+/// Smallest PNG bit depth below 8 that stores every gray level of the image exactly,
+/// e.g. 4 for a page posterized to 16 levels
+fn gray_bit_depth(gray: &GrayImage) -> Option<u8> {
+    let mut used = [false; 256];
+    for pixel in gray.pixels() {
+        used[pixel.0[0] as usize] = true;
+    }
+    [1, 2, 4].into_iter().find(|bits| {
+        let step = 255 / ((1 << bits) - 1);
+        (0..256).all(|value| !used[value] || value % step == 0)
+    })
+}
+
+/// Encode a grayscale PNG with `bits` per pixel, packing the pixels of each row
+fn encode_gray_png(gray: &GrayImage, bits: u8) -> Result<Bytes> {
+    let step = 255 / ((1u8 << bits) - 1);
+    let pixels_per_byte = (8 / bits) as usize;
+    let mut data = Vec::new();
+    for row in gray.rows() {
+        for chunk in row.collect::<Vec<_>>().chunks(pixels_per_byte) {
+            let byte = chunk.iter().enumerate().fold(0u8, |byte, (i, pixel)| {
+                byte | (pixel.0[0] / step) << (8 - bits as usize * (i + 1))
+            });
+            data.push(byte);
+        }
+    }
+
+    let mut buffer = Vec::new();
+    let mut encoder = png::Encoder::new(&mut buffer, gray.width(), gray.height());
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(match bits {
+        1 => png::BitDepth::One,
+        2 => png::BitDepth::Two,
+        _ => png::BitDepth::Four,
+    });
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&data)?;
+    writer.finish()?;
+    Ok(buffer.into())
+}
+
+/// Encode an image, compressing TIFF pages with `tiff_compression`
+pub(crate) fn encode_image_with(
+    image: &DynamicImage,
+    format: ImageFormat,
+    tiff_compression: TiffCompression,
+) -> Result<Bytes> {
+    if format != ImageFormat::Tiff {
+        return encode_image(image, format);
+    }
+
+    let mut buffer = Cursor::new(Vec::new());
+    let mut encoder = tiff_encoder(&mut buffer, tiff_compression)?;
+    write_tiff_page(&mut encoder, image)?;
+    Ok(buffer.into_inner().into())
+}
+
+/// Create a TIFF encoder compressing every page with `tiff_compression`
+pub(crate) fn tiff_encoder<W: Write + Seek>(
+    inner: W,
+    tiff_compression: TiffCompression,
+) -> Result<TiffEncoder<W>> {
+    let compression = match tiff_compression {
+        TiffCompression::None => Compression::Uncompressed,
+        TiffCompression::Lzw => Compression::Lzw,
+        TiffCompression::Deflate => Compression::Deflate(Default::default()),
+    };
+    Ok(TiffEncoder::new(inner)?.with_compression(compression))
+}
+
+/// Write the image as the next directory of the TIFF, keeping gray and alpha pages as is
+pub(crate) fn write_tiff_page<W: Write + Seek>(
+    encoder: &mut TiffEncoder<W>,
+    image: &DynamicImage,
+) -> Result<()> {
+    let (width, height) = (image.width(), image.height());
+    match image {
+        DynamicImage::ImageLuma8(gray) => {
+            encoder.write_image::<colortype::Gray8>(width, height, gray.as_raw())?
+        }
+        DynamicImage::ImageRgba8(rgba) => {
+            encoder.write_image::<colortype::RGBA8>(width, height, rgba.as_raw())?
+        }
+        _ => encoder.write_image::<colortype::RGB8>(width, height, image.to_rgb8().as_raw())?,
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use image::{GenericImageView, Luma, Rgb, RgbImage};
+
+    use super::*;
+
+    #[test]
+    fn test_sanitize_file_name() {
+        // ガ as カ and a combining voiced sound mark
+        let decomposed = "[第1話]\u{30AB}\u{3099}ール/ミーツ";
+        assert_eq!(
+            sanitize_file_name(decomposed, false, 255),
+            "[第1話]ガール_ミーツ"
+        );
+        assert_eq!(
+            sanitize_file_name(decomposed, false, 255),
+            sanitize_file_name("[第1話]ガール/ミーツ", false, 255)
+        );
+        assert_eq!(
+            sanitize_file_name("Café: Vol. 2. ", true, 255),
+            "Cafe_ Vol. 2"
+        );
+        assert_eq!(sanitize_file_name("...", false, 255), "_");
+    }
+
+    #[test]
+    fn test_long_file_name_is_truncated() {
+        // 3 bytes per character, so 254 bytes end in the middle of one
+        let title = "あ".repeat(100);
+
+        let name = sanitize_file_name(&title, false, 254);
+
+        assert_eq!(name.len(), 252);
+        assert!(name.chars().all(|c| c == 'あ'));
+        assert_eq!(sanitize_file_name("abc def", false, 4), "abc");
+    }
+
+    #[tokio::test]
+    async fn test_panicking_task_is_an_error() {
+        let task = tokio::task::spawn_blocking(|| -> Result<()> { panic!("encoder bug") });
+
+        let result = join(task, "Encoding page 2 panicked").await;
+
+        let error = result.unwrap_err();
+        assert_eq!(error.to_string(), "Encoding page 2 panicked");
+        assert!(error.root_cause().to_string().contains("panicked"));
+    }
+
+    #[test]
+    fn test_tiff_round_trip() -> Result<()> {
+        let image = DynamicImage::ImageRgb8(RgbImage::from_fn(16, 24, |x, y| {
+            Rgb([x as u8 * 16, y as u8 * 10, 128])
+        }));
+
+        for compression in [
+            TiffCompression::None,
+            TiffCompression::Lzw,
+            TiffCompression::Deflate,
+        ] {
+            let bytes = encode_image_with(&image, ImageFormat::Tiff, compression)?;
+            assert_eq!(image::guess_format(&bytes)?, ImageFormat::Tiff);
+
+            let decoded = image::load_from_memory(&bytes)?;
+            assert_eq!(decoded.dimensions(), (16, 24));
+            assert_eq!(decoded.to_rgb8(), image.to_rgb8(), "{:?}", compression);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_posterized_png_uses_small_bit_depth() -> Result<()> {
+        // 16 levels of gray, 17 apart
+        let image = DynamicImage::ImageLuma8(GrayImage::from_fn(15, 4, |x, y| {
+            Luma([((x + y * 15) % 16) as u8 * 17])
+        }));
+
+        let bytes = encode_image(&image, ImageFormat::Png)?;
+
+        let decoder = png::Decoder::new(Cursor::new(bytes.as_ref()));
+        assert_eq!(decoder.read_info()?.info().bit_depth, png::BitDepth::Four);
+        let decoded = image::load_from_memory(&bytes)?;
+        assert_eq!(decoded.to_luma8(), image.to_luma8());
+
+        Ok(())
+    }
 }
@@ -3,15 +3,26 @@ pub mod fuz;
 
 pub mod giga;
 
-use std::future::Future;
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{Arc, Mutex, MutexGuard},
+};
 
-use anyhow::Result;
-use reqwest::{header::HeaderMap, Response};
+use anyhow::{anyhow, Context, Result};
+use reqwest::{
+    header::{self, HeaderMap, HeaderValue},
+    Response,
+};
 use url::Url;
 
-use crate::auth::Auth;
+use crate::{
+    auth::Auth,
+    utils::{self, Bytes},
+};
 
 /// Manga viewer enum
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ViewerType {
     Giga,
     Ichijin,
@@ -19,6 +30,250 @@ pub enum ViewerType {
     Fuz,
 }
 
+impl ViewerType {
+    /// Find the viewer serving the host
+    pub fn lookup(host: &str) -> Option<ViewerType> {
+        if giga::viewer::Website::lookup(host).is_some() {
+            return Some(ViewerType::Giga);
+        }
+        #[cfg(feature = "fuz")]
+        if fuz::viewer::Website::lookup(host).is_some() {
+            return Some(ViewerType::Fuz);
+        }
+        None
+    }
+}
+
+/// Environment variable with the proxy url used for every request
+pub const PROXY_ENV: &str = "MANGA_PROXY";
+
+/// Environment variable overriding the `User-Agent` header
+pub const USER_AGENT_ENV: &str = "MANGA_USER_AGENT";
+
+/// Read an environment variable, treating an empty value as unset
+pub(crate) fn env_var(key: &str) -> Option<String> {
+    std::env::var(key).ok().filter(|value| !value.is_empty())
+}
+
+/// Find the website of the host, trying it with and without a leading `www.` too,
+/// since shared links use either
+pub(crate) fn lookup_host<T: Clone>(hosts: &phf::Map<&'static str, T>, host: &str) -> Option<T> {
+    hosts
+        .get(host)
+        .or_else(|| hosts.get(&host_alias(host)))
+        .cloned()
+}
+
+/// The host with a leading `www.` added, or removed if it has one
+pub(crate) fn host_alias(host: &str) -> String {
+    match host.strip_prefix("www.") {
+        Some(host) => host.to_string(),
+        None => format!("www.{}", host),
+    }
+}
+
+/// The `Authorization` value of the auth, `None` for an empty auth
+pub(crate) fn authorization<A: Auth>(auth: Option<&A>) -> Option<String> {
+    auth.map(|auth| auth.create_header())
+        .filter(|value| !value.is_empty())
+}
+
+/// Parse a proxy url and check that the HTTP client can use it
+pub(crate) fn parse_proxy(proxy: &str) -> Result<Url> {
+    let url = Url::parse(proxy)?;
+    reqwest::Proxy::all(url.as_str())?;
+    Ok(url)
+}
+
+/// TLS implementation of the HTTP client
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TlsBackend {
+    /// The default TLS of reqwest, native-tls whichever features are enabled
+    #[default]
+    Default,
+    /// The platform TLS library picked explicitly, needs the `native-tls` feature
+    #[cfg(feature = "native-tls")]
+    NativeTls,
+    /// rustls with the bundled webpki roots, needs the `rustls-tls` feature
+    #[cfg(feature = "rustls-tls")]
+    Rustls,
+}
+
+/// HTTP client options shared by the config builders of the viewers
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ClientOptions {
+    /// Send the base url as the `Referer` header
+    referer: bool,
+    user_agent: Option<String>,
+    proxy: Option<Url>,
+    tls_backend: TlsBackend,
+    /// Never negotiate HTTP/2, for middleboxes that break it
+    http1_only: bool,
+}
+
+impl ClientOptions {
+    /// Options with the defaults of the site
+    pub(crate) fn new(preset: SitePreset) -> Self {
+        Self {
+            referer: preset.referer,
+            ..Self::default()
+        }
+    }
+
+    /// Options of the site with the `MANGA_PROXY` and `MANGA_USER_AGENT` overrides
+    /// looked up with `vars`
+    pub(crate) fn from_vars(
+        preset: SitePreset,
+        vars: impl Fn(&str) -> Option<String>,
+    ) -> Result<Self> {
+        let mut options = Self::new(preset);
+        if let Some(proxy) = vars(PROXY_ENV) {
+            options.proxy =
+                Some(parse_proxy(&proxy).with_context(|| format!("Invalid {}", PROXY_ENV))?);
+        }
+        options.user_agent = vars(USER_AGENT_ENV);
+        Ok(options)
+    }
+
+    pub fn proxy(&self) -> Option<&Url> {
+        self.proxy.as_ref()
+    }
+
+    /// Headers sent with every request to the site at `base_url`
+    pub(crate) fn headers(&self, base_url: &Url, authorization: Option<&str>) -> Result<HeaderMap> {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::USER_AGENT,
+            HeaderValue::from_str(
+                &self
+                    .user_agent
+                    .clone()
+                    .unwrap_or_else(|| utils::UserAgent::Bot.value()),
+            )?,
+        );
+        if self.referer {
+            headers.insert(header::REFERER, HeaderValue::from_str(base_url.as_ref())?);
+        }
+        if let Some(authorization) = authorization {
+            let mut value = HeaderValue::from_str(authorization)?;
+            value.set_sensitive(true);
+            headers.insert(header::AUTHORIZATION, value);
+        }
+        Ok(headers)
+    }
+
+    /// HTTP client for the viewers. Compressed responses are decoded transparently.
+    pub(crate) fn http_client(&self) -> reqwest::Client {
+        let mut builder = reqwest::Client::builder()
+            .gzip(true)
+            .brotli(true)
+            .deflate(true);
+        if let Some(proxy) = &self.proxy {
+            builder =
+                builder.proxy(reqwest::Proxy::all(proxy.as_str()).expect("Invalid proxy url"));
+        }
+        match self.tls_backend {
+            TlsBackend::Default => {}
+            #[cfg(feature = "native-tls")]
+            TlsBackend::NativeTls => builder = builder.use_native_tls(),
+            #[cfg(feature = "rustls-tls")]
+            TlsBackend::Rustls => builder = builder.use_rustls_tls(),
+        }
+        if self.http1_only {
+            builder = builder.http1_only();
+        }
+        builder.build().expect("Failed to build the HTTP client")
+    }
+}
+
+/// Send the request with the `common` headers of the viewer, then the extra `headers`.
+///
+/// The `Authorization` is only sent when `is_site`, the credentials are for the site,
+/// not for the image CDNs.
+pub(crate) async fn send_request<B: Into<reqwest::Body>>(
+    request: reqwest::RequestBuilder,
+    mut common: HeaderMap,
+    is_site: bool,
+    body: Option<B>,
+    headers: Option<HeaderMap>,
+) -> Result<Response> {
+    if !is_site {
+        common.remove(header::AUTHORIZATION);
+    }
+    let mut request = request.headers(common);
+    if let Some(headers) = headers {
+        request = request.headers(headers);
+    }
+    if let Some(body) = body {
+        request = request.body(body);
+    }
+    Ok(request.send().await?.error_for_status()?)
+}
+
+/// Validators and body of a fetched response
+#[derive(Debug, Clone)]
+struct CachedResponse {
+    etag: Option<HeaderValue>,
+    last_modified: Option<HeaderValue>,
+    body: Bytes,
+}
+
+/// Responses kept to revalidate with `If-None-Match`/`If-Modified-Since`.
+///
+/// Clones share the same entries.
+#[derive(Debug, Clone, Default)]
+pub struct ResponseCache {
+    entries: Arc<Mutex<HashMap<Url, CachedResponse>>>,
+}
+
+impl ResponseCache {
+    /// Conditional request headers for the cached response of the url, if it has validators
+    pub fn conditional_headers(&self, url: &Url) -> Result<Option<HeaderMap>> {
+        let entries = self.lock()?;
+        let Some(cached) = entries.get(url) else {
+            return Ok(None);
+        };
+        let mut headers = HeaderMap::new();
+        if let Some(etag) = &cached.etag {
+            headers.insert(header::IF_NONE_MATCH, etag.clone());
+        }
+        if let Some(last_modified) = &cached.last_modified {
+            headers.insert(header::IF_MODIFIED_SINCE, last_modified.clone());
+        }
+        Ok((!headers.is_empty()).then_some(headers))
+    }
+
+    /// Body of the cached response of the url
+    pub fn get(&self, url: &Url) -> Result<Option<Bytes>> {
+        let entries = self.lock()?;
+        Ok(entries.get(url).map(|cached| cached.body.clone()))
+    }
+
+    /// Keep the body if the response has an `ETag` or `Last-Modified` to revalidate it with
+    pub fn store(&self, url: Url, headers: &HeaderMap, body: Bytes) -> Result<()> {
+        let etag = headers.get(header::ETAG).cloned();
+        let last_modified = headers.get(header::LAST_MODIFIED).cloned();
+        if etag.is_none() && last_modified.is_none() {
+            return Ok(());
+        }
+        self.lock()?.insert(
+            url,
+            CachedResponse {
+                etag,
+                last_modified,
+                body,
+            },
+        );
+        Ok(())
+    }
+
+    fn lock(&self) -> Result<MutexGuard<'_, HashMap<Url, CachedResponse>>> {
+        self.entries
+            .lock()
+            .map_err(|_| anyhow!("Response cache lock poisoned"))
+    }
+}
+
 pub trait ViewerConfig {
     fn create_header(&self) -> Result<HeaderMap>;
 }
@@ -27,7 +282,63 @@ pub trait ViewerConfigBuilder<V: ViewerConfig, A: Auth> {
     /// Set auth configuration
     fn set_auth(&mut self, auth: A) -> &mut Self;
 
+    /// HTTP client options of the config
+    fn client_options(&mut self) -> &mut ClientOptions;
+
+    /// Send requests with this `User-Agent` instead of the default one
+    fn with_user_agent(mut self, user_agent: &str) -> Self
+    where
+        Self: Sized,
+    {
+        self.client_options().user_agent = Some(user_agent.to_string());
+        self
+    }
+
+    /// Send every request through the proxy
+    fn with_proxy(mut self, proxy: &str) -> Result<Self>
+    where
+        Self: Sized,
+    {
+        self.client_options().proxy = Some(parse_proxy(proxy)?);
+        Ok(self)
+    }
+
+    /// Use this TLS implementation instead of the default one
+    fn with_tls_backend(mut self, tls_backend: TlsBackend) -> Self
+    where
+        Self: Sized,
+    {
+        self.client_options().tls_backend = tls_backend;
+        self
+    }
+
+    /// Speak HTTP/1.1 only, for networks whose middleboxes break HTTP/2
+    fn with_http1_only(mut self, http1_only: bool) -> Self
+    where
+        Self: Sized,
+    {
+        self.client_options().http1_only = http1_only;
+        self
+    }
+
+    /// Send the base url as the `Referer` header, on by default for the sites that expect it
+    fn with_referer(mut self, referer: bool) -> Self
+    where
+        Self: Sized,
+    {
+        self.client_options().referer = referer;
+        self
+    }
+
     fn build(&self) -> V;
+
+    /// Build the config, failing early on a header value that can't be sent instead of
+    /// at request time
+    fn try_build(&self) -> Result<V> {
+        let config = self.build();
+        config.create_header().context("Invalid request header")?;
+        Ok(config)
+    }
 }
 
 pub trait ViewerClient<V: ViewerConfig> {
@@ -56,6 +367,35 @@ pub trait ViewerClient<V: ViewerConfig> {
         self.fetch_raw::<reqwest::Body>(url, reqwest::Method::POST, Some(body.into()), headers)
     }
 
+    /// GET the url and read the whole body
+    fn fetch_bytes(&self, url: Url) -> impl Future<Output = Result<Bytes>> + Send
+    where
+        Self: Sync,
+    {
+        async move {
+            let res = self.get(url).await?;
+            Ok(res.bytes().await?)
+        }
+    }
+
+    /// HEAD the url and read its `Content-Length`, `None` if the server does not tell
+    fn content_length(&self, url: Url) -> impl Future<Output = Result<Option<u64>>> + Send
+    where
+        Self: Sync,
+    {
+        async move {
+            let res = self
+                .fetch_raw::<reqwest::Body>(url, reqwest::Method::HEAD, None, None)
+                .await?;
+            let length = res
+                .headers()
+                .get(reqwest::header::CONTENT_LENGTH)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.parse().ok());
+            Ok(length)
+        }
+    }
+
     /// Parse episode id from url
     fn parse_episode_id(&self, url: &Url) -> Option<String>;
 }
@@ -64,4 +404,27 @@ pub trait ViewerWebsite<T> {
     fn host(&self) -> &str;
     fn base_url(&self) -> Url;
     fn lookup(host: &str) -> Option<T>;
+
+    /// Recommended defaults for the website
+    fn preset(&self) -> SitePreset {
+        SitePreset::default()
+    }
+}
+
+/// Per-site defaults. The pipeline uses them unless the user overrides them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SitePreset {
+    /// Number of concurrent connections the site tolerates
+    pub num_connections: usize,
+    /// Send the base url as the `Referer` header
+    pub referer: bool,
+}
+
+impl Default for SitePreset {
+    fn default() -> Self {
+        SitePreset {
+            num_connections: 8,
+            referer: false,
+        }
+    }
 }
@@ -1,51 +1,215 @@
 use aes::cipher::generic_array::GenericArray;
 use aes::cipher::KeyIvInit;
 use aes::Aes256Dec;
-use anyhow::Result;
+use anyhow::{bail, Result};
 use cbc::Decryptor;
-use cipher::{BlockDecryptMut, BlockSizeUser};
+use cipher::block_padding::{Padding, Pkcs7};
+use cipher::{Block, BlockDecryptMut, BlockSizeUser};
 use hex::decode;
-use std::sync::{Arc, Mutex};
+use rayon::{iter::IndexedParallelIterator, iter::ParallelIterator, slice::ParallelSliceMut};
+
+/// Blocks decrypted in a row by one thread
+const BLOCKS_PER_CHUNK: usize = 4096;
 
 /// decrypt AES-CBC encrypted data
+///
+/// A CBC block only needs the ciphertext block before it, so the data is split into
+/// chunks decrypted in parallel, each starting from the last ciphertext block before it.
+///
+/// The pages are PKCS#7 padded, so valid padding is stripped from the last block. Data
+/// without valid padding is returned as is, and data that is not a whole number of blocks
+/// is an error instead of decrypting a truncated last block.
 pub fn decrypt_aes_cbc(buffer: &[u8], key_hex: &str, iv_hex: &str) -> Result<Vec<u8>> {
+    decrypt_aes_cbc_in_place(buffer.to_vec(), key_hex, iv_hex)
+}
+
+/// decrypt AES-CBC encrypted data over the owned buffer, without allocating another copy
+pub fn decrypt_aes_cbc_in_place(
+    mut buffer: Vec<u8>,
+    key_hex: &str,
+    iv_hex: &str,
+) -> Result<Vec<u8>> {
     let key_bytes = decode(key_hex)?;
     let iv_bytes = decode(iv_hex)?;
+    let block_size = Aes256Dec::block_size();
+    if key_bytes.len() != 32 || iv_bytes.len() != block_size {
+        bail!(
+            "AES-256-CBC needs a 32 byte key and a {} byte iv",
+            block_size
+        );
+    }
+    if !buffer.len().is_multiple_of(block_size) {
+        bail!(
+            "Encrypted data of {} bytes is not a multiple of the block size",
+            buffer.len()
+        );
+    }
 
     let key = GenericArray::from_slice(&key_bytes);
-    let iv = GenericArray::from_slice(&iv_bytes);
-    let decrypter = Decryptor::<Aes256Dec>::new(&key, &iv);
-    let decrypter = Arc::new(Mutex::new(decrypter));
+    let chunk_size = block_size * BLOCKS_PER_CHUNK;
+    // the ciphertext block before every chunk, kept before the chunks are decrypted over
+    let ivs = std::iter::once(&iv_bytes[..])
+        .chain(
+            buffer
+                .chunks(chunk_size)
+                .map(|chunk| &chunk[chunk.len() - block_size..]),
+        )
+        .map(|iv| *GenericArray::from_slice(iv))
+        .collect::<Vec<_>>();
+    buffer
+        .par_chunks_mut(chunk_size)
+        .enumerate()
+        .for_each(|(i, chunk)| {
+            let mut decrypter = Decryptor::<Aes256Dec>::new(key, &ivs[i]);
+            for block in chunk.chunks_exact_mut(block_size) {
+                decrypter.decrypt_block_mut(GenericArray::from_mut_slice(block));
+            }
+        });
 
-    let mut buffer = buffer
-        .to_vec()
-        .chunks(Aes256Dec::block_size())
-        .map(|chunk| GenericArray::clone_from_slice(chunk))
-        .collect::<Vec<GenericArray<_, _>>>();
-
-    buffer.iter_mut().for_each(|chunk| {
-        decrypter.lock().unwrap().decrypt_block_mut(chunk);
-    });
+    let unpadded = unpadded_len(&buffer);
+    buffer.truncate(unpadded);
+    Ok(buffer)
+}
 
-    Ok(buffer.concat())
+/// Length of the data without its PKCS#7 padding, or the whole length if the last block
+/// is not padded
+fn unpadded_len(decrypted: &[u8]) -> usize {
+    let block_size = Aes256Dec::block_size();
+    let Some(last) = decrypted.len().checked_sub(block_size) else {
+        return decrypted.len();
+    };
+    match <Pkcs7 as Padding<_>>::unpad(Block::<Aes256Dec>::from_slice(&decrypted[last..])) {
+        Ok(rest) => last + rest.len(),
+        Err(_) => decrypted.len(),
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use aes::Aes256Enc;
+    use cbc::Encryptor;
+    use cipher::BlockEncryptMut;
+    use sha2::{Digest, Sha256};
     use std::fs;
 
+    const KEY: &str = "2e009856520e10917accae78097a2e13d9dd7a97d3a5ea293527ec9d0132bba3";
+    const IV: &str = "e8c7e042d6ba9fb85c128d5ceb64b82f";
+    /// SHA-256 of the decrypted sample page
+    const DECRYPTED_SHA256: &str =
+        "50ed87c7cabbf5a2054307014717b9ff5ef76ff722f0c2513f597dd84a00518b";
+
     #[test]
-    fn test_decrypt_image() {
-        let key = "2e009856520e10917accae78097a2e13d9dd7a97d3a5ea293527ec9d0132bba3";
-        let iv = "e8c7e042d6ba9fb85c128d5ceb64b82f";
+    fn test_decrypt_image() -> Result<()> {
+        let encrypted = fs::read("./playground/assets/fuz-encrypted.jpeg")?;
+        let decrypted = decrypt_aes_cbc(&encrypted, KEY, IV)?;
 
-        let image_path = "./playground/assets/fuz-encrypted.jpeg";
-        let output_path = "./playground/output/fuz-decrypted.jpeg";
+        assert_eq!(
+            format!("{:x}", Sha256::digest(&decrypted)),
+            DECRYPTED_SHA256
+        );
+        assert_eq!(image::guess_format(&decrypted)?, image::ImageFormat::Jpeg);
+        let image = image::load_from_memory(&decrypted)?;
+        assert_eq!((image.width(), image.height()), (1350, 1920));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parallel_matches_sequential() -> Result<()> {
+        let encrypted = fs::read("./playground/assets/fuz-encrypted.jpeg")?;
+        // a single decryptor over every block, one after another
+        let key = decode(KEY)?;
+        let iv = decode(IV)?;
+        let mut decrypter = Decryptor::<Aes256Dec>::new(
+            GenericArray::from_slice(&key),
+            GenericArray::from_slice(&iv),
+        );
+        let mut expected = encrypted.clone();
+        for block in expected.chunks_exact_mut(Aes256Dec::block_size()) {
+            decrypter.decrypt_block_mut(GenericArray::from_mut_slice(block));
+        }
+        expected.truncate(unpadded_len(&expected));
+
+        let decrypted = decrypt_aes_cbc(&encrypted, KEY, IV)?;
+
+        assert!(encrypted.len() > Aes256Dec::block_size() * BLOCKS_PER_CHUNK);
+        assert_eq!(decrypted, expected);
+        assert!(image::load_from_memory(&decrypted).is_ok());
+        assert!(decrypted.ends_with(&[0xff, 0xd9]));
+        assert!(decrypt_aes_cbc(&encrypted[..100], KEY, IV).is_err());
+
+        Ok(())
+    }
+
+    fn encrypt(plain: &[u8]) -> Result<Vec<u8>> {
+        let mut encrypter = Encryptor::<Aes256Enc>::new(
+            GenericArray::from_slice(&decode(KEY)?),
+            GenericArray::from_slice(&decode(IV)?),
+        );
+        let mut encrypted = plain.to_vec();
+        for block in encrypted.chunks_exact_mut(Aes256Enc::block_size()) {
+            encrypter.encrypt_block_mut(GenericArray::from_mut_slice(block));
+        }
+        Ok(encrypted)
+    }
+
+    #[test]
+    fn test_padding_is_stripped() -> Result<()> {
+        // 20 bytes padded with 12 bytes of 12
+        let mut padded = vec![0xab; 20];
+        padded.extend([12; 12]);
+        assert_eq!(
+            decrypt_aes_cbc(&encrypt(&padded)?, KEY, IV)?,
+            vec![0xab; 20]
+        );
+
+        // a whole block of padding after block-aligned data
+        let mut padded = vec![0xab; 16];
+        padded.extend([16; 16]);
+        assert_eq!(
+            decrypt_aes_cbc(&encrypt(&padded)?, KEY, IV)?,
+            vec![0xab; 16]
+        );
+
+        // no valid padding, e.g. a page ending with the JPEG end marker
+        let mut unpadded = vec![0xab; 30];
+        unpadded.extend([0xff, 0xd9]);
+        assert_eq!(decrypt_aes_cbc(&encrypt(&unpadded)?, KEY, IV)?, unpadded);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_decrypt_large_payload_in_place() -> Result<()> {
+        // 8 MiB of varying bytes ending with a full block of padding
+        let mut plain = (0..8 * 1024 * 1024 - 16)
+            .map(|i: u32| (i.wrapping_mul(2654435761) >> 24) as u8)
+            .collect::<Vec<_>>();
+        let expected = plain.clone();
+        plain.extend([16; 16]);
+        let encrypted = encrypt(&plain)?;
+        let capacity = encrypted.capacity();
+
+        let decrypted = decrypt_aes_cbc_in_place(encrypted.clone(), KEY, IV)?;
+        assert_eq!(decrypted, expected);
+        assert_eq!(decrypt_aes_cbc(&encrypted, KEY, IV)?, expected);
+
+        let decrypted = decrypt_aes_cbc_in_place(encrypted, KEY, IV)?;
+        assert_eq!(decrypted.capacity(), capacity);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_partial_block_is_an_error() -> Result<()> {
+        let encrypted = encrypt(&[0xab; 32])?;
 
-        let encrypted_data = fs::read(image_path).expect("Failed to read the encrypted image file");
-        let decrypted_data = decrypt_aes_cbc(&encrypted_data, key, iv).unwrap();
+        for len in [1, 15, 17, 31] {
+            assert!(decrypt_aes_cbc(&encrypted[..len], KEY, IV).is_err());
+        }
+        assert_eq!(decrypt_aes_cbc(&[], KEY, IV)?, Vec::<u8>::new());
 
-        fs::write(output_path, &decrypted_data).expect("Failed to write the decrypted image file");
+        Ok(())
     }
 }
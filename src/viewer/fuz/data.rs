@@ -1,9 +1,15 @@
-use anyhow::{bail, Result};
+use anyhow::{bail, Context, Result};
+use url::Url;
 use web_manga_viewer::{
     viewer_page, web_manga_viewer_response::viewer_data, WebMangaViewerResponse,
 };
 
-use crate::data::{MangaEpisode, MangaPage, ScrollDirection};
+use crate::{
+    data::{MangaEpisode, MangaPage, ScrollDirection},
+    viewer::ViewerWebsite,
+};
+
+use super::viewer::Website;
 
 pub mod web_manga_viewer {
     use device_info::{DeviceType, ImageQuality};
@@ -40,6 +46,32 @@ pub mod web_manga_viewer {
                 chapter_interface: Some(ChapterInterface::ChapterId(chapter_id)),
             }
         }
+
+        /// Send the request as this device, keeping the secret already set
+        pub fn with_device_info(self, device_info: DeviceInfo) -> Self {
+            let secret = self
+                .device_info
+                .map(|device_info| device_info.secret)
+                .unwrap_or_default();
+            Self {
+                device_info: Some(DeviceInfo {
+                    secret,
+                    ..device_info
+                }),
+                ..self
+            }
+        }
+
+        /// Authenticate the request with the device secret
+        pub fn with_secret(self, secret: &str) -> Self {
+            Self {
+                device_info: self.device_info.map(|device_info| DeviceInfo {
+                    secret: secret.to_string(),
+                    ..device_info
+                }),
+                ..self
+            }
+        }
     }
 }
 
@@ -58,20 +90,30 @@ pub struct ImagePage {
     /// path for the image
     image_path: String,
 
-    encryption_key: String,
-    encryption_iv: String,
+    /// `None` when the page is served unencrypted
+    encryption_key: Option<String>,
+    encryption_iv: Option<String>,
 
     image_width: u32,
     image_height: u32,
 }
 
 impl ImagePage {
-    pub fn encryption_key(&self) -> &str {
-        &self.encryption_key
+    pub fn encryption_key(&self) -> Option<&str> {
+        self.encryption_key.as_deref()
     }
 
-    pub fn encryption_iv(&self) -> &str {
-        &self.encryption_iv
+    pub fn encryption_iv(&self) -> Option<&str> {
+        self.encryption_iv.as_deref()
+    }
+
+    /// (width, height) from the metadata, or `None` if the server did not send them
+    pub fn dimensions(&self) -> Option<(u32, u32)> {
+        if self.image_width == 0 || self.image_height == 0 {
+            None
+        } else {
+            Some((self.image_width, self.image_height))
+        }
     }
 }
 
@@ -82,9 +124,26 @@ pub struct ExtraPage {
     slot_id: u32,
 }
 
+impl ExtraPage {
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+
+    pub fn slot_id(&self) -> u32 {
+        self.slot_id
+    }
+}
+
 impl Page {
-    pub fn new(page: web_manga_viewer::ViewerPage, index: usize) -> Self {
-        match page.content.unwrap() {
+    pub fn new(page: web_manga_viewer::ViewerPage, index: usize) -> Result<Self> {
+        let content = page
+            .content
+            .with_context(|| format!("Page {} has no content", index))?;
+        let page = match content {
             viewer_page::Content::Image(page) => {
                 if page.is_extra_page() {
                     Page::Extra(ExtraPage {
@@ -96,8 +155,8 @@ impl Page {
                     Page::Image(ImagePage {
                         index,
                         image_path: page.image_url,
-                        encryption_key: page.encryption_key.unwrap(),
-                        encryption_iv: page.iv.unwrap(),
+                        encryption_key: page.encryption_key,
+                        encryption_iv: page.iv,
                         image_width: page.image_width,
                         image_height: page.image_height,
                     })
@@ -105,7 +164,8 @@ impl Page {
             }
             viewer_page::Content::Webview(web_view) => Page::WebView { url: web_view.url },
             viewer_page::Content::LastPage(_) => Page::Last,
-        }
+        };
+        Ok(page)
     }
 
     pub fn image_path(&self) -> Result<String> {
@@ -125,11 +185,26 @@ impl MangaPage for Page {
     }
 
     fn is_image(&self) -> bool {
+        matches!(self, Page::Image(_))
+    }
+
+    fn dimensions(&self) -> Option<(u32, u32)> {
         match self {
-            Page::Image(_) => true,
-            _ => false,
+            Page::Image(page) => page.dimensions(),
+            _ => None,
         }
     }
+
+    /// Image paths are relative to the CDN, so only an absolute path is returned
+    fn url(&self) -> Result<Url> {
+        let path = self.image_path()?;
+        Url::parse(&path)
+            .with_context(|| format!("Page path {} is relative to the image CDN", path))
+    }
+
+    fn resolved_url(&self, base: &Url) -> Result<Url> {
+        Ok(base.join(&self.image_path()?)?)
+    }
 }
 
 /// ComicFuz manga episode
@@ -140,10 +215,14 @@ pub struct Episode {
     title: String,
     pages: Vec<Page>,
     scroll_direction: ScrollDirection,
+    /// Base url of the site the episode was read from
+    base_url: Url,
 }
 
-impl From<WebMangaViewerResponse> for Episode {
-    fn from(value: WebMangaViewerResponse) -> Self {
+impl TryFrom<WebMangaViewerResponse> for Episode {
+    type Error = anyhow::Error;
+
+    fn try_from(value: WebMangaViewerResponse) -> Result<Self> {
         let chapters: Vec<web_manga_viewer::Chapter> = value
             .chapters
             .into_iter()
@@ -152,17 +231,17 @@ impl From<WebMangaViewerResponse> for Episode {
         let index = chapters
             .iter()
             .position(|c| c.chapter_id == value.chapter_id)
-            .unwrap();
+            .with_context(|| format!("Chapter {} is not in the chapter list", value.chapter_id))?;
         let chapter = &chapters[index];
 
-        let viewer_data = value.viewer_data.unwrap();
+        let viewer_data = value.viewer_data.context("Response has no viewer data")?;
         let pages = &viewer_data
             .pages
             .clone()
             .into_iter()
             .enumerate()
             .map(|(i, page)| Page::new(page, i))
-            .collect::<Vec<_>>();
+            .collect::<Result<Vec<_>>>()?;
 
         let scroll_direction = match &viewer_data.scroll_direction() {
             viewer_data::ScrollDirection::Left => ScrollDirection::RightToLeft,
@@ -171,13 +250,25 @@ impl From<WebMangaViewerResponse> for Episode {
             viewer_data::ScrollDirection::None => ScrollDirection::Unknown,
         };
 
-        Self {
+        Ok(Self {
             id: chapter.chapter_id.to_string(),
             index,
             title: chapter.chapter_main_name.clone(),
             pages: pages.clone(),
-            scroll_direction: scroll_direction,
-        }
+            scroll_direction,
+            base_url: Website::ComicFuz.base_url(),
+        })
+    }
+}
+
+impl Episode {
+    pub fn scroll_direction(&self) -> ScrollDirection {
+        self.scroll_direction
+    }
+
+    /// Record the site the episode was read from, ComicFuz unless set
+    pub fn with_base_url(self, base_url: Url) -> Self {
+        Self { base_url, ..self }
     }
 }
 
@@ -197,4 +288,134 @@ impl MangaEpisode<Page> for Episode {
     fn pages(&self) -> Vec<Page> {
         self.pages.clone()
     }
+
+    fn url(&self) -> Option<Url> {
+        self.base_url
+            .join(&format!("manga/viewer/{}", self.id))
+            .ok()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use prost::Message;
+    use web_manga_viewer::{
+        web_manga_viewer_response::ViewerData, Chapter, ChapterGroup, ViewerPage,
+    };
+
+    use crate::testing;
+
+    use super::*;
+
+    fn response(pages: Vec<ViewerPage>) -> WebMangaViewerResponse {
+        WebMangaViewerResponse {
+            chapter_id: 1,
+            chapters: vec![ChapterGroup {
+                chapters: vec![Chapter {
+                    chapter_id: 1,
+                    chapter_main_name: "mock".to_string(),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+            viewer_data: Some(ViewerData {
+                pages,
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_missing_fields_are_errors() {
+        let mut missing_viewer_data = response(Vec::new());
+        missing_viewer_data.viewer_data = None;
+        let error = Episode::try_from(missing_viewer_data).unwrap_err();
+        assert!(error.to_string().contains("viewer data"), "{}", error);
+
+        let mut unknown_chapter = response(Vec::new());
+        unknown_chapter.chapter_id = 2;
+        let error = Episode::try_from(unknown_chapter).unwrap_err();
+        assert!(error.to_string().contains("Chapter 2"), "{}", error);
+
+        let empty_page = response(vec![ViewerPage::default(), ViewerPage { content: None }]);
+        let error = Episode::try_from(empty_page).unwrap_err();
+        assert!(error.to_string().contains("Page 0"), "{}", error);
+    }
+
+    #[test]
+    fn test_chapter_in_later_group() -> Result<()> {
+        let chapter = |chapter_id: u32| Chapter {
+            chapter_id,
+            chapter_main_name: format!("chapter {}", chapter_id),
+            ..Default::default()
+        };
+        let mut value = response(Vec::new());
+        value.chapter_id = 12;
+        value.chapters = vec![
+            ChapterGroup {
+                chapters: vec![chapter(1), chapter(2)],
+                ..Default::default()
+            },
+            ChapterGroup {
+                chapters: vec![chapter(11), chapter(12)],
+                ..Default::default()
+            },
+        ];
+
+        let episode = Episode::try_from(value.clone())?;
+        assert_eq!(episode.id(), "12");
+        assert_eq!(episode.title(), Some("chapter 12".to_string()));
+        // counted across the groups
+        assert_eq!(episode.index(), 3);
+
+        value.chapter_id = 13;
+        let error = Episode::try_from(value).unwrap_err();
+        assert!(error.to_string().contains("Chapter 13"), "{}", error);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_page_dimensions() -> Result<()> {
+        let bytes = std::fs::read(testing::FUZ_EPISODE)?;
+        let episode = Episode::try_from(WebMangaViewerResponse::decode(&bytes[..])?)?;
+
+        let dimensions = episode
+            .pages()
+            .iter()
+            .map(|page| page.dimensions())
+            .collect::<Vec<_>>();
+        let image = Some((1350, 1920));
+        // the web view and the last page have no size
+        assert_eq!(dimensions, vec![image, image, image, None, None]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_page_url() -> Result<()> {
+        let bytes = std::fs::read(testing::FUZ_EPISODE)?;
+        let episode = Episode::try_from(WebMangaViewerResponse::decode(&bytes[..])?)?;
+        let page = &episode.pages()[1];
+
+        let base = Url::parse("https://img.comic-fuz.com")?;
+        assert_eq!(
+            page.resolved_url(&base)?.as_str(),
+            "https://img.comic-fuz.com/f/2443/1.jpeg.enc?hash=4b1d0f"
+        );
+        assert!(page.url().is_err());
+        assert!(episode.pages()[3].resolved_url(&base).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_partial_response() -> Result<()> {
+        let episode = Episode::try_from(response(Vec::new()))?;
+        assert_eq!(episode.title(), Some("mock".to_string()));
+        assert!(episode.pages().is_empty());
+
+        Ok(())
+    }
 }
@@ -1,21 +1,27 @@
-use std::path::Path;
+use std::{future::Future, path::Path, sync::Mutex};
 
-use anyhow::{bail, Context, Ok, Result};
-use futures::{stream, StreamExt, TryStreamExt};
-use image::DynamicImage;
-use rayon::slice::ParallelSliceMut;
+use anyhow::{anyhow, bail, Context, Ok, Result};
+use futures::{future::BoxFuture, stream, Stream, StreamExt, TryStreamExt};
+use image::{DynamicImage, GenericImageView};
 use url::Url;
 
-#[cfg(feature = "pdf")]
-use crate::io::pdf::PdfWriter;
 use crate::{
+    cache::ImageCache,
+    color,
     data::{MangaEpisode, MangaPage},
-    io::{raw::RawWriter, zip::ZipWriter, EpisodeWriter},
-    pipeline::{EpisodePipeline, EpisodePipelineBuilder, SaveFormat, WriterConifg},
+    io::{contact_sheet::ContactSheetWriter, EpisodeWriter, PageImage},
+    limit::{ByteLimiter, BytePermit, HostLimiter, PageFetcher, RateLimiter, Turn},
+    pipeline::{
+        collect_pages, stream_pages, Defaults, DownloadReport, EpisodePipeline,
+        EpisodePipelineBuilder, ErrorPolicy, HeldPage, NoImagePages, OutputHook, SaveFormat,
+        SolveKind, WriterConifg,
+    },
     progress::ProgressConfig,
-    solver::ImageSolver,
-    utils::Bytes,
-    viewer::{ViewerClient, ViewerConfigBuilder},
+    retry::{solve_with_refetch, RetryBudget},
+    solver::SolvedImage,
+    transform::{self, Transforms},
+    utils::{self, Bytes},
+    viewer::{SitePreset, ViewerClient, ViewerConfigBuilder, ViewerWebsite},
 };
 
 use super::{
@@ -31,7 +37,23 @@ pub struct Pipeline {
     progress: ProgressConfig,
     writer_config: WriterConifg,
     num_threads: usize,
-    num_connections: usize,
+    /// `None` uses the site preset
+    num_connections: Option<usize>,
+    preset: SitePreset,
+    /// The image cache and the limiters of the fetched pages
+    fetcher: PageFetcher,
+    max_solve_retries: usize,
+    /// `None` leaves the retries of the episode unlimited
+    retry_budget: Option<usize>,
+    output_hook: Option<OutputHook>,
+    convert_to_srgb: bool,
+    contact_sheet: Option<ContactSheetWriter>,
+    best_effort: bool,
+    error_policy: ErrorPolicy,
+    transforms: Transforms,
+    sequential: bool,
+    /// Write the fetched pages without solving them
+    no_solve: bool,
 }
 
 impl Default for Pipeline {
@@ -41,7 +63,19 @@ impl Default for Pipeline {
             progress: ProgressConfig::default(),
             writer_config: WriterConifg::new(SaveFormat::Raw, image::ImageFormat::Png),
             num_threads: num_cpus::get(),
-            num_connections: 8,
+            num_connections: None,
+            preset: Website::ComicFuz.preset(),
+            fetcher: PageFetcher::default(),
+            max_solve_retries: 0,
+            retry_budget: None,
+            output_hook: None,
+            convert_to_srgb: false,
+            contact_sheet: None,
+            best_effort: false,
+            error_policy: ErrorPolicy::FailFast,
+            transforms: Vec::new(),
+            sequential: false,
+            no_solve: false,
         }
     }
 }
@@ -60,15 +94,62 @@ impl Pipeline {
             progress,
             writer_config,
             num_threads,
-            num_connections,
+            num_connections: Some(num_connections),
+            preset: website.preset(),
+            fetcher: PageFetcher::default(),
+            max_solve_retries: 0,
+            retry_budget: None,
+            output_hook: None,
+            convert_to_srgb: false,
+            contact_sheet: None,
+            best_effort: false,
+            error_policy: ErrorPolicy::FailFast,
+            transforms: Vec::new(),
+            sequential: false,
+            no_solve: false,
         }
     }
+
+    /// Pipeline for the website with the settings shared by every download
+    pub fn from_defaults(website: Website, defaults: &Defaults) -> Result<Self> {
+        let mut builder = ConfigBuilder::new(website);
+        if let Some(user_agent) = defaults.user_agent() {
+            builder = builder.with_user_agent(user_agent);
+        }
+        if let Some(proxy) = defaults.proxy() {
+            builder = builder.with_proxy(proxy.as_str())?;
+        }
+        let config = match defaults.netrc() {
+            Some(netrc) => builder.with_netrc(netrc).try_build()?,
+            None => builder.try_build()?,
+        };
+        let pipe = Self::default()
+            .set_website(website)
+            .with_client(Client::new(config))
+            .set_progress(defaults.progress())
+            .set_writer_config(defaults.writer_config())
+            .set_num_threads(defaults.num_threads());
+        Ok(match defaults.num_connections() {
+            Some(num_connections) => pipe.set_num_connections(num_connections),
+            None => pipe,
+        })
+    }
+
+    /// Send the requests with this client instead of the one of the website,
+    /// e.g. one configured by a custom login flow
+    pub fn with_client(self, client: Client) -> Self {
+        Self { client, ..self }
+    }
 }
 
 impl EpisodePipelineBuilder<Website, Page, Episode, Pipeline> for Pipeline {
     fn set_website(self, website: Website) -> Self {
         let client = Client::new(ConfigBuilder::new(website).build());
-        Self { client, ..self }
+        Self {
+            client,
+            preset: website.preset(),
+            ..self
+        }
     }
 
     fn set_progress(self, progress: ProgressConfig) -> Self {
@@ -91,10 +172,148 @@ impl EpisodePipelineBuilder<Website, Page, Episode, Pipeline> for Pipeline {
 
     fn set_num_connections(self, num_connections: usize) -> Self {
         Self {
-            num_connections,
+            num_connections: Some(num_connections),
+            ..self
+        }
+    }
+
+    fn set_max_in_flight_bytes(self, max_in_flight_bytes: Option<usize>) -> Self {
+        Self {
+            fetcher: self
+                .fetcher
+                .with_byte_limiter(max_in_flight_bytes.map(ByteLimiter::new)),
+            ..self
+        }
+    }
+
+    fn set_max_connections_per_host(self, max_connections_per_host: Option<usize>) -> Self {
+        Self {
+            fetcher: self
+                .fetcher
+                .with_host_limiter(max_connections_per_host.map(HostLimiter::new)),
+            ..self
+        }
+    }
+
+    fn set_max_solve_retries(self, max_solve_retries: usize) -> Self {
+        Self {
+            max_solve_retries,
+            ..self
+        }
+    }
+
+    fn set_convert_to_srgb(self, convert_to_srgb: bool) -> Self {
+        Self {
+            convert_to_srgb,
+            ..self
+        }
+    }
+
+    fn set_contact_sheet(self, contact_sheet: Option<ContactSheetWriter>) -> Self {
+        Self {
+            contact_sheet,
+            ..self
+        }
+    }
+
+    fn set_best_effort(self, best_effort: bool) -> Self {
+        Self {
+            best_effort,
+            ..self
+        }
+    }
+
+    fn set_error_policy(self, error_policy: ErrorPolicy) -> Self {
+        Self {
+            error_policy,
+            ..self
+        }
+    }
+
+    fn set_transforms(self, transforms: Transforms) -> Self {
+        Self { transforms, ..self }
+    }
+
+    fn set_sequential(self, sequential: bool) -> Self {
+        Self { sequential, ..self }
+    }
+
+    fn set_no_solve(self, no_solve: bool) -> Self {
+        Self { no_solve, ..self }
+    }
+
+    fn set_retry_budget(self, retry_budget: Option<usize>) -> Self {
+        Self {
+            retry_budget,
             ..self
         }
     }
+
+    fn set_image_cache(self, image_cache: Option<ImageCache>) -> Self {
+        Self {
+            fetcher: self.fetcher.with_image_cache(image_cache),
+            ..self
+        }
+    }
+
+    fn set_rate_limiter(self, rate_limiter: Option<RateLimiter>) -> Self {
+        Self {
+            fetcher: self.fetcher.with_rate_limiter(rate_limiter),
+            ..self
+        }
+    }
+
+    fn set_output_hook(self, output_hook: Option<OutputHook>) -> Self {
+        Self {
+            output_hook,
+            ..self
+        }
+    }
+}
+
+impl crate::pipeline::DynPipeline for Pipeline {
+    fn download<'a>(&'a self, url: &'a Url, path: &'a Path) -> BoxFuture<'a, Result<()>> {
+        Box::pin(EpisodePipeline::download(self, url, path))
+    }
+
+    fn download_in<'a>(&'a self, url: &'a Url, dir: &'a Path) -> BoxFuture<'a, Result<()>> {
+        Box::pin(EpisodePipeline::download_in(self, url, dir))
+    }
+}
+
+/// A page with its position in the episode and its fetched bytes, holding their
+/// reservation of the in-flight bytes
+type FetchedPage = (usize, Page, Result<(Bytes, Option<BytePermit>)>);
+
+/// The solver of the page with its own key and iv, only image pages can be solved
+fn page_solver(page: Option<Page>) -> Result<Solver> {
+    match page.context("Page is required to solve image")? {
+        Page::Image(image_page) => Solver::from_page(&image_page),
+        _ => bail!("Page is not an image"),
+    }
+}
+
+/// Decrypt a page with its dimensions, off the async workers since it is CPU bound
+fn solve_page(
+    solver: &Solver,
+    bytes: Bytes,
+    convert_to_srgb: bool,
+    keep_original: bool,
+    format: image::ImageFormat,
+) -> Result<SolvedImage> {
+    let image = solver.solve_owned_with_dimensions(bytes)?;
+    if convert_to_srgb && color::icc_profile(image.bytes())?.is_some() {
+        // re-encode only the pages that need the conversion
+        let converted = color::load_as_srgb(image.bytes())?;
+        let bytes = utils::encode_image(&converted, format)?;
+        return Ok(SolvedImage::new(bytes, Some(converted.dimensions())));
+    }
+    if !keep_original && image::guess_format(image.bytes()).ok() != Some(format) {
+        let decoded = image::load_from_memory(image.bytes())?;
+        let bytes = utils::encode_image(&decoded, format)?;
+        return Ok(SolvedImage::new(bytes, Some(decoded.dimensions())));
+    }
+    Ok(image)
 }
 
 impl EpisodePipeline<Page, Episode> for Pipeline {
@@ -104,201 +323,1099 @@ impl EpisodePipeline<Page, Episode> for Pipeline {
             .context("Failed to parse episode id")
     }
 
+    fn solve_kind(&self) -> SolveKind {
+        SolveKind::Bytes
+    }
+
     async fn fetch_episode(&self, episode_id: &str) -> Result<Episode> {
         self.client.get_episode(episode_id).await
     }
 
+    fn page_url(&self, page: &Page) -> Result<Url> {
+        self.client.image_url(page.image_path()?)
+    }
+
     async fn fetch_image(&self, page: &Page) -> Result<Bytes> {
-        let url = self.client.image_url(page.image_path()?)?;
-        let res = self.client.get(url).await?;
-        let bytes = res.bytes().await?;
+        let (bytes, _permit) = self.fetch_image_limited(page, None).await?;
+        Ok(bytes)
+    }
 
-        Ok(bytes.into())
+    async fn fetch_image_size(&self, url: &Url) -> Result<Option<u64>> {
+        let _host_permit = self.fetcher.acquire_host(url).await?;
+        self.client.content_length(url.clone()).await
     }
 
     async fn solve_image_bytes(&self, bytes: Bytes, page: Option<Page>) -> Result<Bytes> {
-        let page = page.context("Page is required to solve image")?;
-
-        if let Page::Image(image_page) = page {
-            let solver = Solver::new(image_page.encryption_key(), image_page.encryption_iv());
-            let image = solver.solve(bytes)?;
-            Ok(image)
-        } else {
-            bail!("Page is not an image")
-        }
+        let solver = page_solver(page)?;
+        let task = tokio::task::spawn_blocking(move || solver.solve_owned(bytes));
+        utils::join(task, "Solving a page panicked").await
     }
 
     async fn solve_image(&self, bytes: Bytes, page: Option<Page>) -> Result<DynamicImage> {
-        let page = page.context("Page is required to solve image")?;
+        let solver = page_solver(page)?;
+        let convert_to_srgb = self.convert_to_srgb;
+        let task = tokio::task::spawn_blocking(move || {
+            let solved = solver.solve_owned(bytes)?;
+            if convert_to_srgb {
+                return color::load_as_srgb(solved);
+            }
+            Ok(image::load_from_memory(&solved)?)
+        });
+        utils::join(task, "Solving a page panicked").await
+    }
 
-        if let Page::Image(image_page) = page {
-            let solver = Solver::new(image_page.encryption_key(), image_page.encryption_iv());
-            let image = solver.solve_from_bytes(bytes)?;
-            Ok(image)
-        } else {
-            bail!("Page is not an image")
-        }
+    async fn solve_image_with_dimensions(
+        &self,
+        bytes: Bytes,
+        page: Option<Page>,
+    ) -> Result<SolvedImage> {
+        let solver = page_solver(page)?;
+        let convert_to_srgb = self.convert_to_srgb;
+        // the decrypted page is already a valid image, so it is only decoded and encoded
+        // again when saving in another format, unless the decrypted bytes are kept
+        let keep_original =
+            self.writer_config.keep_original() && self.solve_kind().is_lossless_passthrough();
+        let format = self.writer_config.image_format();
+        let task = tokio::task::spawn_blocking(move || {
+            solve_page(&solver, bytes, convert_to_srgb, keep_original, format)
+        });
+        utils::join(task, "Solving a page panicked").await
     }
 
     async fn write_image_bytes<T: AsRef<Path>>(&self, images: Vec<Bytes>, path: T) -> Result<()> {
-        let writer_config = &self.writer_config;
-
-        match writer_config.save_format() {
-            SaveFormat::Raw => {
-                let writer = RawWriter::new(
-                    self.progress.clone(),
-                    self.writer_config.image_format(),
-                    self.num_threads,
-                );
-                writer.write(images, path).await?;
-            }
-            SaveFormat::Zip {
-                compression_method,
-                extension,
-            } => {
-                let writer = ZipWriter::new(
-                    compression_method,
-                    self.writer_config.image_format(),
-                    extension,
-                    self.num_threads,
-                    self.progress.clone(),
-                );
-                writer.write(images, path).await?;
-            }
-            #[cfg(feature = "pdf")]
-            SaveFormat::Pdf => {
-                let writer =
-                    PdfWriter::new(self.progress.clone(), self.writer_config.image_format());
-                writer.write(images, path).await?;
-            }
-        }
-
-        Ok(())
+        self.writer_config
+            .writer(self.progress.clone(), self.num_threads)
+            .write(self.writer_config.page_order(images), path)
+            .await
     }
 
     async fn write_images<T: AsRef<Path>>(&self, images: Vec<DynamicImage>, path: T) -> Result<()> {
-        let writer_config = &self.writer_config;
-
-        match writer_config.save_format() {
-            SaveFormat::Raw => {
-                let writer = RawWriter::new(
-                    self.progress.clone(),
-                    self.writer_config.image_format(),
-                    self.num_threads,
-                );
-                writer.write_images(images, path).await?;
-            }
-            SaveFormat::Zip {
-                compression_method,
-                extension,
-            } => {
-                let writer = ZipWriter::new(
-                    compression_method,
-                    self.writer_config.image_format(),
-                    extension,
-                    self.num_threads,
-                    self.progress.clone(),
-                );
-                writer.write_images(images, path).await?;
-            }
-            #[cfg(feature = "pdf")]
-            SaveFormat::Pdf => {
-                let writer =
-                    PdfWriter::new(self.progress.clone(), self.writer_config.image_format());
-                writer.write_images(images, path).await?;
-            }
-        }
+        self.writer_config
+            .writer(self.progress.clone(), self.num_threads)
+            .write_images(self.writer_config.page_order(images), path)
+            .await
+    }
 
-        Ok(())
+    async fn write_solved_images<T: AsRef<Path>>(
+        &self,
+        images: Vec<SolvedImage>,
+        path: T,
+    ) -> Result<()> {
+        self.writer_config
+            .writer(self.progress.clone(), self.num_threads)
+            .write_solved(self.writer_config.page_order(images), path)
+            .await
     }
 
-    async fn download<T: AsRef<Path>>(&self, url: &Url, path: T) -> Result<()> {
-        let episode_id = self.parse_episode_id(url)?;
-        let episode = self.fetch_episode(&episode_id).await?;
+    fn page_stream<'a>(
+        &'a self,
+        episode: &Episode,
+    ) -> Result<impl Stream<Item = Result<(usize, DynamicImage)>> + use<'a>> {
         let pages = episode
             .pages()
             .into_iter()
             .filter(|page| page.is_image())
             .collect::<Vec<_>>();
-
-        let mut images = self
-            .progress
-            .build_with_message(pages.len(), "Downloading...")?
-            .wrap_stream(stream::iter(pages))
-            .enumerate()
-            .map(|(i, page)| async move { Ok((i, page.clone(), self.fetch_image(&page).await?)) })
-            .buffer_unordered(self.num_connections)
-            .map_ok(|(i, page, image)| async move {
-                Ok((i, self.solve_image_bytes(image, Some(page)).await?))
+        let budget = RetryBudget::new(self.retry_budget);
+        // the next page isn't read ahead while one is transformed in sequential mode
+        let num_transforms = match self.sequential {
+            true => 1,
+            false => self.num_threads.max(1),
+        };
+        let results = self
+            .decoded_pages(pages, budget)?
+            .map(move |(i, image)| async move {
+                let images = match image {
+                    std::result::Result::Ok(image) => {
+                        transform::apply_all_blocking(&self.transforms, image).await
+                    }
+                    Err(e) => Err(e),
+                };
+                (i, images)
             })
-            .try_buffer_unordered(self.num_threads)
-            .try_collect::<Vec<_>>()
-            .await?;
-        images.par_sort_by_key(|&(i, _)| i);
-        let images = images
-            .into_iter()
-            .map(|(_, image)| image)
-            .collect::<Vec<_>>();
+            .buffered(num_transforms);
+        // the pages a transform splits a page into share its index
+        Ok(results
+            .map(|(i, images)| {
+                images
+                    .map(|images| stream::iter(images.into_iter().map(move |image| Ok((i, image)))))
+            })
+            .try_flatten())
+    }
 
-        self.write_image_bytes(images, path).await?;
-        Ok(())
+    async fn download<T: AsRef<Path>>(&self, url: &Url, path: T) -> Result<()> {
+        let episode_id = self.parse_episode_id(url)?;
+        let episode = self.fetch_episode(&episode_id).await?;
+
+        self.download_episode(&episode, path).await
     }
 
     async fn download_in<T: AsRef<Path>>(&self, url: &Url, dir: T) -> Result<()> {
         let episode_id = self.parse_episode_id(url)?;
         let episode = self.fetch_episode(&episode_id).await?;
 
-        let mut path = dir.as_ref().join(
-            episode
-                .title()
-                .context("Episode title not found")?
-                .replace(".", "_"),
-        );
-        match self.writer_config.save_format() {
-            SaveFormat::Raw => {} // Do nothing
-            SaveFormat::Zip { .. } => {
-                path.set_extension("zip");
+        let name = self.writer_config.episode_name(&episode)?;
+        let path = dir.as_ref().join(self.writer_config.with_extension(&name));
+
+        self.download_episode(&episode, path).await
+    }
+}
+
+impl Pipeline {
+    /// Number of concurrent connections, from the site preset unless overridden
+    pub fn num_connections(&self) -> usize {
+        self.num_connections.unwrap_or(self.preset.num_connections)
+    }
+
+    /// Fetch an image and reserve its size from the in-flight bytes limit, in the `turn`
+    /// of the page if the pages are written in order
+    async fn fetch_image_limited(
+        &self,
+        page: &Page,
+        turn: Option<Turn>,
+    ) -> Result<(Bytes, Option<BytePermit>)> {
+        let url = self.page_url(page)?;
+        let get = |url| self.client.get(url);
+        match turn {
+            Some(turn) => self.fetcher.fetch_in_turn(url, get, turn).await,
+            None => self.fetcher.fetch(url, get).await,
+        }
+    }
+
+    /// Fetch the image again from the network, past a cached body that failed to solve
+    async fn refetch_image(&self, page: &Page) -> Result<Bytes> {
+        self.fetcher
+            .refetch(self.page_url(page)?, |url| self.client.get(url))
+            .await
+    }
+
+    /// Fetch the pages, yielding each with its position in the episode as it arrives.
+    ///
+    /// The pages are put in reading order by their own index first, so the yielded index
+    /// is the position in the episode whatever order the pages are given in.
+    ///
+    /// `in_order` yields the pages in the order they are written and reserves their bytes
+    /// in that order, for a writer taking them as they come.
+    fn fetched_pages<'a>(
+        &'a self,
+        pages: Vec<Page>,
+        in_order: bool,
+    ) -> Result<impl Stream<Item = FetchedPage> + 'a> {
+        let mut pages = pages
+            .into_iter()
+            .map(|page| Ok((page.index()?, page)))
+            .collect::<Result<Vec<_>>>()?;
+        pages.sort_by_key(|(index, _)| *index);
+        let mut pages = pages
+            .into_iter()
+            .map(|(_, page)| page)
+            .enumerate()
+            .collect::<Vec<_>>();
+        let turns = match in_order {
+            true => {
+                pages = self.writer_config.page_order(pages);
+                Turn::chain(pages.len()).into_iter().map(Some).collect()
             }
-            #[cfg(feature = "pdf")]
-            SaveFormat::Pdf => {
-                path.set_extension("pdf");
+            false => pages.iter().map(|_| None).collect::<Vec<_>>(),
+        };
+        let pages = self
+            .progress
+            .build_with_message(pages.len(), "Downloading...")?
+            .wrap_stream(stream::iter(pages.into_iter().zip(turns)));
+        let fetch = move |((i, page), turn): ((usize, Page), Option<Turn>)| async move {
+            let fetched = self.fetch_image_limited(&page, turn).await;
+            (i, page, fetched)
+        };
+
+        if self.sequential {
+            // the next page is fetched only when the stream is polled again
+            return Ok(pages.then(fetch).left_stream());
+        }
+        if in_order {
+            let fetched = pages.map(fetch).buffered(self.num_connections());
+            return Ok(fetched.left_stream().right_stream());
+        }
+        Ok(pages
+            .map(fetch)
+            .buffer_unordered(self.num_connections())
+            .right_stream()
+            .right_stream())
+    }
+
+    /// Solve a fetched page, fetching it again when it fails to solve. The reservation of
+    /// its fetched bytes is kept with the solved page.
+    async fn solve_fetched<T, S, Fut>(
+        &self,
+        (i, page, fetched): FetchedPage,
+        budget: &RetryBudget,
+        solve: S,
+    ) -> HeldPage<T>
+    where
+        S: Fn(Bytes, Page) -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let solved = async {
+            let (image, permit) = fetched?;
+            let image = solve_with_refetch(
+                image,
+                self.max_solve_retries,
+                budget,
+                || self.refetch_image(&page),
+                |image| solve(image, page.clone()),
+            )
+            .await?;
+            Ok((image, permit))
+        };
+        // point at the page that failed
+        let solved = solved.await.with_context(|| {
+            self.page_url(&page)
+                .map_or(format!("page {}", i), |url| format!("page {} ({})", i, url))
+        });
+        (i, solved)
+    }
+
+    /// Fetch and solve the pages concurrently, yielding each result with its page index
+    fn solved_pages<'a, T, S, Fut>(
+        &'a self,
+        pages: Vec<Page>,
+        budget: RetryBudget,
+        solve: S,
+    ) -> Result<impl Stream<Item = (usize, Result<T>)> + 'a>
+    where
+        T: 'a,
+        S: Fn(Bytes, Page) -> Fut + Copy + 'a,
+        Fut: Future<Output = Result<T>> + 'a,
+    {
+        let results = self.held_pages(pages, budget, solve, false)?;
+        // the fetched bytes are no longer held once the page is solved
+        Ok(results.map(|(i, solved)| (i, solved.map(|(image, _)| image))))
+    }
+
+    /// Fetch and solve the pages concurrently, yielding each result with the reservation
+    /// of its fetched bytes. `in_order` yields them in the order they are written.
+    fn held_pages<'a, T, S, Fut>(
+        &'a self,
+        pages: Vec<Page>,
+        budget: RetryBudget,
+        solve: S,
+        in_order: bool,
+    ) -> Result<impl Stream<Item = HeldPage<T>> + 'a>
+    where
+        T: 'a,
+        S: Fn(Bytes, Page) -> Fut + Copy + 'a,
+        Fut: Future<Output = Result<T>> + 'a,
+    {
+        let fetched = self.fetched_pages(pages, in_order)?;
+        let solve_page = move |page: FetchedPage| {
+            let budget = budget.clone();
+            async move { self.solve_fetched(page, &budget, solve).await }
+        };
+
+        if self.sequential {
+            // each page is fetched only after the one before it is solved
+            return Ok(fetched.then(solve_page).left_stream());
+        }
+        if in_order {
+            let results = fetched.map(solve_page).buffered(self.num_threads);
+            return Ok(results.left_stream().right_stream());
+        }
+        Ok(fetched
+            .map(solve_page)
+            .buffer_unordered(self.num_threads)
+            .right_stream()
+            .right_stream())
+    }
+
+    /// Whether the pages are handed to the writer as they are solved, holding their bytes
+    /// until written. Only with a bytes limit, since the pages then wait for each other.
+    fn streams_pages(&self) -> bool {
+        self.fetcher.byte_limiter().is_some() && self.contact_sheet.is_none()
+    }
+
+    /// Fetch and solve the pages in the order they are written and write them as they come
+    async fn stream_episode<T, S, Fut>(
+        &self,
+        episode: &Episode,
+        pages: Vec<Page>,
+        budget: RetryBudget,
+        solve: S,
+        path: &Path,
+    ) -> Result<DownloadReport>
+    where
+        T: PageImage,
+        S: Fn(Bytes, Page) -> Fut + Copy + Send + Sync,
+        Fut: Future<Output = Result<Vec<T>>> + Send,
+    {
+        let total_pages = pages.len();
+        let results = self
+            .held_pages(pages, budget, solve, true)?
+            .map(|(i, solved)| {
+                let solved =
+                    solved.map(|(images, permit)| (self.writer_config.page_order(images), permit));
+                (i, solved)
+            });
+        let report = Mutex::new(DownloadReport {
+            total_pages,
+            ..Default::default()
+        });
+        self.writer_config
+            .episode_writer(episode, self.progress.clone(), self.num_threads)
+            .write_stream(
+                stream_pages(results, self.best_effort, self.error_policy, &report),
+                total_pages,
+                path,
+            )
+            .await?;
+        report
+            .into_inner()
+            .map_err(|_| anyhow!("Download report lock poisoned"))
+    }
+
+    /// Fetch the pages and decrypt and decode them a batch at a time with
+    /// [`Solver::solve_all`], yielding each image with its page index.
+    fn decoded_pages<'a>(
+        &'a self,
+        pages: Vec<Page>,
+        budget: RetryBudget,
+    ) -> Result<impl Stream<Item = (usize, Result<DynamicImage>)> + 'a> {
+        let batch_size = match self.sequential {
+            true => 1,
+            false => self.num_threads.max(1),
+        };
+        let batches = self
+            .fetched_pages(pages, false)?
+            .ready_chunks(batch_size)
+            .map(move |batch| {
+                let budget = budget.clone();
+                async move { self.decode_batch(batch, &budget).await }
+            });
+        // the next batch is fetched while one is decoded, unless the pages go one by one
+        let decoded = match self.sequential {
+            true => batches.buffered(1).left_stream(),
+            false => batches.buffered(2).right_stream(),
+        };
+        Ok(decoded.flat_map(stream::iter))
+    }
+
+    /// Decode the fetched pages together. The pages the batch can't take, or fails to
+    /// solve, are solved on their own and fetched again if the retries allow.
+    async fn decode_batch(
+        &self,
+        batch: Vec<FetchedPage>,
+        budget: &RetryBudget,
+    ) -> Vec<(usize, Result<DynamicImage>)> {
+        // the sRGB conversion reads the profile from the decrypted bytes, so it is left
+        // to the pages on their own, as are the pages served unencrypted
+        let inputs = batch
+            .iter()
+            .map(|(_, page, fetched)| match (page, fetched) {
+                (Page::Image(image_page), std::result::Result::Ok((bytes, _)))
+                    if !self.convert_to_srgb =>
+                {
+                    let key_hex = image_page.encryption_key()?;
+                    let iv_hex = image_page.encryption_iv()?;
+                    Some((bytes.clone(), key_hex.to_string(), iv_hex.to_string()))
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        let positions = inputs
+            .iter()
+            .enumerate()
+            .filter_map(|(n, input)| input.as_ref().map(|_| n))
+            .collect::<Vec<_>>();
+        let inputs = inputs.into_iter().flatten().collect::<Vec<_>>();
+        let task = tokio::task::spawn_blocking(move || Ok(Solver::solve_all(inputs)));
+        let mut solved = (0..batch.len()).map(|_| None).collect::<Vec<_>>();
+        match utils::join(task, "Solving a batch of pages panicked").await {
+            std::result::Result::Ok(images) => {
+                for (n, image) in positions.into_iter().zip(images) {
+                    solved[n] = image.ok();
+                }
             }
+            Err(e) => tracing::warn!("{:#}, solving the pages one by one", e),
+        }
+
+        let mut results = Vec::with_capacity(batch.len());
+        for (page, image) in batch.into_iter().zip(solved) {
+            let result = match image {
+                Some(image) => (page.0, Ok(image)),
+                None => {
+                    let (i, solved) = self
+                        .solve_fetched(page, budget, |bytes, page| {
+                            self.solve_image(bytes, Some(page))
+                        })
+                        .await;
+                    // the fetched bytes are no longer held once the page is solved
+                    (i, solved.map(|(image, _)| image))
+                }
+            };
+            results.push(result);
         }
+        results
+    }
 
+    /// Fetch and write the pages as they are, without solving them
+    async fn download_unsolved(&self, episode: &Episode, path: &Path) -> Result<()> {
         let pages = episode
             .pages()
             .into_iter()
             .filter(|page| page.is_image())
             .collect::<Vec<_>>();
-
-        let mut images = self
-            .progress
-            .build_with_message(pages.len(), "Downloading...")?
-            .wrap_stream(stream::iter(pages))
-            .enumerate()
-            .map(|(i, page)| async move { Ok((i, page.clone(), self.fetch_image(&page).await?)) })
-            .buffer_unordered(self.num_connections)
-            .map_ok(|(i, page, image)| async move {
-                Ok((i, self.solve_image_bytes(image, Some(page)).await?))
-            })
-            .try_buffer_unordered(self.num_threads)
-            .try_collect::<Vec<_>>()
+        let total_pages = pages.len();
+        tracing::info!(
+            "Downloading {} unsolved pages of episode {} to {}",
+            total_pages,
+            episode.id(),
+            path.display()
+        );
+        let budget = RetryBudget::new(self.retry_budget);
+        if self.streams_pages() {
+            let solve = |image, _| async move { Result::<_>::Ok(vec![image]) };
+            let report = self
+                .stream_episode(episode, pages, budget, solve, path)
+                .await?;
+            if !report.is_complete() {
+                report.save(path).await?;
+            }
+            return Ok(());
+        }
+        let results =
+            self.solved_pages(
+                pages,
+                budget,
+                |image, _| async move { Result::<_>::Ok(image) },
+            )?;
+        let (images, report) =
+            collect_pages(results, total_pages, self.best_effort, self.error_policy).await?;
+        self.writer_config
+            .episode_writer(episode, self.progress.clone(), self.num_threads)
+            .with_page_indices(
+                report
+                    .page_indices(images.len())
+                    .map(|indices| self.writer_config.page_order(indices)),
+            )
+            .write(self.writer_config.page_order(images), path)
             .await?;
-        images.par_sort_by_key(|&(i, _)| i);
-        let images = images
+        if !report.is_complete() {
+            report.save(path).await?;
+        }
+        Ok(())
+    }
+
+    /// Download, solve and write all image pages of the episode
+    async fn download_episode<T: AsRef<Path>>(&self, episode: &Episode, path: T) -> Result<()> {
+        // nothing to write, and empty archives are rejected by some readers
+        NoImagePages::ensure(episode)?;
+        let path = OutputHook::output_path(self.output_hook.as_ref(), episode, path.as_ref())?;
+        if self.no_solve {
+            return self.download_unsolved(episode, &path).await;
+        }
+        let pages = episode
+            .pages()
             .into_iter()
-            .map(|(_, image)| image)
+            .filter(|page| page.is_image())
             .collect::<Vec<_>>();
+        let total_pages = pages.len();
+        tracing::info!(
+            "Downloading {} pages of episode {} to {}",
+            pages.len(),
+            episode.id(),
+            path.display()
+        );
 
-        self.write_image_bytes(images, path).await?;
+        let budget = RetryBudget::new(self.retry_budget);
+        let solve = |image, page| async move {
+            if self.transforms.is_empty() {
+                return Ok(vec![
+                    self.solve_image_with_dimensions(image, Some(page)).await?,
+                ]);
+            }
+            // transformed pages are encoded here, as with the sRGB conversion
+            let image = self.solve_image(image, Some(page)).await?;
+            let (transforms, format) = (self.transforms.clone(), self.writer_config.image_format());
+            let task = tokio::task::spawn_blocking(move || {
+                transform::apply_all(&transforms, image)?
+                    .iter()
+                    .map(|image| {
+                        let bytes = utils::encode_image(image, format)?;
+                        Ok(SolvedImage::new(bytes, Some(image.dimensions())))
+                    })
+                    .collect::<Result<Vec<_>>>()
+            });
+            utils::join(task, "Transforming a page panicked").await
+        };
+        let (images, mut report) = match self.streams_pages() {
+            true => {
+                let report = self
+                    .stream_episode(episode, pages, budget.clone(), solve, &path)
+                    .await?;
+                (None, report)
+            }
+            false => {
+                let results = self.solved_pages(pages, budget.clone(), solve)?;
+                let (images, report) =
+                    collect_pages(results, total_pages, self.best_effort, self.error_policy)
+                        .await?;
+                (Some(images), report)
+            }
+        };
+        report.retries = budget.spent();
+        if report.retries > 0 {
+            tracing::info!(
+                "Spent {} retries of the budget of {}",
+                report.retries,
+                budget
+                    .limit()
+                    .map_or("unlimited".to_string(), |limit| limit.to_string())
+            );
+        }
+        // the streamed pages are already written
+        if let Some(images) = images {
+            let images = images.into_iter().flatten().collect::<Vec<_>>();
+
+            if let Some(contact_sheet) = &self.contact_sheet {
+                contact_sheet
+                    .clone()
+                    .with_direction(episode.scroll_direction())
+                    .write(
+                        images.iter().map(|image| image.bytes().clone()).collect(),
+                        &path,
+                    )
+                    .await?;
+            }
+            self.writer_config
+                .episode_writer(episode, self.progress.clone(), self.num_threads)
+                .with_page_indices(
+                    report
+                        .page_indices(images.len())
+                        .map(|indices| self.writer_config.page_order(indices)),
+                )
+                .write_solved(self.writer_config.page_order(images), &path)
+                .await?;
+        }
+        if !report.is_complete() {
+            tracing::warn!(
+                "{} of {} pages failed, see {}",
+                report.failures.len(),
+                report.total_pages,
+                DownloadReport::report_path(&path)?.display()
+            );
+            report.save(&path).await?;
+        }
         Ok(())
     }
 }
 
 #[cfg(test)]
 mod test {
+    use std::io::Read;
+
+    use prost::Message;
+    use wiremock::{
+        matchers::{header, method, path, path_regex},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    use crate::{
+        testing,
+        viewer::fuz::data::web_manga_viewer::{viewer_page, ViewerPage, WebMangaViewerResponse},
+    };
+
     use super::*;
 
+    fn encrypted_sample_page(image_width: u32, image_height: u32) -> Page {
+        let page = ViewerPage {
+            content: Some(viewer_page::Content::Image(viewer_page::Image {
+                image_url: "/sample.jpeg.enc".to_string(),
+                iv: Some("e8c7e042d6ba9fb85c128d5ceb64b82f".to_string()),
+                encryption_key: Some(
+                    "2e009856520e10917accae78097a2e13d9dd7a97d3a5ea293527ec9d0132bba3".to_string(),
+                ),
+                image_width,
+                image_height,
+                ..Default::default()
+            })),
+        };
+        Page::new(page, 0).unwrap()
+    }
+
+    #[test]
+    fn test_site_preset_defaults() {
+        let pipe = Pipeline::default().set_website(Website::ComicFuz);
+        assert_eq!(
+            pipe.num_connections(),
+            Website::ComicFuz.preset().num_connections
+        );
+        assert_eq!(pipe.num_connections(), 4);
+
+        // an explicit value wins regardless of the order
+        let pipe = Pipeline::default()
+            .set_num_connections(16)
+            .set_website(Website::ComicFuz);
+        assert_eq!(pipe.num_connections(), 16);
+    }
+
+    #[test]
+    fn test_solve_kind() {
+        let pipe = Pipeline::default();
+        assert_eq!(pipe.solve_kind(), SolveKind::Bytes);
+        assert!(pipe.solve_kind().is_lossless_passthrough());
+    }
+
+    #[tokio::test]
+    async fn test_solve_uses_page_dimensions() -> Result<()> {
+        let bytes = Bytes::from(std::fs::read("playground/assets/fuz-encrypted.jpeg")?);
+        // kept as decrypted, so the dimensions are not probed while encoding
+        let pipe = Pipeline::default()
+            .set_writer_config(WriterConifg::new(SaveFormat::Raw, image::ImageFormat::Jpeg));
+
+        let page = encrypted_sample_page(1200, 1700);
+        let solved = pipe
+            .solve_image_with_dimensions(bytes.clone(), Some(page))
+            .await?;
+        assert_eq!(solved.dimensions(), Some((1200, 1700)));
+
+        // missing dimensions are left to the writer to probe
+        let page = encrypted_sample_page(0, 0);
+        let solved = pipe.solve_image_with_dimensions(bytes, Some(page)).await?;
+        assert_eq!(solved.dimensions(), None);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_keep_original_writes_decrypted_bytes() -> Result<()> {
+        let bytes = Bytes::from(std::fs::read(testing::FUZ_PAGE)?);
+        let page = encrypted_sample_page(1350, 1920);
+        let decrypted = page_solver(Some(page.clone()))?.solve_owned(bytes.clone())?;
+        let writer_config = WriterConifg::new(SaveFormat::Raw, image::ImageFormat::Png);
+
+        let pipe = Pipeline::default().set_writer_config(writer_config.clone());
+        let solved = pipe
+            .solve_image_with_dimensions(bytes.clone(), Some(page.clone()))
+            .await?;
+        assert_eq!(
+            image::guess_format(solved.bytes())?,
+            image::ImageFormat::Png
+        );
+
+        // the JPEG is kept whatever the image format
+        let pipe = Pipeline::default().set_writer_config(writer_config.with_keep_original(true));
+        let solved = pipe.solve_image_with_dimensions(bytes, Some(page)).await?;
+        assert_eq!(solved.bytes(), &decrypted);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_list_recorded_pages() -> Result<()> {
+        let server = testing::fuz_server().await?;
+        let url = Url::parse(&format!(
+            "{}/manga/viewer/{}",
+            server.uri(),
+            testing::FUZ_CHAPTER_ID
+        ))?;
+        let config = ConfigBuilder::custom(server.uri(), server.uri(), server.uri())?.build();
+        let pipe = Pipeline {
+            client: Client::new(config),
+            ..Pipeline::default()
+        };
+
+        let pages = pipe.list_pages(&url).await?;
+
+        // the web view and the last page are not listed
+        assert_eq!(pages.len(), 3);
+        for (_, url) in &pages {
+            assert!(url.path().ends_with(".jpeg.enc"));
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_episode_url_is_on_the_configured_site() -> Result<()> {
+        let server = testing::fuz_server().await?;
+        let config = ConfigBuilder::custom(server.uri(), server.uri(), server.uri())?.build();
+        let pipe = Pipeline {
+            client: Client::new(config),
+            ..Pipeline::default()
+        };
+
+        let episode = pipe.fetch_episode(testing::FUZ_CHAPTER_ID).await?;
+        let expected = format!("{}/manga/viewer/{}", server.uri(), testing::FUZ_CHAPTER_ID);
+        assert_eq!(episode.url().map(String::from), Some(expected));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_out_of_order_pages_are_in_reading_order() -> Result<()> {
+        let server = MockServer::start().await;
+        // the first pages are the slowest, so they finish last
+        for index in 0..4u8 {
+            let image = image::GrayImage::from_pixel(1, 1, image::Luma([index * 10]));
+            let bytes =
+                utils::encode_image(&DynamicImage::ImageLuma8(image), image::ImageFormat::Png)?;
+            Mock::given(method("GET"))
+                .and(path(format!("/page-{}.png", index)))
+                .respond_with(
+                    ResponseTemplate::new(200)
+                        .set_body_bytes(bytes.to_vec())
+                        .set_delay(std::time::Duration::from_millis(50 * (4 - index as u64))),
+                )
+                .mount(&server)
+                .await;
+        }
+        let pages = [2, 0, 3, 1]
+            .into_iter()
+            .map(|index| {
+                let page = ViewerPage {
+                    content: Some(viewer_page::Content::Image(viewer_page::Image {
+                        image_url: format!("/page-{}.png", index),
+                        ..Default::default()
+                    })),
+                };
+                Page::new(page, index)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let config = ConfigBuilder::custom(server.uri(), server.uri(), server.uri())?.build();
+        let pipe = Pipeline::default()
+            .with_client(Client::new(config))
+            .set_progress(ProgressConfig::disabled());
+
+        let budget = RetryBudget::new(None);
+        let results = pipe.solved_pages(pages, budget, |image, page| {
+            pipe.solve_image(image, Some(page))
+        })?;
+        let (images, _) = collect_pages(results, 4, false, ErrorPolicy::FailFast).await?;
+
+        let values = images
+            .iter()
+            .map(|image| image.to_luma8().get_pixel(0, 0).0[0])
+            .collect::<Vec<_>>();
+        assert_eq!(values, vec![0, 10, 20, 30]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_web_view_only_episode_is_rejected() -> Result<()> {
+        let bytes = std::fs::read(testing::FUZ_EPISODE)?;
+        let mut response = WebMangaViewerResponse::decode(&bytes[..])?;
+        if let Some(viewer_data) = response.viewer_data.as_mut() {
+            viewer_data.pages = (0..3)
+                .map(|i| ViewerPage {
+                    content: Some(viewer_page::Content::Webview(viewer_page::WebView {
+                        url: format!("https://comic-fuz.com/webview/{}", i),
+                    })),
+                })
+                .collect();
+        }
+        let episode = Episode::try_from(response)?;
+        let path = Path::new("playground/output/fuz_web_view_only.zip");
+        let _ = std::fs::remove_file(path);
+        let pipe = Pipeline::default()
+            .set_progress(ProgressConfig::disabled())
+            .set_writer_config(WriterConifg::new(
+                SaveFormat::Zip {
+                    compression_method: zip::CompressionMethod::Stored,
+                    extension: None,
+                },
+                image::ImageFormat::Png,
+            ));
+
+        let error = pipe.download_episode(&episode, path).await.unwrap_err();
+
+        let error = error.downcast_ref::<NoImagePages>().unwrap();
+        assert_eq!(error.id, testing::FUZ_CHAPTER_ID);
+        assert!(!path.exists());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_download_with_external_client() -> Result<()> {
+        let server = MockServer::start().await;
+        // only the requests of the logged in client are answered
+        Mock::given(method("POST"))
+            .and(path("/v1/web_manga_viewer"))
+            .and(header("user-agent", "logged-in/1.0"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_raw(std::fs::read(testing::FUZ_EPISODE)?, "application/protobuf"),
+            )
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path_regex(r"\.jpeg\.enc$"))
+            .and(header("user-agent", "logged-in/1.0"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_bytes(std::fs::read(testing::FUZ_PAGE)?),
+            )
+            .mount(&server)
+            .await;
+        let client = Client::new(
+            ConfigBuilder::custom(server.uri(), server.uri(), server.uri())?
+                .with_user_agent("logged-in/1.0")
+                .build(),
+        );
+        let url = Url::parse(&format!(
+            "{}/manga/viewer/{}",
+            server.uri(),
+            testing::FUZ_CHAPTER_ID
+        ))?;
+        let path = Path::new("playground/output/fuz_external_client");
+        let _ = std::fs::remove_dir_all(path);
+
+        let pipe = Pipeline::default()
+            .with_client(client)
+            .set_progress(ProgressConfig::disabled());
+        pipe.download(&url, path).await?;
+
+        assert_eq!(std::fs::read_dir(path)?.count(), 3);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_download_recorded_episode() -> Result<()> {
+        let server = testing::fuz_server().await?;
+        let url = Url::parse(&format!(
+            "{}/manga/viewer/{}",
+            server.uri(),
+            testing::FUZ_CHAPTER_ID
+        ))?;
+        let path = "playground/output/fuz_recorded.zip";
+
+        let config = ConfigBuilder::custom(server.uri(), server.uri(), server.uri())?.build();
+        let pipe = Pipeline {
+            client: Client::new(config),
+            ..Pipeline::default()
+        }
+        .set_progress(ProgressConfig::disabled())
+        .set_writer_config(WriterConifg::new(
+            SaveFormat::Zip {
+                compression_method: zip::CompressionMethod::Stored,
+                extension: None,
+            },
+            image::ImageFormat::Jpeg,
+        ));
+        pipe.download(&url, path).await?;
+
+        // the web view and the last page are not images
+        let mut archive = zip::ZipArchive::new(std::fs::File::open(path)?)?;
+        assert_eq!(archive.len(), 3);
+        for i in 0..3 {
+            let mut bytes = Vec::new();
+            archive
+                .by_name(&format!("{}.jpg", i))?
+                .read_to_end(&mut bytes)?;
+            assert_eq!(image::load_from_memory(&bytes)?.dimensions(), (1350, 1920));
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_limited_download_streams_pages_in_order() -> Result<()> {
+        let server = testing::fuz_server().await?;
+        let url = Url::parse(&format!(
+            "{}/manga/viewer/{}",
+            server.uri(),
+            testing::FUZ_CHAPTER_ID
+        ))?;
+        let path = "playground/output/fuz_limited.zip";
+
+        let config = ConfigBuilder::custom(server.uri(), server.uri(), server.uri())?.build();
+        // a single byte holds one page at a time, from the fetch until it is in the zip
+        let pipe = Pipeline {
+            client: Client::new(config),
+            ..Pipeline::default()
+        }
+        .set_progress(ProgressConfig::disabled())
+        .set_max_in_flight_bytes(Some(1))
+        .set_writer_config(
+            WriterConifg::new(
+                SaveFormat::Zip {
+                    compression_method: zip::CompressionMethod::Stored,
+                    extension: None,
+                },
+                image::ImageFormat::Jpeg,
+            )
+            .with_reverse_pages(true),
+        );
+        pipe.download(&url, path).await?;
+
+        let limiter = pipe.fetcher.byte_limiter().context("no byte limiter")?;
+        assert_eq!(limiter.high_water_mark(), 1);
+        let mut archive = zip::ZipArchive::new(std::fs::File::open(path)?)?;
+        let names = archive.file_names().map(str::to_string).collect::<Vec<_>>();
+        assert_eq!(names, vec!["0.jpg", "1.jpg", "2.jpg"]);
+        for i in 0..3 {
+            let mut bytes = Vec::new();
+            archive
+                .by_name(&format!("{}.jpg", i))?
+                .read_to_end(&mut bytes)?;
+            assert_eq!(image::load_from_memory(&bytes)?.dimensions(), (1350, 1920));
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_page_stream_runs_transforms() -> Result<()> {
+        let server = testing::fuz_server().await?;
+        let config = ConfigBuilder::custom(server.uri(), server.uri(), server.uri())?.build();
+        let pipe = Pipeline {
+            client: Client::new(config),
+            ..Pipeline::default()
+        }
+        .set_progress(ProgressConfig::disabled())
+        .set_transforms(vec![
+            std::sync::Arc::new(transform::Grayscale),
+            std::sync::Arc::new(transform::Resize::new(20, 30)),
+        ]);
+        let episode = pipe.fetch_episode(testing::FUZ_CHAPTER_ID).await?;
+
+        let pages = pipe.page_stream(&episode)?.try_collect::<Vec<_>>().await?;
+
+        assert_eq!(pages.len(), 3);
+        for (_, image) in pages {
+            // the 1350x1920 pages fit in 20x30 keeping their aspect ratio
+            assert_eq!(image.dimensions(), (20, 28));
+            assert_eq!(image.color(), image::ColorType::L8);
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_img_url_override() -> Result<()> {
+        let server = testing::fuz_server().await?;
+        let mirror = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path_regex(r"\.jpeg\.enc$"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_bytes(std::fs::read(testing::FUZ_PAGE)?),
+            )
+            .mount(&mirror)
+            .await;
+        let url = Url::parse(&format!(
+            "{}/manga/viewer/{}",
+            server.uri(),
+            testing::FUZ_CHAPTER_ID
+        ))?;
+        let config = ConfigBuilder::custom(server.uri(), server.uri(), server.uri())?
+            .with_img_url(&mirror.uri())?
+            .build();
+        let pipe = Pipeline {
+            client: Client::new(config),
+            ..Pipeline::default()
+        };
+
+        let pages = pipe.list_pages(&url).await?;
+        assert!(pages
+            .iter()
+            .all(|(_, url)| url.as_str().starts_with(&mirror.uri())));
+        let episode = pipe.fetch_episode(testing::FUZ_CHAPTER_ID).await?;
+        let page = episode
+            .pages()
+            .into_iter()
+            .find(|page| page.is_image())
+            .unwrap();
+        pipe.fetch_image(&page).await?;
+        assert!(!mirror
+            .received_requests()
+            .await
+            .unwrap_or_default()
+            .is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_no_solve_keeps_pages_encrypted() -> Result<()> {
+        let server = testing::fuz_server().await?;
+        let url = Url::parse(&format!(
+            "{}/manga/viewer/{}",
+            server.uri(),
+            testing::FUZ_CHAPTER_ID
+        ))?;
+        let path = "playground/output/fuz_no_solve.zip";
+
+        let config = ConfigBuilder::custom(server.uri(), server.uri(), server.uri())?.build();
+        let pipe = Pipeline {
+            client: Client::new(config),
+            ..Pipeline::default()
+        }
+        .set_progress(ProgressConfig::disabled())
+        .set_writer_config(WriterConifg::new(
+            SaveFormat::Zip {
+                compression_method: zip::CompressionMethod::Stored,
+                extension: None,
+            },
+            image::ImageFormat::Jpeg,
+        ))
+        .set_no_solve(true);
+        pipe.download(&url, path).await?;
+
+        let fetched = std::fs::read(testing::FUZ_PAGE)?;
+        let mut archive = zip::ZipArchive::new(std::fs::File::open(path)?)?;
+        assert_eq!(archive.len(), 3);
+        for i in 0..3 {
+            let mut bytes = Vec::new();
+            archive
+                .by_name(&format!("{}.jpg", i))?
+                .read_to_end(&mut bytes)?;
+            assert_eq!(bytes, fetched);
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_raw_pages_are_the_decrypted_bytes() -> Result<()> {
+        let server = testing::fuz_server().await?;
+        let url = Url::parse(&format!(
+            "{}/manga/viewer/{}",
+            server.uri(),
+            testing::FUZ_CHAPTER_ID
+        ))?;
+        let decrypted = crate::viewer::fuz::crypto::decrypt_aes_cbc(
+            &std::fs::read(testing::FUZ_PAGE)?,
+            "2e009856520e10917accae78097a2e13d9dd7a97d3a5ea293527ec9d0132bba3",
+            "e8c7e042d6ba9fb85c128d5ceb64b82f",
+        )?;
+        let config = ConfigBuilder::custom(server.uri(), server.uri(), server.uri())?.build();
+        let pipe = Pipeline::default()
+            .with_client(Client::new(config))
+            .set_progress(ProgressConfig::disabled());
+
+        // saved as they were decrypted when the format matches
+        let path = Path::new("playground/output/fuz_raw_jpeg");
+        let _ = std::fs::remove_dir_all(path);
+        pipe.clone()
+            .set_writer_config(WriterConifg::new(SaveFormat::Raw, image::ImageFormat::Jpeg))
+            .download(&url, path)
+            .await?;
+        for i in 0..3 {
+            assert_eq!(std::fs::read(path.join(format!("{}.jpg", i)))?, decrypted);
+        }
+
+        // encoded again only for another format
+        let path = Path::new("playground/output/fuz_raw_png");
+        let _ = std::fs::remove_dir_all(path);
+        pipe.set_writer_config(WriterConifg::new(SaveFormat::Raw, image::ImageFormat::Png))
+            .download(&url, path)
+            .await?;
+        let page = std::fs::read(path.join("0.png"))?;
+        assert_eq!(image::guess_format(&page)?, image::ImageFormat::Png);
+        assert_eq!(image::load_from_memory(&page)?.dimensions(), (1350, 1920));
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_pipeline_download_raw() -> Result<()> {
         let url = Url::parse("https://comic-fuz.com/manga/viewer/44994")?;
@@ -1,30 +1,93 @@
-use anyhow::Result;
+use anyhow::{bail, Result};
 use image::DynamicImage;
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
 
-use crate::{solver::ImageSolver, utils::Bytes};
+use crate::{
+    solver::{ImageSolver, SolvedImage},
+    utils::Bytes,
+};
 
-use super::crypto::decrypt_aes_cbc;
+use super::{
+    crypto::{decrypt_aes_cbc, decrypt_aes_cbc_in_place},
+    data::ImagePage,
+};
 
 /// ComicFuz image solver
 #[derive(Debug, Clone)]
 pub struct Solver {
-    key_hex: String,
-    iv_hex: String,
+    /// (key, iv) in hex, `None` for pages served unencrypted
+    encryption: Option<(String, String)>,
+    dimensions: Option<(u32, u32)>,
 }
 
 impl Solver {
     pub fn new(key_hex: &str, iv_hex: &str) -> Self {
         Solver {
-            key_hex: key_hex.to_string(),
-            iv_hex: iv_hex.to_string(),
+            encryption: Some((key_hex.to_string(), iv_hex.to_string())),
+            dimensions: None,
         }
     }
+
+    /// Solver returning the bytes as they are, for pages served unencrypted
+    pub fn unencrypted() -> Self {
+        Solver {
+            encryption: None,
+            dimensions: None,
+        }
+    }
+
+    /// Solver for the page, decrypting only when the page has a key and an iv
+    pub fn from_page(page: &ImagePage) -> Result<Self> {
+        let solver = match (page.encryption_key(), page.encryption_iv()) {
+            (Some(key_hex), Some(iv_hex)) => Solver::new(key_hex, iv_hex),
+            (None, None) => Solver::unencrypted(),
+            _ => bail!("Page has an encryption key or iv but not both"),
+        };
+        Ok(solver.with_dimensions(page.dimensions()))
+    }
+
+    /// Set the image dimensions known from the page metadata
+    pub fn with_dimensions(self, dimensions: Option<(u32, u32)>) -> Self {
+        Solver { dimensions, ..self }
+    }
+
+    /// Decrypt the fetched bytes over their own buffer, copying them only when
+    /// the buffer is shared, e.g. with the image cache
+    pub fn solve_owned(&self, bytes: Bytes) -> Result<Bytes> {
+        match &self.encryption {
+            Some((key_hex, iv_hex)) => {
+                Ok(decrypt_aes_cbc_in_place(Vec::from(bytes), key_hex, iv_hex)?.into())
+            }
+            None => Ok(bytes),
+        }
+    }
+
+    /// Like [`Solver::solve_owned`], keeping the dimensions from the page metadata
+    pub fn solve_owned_with_dimensions(&self, bytes: Bytes) -> Result<SolvedImage> {
+        Ok(SolvedImage::new(self.solve_owned(bytes)?, self.dimensions))
+    }
+
+    /// Decrypt and decode the pages in parallel, each with its own key and iv in hex.
+    /// The results are in the order of the pages, a page that fails doesn't fail the others.
+    pub fn solve_all<B: AsRef<[u8]> + Send>(
+        pages: Vec<(B, String, String)>,
+    ) -> Vec<Result<DynamicImage>> {
+        pages
+            .into_par_iter()
+            .map(|(bytes, key_hex, iv_hex)| Solver::new(&key_hex, &iv_hex).solve_from_bytes(bytes))
+            .collect()
+    }
 }
 
 impl Solver {
     /// decrypts the image AES-CBC encryption
     fn solve_buffer<B: AsRef<[u8]>>(&self, buffer: B) -> Result<Bytes> {
-        decrypt_aes_cbc(buffer.as_ref(), &self.key_hex, &self.iv_hex)
+        match &self.encryption {
+            Some((key_hex, iv_hex)) => {
+                Ok(decrypt_aes_cbc(buffer.as_ref(), key_hex, iv_hex)?.into())
+            }
+            None => Ok(Bytes::copy_from_slice(buffer.as_ref())),
+        }
     }
 }
 
@@ -38,4 +101,108 @@ impl ImageSolver for Solver {
         let image = image::load_from_memory(&buffer)?;
         Ok(image)
     }
+
+    fn solve_with_dimensions<T: AsRef<[u8]>>(&self, bytes: T) -> Result<SolvedImage> {
+        let buffer = self.solve_buffer(bytes)?;
+        Ok(SolvedImage::new(buffer, self.dimensions))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use image::GenericImageView;
+
+    use crate::{
+        testing,
+        viewer::fuz::data::{
+            web_manga_viewer::{viewer_page, ViewerPage},
+            Page,
+        },
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_unencrypted_page_is_unchanged() -> Result<()> {
+        let page = Page::new(
+            ViewerPage {
+                content: Some(viewer_page::Content::Image(viewer_page::Image {
+                    image_url: "/public.jpeg".to_string(),
+                    iv: None,
+                    encryption_key: None,
+                    ..Default::default()
+                })),
+            },
+            0,
+        )?;
+        let Page::Image(page) = page else {
+            bail!("Page is not an image");
+        };
+
+        let bytes = b"\xff\xd8 not encrypted";
+        let solved = Solver::from_page(&page)?.solve(bytes)?;
+        assert_eq!(&solved[..], &bytes[..]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_solve_owned_matches_solve() -> Result<()> {
+        let key_hex = "2e009856520e10917accae78097a2e13d9dd7a97d3a5ea293527ec9d0132bba3";
+        let iv_hex = "e8c7e042d6ba9fb85c128d5ceb64b82f";
+        let encrypted = Bytes::from(std::fs::read(testing::FUZ_PAGE)?);
+        let solver = Solver::new(key_hex, iv_hex);
+
+        let copied = solver.solve(&encrypted)?;
+        assert_eq!(solver.solve_owned(encrypted)?, copied);
+
+        // unencrypted bytes are handed back without a copy
+        let bytes = Bytes::from_static(b"\xff\xd8 not encrypted");
+        let solved = Solver::unencrypted().solve_owned(bytes.clone())?;
+        assert_eq!(solved.as_ptr(), bytes.as_ptr());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_solve_all_matches_sequential() -> Result<()> {
+        // pages of their own size and color, each encrypted with its own key and iv
+        let pages = (0..4u8)
+            .map(|i| {
+                let image = DynamicImage::ImageRgb8(image::RgbImage::from_pixel(
+                    8 + i as u32,
+                    12,
+                    image::Rgb([i * 60, 255 - i * 60, 128]),
+                ));
+                let plain = crate::utils::encode_image(&image, image::ImageFormat::Png)?;
+                let key_hex = format!("{:02x}", i + 1).repeat(32);
+                let iv_hex = format!("{:02x}", i + 101).repeat(16);
+                let encrypted = testing::encrypt_fuz_page(&plain, &key_hex, &iv_hex)?;
+                Ok((Bytes::from(encrypted), key_hex, iv_hex))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let sequential = pages
+            .iter()
+            .map(|(bytes, key_hex, iv_hex)| Solver::new(key_hex, iv_hex).solve_from_bytes(bytes))
+            .collect::<Result<Vec<_>>>()?;
+        let batch = Solver::solve_all(pages.clone())
+            .into_iter()
+            .collect::<Result<Vec<_>>>()?;
+
+        assert_eq!(batch, sequential);
+        let sizes = batch
+            .iter()
+            .map(|image| image.dimensions())
+            .collect::<Vec<_>>();
+        assert_eq!(sizes, [(8, 12), (9, 12), (10, 12), (11, 12)]);
+        // a page with the key of another page fails on its own
+        let mut swapped = pages;
+        swapped[1].1 = swapped[2].1.clone();
+        let results = Solver::solve_all(swapped);
+        assert!(results[0].is_ok() && results[2].is_ok());
+        assert!(results[1].is_err());
+
+        Ok(())
+    }
 }
@@ -7,12 +7,19 @@ use reqwest::header::{self, HeaderMap, HeaderValue};
 use reqwest::Response;
 use url::Url;
 
-use crate::auth::EmptyAuth;
-use crate::utils;
-use crate::viewer::{ViewerClient, ViewerConfig, ViewerConfigBuilder, ViewerWebsite};
+use crate::auth::{Auth, EmptyAuth, Netrc, NetrcAuth};
+use crate::viewer::{
+    authorization, env_var, lookup_host, send_request, ClientOptions, SitePreset, ViewerClient,
+    ViewerConfig, ViewerConfigBuilder, ViewerWebsite,
+};
 
 use super::data::{web_manga_viewer, Episode};
 
+pub use web_manga_viewer::device_info::DeviceType;
+
+/// Environment variable with the device secret sent to the API
+pub const SECRET_ENV: &str = "MANGA_FUZ_SECRET";
+
 /// ComicFuz website family
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Website {
@@ -42,7 +49,15 @@ impl ViewerWebsite<Website> for Website {
     }
 
     fn lookup(host: &str) -> Option<Website> {
-        HOST_TO_WEBSITE.get(host).map(|w| *w)
+        lookup_host(&HOST_TO_WEBSITE, host)
+    }
+
+    /// The API throttles many parallel requests
+    fn preset(&self) -> SitePreset {
+        SitePreset {
+            num_connections: 4,
+            referer: true,
+        }
     }
 }
 
@@ -70,74 +85,204 @@ pub struct Config {
     base_url: Url,
     api_url: Url,
     img_url: Url,
+    client_options: ClientOptions,
+    secret: Option<String>,
+    /// Device the API requests are sent as
+    device_info: web_manga_viewer::DeviceInfo,
+    /// `Authorization` sent to the site and the API
+    authorization: Option<String>,
+}
+
+impl Config {
+    /// Whether the url is on the site or the API, which get the `Authorization`
+    fn is_site(&self, url: &Url) -> bool {
+        url.origin() == self.base_url.origin() || url.origin() == self.api_url.origin()
+    }
 }
 
 impl ViewerConfig for Config {
     fn create_header(&self) -> Result<HeaderMap> {
-        let mut headers = HeaderMap::new();
-        headers.insert(
-            header::USER_AGENT,
-            HeaderValue::from_str(&utils::UserAgent::Bot.value())?,
-        );
-        headers.insert(
-            header::REFERER,
-            HeaderValue::from_str(&self.base_url.to_string())?,
-        );
-        Ok(headers)
+        self.client_options
+            .headers(&self.base_url, self.authorization.as_deref())
     }
 }
 
 #[derive(Debug, Clone)]
-pub struct ConfigBuilder {
+pub struct ConfigBuilder<A: Auth = EmptyAuth> {
     base_url: Url,
     api_url: Url,
     img_url: Url,
-    auth: Option<EmptyAuth>,
+    client_options: ClientOptions,
+    secret: Option<String>,
+    device_info: web_manga_viewer::DeviceInfo,
+    auth: Option<A>,
 }
 
-impl ConfigBuilder {
+impl Default for ConfigBuilder {
     /// comic-fuz.com default config
-    pub fn default() -> Self {
+    fn default() -> Self {
         Self {
             base_url: Website::ComicFuz.base_url(),
             api_url: Website::ComicFuz.api_url(),
             img_url: Website::ComicFuz.img_url(),
+            client_options: ClientOptions::new(Website::ComicFuz.preset()),
+            secret: None,
+            device_info: web_manga_viewer::DeviceInfo::web_pc(),
             auth: None,
         }
     }
+}
 
+impl ConfigBuilder {
     /// Create a new ConfigBuilder from preset
     pub fn new(website: Website) -> Self {
         Self {
             base_url: website.base_url(),
             api_url: website.api_url(),
             img_url: website.img_url(),
+            client_options: ClientOptions::new(website.preset()),
+            secret: None,
+            device_info: web_manga_viewer::DeviceInfo::web_pc(),
             auth: None,
         }
     }
 
+    /// Create a new ConfigBuilder from preset with the overrides in the environment.
+    ///
+    /// Reads `MANGA_FUZ_SECRET`, `MANGA_PROXY` and `MANGA_USER_AGENT`,
+    /// unset variables keep the defaults.
+    pub fn from_env(website: Website) -> Result<Self> {
+        Self::from_vars(website, env_var)
+    }
+
+    /// Like [`ConfigBuilder::from_env`], looking the variables up with `vars`
+    pub fn from_vars(website: Website, vars: impl Fn(&str) -> Option<String>) -> Result<Self> {
+        Ok(Self {
+            client_options: ClientOptions::from_vars(website.preset(), &vars)?,
+            secret: vars(SECRET_ENV),
+            ..Self::new(website)
+        })
+    }
+
     /// Create a new ConfigBuilder from custom url
     pub fn custom(base_url: String, api_url: String, img_url: String) -> Result<Self> {
         Ok(Self {
             base_url: Url::parse(&base_url)?,
             api_url: Url::parse(&api_url)?,
             img_url: Url::parse(&img_url)?,
+            client_options: ClientOptions::new(Website::ComicFuz.preset()),
+            secret: None,
+            device_info: web_manga_viewer::DeviceInfo::web_pc(),
             auth: None,
         })
     }
 }
 
-impl ViewerConfigBuilder<Config, EmptyAuth> for ConfigBuilder {
-    fn set_auth(&mut self, auth: EmptyAuth) -> &mut Self {
+impl<A: Auth> ConfigBuilder<A> {
+    /// Authenticate every request with this auth, e.g. the credentials of a netrc file
+    pub fn with_auth<B: Auth>(self, auth: B) -> ConfigBuilder<B> {
+        self.replace_auth(Some(auth))
+    }
+
+    /// Authenticate with the credentials of the host of the site in the netrc, if it has any
+    pub fn with_netrc(self, netrc: &Netrc) -> ConfigBuilder<NetrcAuth> {
+        let auth = self.base_url.host_str().and_then(|host| netrc.auth(host));
+        self.replace_auth(auth)
+    }
+
+    fn replace_auth<B: Auth>(self, auth: Option<B>) -> ConfigBuilder<B> {
+        ConfigBuilder {
+            base_url: self.base_url,
+            api_url: self.api_url,
+            img_url: self.img_url,
+            client_options: self.client_options,
+            secret: self.secret,
+            device_info: self.device_info,
+            auth,
+        }
+    }
+
+    /// Fetch the images from this CDN instead of the one of the website, e.g. a mirror
+    pub fn with_img_url(self, img_url: &str) -> Result<Self> {
+        Ok(Self {
+            img_url: Url::parse(img_url)?,
+            ..self
+        })
+    }
+
+    /// Send the device secret of a logged in account with the API requests
+    pub fn with_secret(self, secret: &str) -> Self {
+        Self {
+            secret: Some(secret.to_string()),
+            ..self
+        }
+    }
+
+    /// Send the API requests as this kind of device instead of a browser, e.g. to get
+    /// the images served to the apps
+    pub fn with_device_type(self, device_type: DeviceType) -> Self {
+        Self {
+            device_info: web_manga_viewer::DeviceInfo {
+                device_type: device_type.into(),
+                ..self.device_info
+            },
+            ..self
+        }
+    }
+
+    /// Send the API requests as a tablet
+    pub fn with_tablet(self, is_tablet: bool) -> Self {
+        Self {
+            device_info: web_manga_viewer::DeviceInfo {
+                is_tablet,
+                ..self.device_info
+            },
+            ..self
+        }
+    }
+
+    /// Version of the app the API requests claim to come from
+    pub fn with_app_ver(self, app_ver: &str) -> Self {
+        Self {
+            device_info: web_manga_viewer::DeviceInfo {
+                app_ver: app_ver.to_string(),
+                ..self.device_info
+            },
+            ..self
+        }
+    }
+
+    /// Version of the OS the API requests claim to come from
+    pub fn with_os_ver(self, os_ver: &str) -> Self {
+        Self {
+            device_info: web_manga_viewer::DeviceInfo {
+                os_ver: os_ver.to_string(),
+                ..self.device_info
+            },
+            ..self
+        }
+    }
+}
+
+impl<A: Auth> ViewerConfigBuilder<Config, A> for ConfigBuilder<A> {
+    fn set_auth(&mut self, auth: A) -> &mut Self {
         self.auth = Some(auth);
         self
     }
 
+    fn client_options(&mut self) -> &mut ClientOptions {
+        &mut self.client_options
+    }
+
     fn build(&self) -> Config {
         Config {
             base_url: self.base_url.clone(),
             api_url: self.api_url.clone(),
             img_url: self.img_url.clone(),
+            client_options: self.client_options.clone(),
+            secret: self.secret.clone(),
+            device_info: self.device_info.clone(),
+            authorization: authorization(self.auth.as_ref()),
         }
     }
 }
@@ -151,7 +296,7 @@ pub struct Client {
 
 impl ViewerClient<Config> for Client {
     fn new(config: Config) -> Self {
-        let client = reqwest::Client::new();
+        let client = config.client_options.http_client();
         Self { client, config }
     }
 
@@ -162,18 +307,16 @@ impl ViewerClient<Config> for Client {
         body: Option<B>,
         headers: Option<HeaderMap>,
     ) -> Result<Response> {
-        let mut req = self
-            .client
-            .request(method, url)
-            .headers(self.config.create_header()?);
-        if let Some(headers) = headers {
-            req = req.headers(headers);
-        }
-        if let Some(body) = body {
-            req = req.body(body);
-        }
-        let res = req.send().await?.error_for_status()?;
-        Ok(res)
+        let is_site = self.config.is_site(&url);
+        let request = self.client.request(method, url);
+        send_request(
+            request,
+            self.config.create_header()?,
+            is_site,
+            body,
+            headers,
+        )
+        .await
     }
 
     /// Parse episode id from url
@@ -225,31 +368,215 @@ impl Client {
 
     /// Get episode
     pub async fn get_episode(&self, episode_id: &str) -> Result<Episode> {
-        let message = web_manga_viewer::WebMangaViewerRequest::free_chapter_id(episode_id.parse()?);
+        let mut message =
+            web_manga_viewer::WebMangaViewerRequest::free_chapter_id(episode_id.parse()?)
+                .with_device_info(self.config.device_info.clone());
+        if let Some(secret) = &self.config.secret {
+            message = message.with_secret(secret);
+        }
         let res = self.api_v1_web_manga_viewer(message).await?;
-        let episode = Episode::from(res);
+        let episode = Episode::try_from(res)?.with_base_url(self.config.base_url.clone());
         Ok(episode)
     }
 }
 
 #[cfg(test)]
 mod test {
-    use std::sync::Arc;
+    use std::{collections::HashMap, io::Write, sync::Arc};
 
-    use anyhow::bail;
+    use anyhow::{bail, Context};
+    use flate2::{write::GzEncoder, Compression};
     use futures::StreamExt;
+    use image::GenericImageView;
     use indicatif::ParallelProgressIterator;
+    use prost::Message;
     use rayon::iter::{IntoParallelIterator, IntoParallelRefIterator, ParallelIterator};
+    use wiremock::{
+        matchers::{header_regex, method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
 
     use crate::{
+        auth::BearerAuth,
         data::{MangaEpisode, MangaPage},
         progress::ProgressConfig,
         solver::ImageSolver,
-        viewer::fuz::{data::Page, solver::Solver},
+        testing,
+        viewer::fuz::{
+            data::{
+                web_manga_viewer::{viewer_page, ViewerPage},
+                Page,
+            },
+            solver::Solver,
+        },
+        viewer::PROXY_ENV,
     };
 
     use super::*;
 
+    #[test]
+    fn test_lookup_with_www() {
+        for host in ["comic-fuz.com", "www.comic-fuz.com"] {
+            assert_eq!(Website::lookup(host), Some(Website::ComicFuz));
+        }
+    }
+
+    #[test]
+    fn test_config_from_vars() -> Result<()> {
+        let vars = HashMap::from([(SECRET_ENV, "device-secret")]);
+        let lookup = |key: &str| vars.get(key).map(|value| value.to_string());
+
+        let config = ConfigBuilder::from_vars(Website::ComicFuz, lookup)?.build();
+        assert_eq!(config.secret.as_deref(), Some("device-secret"));
+
+        let config = ConfigBuilder::from_vars(Website::ComicFuz, |_| None)?.build();
+        assert_eq!(config.secret, None);
+
+        let proxy = HashMap::from([(PROXY_ENV, "not a url")]);
+        let lookup = |key: &str| proxy.get(key).map(|value| value.to_string());
+        assert!(ConfigBuilder::from_vars(Website::ComicFuz, lookup).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_bearer_auth_header() -> Result<()> {
+        let mut builder = ConfigBuilder::default().with_auth(BearerAuth::new("token123"));
+        let headers = builder.build().create_header()?;
+        assert_eq!(headers[header::AUTHORIZATION], "Bearer token123");
+
+        builder.set_auth(BearerAuth::new("renewed"));
+        let headers = builder.build().create_header()?;
+        assert_eq!(headers[header::AUTHORIZATION], "Bearer renewed");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_image_cdn_gets_no_auth() -> Result<()> {
+        let server = testing::fuz_server().await?;
+        let cdn = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/page.jpg"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(b"jpeg".to_vec()))
+            .mount(&cdn)
+            .await;
+        let client = Client::new(
+            ConfigBuilder::custom(server.uri(), server.uri(), cdn.uri())?
+                .with_auth(BearerAuth::new("token123"))
+                .build(),
+        );
+
+        client.get_episode(testing::FUZ_CHAPTER_ID).await?;
+        client
+            .get(client.image_url("/page.jpg".to_string())?)
+            .await?;
+
+        let requests = server.received_requests().await.unwrap_or_default();
+        assert!(requests
+            .iter()
+            .all(|request| request.headers["authorization"] == "Bearer token123"));
+        let requests = cdn.received_requests().await.unwrap_or_default();
+        assert_eq!(requests.len(), 1);
+        assert!(!requests[0].headers.contains_key("authorization"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_device_type_is_sent() -> Result<()> {
+        let server = testing::fuz_server().await?;
+        let config = ConfigBuilder::custom(server.uri(), server.uri(), server.uri())?
+            .with_device_type(DeviceType::Android)
+            .with_tablet(true)
+            .with_app_ver("2.0.0")
+            .with_os_ver("14")
+            .with_secret("device-secret")
+            .build();
+        let client = Client::new(config);
+
+        client.get_episode(testing::FUZ_CHAPTER_ID).await?;
+
+        let requests = server.received_requests().await.unwrap_or_default();
+        let request = requests
+            .iter()
+            .find(|request| request.url.path() == "/v1/web_manga_viewer")
+            .context("No viewer request")?;
+        let message = web_manga_viewer::WebMangaViewerRequest::decode(request.body.as_slice())?;
+        let device_info = message.device_info.context("No device info")?;
+        assert_eq!(device_info.device_type(), DeviceType::Android);
+        assert!(device_info.is_tablet);
+        assert_eq!(device_info.app_ver, "2.0.0");
+        assert_eq!(device_info.os_ver, "14");
+        assert_eq!(device_info.secret, "device-secret");
+
+        // a browser unless chosen
+        let config = ConfigBuilder::default().build();
+        assert_eq!(config.device_info.device_type(), DeviceType::Browser);
+        assert!(!config.device_info.is_tablet);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_fetch_gzip_protobuf() -> Result<()> {
+        let page = ViewerPage {
+            content: Some(viewer_page::Content::Image(viewer_page::Image {
+                image_url: "/sample.jpeg.enc".to_string(),
+                ..Default::default()
+            })),
+        };
+        let mut gzip = GzEncoder::new(Vec::new(), Compression::default());
+        gzip.write_all(&page.encode_to_vec())?;
+
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v1/web_manga_viewer"))
+            .and(header_regex("accept-encoding", "gzip"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("content-encoding", "gzip")
+                    .set_body_bytes(gzip.finish()?),
+            )
+            .mount(&server)
+            .await;
+        let config = ConfigBuilder::custom(server.uri(), server.uri(), server.uri())?.build();
+        let client = Client::new(config);
+
+        let url = Url::parse(&format!("{}/v1/web_manga_viewer", server.uri()))?;
+        let decoded: ViewerPage = client.fetch_protobuf(url, ViewerPage::default()).await?;
+        assert_eq!(decoded, page);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_get_recorded_episode() -> Result<()> {
+        let server = testing::fuz_server().await?;
+        let config = ConfigBuilder::custom(server.uri(), server.uri(), server.uri())?.build();
+        let client = Client::new(config);
+
+        let episode = client.get_episode(testing::FUZ_CHAPTER_ID).await?;
+        assert_eq!(episode.id(), testing::FUZ_CHAPTER_ID);
+        assert_eq!(episode.index(), 1);
+
+        let pages = episode.pages();
+        assert_eq!(pages.iter().filter(|page| page.is_image()).count(), 3);
+        let Page::Image(page) = &pages[0] else {
+            bail!("Page is not an image");
+        };
+        let url = client.image_url(pages[0].image_path()?)?;
+        let solved =
+            Solver::from_page(page)?.solve_with_dimensions(client.fetch_bytes(url).await?)?;
+        assert_eq!(solved.dimensions(), Some((1350, 1920)));
+        assert_eq!(
+            image::load_from_memory(solved.bytes())?.dimensions(),
+            (1350, 1920)
+        );
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_fetch_protobuf() -> Result<()> {
         let chapter_ids = vec!["2443", "36429", "45054", "57443"];
@@ -310,7 +637,7 @@ mod test {
                 if let Page::Image(img) = page {
                     println!("Solving page {}", page.index()?);
                     println!("page: {:?}", page);
-                    let solver = Solver::new(img.encryption_key(), img.encryption_iv());
+                    let solver = Solver::from_page(img)?;
                     let image = solver.solve(bytes)?;
                     Result::<_>::Ok((image, page.index()?))
                 } else {
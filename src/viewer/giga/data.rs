@@ -2,13 +2,16 @@ use std::fmt;
 
 use anyhow::{bail, Result};
 use chrono::{DateTime, Utc};
-use serde::de::{SeqAccess, Visitor};
-use serde::{Deserialize, Deserializer, Serialize};
+use serde::de::{self, SeqAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use url::Url;
 
-use crate::data::{MangaEpisode, MangaPage};
+use crate::data::{MangaEpisode, MangaPage, ScrollDirection};
 
-/// ChojuGiga viewer page struct
+/// ChojuGiga viewer page struct.
+///
+/// Pages that are not images, e.g. the back matter with credits and ads, are kept as `Other`
+/// but not downloaded, so an episode can have fewer pages than the site shows.
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 #[serde(rename_all = "camelCase")]
 #[serde(untagged)]
@@ -46,18 +49,16 @@ impl<'de> Visitor<'de> for PageVisitor {
     {
         let mut pages = Vec::new();
         let mut index = 0;
-        while let Some(mut page) = seq.next_element::<Page>()? {
+        while let Some(page) = seq.next_element::<Page>()? {
             match page {
-                Page::Image(ref mut image_page) => {
+                Page::Image(image_page) => {
                     pages.push(Page::Image(ImagePage {
-                        height: image_page.height,
-                        width: image_page.width,
-                        url: image_page.url.clone(),
-                        index: index,
+                        index,
+                        ..image_page
                     }));
                     index += 1;
                 }
-                _ => {}
+                other => pages.push(other),
             }
         }
         Ok(pages)
@@ -71,15 +72,6 @@ where
     deserializer.deserialize_seq(PageVisitor)
 }
 
-impl Page {
-    pub fn url(&self) -> Result<Url> {
-        match self {
-            Page::Image(ImagePage { url, .. }) => Ok(url.clone()),
-            _ => bail!("Page is not an image"),
-        }
-    }
-}
-
 impl MangaPage for Page {
     fn index(&self) -> Result<usize> {
         match self {
@@ -89,16 +81,32 @@ impl MangaPage for Page {
     }
 
     fn is_image(&self) -> bool {
+        matches!(self, Page::Image(_))
+    }
+
+    fn dimensions(&self) -> Option<(u32, u32)> {
+        match self {
+            Page::Image(ImagePage { width, height, .. }) if *width > 0 && *height > 0 => {
+                Some((*width, *height))
+            }
+            _ => None,
+        }
+    }
+
+    fn url(&self) -> Result<Url> {
         match self {
-            Page::Image(_) => true,
-            _ => false,
+            Page::Image(ImagePage { url, .. }) => Ok(url.clone()),
+            _ => bail!("Page is not an image"),
         }
     }
 }
 
-/// ChojuGiga viewer episode struct
+/// ChojuGiga viewer episode struct.
+///
+/// GigaViewer wraps the episode in `readableProduct` or `episode`, sometimes next to
+/// other top-level keys, and some responses have the bare episode. All of them parse.
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
-#[serde(rename_all = "camelCase")]
+#[serde(remote = "Self", rename_all = "camelCase")]
 pub enum Episode {
     #[serde(alias = "readableProduct", rename_all = "camelCase")]
     ReadableProduct {
@@ -117,6 +125,60 @@ pub enum Episode {
     },
 }
 
+/// Top-level keys the episode is wrapped in
+const EPISODE_WRAPPERS: [&str; 2] = ["readableProduct", "episode"];
+
+impl Episode {
+    /// Take the episode out of the wrappers and ignore any other top-level keys
+    fn unwrap_shape(mut value: serde_json::Value) -> Result<serde_json::Value, String> {
+        loop {
+            let Some(map) = value.as_object_mut() else {
+                return Ok(value);
+            };
+            if let Some(inner) = EPISODE_WRAPPERS.iter().find_map(|key| map.remove(*key)) {
+                value = inner;
+            } else if map.contains_key("typeName") {
+                return Ok(value);
+            } else {
+                return Err(format!(
+                    "unknown episode shape, expected one of {:?}",
+                    EPISODE_WRAPPERS
+                ));
+            }
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Episode {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = serde_json::Value::deserialize(deserializer)?;
+        let product = Episode::unwrap_shape(value).map_err(de::Error::custom)?;
+        Episode::deserialize(serde_json::json!({ "readableProduct": product }))
+            .map_err(de::Error::custom)
+    }
+}
+
+impl Serialize for Episode {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        Episode::serialize(self, serializer)
+    }
+}
+
+/// The episode can't be read for free, e.g. it is paywalled or not released yet
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EpisodeNotPublic {
+    pub id: String,
+    pub title: String,
+}
+
+impl fmt::Display for EpisodeNotPublic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Episode {} ({}) is not public", self.id, self.title)
+    }
+}
+
+impl std::error::Error for EpisodeNotPublic {}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 #[serde(rename_all = "camelCase")]
 pub struct EpisodePageStructure {
@@ -155,9 +217,70 @@ impl Episode {
             Episode::ReadableProduct { url, .. } => url.clone(),
         }
     }
+
+    pub fn is_public(&self) -> bool {
+        match self {
+            Episode::ReadableProduct { is_public, .. } => *is_public,
+        }
+    }
+
+    /// Number of pages that are not images and are left out of `pages`
+    pub fn other_page_count(&self) -> usize {
+        self.other_page_positions().len()
+    }
+
+    /// Number of image pages before each page that is not an image
+    pub fn other_page_positions(&self) -> Vec<usize> {
+        let Episode::ReadableProduct { page_structure, .. } = self;
+        let Some(EpisodePageStructure { pages, .. }) = page_structure else {
+            return Vec::new();
+        };
+        let mut images = 0;
+        let mut positions = Vec::new();
+        for page in pages {
+            match page.is_image() {
+                true => images += 1,
+                false => positions.push(images),
+            }
+        }
+        positions
+    }
+
+    /// Fail with `EpisodeNotPublic` unless the episode is free to read
+    pub fn ensure_public(&self) -> Result<(), EpisodeNotPublic> {
+        match self {
+            Episode::ReadableProduct {
+                id,
+                title,
+                is_public: false,
+                ..
+            } => Err(EpisodeNotPublic {
+                id: id.clone(),
+                title: title.clone(),
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn scroll_direction(&self) -> ScrollDirection {
+        match self {
+            Episode::ReadableProduct { page_structure, .. } => {
+                match page_structure.as_ref().map(|s| &s.reading_direction) {
+                    Some(ReadingDirection::RightToLeft) => ScrollDirection::RightToLeft,
+                    Some(ReadingDirection::LeftToRight) => ScrollDirection::LeftToRight,
+                    Some(ReadingDirection::TopToBottom) => ScrollDirection::TopToBottom,
+                    None => ScrollDirection::Unknown,
+                }
+            }
+        }
+    }
 }
 
 impl MangaEpisode<Page> for Episode {
+    fn url(&self) -> Option<Url> {
+        Some(Episode::url(self))
+    }
+
     fn id(&self) -> String {
         match self {
             Episode::ReadableProduct { id, .. } => id.clone(),
@@ -180,7 +303,11 @@ impl MangaEpisode<Page> for Episode {
         match self {
             Episode::ReadableProduct { page_structure, .. } => {
                 if let Some(EpisodePageStructure { pages, .. }) = page_structure {
-                    pages.clone()
+                    pages
+                        .iter()
+                        .filter(|page| page.is_image())
+                        .cloned()
+                        .collect()
                 } else {
                     Vec::new()
                 }
@@ -189,9 +316,226 @@ impl MangaEpisode<Page> for Episode {
     }
 }
 
+/// ChojuGiga series with the episodes listed in its RSS feed
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 #[serde(rename_all = "camelCase")]
 pub struct Series {
     id: String,
     title: String,
+    description: Option<String>,
+    #[serde(default)]
+    episodes: Vec<SeriesEpisode>,
+}
+
+/// An episode listed in a series feed, without its pages
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SeriesEpisode {
+    id: String,
+    title: String,
+    url: Url,
+    published_at: Option<DateTime<Utc>>,
+}
+
+impl Series {
+    /// Read the series from its RSS feed. Items that don't link to an episode are skipped.
+    #[cfg(feature = "giga")]
+    pub fn from_channel(id: &str, channel: &rss::Channel) -> Self {
+        let episodes = channel
+            .items()
+            .iter()
+            .filter_map(SeriesEpisode::from_item)
+            .collect();
+        Series {
+            id: id.to_string(),
+            title: channel.title().to_string(),
+            description: Some(channel.description().to_string()).filter(|d| !d.is_empty()),
+            episodes,
+        }
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn title(&self) -> &str {
+        &self.title
+    }
+
+    pub fn description(&self) -> Option<&str> {
+        self.description.as_deref()
+    }
+
+    /// Episodes in the order of the feed, the latest first
+    pub fn episodes(&self) -> &[SeriesEpisode] {
+        &self.episodes
+    }
+}
+
+impl SeriesEpisode {
+    #[cfg(feature = "giga")]
+    fn from_item(item: &rss::Item) -> Option<Self> {
+        let url = Url::parse(item.link()?).ok()?;
+        let mut segments = url.path_segments()?;
+        let id = match (segments.next(), segments.next()) {
+            (Some("episode"), Some(id)) if !id.is_empty() => id.to_string(),
+            _ => return None,
+        };
+        let published_at = item
+            .pub_date()
+            .and_then(|date| DateTime::parse_from_rfc2822(date).ok())
+            .map(|date| date.with_timezone(&Utc));
+
+        Some(SeriesEpisode {
+            id,
+            title: item.title().unwrap_or_default().to_string(),
+            url,
+            published_at,
+        })
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn title(&self) -> &str {
+        &self.title
+    }
+
+    pub fn url(&self) -> &Url {
+        &self.url
+    }
+
+    pub fn published_at(&self) -> Option<DateTime<Utc>> {
+        self.published_at
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::testing;
+
+    use super::*;
+
+    fn episode_json(pages: serde_json::Value) -> serde_json::Value {
+        serde_json::json!({
+            "readableProduct": {
+                "id": "1",
+                "title": "mock",
+                "typeName": "episode",
+                "isPublic": true,
+                "number": 1,
+                "permalink": "https://shonenjumpplus.com/episode/1",
+                "pageStructure": {
+                    "choJuGiga": "baku",
+                    "readingDirection": "rtl",
+                    "pages": pages,
+                },
+            }
+        })
+    }
+
+    #[test]
+    fn test_malformed_episode_is_error() {
+        let mut missing_id = episode_json(serde_json::json!([]));
+        missing_id["readableProduct"]
+            .as_object_mut()
+            .unwrap()
+            .remove("id");
+        assert!(serde_json::from_value::<Episode>(missing_id).is_err());
+
+        let bad_page = episode_json(serde_json::json!([{ "src": "not a url", "width": 1 }]));
+        assert!(serde_json::from_value::<Episode>(bad_page).is_err());
+
+        assert!(serde_json::from_str::<Episode>("{\"readableProduct\": null}").is_err());
+        assert!(serde_json::from_str::<Episode>("{\"viewer\": {}}").is_err());
+    }
+
+    #[test]
+    fn test_parse_alternate_shapes() -> Result<()> {
+        let expected: Episode =
+            serde_json::from_str(&std::fs::read_to_string(testing::GIGA_EPISODE)?)?;
+
+        for fixture in [
+            testing::GIGA_EPISODE_EXTRA_KEYS,
+            testing::GIGA_EPISODE_WRAPPED,
+            testing::GIGA_EPISODE_BARE,
+        ] {
+            let episode: Episode = serde_json::from_str(&std::fs::read_to_string(fixture)?)?;
+            assert_eq!(episode, expected, "{}", fixture);
+        }
+        // serialized episodes parse back
+        let json = serde_json::to_string(&expected)?;
+        assert_eq!(serde_json::from_str::<Episode>(&json)?, expected);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_count_other_pages() -> Result<()> {
+        let image = |i: usize| {
+            serde_json::json!({
+                "type": "main",
+                "src": format!("https://example.com/page/{}", i),
+                "width": 40,
+                "height": 60,
+            })
+        };
+        let episode: Episode = serde_json::from_value(episode_json(serde_json::json!([
+            { "type": "other" },
+            image(0),
+            image(1),
+            { "type": "backMatter", "linkPosition": "last" },
+        ])))?;
+
+        let pages = episode.pages();
+        assert_eq!(pages.len(), 2);
+        assert_eq!(pages[1].index()?, 1);
+        assert_eq!(episode.other_page_count(), 2);
+        assert_eq!(episode.other_page_positions(), vec![0, 2]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_page_dimensions() -> Result<()> {
+        let episode: Episode =
+            serde_json::from_str(&std::fs::read_to_string(testing::GIGA_EPISODE)?)?;
+
+        let dimensions = episode
+            .pages()
+            .iter()
+            .map(|page| page.dimensions())
+            .collect::<Vec<_>>();
+        assert_eq!(dimensions, vec![Some((595, 842)); 3]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_page_url() -> Result<()> {
+        let episode: Episode =
+            serde_json::from_str(&std::fs::read_to_string(testing::GIGA_EPISODE)?)?;
+        let page = &episode.pages()[2];
+
+        let expected = "https://cdn-ak-img.shonenjumpplus.com/public/page/2/9324103625676410700-2";
+        assert_eq!(page.url()?.as_str(), expected);
+        // absolute urls ignore the base
+        let base = Url::parse("https://example.com")?;
+        assert_eq!(page.resolved_url(&base)?.as_str(), expected);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_partial_episode() -> Result<()> {
+        let mut episode = episode_json(serde_json::json!([]));
+        episode["readableProduct"]["pageStructure"] = serde_json::Value::Null;
+        let episode = serde_json::from_value::<Episode>(episode)?;
+
+        assert!(episode.pages().is_empty());
+        assert_eq!(episode.scroll_direction(), ScrollDirection::Unknown);
+
+        Ok(())
+    }
 }
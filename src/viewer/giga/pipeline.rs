@@ -1,23 +1,35 @@
-use std::{path::Path, sync::Arc};
+use std::{future::Future, path::Path, sync::Mutex};
 
-use anyhow::{Context, Ok, Result};
-use futures::{stream, StreamExt, TryStreamExt};
-use image::DynamicImage;
-use rayon::slice::ParallelSliceMut;
+use anyhow::{anyhow, Context, Ok, Result};
+use futures::{future::BoxFuture, stream, Stream, StreamExt, TryStreamExt};
+use image::{DynamicImage, GenericImageView, Rgb, RgbImage};
 use url::Url;
 
-#[cfg(feature = "pdf")]
-use crate::io::pdf::PdfWriter;
 use crate::{
-    data::MangaEpisode,
-    io::{raw::RawWriter, zip::ZipWriter, EpisodeWriter},
-    pipeline::{EpisodePipeline, EpisodePipelineBuilder, SaveFormat, WriterConifg},
+    cache::ImageCache,
+    color,
+    data::{MangaEpisode, MangaPage},
+    io::{contact_sheet::ContactSheetWriter, webtoon::WebtoonWriter, EpisodeWriter, PageImage},
+    limit::{ByteLimiter, BytePermit, HostLimiter, PageFetcher, RateLimiter, Turn},
+    pipeline::{
+        collect_pages, stream_pages, Defaults, DownloadReport, EpisodePipeline,
+        EpisodePipelineBuilder, ErrorPolicy, HeldPage, NoImagePages, OutputHook, SaveFormat,
+        SeriesLayout, SolveKind, WriterConifg,
+    },
     progress::ProgressConfig,
-    solver::ImageSolver,
-    utils::Bytes,
-    viewer::{ViewerClient, ViewerConfigBuilder},
+    retry::{solve_with_refetch, RetryBudget},
+    solver::{ImageSolver, SolvedImage},
+    transform::{self, Transforms},
+    utils::{self, Bytes},
+    viewer::{SitePreset, ViewerClient, ViewerConfigBuilder, ViewerWebsite},
 };
 
+#[cfg(feature = "giga")]
+use crate::pipeline::SeriesState;
+
+#[cfg(feature = "giga")]
+use super::data::Series;
+
 use super::{
     data::{Episode, Page},
     solver::Solver,
@@ -31,7 +43,36 @@ pub struct Pipeline {
     progress: ProgressConfig,
     writer_config: WriterConifg,
     num_threads: usize,
-    num_connections: usize,
+    /// `None` uses the site preset
+    num_connections: Option<usize>,
+    preset: SitePreset,
+    /// The image cache and the limiters of the fetched pages
+    fetcher: PageFetcher,
+    max_solve_retries: usize,
+    /// `None` leaves the retries of the episode unlimited
+    retry_budget: Option<usize>,
+    output_hook: Option<OutputHook>,
+    convert_to_srgb: bool,
+    contact_sheet: Option<ContactSheetWriter>,
+    best_effort: bool,
+    error_policy: ErrorPolicy,
+    transforms: Transforms,
+    sequential: bool,
+    /// Write the fetched pages without solving them
+    no_solve: bool,
+    /// Download the episodes of a series even when the state marks them done
+    #[cfg_attr(not(feature = "giga"), allow(dead_code))]
+    force: bool,
+    #[cfg_attr(not(feature = "giga"), allow(dead_code))]
+    series_layout: SeriesLayout,
+    /// Package every this many episodes of a series into a volume
+    #[cfg_attr(not(feature = "giga"), allow(dead_code))]
+    volume_size: Option<usize>,
+    /// Write a blank page for every page that is not an image instead of skipping it
+    other_page_placeholders: bool,
+    /// Warn about solved pages that still look scrambled
+    verify_solved: bool,
+    solver: Solver,
 }
 
 impl Default for Pipeline {
@@ -41,7 +82,25 @@ impl Default for Pipeline {
             progress: ProgressConfig::default(),
             writer_config: WriterConifg::new(SaveFormat::Raw, image::ImageFormat::Png),
             num_threads: num_cpus::get(),
-            num_connections: 8,
+            num_connections: None,
+            preset: Website::ShonenJumpPlus.preset(),
+            fetcher: PageFetcher::default(),
+            max_solve_retries: 0,
+            retry_budget: None,
+            output_hook: None,
+            convert_to_srgb: false,
+            contact_sheet: None,
+            best_effort: false,
+            error_policy: ErrorPolicy::FailFast,
+            transforms: Vec::new(),
+            sequential: false,
+            no_solve: false,
+            force: false,
+            series_layout: SeriesLayout::Flat,
+            volume_size: None,
+            other_page_placeholders: false,
+            verify_solved: false,
+            solver: Solver::default(),
         }
     }
 }
@@ -54,21 +113,125 @@ impl Pipeline {
         num_threads: usize,
         num_connections: usize,
     ) -> Self {
-        let client = Client::new(ConfigBuilder::new(website).build());
+        let client = Client::new(ConfigBuilder::new(website.clone()).build());
         Self {
             client,
             progress,
             writer_config,
             num_threads,
-            num_connections,
+            num_connections: Some(num_connections),
+            preset: website.preset(),
+            fetcher: PageFetcher::default(),
+            max_solve_retries: 0,
+            retry_budget: None,
+            output_hook: None,
+            convert_to_srgb: false,
+            contact_sheet: None,
+            best_effort: false,
+            error_policy: ErrorPolicy::FailFast,
+            transforms: Vec::new(),
+            sequential: false,
+            no_solve: false,
+            force: false,
+            series_layout: SeriesLayout::Flat,
+            volume_size: None,
+            other_page_placeholders: false,
+            verify_solved: false,
+            solver: Solver::default(),
+        }
+    }
+
+    /// Override the deobfuscation parameters, mainly for diagnosing broken pages
+    pub fn set_solver(self, solver: Solver) -> Self {
+        Self { solver, ..self }
+    }
+
+    /// The solver encoding the solved pages in the format of the writer
+    fn page_solver(&self) -> Solver {
+        let solver = self.solver.clone();
+        solver.with_image_format(self.writer_config.image_format())
+    }
+
+    /// Check every solved page for tiles still out of place and warn about it.
+    /// Off by default, the check costs a pass over the tile boundaries of each page.
+    pub fn set_verify_solved(self, verify_solved: bool) -> Self {
+        Self {
+            verify_solved,
+            ..self
+        }
+    }
+
+    /// Pipeline for the website with the settings shared by every download
+    pub fn from_defaults(website: Website, defaults: &Defaults) -> Result<Self> {
+        let mut builder = ConfigBuilder::new(website.clone());
+        if let Some(user_agent) = defaults.user_agent() {
+            builder = builder.with_user_agent(user_agent);
+        }
+        if let Some(proxy) = defaults.proxy() {
+            builder = builder.with_proxy(proxy.as_str())?;
+        }
+        let config = match defaults.netrc() {
+            Some(netrc) => builder.with_netrc(netrc).try_build()?,
+            None => builder.try_build()?,
+        };
+        let pipe = Self::default()
+            .set_website(website)
+            .with_client(Client::new(config))
+            .set_progress(defaults.progress())
+            .set_writer_config(defaults.writer_config())
+            .set_num_threads(defaults.num_threads());
+        Ok(match defaults.num_connections() {
+            Some(num_connections) => pipe.set_num_connections(num_connections),
+            None => pipe,
+        })
+    }
+
+    /// Send the requests with this client instead of the one of the website,
+    /// e.g. one configured by a custom login flow
+    pub fn with_client(self, client: Client) -> Self {
+        Self { client, ..self }
+    }
+
+    /// Write a blank page in place of every page that is not an image, e.g. the credits,
+    /// so the page count matches the site
+    pub fn set_other_page_placeholders(self, other_page_placeholders: bool) -> Self {
+        Self {
+            other_page_placeholders,
+            ..self
+        }
+    }
+
+    /// Download every episode of a series again, ignoring the recorded state
+    pub fn set_force(self, force: bool) -> Self {
+        Self { force, ..self }
+    }
+
+    /// Lay out the episodes of a series for a library server, e.g. Komga
+    pub fn set_series_layout(self, series_layout: SeriesLayout) -> Self {
+        Self {
+            series_layout,
+            ..self
+        }
+    }
+
+    /// Package every `volume_size` episodes of a series into one file, the last volume
+    /// taking the rest. `None` saves every episode on its own.
+    pub fn set_volume_size(self, volume_size: Option<usize>) -> Self {
+        Self {
+            volume_size,
+            ..self
         }
     }
 }
 
 impl EpisodePipelineBuilder<Website, Page, Episode, Pipeline> for Pipeline {
     fn set_website(self, website: Website) -> Self {
-        let client = Client::new(ConfigBuilder::new(website).build());
-        Self { client, ..self }
+        let client = Client::new(ConfigBuilder::new(website.clone()).build());
+        Self {
+            client,
+            preset: website.preset(),
+            ..self
+        }
     }
 
     fn set_progress(self, progress: ProgressConfig) -> Self {
@@ -91,10 +254,113 @@ impl EpisodePipelineBuilder<Website, Page, Episode, Pipeline> for Pipeline {
 
     fn set_num_connections(self, num_connections: usize) -> Self {
         Self {
-            num_connections,
+            num_connections: Some(num_connections),
+            ..self
+        }
+    }
+
+    fn set_max_in_flight_bytes(self, max_in_flight_bytes: Option<usize>) -> Self {
+        Self {
+            fetcher: self
+                .fetcher
+                .with_byte_limiter(max_in_flight_bytes.map(ByteLimiter::new)),
+            ..self
+        }
+    }
+
+    fn set_max_connections_per_host(self, max_connections_per_host: Option<usize>) -> Self {
+        Self {
+            fetcher: self
+                .fetcher
+                .with_host_limiter(max_connections_per_host.map(HostLimiter::new)),
+            ..self
+        }
+    }
+
+    fn set_max_solve_retries(self, max_solve_retries: usize) -> Self {
+        Self {
+            max_solve_retries,
+            ..self
+        }
+    }
+
+    fn set_convert_to_srgb(self, convert_to_srgb: bool) -> Self {
+        Self {
+            convert_to_srgb,
+            ..self
+        }
+    }
+
+    fn set_contact_sheet(self, contact_sheet: Option<ContactSheetWriter>) -> Self {
+        Self {
+            contact_sheet,
+            ..self
+        }
+    }
+
+    fn set_best_effort(self, best_effort: bool) -> Self {
+        Self {
+            best_effort,
+            ..self
+        }
+    }
+
+    fn set_error_policy(self, error_policy: ErrorPolicy) -> Self {
+        Self {
+            error_policy,
+            ..self
+        }
+    }
+
+    fn set_transforms(self, transforms: Transforms) -> Self {
+        Self { transforms, ..self }
+    }
+
+    fn set_sequential(self, sequential: bool) -> Self {
+        Self { sequential, ..self }
+    }
+
+    fn set_no_solve(self, no_solve: bool) -> Self {
+        Self { no_solve, ..self }
+    }
+
+    fn set_retry_budget(self, retry_budget: Option<usize>) -> Self {
+        Self {
+            retry_budget,
+            ..self
+        }
+    }
+
+    fn set_image_cache(self, image_cache: Option<ImageCache>) -> Self {
+        Self {
+            fetcher: self.fetcher.with_image_cache(image_cache),
+            ..self
+        }
+    }
+
+    fn set_rate_limiter(self, rate_limiter: Option<RateLimiter>) -> Self {
+        Self {
+            fetcher: self.fetcher.with_rate_limiter(rate_limiter),
             ..self
         }
     }
+
+    fn set_output_hook(self, output_hook: Option<OutputHook>) -> Self {
+        Self {
+            output_hook,
+            ..self
+        }
+    }
+}
+
+impl crate::pipeline::DynPipeline for Pipeline {
+    fn download<'a>(&'a self, url: &'a Url, path: &'a Path) -> BoxFuture<'a, Result<()>> {
+        Box::pin(EpisodePipeline::download(self, url, path))
+    }
+
+    fn download_in<'a>(&'a self, url: &'a Url, dir: &'a Path) -> BoxFuture<'a, Result<()>> {
+        Box::pin(EpisodePipeline::download_in(self, url, dir))
+    }
 }
 
 impl EpisodePipeline<Page, Episode> for Pipeline {
@@ -104,178 +370,2027 @@ impl EpisodePipeline<Page, Episode> for Pipeline {
             .context("Failed to parse episode id")
     }
 
+    fn solve_kind(&self) -> SolveKind {
+        SolveKind::Pixel
+    }
+
     async fn fetch_episode(&self, episode_id: &str) -> Result<Episode> {
-        self.client.get_episode(episode_id).await
+        let episode = self.client.get_episode(episode_id).await?;
+        episode.ensure_public()?;
+        Ok(episode)
     }
 
-    async fn fetch_image(&self, page: &Page) -> Result<Bytes> {
-        let client = self.client.clone();
+    fn page_url(&self, page: &Page) -> Result<Url> {
+        Ok(self.client.image_url(page.url()?))
+    }
 
-        let url = page.url()?;
-        let res = client.get(url).await?;
-        let bytes = res.bytes().await?;
+    async fn fetch_image(&self, page: &Page) -> Result<Bytes> {
+        let (bytes, _permit) = self.fetch_image_limited(page, None).await?;
+        Ok(bytes)
+    }
 
-        Ok(bytes.into())
+    async fn fetch_image_size(&self, url: &Url) -> Result<Option<u64>> {
+        let _host_permit = self.fetcher.acquire_host(url).await?;
+        self.client.content_length(url.clone()).await
     }
 
     async fn solve_image_bytes(&self, image: Bytes, _page: Option<Page>) -> Result<Bytes> {
-        let solver = Arc::new(Solver::new());
-        let image = solver.solve(image)?;
-        Ok(image)
+        let solver = self.page_solver();
+        let task = tokio::task::spawn_blocking(move || solver.solve(image));
+        utils::join(task, "Solving a page panicked").await
     }
 
-    async fn solve_image(&self, image: Bytes, _page: Option<Page>) -> Result<DynamicImage> {
-        let solver = Arc::new(Solver::new());
-        let image = solver.solve_from_bytes(image)?;
-        Ok(image)
+    async fn solve_image(&self, image: Bytes, page: Option<Page>) -> Result<DynamicImage> {
+        let solver = self.solver.clone();
+        let (convert_to_srgb, verify_solved) = (self.convert_to_srgb, self.verify_solved);
+        let task = tokio::task::spawn_blocking(move || {
+            solve_page(&solver, image, page, convert_to_srgb, verify_solved)
+        });
+        utils::join(task, "Solving a page panicked").await
     }
 
-    async fn write_image_bytes<T: AsRef<Path>>(&self, images: Vec<Bytes>, path: T) -> Result<()> {
-        let writer_config = &self.writer_config;
-
-        match writer_config.save_format() {
-            SaveFormat::Raw => {
-                let writer = RawWriter::new(
-                    self.progress.clone(),
-                    self.writer_config.image_format(),
-                    self.num_threads,
-                );
-                writer.write(images, path).await?;
-            }
-            SaveFormat::Zip {
-                compression_method,
-                extension,
-            } => {
-                let writer = ZipWriter::new(
-                    compression_method,
-                    self.writer_config.image_format(),
-                    extension,
-                    self.num_threads,
-                    self.progress.clone(),
-                );
-                writer.write(images, path).await?;
-            }
-            #[cfg(feature = "pdf")]
-            SaveFormat::Pdf => {
-                let writer =
-                    PdfWriter::new(self.progress.clone(), self.writer_config.image_format());
-                writer.write(images, path).await?;
-            }
+    async fn solve_image_with_dimensions(
+        &self,
+        image: Bytes,
+        page: Option<Page>,
+    ) -> Result<SolvedImage> {
+        if self.convert_to_srgb {
+            let image = self.solve_image(image, page).await?;
+            let format = self.writer_config.image_format();
+            let task = tokio::task::spawn_blocking(move || {
+                let bytes = utils::encode_image(&image, format)?;
+                Result::<_>::Ok(SolvedImage::new(bytes, Some(image.dimensions())))
+            });
+            return utils::join(task, "Encoding a page panicked").await;
         }
+        let solver = self.page_solver();
+        let task = tokio::task::spawn_blocking(move || solver.solve_with_dimensions(image));
+        utils::join(task, "Solving a page panicked").await
+    }
 
-        Ok(())
+    async fn write_image_bytes<T: AsRef<Path>>(&self, images: Vec<Bytes>, path: T) -> Result<()> {
+        self.writer_config
+            .writer(self.progress.clone(), self.num_threads)
+            .write(self.writer_config.page_order(images), path)
+            .await
     }
 
     async fn write_images<T: AsRef<Path>>(&self, images: Vec<DynamicImage>, path: T) -> Result<()> {
-        let writer_config = &self.writer_config;
-
-        match writer_config.save_format() {
-            SaveFormat::Raw => {
-                let writer = RawWriter::new(
-                    self.progress.clone(),
-                    self.writer_config.image_format(),
-                    self.num_threads,
-                );
-                writer.write_images(images, path).await?;
-            }
-            SaveFormat::Zip {
-                compression_method,
-                extension,
-            } => {
-                let writer = ZipWriter::new(
-                    compression_method,
-                    self.writer_config.image_format(),
-                    extension,
-                    self.num_threads,
-                    self.progress.clone(),
-                );
-                writer.write_images(images, path).await?;
-            }
-            #[cfg(feature = "pdf")]
-            SaveFormat::Pdf => {
-                let writer =
-                    PdfWriter::new(self.progress.clone(), self.writer_config.image_format());
-                writer.write_images(images, path).await?;
-            }
-        }
+        self.writer_config
+            .writer(self.progress.clone(), self.num_threads)
+            .write_images(self.writer_config.page_order(images), path)
+            .await
+    }
 
-        Ok(())
+    async fn write_solved_images<T: AsRef<Path>>(
+        &self,
+        images: Vec<SolvedImage>,
+        path: T,
+    ) -> Result<()> {
+        self.writer_config
+            .writer(self.progress.clone(), self.num_threads)
+            .write_solved(self.writer_config.page_order(images), path)
+            .await
+    }
+
+    fn page_stream<'a>(
+        &'a self,
+        episode: &Episode,
+    ) -> Result<impl Stream<Item = Result<(usize, DynamicImage)>> + use<'a>> {
+        let budget = RetryBudget::new(self.retry_budget);
+        let results = self.solved_pages(episode.pages(), budget, move |image, _| async move {
+            let image = self.solve_image(image, None).await?;
+            transform::apply_all_blocking(&self.transforms, image).await
+        })?;
+        // the pages a transform splits a page into share its index
+        Ok(results
+            .map(|(i, images)| {
+                images
+                    .map(|images| stream::iter(images.into_iter().map(move |image| Ok((i, image)))))
+            })
+            .try_flatten())
     }
 
     async fn download<T: AsRef<Path>>(&self, url: &Url, path: T) -> Result<()> {
         let episode_id = self.parse_episode_id(url)?;
         let episode = self.fetch_episode(&episode_id).await?;
 
-        let pages = episode.pages();
-        let mut images = self
+        self.download_episode(&episode, path).await
+    }
+
+    async fn download_in<T: AsRef<Path>>(&self, url: &Url, dir: T) -> Result<()> {
+        let episode_id = self.parse_episode_id(url)?;
+        let episode = self.fetch_episode(&episode_id).await?;
+
+        let name = self.writer_config.episode_name(&episode)?;
+        let path = dir.as_ref().join(self.writer_config.with_extension(&name));
+
+        self.download_episode(&episode, path).await
+    }
+}
+
+impl Pipeline {
+    /// Number of concurrent connections, from the site preset unless overridden
+    pub fn num_connections(&self) -> usize {
+        self.num_connections.unwrap_or(self.preset.num_connections)
+    }
+
+    /// Fetch an image and reserve its size from the in-flight bytes limit, in the `turn`
+    /// of the page if the pages are written in order
+    async fn fetch_image_limited(
+        &self,
+        page: &Page,
+        turn: Option<Turn>,
+    ) -> Result<(Bytes, Option<BytePermit>)> {
+        let url = self.page_url(page)?;
+        let get = |url| self.client.get_image(url);
+        match turn {
+            Some(turn) => self.fetcher.fetch_in_turn(url, get, turn).await,
+            None => self.fetcher.fetch(url, get).await,
+        }
+    }
+
+    /// Fetch the image again from the network, past a cached body that failed to solve
+    async fn refetch_image(&self, page: &Page) -> Result<Bytes> {
+        self.fetcher
+            .refetch(self.page_url(page)?, |url| self.client.get_image(url))
+            .await
+    }
+
+    /// Fetch and solve the pages concurrently, yielding each result with its page index
+    fn solved_pages<'a, T, S, Fut>(
+        &'a self,
+        pages: Vec<Page>,
+        budget: RetryBudget,
+        solve: S,
+    ) -> Result<impl Stream<Item = (usize, Result<T>)> + 'a>
+    where
+        T: 'a,
+        S: Fn(Bytes, Page) -> Fut + Copy + 'a,
+        Fut: Future<Output = Result<T>> + 'a,
+    {
+        let pages = pages.into_iter().enumerate().collect();
+        let results = self.held_pages(pages, budget, solve, false)?;
+        // the fetched bytes are no longer held once the page is solved
+        Ok(results.map(|(i, solved)| (i, solved.map(|(image, _)| image))))
+    }
+
+    /// Fetch and solve the pages given with their index, yielding each result with the
+    /// reservation of its fetched bytes.
+    ///
+    /// `in_order` yields the pages in the order given and reserves their bytes in that
+    /// order, for a writer taking them as they come.
+    fn held_pages<'a, T, S, Fut>(
+        &'a self,
+        pages: Vec<(usize, Page)>,
+        budget: RetryBudget,
+        solve: S,
+        in_order: bool,
+    ) -> Result<impl Stream<Item = HeldPage<T>> + 'a>
+    where
+        T: 'a,
+        S: Fn(Bytes, Page) -> Fut + Copy + 'a,
+        Fut: Future<Output = Result<T>> + 'a,
+    {
+        let turns = match in_order {
+            true => Turn::chain(pages.len()).into_iter().map(Some).collect(),
+            false => pages.iter().map(|_| None).collect::<Vec<_>>(),
+        };
+        let pages = self
             .progress
             .build_with_message(pages.len(), "Downloading...")?
-            .wrap_stream(stream::iter(pages))
-            .enumerate()
-            .map(|(i, page)| async move { Ok((i, self.fetch_image(&page).await?)) })
-            .buffer_unordered(self.num_connections)
-            .map_ok(|(i, image)| async move { Ok((i, self.solve_image(image, None).await?)) })
-            .try_buffer_unordered(self.num_threads)
-            .try_collect::<Vec<_>>()
+            .wrap_stream(stream::iter(pages.into_iter().zip(turns)));
+        let fetch = move |((i, page), turn): ((usize, Page), Option<Turn>)| async move {
+            let fetched = self.fetch_image_limited(&page, turn).await;
+            (i, page, fetched)
+        };
+        let solve_page =
+            move |(i, page, fetched): (usize, Page, Result<(Bytes, Option<BytePermit>)>)| {
+                let budget = budget.clone();
+                async move {
+                    let solved = async {
+                        let (image, permit) = fetched?;
+                        let image = solve_with_refetch(
+                            image,
+                            self.max_solve_retries,
+                            &budget,
+                            || self.refetch_image(&page),
+                            |image| solve(image, page.clone()),
+                        )
+                        .await?;
+                        Ok((image, permit))
+                    };
+                    // point at the page that failed
+                    let solved = solved.await.with_context(|| {
+                        self.page_url(&page)
+                            .map_or(format!("page {}", i), |url| format!("page {} ({})", i, url))
+                    });
+                    (i, solved)
+                }
+            };
+
+        if self.sequential {
+            // each page is fetched only after the one before it is solved
+            return Ok(pages.then(fetch).then(solve_page).left_stream());
+        }
+        if in_order {
+            let results = pages
+                .map(fetch)
+                .buffered(self.num_connections())
+                .map(solve_page)
+                .buffered(self.num_threads);
+            return Ok(results.left_stream().right_stream());
+        }
+        let results = pages
+            .map(fetch)
+            .buffer_unordered(self.num_connections())
+            .map(solve_page)
+            .buffer_unordered(self.num_threads);
+
+        Ok(results.right_stream().right_stream())
+    }
+
+    /// Whether the pages are handed to the writer as they are solved, holding their bytes
+    /// until written. Only with a bytes limit, since the pages then wait for each other.
+    fn streams_pages(&self) -> bool {
+        self.fetcher.byte_limiter().is_some() && self.contact_sheet.is_none()
+    }
+
+    /// Fetch and solve the pages in the order they are written and write them as they come
+    async fn stream_episode<T, S, Fut>(
+        &self,
+        episode: &Episode,
+        pages: Vec<Page>,
+        budget: RetryBudget,
+        solve: S,
+        path: &Path,
+    ) -> Result<DownloadReport>
+    where
+        T: PageImage,
+        S: Fn(Bytes, Page) -> Fut + Copy + Send + Sync,
+        Fut: Future<Output = Result<Vec<T>>> + Send,
+    {
+        let total_pages = pages.len();
+        let pages = self
+            .writer_config
+            .page_order(pages.into_iter().enumerate().collect());
+        let results = self
+            .held_pages(pages, budget, solve, true)?
+            .map(|(i, solved)| {
+                let solved =
+                    solved.map(|(images, permit)| (self.writer_config.page_order(images), permit));
+                (i, solved)
+            });
+        let report = Mutex::new(DownloadReport {
+            total_pages,
+            ..Default::default()
+        });
+        self.writer_config
+            .episode_writer(episode, self.progress.clone(), self.num_threads)
+            .write_stream(
+                stream_pages(results, self.best_effort, self.error_policy, &report),
+                total_pages,
+                path,
+            )
             .await?;
-        images.par_sort_by_key(|&(i, _)| i);
-        let images = images
-            .into_iter()
-            .map(|(_, image)| image)
-            .collect::<Vec<_>>();
+        report
+            .into_inner()
+            .map_err(|_| anyhow!("Download report lock poisoned"))
+    }
+
+    /// Download the episodes of the series at `url` into `dir`, the oldest first.
+    ///
+    /// Each finished episode is recorded in the series state in `dir`,
+    /// and is skipped by later runs unless forced.
+    #[cfg(feature = "giga")]
+    pub async fn download_series_in<T: AsRef<Path>>(&self, url: &Url, dir: T) -> Result<()> {
+        let dir = dir.as_ref();
+        let series_id = self
+            .client
+            .parse_series_id(url)
+            .with_context(|| format!("Series id not found: {}", url))?;
+        let series = self.client.get_series(&series_id).await?;
+        let mut state = SeriesState::load(dir).await?;
+        if let Some(volume_size) = self.volume_size {
+            return self
+                .download_volumes_in(&series, volume_size, dir, state)
+                .await;
+        }
 
-        self.write_images(images, path).await?;
+        for episode in series.episodes().iter().rev() {
+            if !self.force && state.is_completed(episode.id()) {
+                tracing::info!("Skipping episode {}, already downloaded", episode.id());
+                continue;
+            }
+            let downloaded = async {
+                let episode = self.fetch_episode(episode.id()).await?;
+                if let Err(e) = NoImagePages::ensure(&episode) {
+                    // not recorded as done, the pages may be published later
+                    tracing::warn!("Skipping episode: {}", e);
+                    return Ok(false);
+                }
+                let path = self.series_layout.episode_path(
+                    dir,
+                    series.title(),
+                    &episode,
+                    &self.writer_config,
+                )?;
+                // the layout may nest the episode under a directory of the series
+                if let Some(parent) = path.parent() {
+                    tokio::fs::create_dir_all(parent).await?;
+                }
+                self.download_episode(&episode, path).await?;
+                Ok(true)
+            }
+            .await
+            .with_context(|| format!("Failed to download episode {}", episode.id()))?;
+            if downloaded {
+                state.complete(episode.id());
+                state.save(dir).await?;
+            }
+        }
         Ok(())
     }
 
-    async fn download_in<T: AsRef<Path>>(&self, url: &Url, dir: T) -> Result<()> {
-        let episode_id = self.parse_episode_id(url)?;
-        let episode = self.fetch_episode(&episode_id).await?;
+    /// Download the episodes of the series packaged into volumes of `volume_size` episodes,
+    /// the oldest first. A volume is written again until all of its episodes are done.
+    #[cfg(feature = "giga")]
+    async fn download_volumes_in(
+        &self,
+        series: &Series,
+        volume_size: usize,
+        dir: &Path,
+        mut state: SeriesState,
+    ) -> Result<()> {
+        if volume_size == 0 {
+            anyhow::bail!("Volume size must be positive");
+        }
+        let numbers = series
+            .episodes()
+            .iter()
+            .rev()
+            .map(|episode| state.assign_volume(episode.id(), volume_size))
+            .collect::<std::collections::BTreeSet<_>>();
+        state.save(dir).await?;
 
-        let mut path = dir
-            .as_ref()
-            .join(episode.title().context("Episode title not found")?);
-        match self.writer_config.save_format() {
-            SaveFormat::Raw => {} // Do nothing
-            SaveFormat::Zip { .. } => {
-                path.set_extension("zip");
+        for number in numbers {
+            let ids = state.volume(number).to_vec();
+            if !self.force && ids.iter().all(|id| state.is_completed(id)) {
+                tracing::info!("Skipping volume {}, already downloaded", number);
+                continue;
             }
-            #[cfg(feature = "pdf")]
-            SaveFormat::Pdf => {
-                path.set_extension("pdf");
+            let completed = async {
+                let mut completed = Vec::new();
+                let mut pages = Vec::new();
+                for id in ids {
+                    let episode = self.fetch_episode(&id).await?;
+                    if let Err(e) = NoImagePages::ensure(&episode) {
+                        // left out of the volume until the pages are published
+                        tracing::warn!("Skipping episode: {}", e);
+                        continue;
+                    }
+                    pages.extend(self.encoded_pages(&episode).await?);
+                    completed.push(id);
+                }
+                if pages.is_empty() {
+                    return Ok(completed);
+                }
+                let path = self.series_layout.volume_path(
+                    dir,
+                    series.title(),
+                    number,
+                    &self.writer_config,
+                );
+                if let Some(parent) = path.parent() {
+                    tokio::fs::create_dir_all(parent).await?;
+                }
+                self.writer_config
+                    .writer(self.progress.clone(), self.num_threads)
+                    .write_solved(pages, path)
+                    .await?;
+                Ok(completed)
+            }
+            .await
+            .with_context(|| format!("Failed to download volume {}", number))?;
+            for id in completed {
+                state.complete(&id);
             }
+            state.save(dir).await?;
+        }
+        Ok(())
+    }
+
+    /// The pages of the episode encoded in the image format of the writer, in the order
+    /// they are written. A volume holds these instead of the decoded pages of its episodes.
+    #[cfg(feature = "giga")]
+    async fn encoded_pages(&self, episode: &Episode) -> Result<Vec<SolvedImage>> {
+        let mut pages = self.page_stream(episode)?.try_collect::<Vec<_>>().await?;
+        pages.sort_by_key(|(index, _)| *index);
+        let format = self.writer_config.image_format();
+        let task = tokio::task::spawn_blocking(move || {
+            pages
+                .iter()
+                .map(|(_, image)| {
+                    let bytes = utils::encode_image(image, format)?;
+                    Ok(SolvedImage::new(bytes, Some(image.dimensions())))
+                })
+                .collect::<Result<Vec<_>>>()
+        });
+        let pages = utils::join(task, "Encoding a page panicked").await?;
+        Ok(self.writer_config.page_order(pages))
+    }
+
+    /// Join the pages of the episodes into continuous strips, in episode then page order,
+    /// for vertical scroll series split into many short chapters
+    pub async fn download_webtoon<T: AsRef<Path>>(
+        &self,
+        episodes: &[Episode],
+        writer: &WebtoonWriter,
+        path: T,
+    ) -> Result<()> {
+        let mut images = Vec::new();
+        for episode in episodes {
+            let mut pages = self.page_stream(episode)?.try_collect::<Vec<_>>().await?;
+            pages.sort_by_key(|(index, _)| *index);
+            images.extend(pages.into_iter().map(|(_, image)| image));
+        }
+        writer.write_images(images, path).await
+    }
+
+    /// Join the episodes of the series at `url` in `range` into continuous strips.
+    /// The range counts from the oldest episode.
+    #[cfg(feature = "giga")]
+    pub async fn download_series_webtoon<T: AsRef<Path>>(
+        &self,
+        url: &Url,
+        range: std::ops::Range<usize>,
+        writer: &WebtoonWriter,
+        path: T,
+    ) -> Result<()> {
+        let series_id = self
+            .client
+            .parse_series_id(url)
+            .with_context(|| format!("Series id not found: {}", url))?;
+        let series = self.client.get_series(&series_id).await?;
+
+        let mut episodes = Vec::new();
+        for episode in series
+            .episodes()
+            .iter()
+            .rev()
+            .skip(range.start)
+            .take(range.len())
+        {
+            episodes.push(self.fetch_episode(episode.id()).await?);
+        }
+        if episodes.is_empty() {
+            anyhow::bail!("No episodes of series {} in {:?}", series_id, range);
         }
+        self.download_webtoon(&episodes, writer, path).await
+    }
 
+    /// Fetch and write the pages as they are, without solving them
+    async fn download_unsolved(&self, episode: &Episode, path: &Path) -> Result<()> {
         let pages = episode.pages();
-        let mut images = self
-            .progress
-            .build_with_message(pages.len(), "Downloading...")?
-            .wrap_stream(stream::iter(pages))
-            .enumerate()
-            .map(|(i, page)| async move { Ok((i, self.fetch_image(&page).await?)) })
-            .buffer_unordered(self.num_connections)
-            .map_ok(|(i, image)| async move { Ok((i, self.solve_image(image, None).await?)) })
-            .try_buffer_unordered(self.num_threads)
-            .try_collect::<Vec<_>>()
+        let total_pages = pages.len();
+        tracing::info!(
+            "Downloading {} unsolved pages of episode {} to {}",
+            total_pages,
+            episode.id(),
+            path.display()
+        );
+        let budget = RetryBudget::new(self.retry_budget);
+        if self.streams_pages() {
+            let solve = |image, _| async move { Result::<_>::Ok(vec![image]) };
+            let report = self
+                .stream_episode(episode, pages, budget, solve, path)
+                .await?;
+            if !report.is_complete() {
+                report.save(path).await?;
+            }
+            return Ok(());
+        }
+        let results =
+            self.solved_pages(
+                pages,
+                budget,
+                |image, _| async move { Result::<_>::Ok(image) },
+            )?;
+        let (images, report) =
+            collect_pages(results, total_pages, self.best_effort, self.error_policy).await?;
+        self.writer_config
+            .episode_writer(episode, self.progress.clone(), self.num_threads)
+            .with_page_indices(
+                report
+                    .page_indices(images.len())
+                    .map(|indices| self.writer_config.page_order(indices)),
+            )
+            .write(self.writer_config.page_order(images), path)
             .await?;
-        images.par_sort_by_key(|&(i, _)| i);
-        let images = images
-            .into_iter()
-            .map(|(_, image)| image)
-            .collect::<Vec<_>>();
+        if !report.is_complete() {
+            report.save(path).await?;
+        }
+        Ok(())
+    }
 
-        self.write_images(images, path).await?;
+    /// Download, solve and write all pages of the episode
+    async fn download_episode<T: AsRef<Path>>(&self, episode: &Episode, path: T) -> Result<()> {
+        // nothing to write, and empty archives are rejected by some readers
+        NoImagePages::ensure(episode)?;
+        let path = OutputHook::output_path(self.output_hook.as_ref(), episode, path.as_ref())?;
+        if self.no_solve {
+            return self.download_unsolved(episode, &path).await;
+        }
+        let pages = episode.pages();
+        let total_pages = pages.len();
+        tracing::info!(
+            "Downloading {} pages of episode {} to {}",
+            pages.len(),
+            episode.id(),
+            path.display()
+        );
+        let other_pages = episode.other_page_positions();
+        if !other_pages.is_empty() {
+            tracing::info!(
+                "{} pages of episode {} are not images and are {}",
+                other_pages.len(),
+                episode.id(),
+                match self.other_page_placeholders {
+                    true => "written as blank pages",
+                    false => "skipped",
+                }
+            );
+        }
+        let placeholders = match self.other_page_placeholders {
+            true => other_pages.as_slice(),
+            false => &[],
+        };
+        let budget = RetryBudget::new(self.retry_budget);
+        let solve = |image, page: Page| async move {
+            let image = self.solve_image(image, Some(page.clone())).await?;
+            let images = transform::apply_all_blocking(&self.transforms, image).await?;
+            Ok(with_placeholders(images, page.index()?, placeholders))
+        };
+        let (images, mut report) = match self.streams_pages() {
+            true => {
+                let report = self
+                    .stream_episode(episode, pages, budget.clone(), solve, &path)
+                    .await?;
+                (None, report)
+            }
+            false => {
+                let results = self.solved_pages(pages, budget.clone(), solve)?;
+                let (images, report) =
+                    collect_pages(results, total_pages, self.best_effort, self.error_policy)
+                        .await?;
+                (Some(images), report)
+            }
+        };
+        report.retries = budget.spent();
+        if report.retries > 0 {
+            tracing::info!(
+                "Spent {} retries of the budget of {}",
+                report.retries,
+                budget
+                    .limit()
+                    .map_or("unlimited".to_string(), |limit| limit.to_string())
+            );
+        }
+        // the streamed pages are already written
+        if let Some(images) = images {
+            let mut images = images.into_iter().flatten().collect::<Vec<_>>();
+
+            if let Some(contact_sheet) = &self.contact_sheet {
+                images = contact_sheet
+                    .clone()
+                    .with_direction(episode.scroll_direction())
+                    .write_sheet_owned(images, &path)
+                    .await?;
+            }
+            self.writer_config
+                .episode_writer(episode, self.progress.clone(), self.num_threads)
+                .with_page_indices(
+                    report
+                        .page_indices(images.len())
+                        .map(|indices| self.writer_config.page_order(indices)),
+                )
+                .write_images(self.writer_config.page_order(images), &path)
+                .await?;
+        }
+        if !report.is_complete() {
+            tracing::warn!(
+                "{} of {} pages failed, see {}",
+                report.failures.len(),
+                report.total_pages,
+                DownloadReport::report_path(&path)?.display()
+            );
+            report.save(&path).await?;
+        }
         Ok(())
     }
 }
 
+/// Descramble a page, off the async workers since it is CPU bound
+fn solve_page(
+    solver: &Solver,
+    image: Bytes,
+    page: Option<Page>,
+    convert_to_srgb: bool,
+    verify_solved: bool,
+) -> Result<DynamicImage> {
+    let icc = match convert_to_srgb {
+        true => color::icc_profile(&image)?,
+        false => None,
+    };
+    let image = solver.solve_from_bytes(image)?;
+    if verify_solved && solver.looks_scrambled(&image) {
+        let page = page
+            .and_then(|page| page.index().ok())
+            .map_or("A page".to_string(), |index| format!("Page {}", index));
+        tracing::warn!(
+            "{} still looks scrambled, the solver parameters may be wrong",
+            page
+        );
+    }
+    match icc {
+        Some(icc) => color::convert_to_srgb(image, &icc),
+        None => Ok(image),
+    }
+}
+
+/// Add a blank page of the same size for every page that is not an image next to the
+/// image page `index`, given the number of image pages before each of them
+fn with_placeholders(
+    images: Vec<DynamicImage>,
+    index: usize,
+    positions: &[usize],
+) -> Vec<DynamicImage> {
+    let Some((width, height)) = images.first().map(|image| image.dimensions()) else {
+        return images;
+    };
+    let blanks = |count: usize| {
+        vec![
+            DynamicImage::ImageRgb8(RgbImage::from_pixel(width, height, Rgb([255, 255, 255])));
+            count
+        ]
+    };
+    let before = match index {
+        0 => positions.iter().filter(|&&position| position == 0).count(),
+        _ => 0,
+    };
+    let after = positions
+        .iter()
+        .filter(|&&position| position == index + 1)
+        .count();
+
+    let mut pages = blanks(before);
+    pages.extend(images);
+    pages.extend(blanks(after));
+    pages
+}
+
 #[cfg(test)]
 mod test {
 
-    use crate::viewer::ViewerWebsite;
+    use std::{io::Read, sync::Arc};
+
+    use wiremock::{
+        matchers::{header, method, path, path_regex},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    use crate::{
+        pipeline::{MissingTitle, SIZE_SAMPLE_PAGES},
+        testing, utils,
+        viewer::{giga::data::EpisodeNotPublic, ViewerConfig, ViewerWebsite},
+    };
 
     use super::*;
 
+    /// Serve an episode of PNG pages where the pages in `missing` are not found
+    async fn mock_episode(
+        server: &MockServer,
+        num_pages: usize,
+        missing: &[usize],
+    ) -> Result<Episode> {
+        let page = utils::encode_image(&DynamicImage::new_rgb8(40, 60), image::ImageFormat::Png)?;
+        for i in 0..num_pages {
+            let response = match missing.contains(&i) {
+                true => ResponseTemplate::new(404),
+                false => ResponseTemplate::new(200).set_body_bytes(page.clone()),
+            };
+            Mock::given(method("GET"))
+                .and(path(format!("/page/{}", i)))
+                .respond_with(response)
+                .mount(server)
+                .await;
+        }
+
+        let pages = (0..num_pages)
+            .map(|i| {
+                serde_json::json!({
+                    "type": "main",
+                    "src": format!("{}/page/{}", server.uri(), i),
+                    "width": 40,
+                    "height": 60,
+                })
+            })
+            .collect::<Vec<_>>();
+        let episode = serde_json::from_value(serde_json::json!({
+            "readableProduct": {
+                "id": "1",
+                "title": "mock",
+                "typeName": "episode",
+                "isPublic": true,
+                "number": 1,
+                "permalink": format!("{}/episode/1", server.uri()),
+                "pageStructure": {
+                    "choJuGiga": "baku",
+                    "readingDirection": "rtl",
+                    "pages": pages,
+                },
+            }
+        }))?;
+        Ok(episode)
+    }
+
+    #[tokio::test]
+    async fn test_page_stream_yields_every_index() -> Result<()> {
+        let server = MockServer::start().await;
+        let episode = mock_episode(&server, 6, &[]).await?;
+        let pipe = Pipeline::default().set_progress(ProgressConfig::disabled());
+
+        let mut pages = pipe
+            .page_stream(&episode)?
+            .map(|page| page.map(|(i, image)| (i, image.dimensions())))
+            .try_collect::<Vec<_>>()
+            .await?;
+        pages.sort_by_key(|(i, _)| *i);
+
+        assert_eq!(pages, (0..6).map(|i| (i, (40, 60))).collect::<Vec<_>>());
+
+        Ok(())
+    }
+
+    /// Serves the mock pages after `delay`, recording when each request arrived
+    struct RecordArrivals {
+        page: Vec<u8>,
+        delay: std::time::Duration,
+        arrivals: Arc<std::sync::Mutex<Vec<std::time::Instant>>>,
+    }
+
+    impl wiremock::Respond for RecordArrivals {
+        fn respond(&self, _: &wiremock::Request) -> ResponseTemplate {
+            self.arrivals
+                .lock()
+                .unwrap()
+                .push(std::time::Instant::now());
+            ResponseTemplate::new(200)
+                .set_body_bytes(self.page.clone())
+                .set_delay(self.delay)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_connections_per_host_are_capped() -> Result<()> {
+        let server = MockServer::start().await;
+        let delay = std::time::Duration::from_millis(100);
+        let arrivals = Arc::new(std::sync::Mutex::new(Vec::new()));
+        // mounted first, so it answers before the pages of the mock episode
+        Mock::given(method("GET"))
+            .and(path_regex("^/page/"))
+            .respond_with(RecordArrivals {
+                page: utils::encode_image(
+                    &DynamicImage::new_rgb8(40, 60),
+                    image::ImageFormat::Png,
+                )?
+                .to_vec(),
+                delay,
+                arrivals: arrivals.clone(),
+            })
+            .mount(&server)
+            .await;
+        let episode = mock_episode(&server, 6, &[]).await?;
+        let pipe = Pipeline::default()
+            .set_progress(ProgressConfig::disabled())
+            .set_num_connections(6)
+            .set_max_connections_per_host(Some(2));
+
+        let pages = pipe.page_stream(&episode)?.try_collect::<Vec<_>>().await?;
+        assert_eq!(pages.len(), 6);
+
+        // a slot is only free again once its response arrived, so no more than
+        // 2 requests can arrive within less than the response delay
+        let arrivals = arrivals.lock().unwrap().clone();
+        assert_eq!(arrivals.len(), 6);
+        for arrival in &arrivals {
+            let overlapping = arrivals
+                .iter()
+                .filter(|other| *other >= arrival && **other - *arrival < delay / 2)
+                .count();
+            assert!(overlapping <= 2);
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_rate_limiter_caps_bandwidth() -> Result<()> {
+        let server = testing::giga_server().await?;
+        let limiter = RateLimiter::new(256 * 1024);
+        let pipe = Pipeline {
+            client: Client::new(ConfigBuilder::custom(server.uri())?.build()),
+            ..Pipeline::default()
+        }
+        .set_progress(ProgressConfig::disabled())
+        .set_rate_limiter(Some(limiter.clone()));
+        let episode = pipe.fetch_episode(testing::GIGA_EPISODE_ID).await?;
+        let total_bytes = std::fs::metadata(testing::GIGA_PAGE)?.len() * 3;
+
+        let started = std::time::Instant::now();
+        let pages = pipe.page_stream(&episode)?.try_collect::<Vec<_>>().await?;
+        let elapsed = started.elapsed();
+
+        assert_eq!(pages.len(), 3);
+        // about 2.5 seconds for the 3 pages of 210 KiB
+        let expected = total_bytes as f64 / limiter.bytes_per_sec() as f64;
+        assert!(elapsed.as_secs_f64() >= expected);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_srgb_pages_are_encoded() -> Result<()> {
+        let pipe = Pipeline::default()
+            .set_progress(ProgressConfig::disabled())
+            .set_convert_to_srgb(true);
+        let bytes = Bytes::from(std::fs::read(testing::GIGA_SAMPLE)?);
+
+        let solved = pipe.solve_image_with_dimensions(bytes, None).await?;
+        assert_eq!(
+            image::guess_format(solved.bytes())?,
+            image::ImageFormat::Png
+        );
+        let decoded = image::load_from_memory(solved.bytes())?;
+        assert_eq!(Some(decoded.dimensions()), solved.dimensions());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_in_flight_bytes_stay_under_limit() -> Result<()> {
+        let server = testing::giga_server().await?;
+        let page_size = std::fs::metadata(testing::GIGA_PAGE)?.len() as usize;
+        // room for one page at a time, whatever the number of connections
+        let max_bytes = page_size * 3 / 2;
+        let pipe = Pipeline {
+            client: Client::new(ConfigBuilder::custom(server.uri())?.build()),
+            ..Pipeline::default()
+        }
+        .set_progress(ProgressConfig::disabled())
+        .set_num_connections(3)
+        .set_max_in_flight_bytes(Some(max_bytes));
+        let episode = pipe.fetch_episode(testing::GIGA_EPISODE_ID).await?;
+
+        let pages = pipe.page_stream(&episode)?.try_collect::<Vec<_>>().await?;
+        assert_eq!(pages.len(), 3);
+
+        let limiter = pipe.fetcher.byte_limiter().context("no byte limiter")?;
+        assert_eq!(limiter.high_water_mark(), page_size);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_limited_pages_are_held_until_written() -> Result<()> {
+        let server = MockServer::start().await;
+        let episode = mock_episode(&server, 6, &[2]).await?;
+        let page_size =
+            utils::encode_image(&DynamicImage::new_rgb8(40, 60), image::ImageFormat::Png)?.len();
+
+        let dir = Path::new("playground/output/giga_limited_pages");
+        let _ = tokio::fs::remove_dir_all(dir).await;
+        // room for one page at a time, from the fetch until it is on disk
+        let pipe = Pipeline::default()
+            .set_progress(ProgressConfig::disabled())
+            .set_num_connections(3)
+            .set_max_in_flight_bytes(Some(page_size * 3 / 2))
+            .set_best_effort(true);
+        pipe.download_episode(&episode, dir).await?;
+
+        let limiter = pipe.fetcher.byte_limiter().context("no byte limiter")?;
+        assert_eq!(limiter.high_water_mark(), page_size);
+        // the failed page leaves a gap in the names, as when the pages are collected
+        for name in ["0.png", "1.png", "3.png", "4.png", "5.png"] {
+            assert!(dir.join(name).exists(), "{} not written", name);
+        }
+        assert!(!dir.join("2.png").exists());
+        let report = tokio::fs::read_to_string(DownloadReport::report_path(dir)?).await?;
+        assert!(report.contains("page 2:"), "{}", report);
+
+        // without best effort the failure still aborts the episode
+        let _ = tokio::fs::remove_dir_all(dir).await;
+        let pipe = pipe.set_best_effort(false);
+        assert!(pipe.download_episode(&episode, dir).await.is_err());
+        let written = std::fs::read_dir(dir).map(|dir| dir.count()).unwrap_or(0);
+        assert_eq!(written, 0);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sequential_keeps_page_order() -> Result<()> {
+        let server = MockServer::start().await;
+        let episode = mock_episode(&server, 8, &[]).await?;
+        let pipe = Pipeline::default()
+            .set_progress(ProgressConfig::disabled())
+            .set_sequential(true);
+
+        let indices = pipe
+            .page_stream(&episode)?
+            .map(|page| page.map(|(i, _)| i))
+            .try_collect::<Vec<_>>()
+            .await?;
+        assert_eq!(indices, (0..8).collect::<Vec<_>>());
+        // the server sees the pages requested in order too
+        let requested = server
+            .received_requests()
+            .await
+            .unwrap_or_default()
+            .iter()
+            .map(|request| request.url.path().to_string())
+            .collect::<Vec<_>>();
+        let expected = (0..8).map(|i| format!("/page/{}", i)).collect::<Vec<_>>();
+        assert_eq!(requested, expected);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_comic_bushi_first_page_resolves() -> Result<()> {
+        let url = Url::parse("https://comicbushi-web.com/episode/1")?;
+        let website = Website::lookup(url.host_str().unwrap()).unwrap();
+        assert_eq!(website, Website::ComicBushi);
+        assert_eq!(website.base_url().as_str(), "https://comicbushi-web.com/");
+
+        // the image CDN only answers requests from the site
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path_regex("^/public/page/"))
+            .and(header("referer", "https://comicbushi-web.com/"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_bytes(std::fs::read(testing::GIGA_PAGE)?),
+            )
+            .mount(&server)
+            .await;
+        let episode: Episode = serde_json::from_str(
+            &std::fs::read_to_string(testing::GIGA_EPISODE)?
+                .replace(testing::GIGA_IMAGE_HOST, &server.uri()),
+        )?;
+        let pipe = Pipeline::default().set_website(website);
+
+        let page = episode.pages().into_iter().next().unwrap();
+        assert!(pipe.page_url(&page)?.as_str().starts_with(&server.uri()));
+        let image = pipe.fetch_image(&page).await?;
+        assert_eq!(image, std::fs::read(testing::GIGA_PAGE)?);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_image_host_override() -> Result<()> {
+        let server = MockServer::start().await;
+        // the episode still points at the original CDN
+        Mock::given(method("GET"))
+            .and(path(format!("/episode/{}.json", testing::GIGA_EPISODE_ID)))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_raw(std::fs::read(testing::GIGA_EPISODE)?, "application/json"),
+            )
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path_regex("^/public/page/"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_bytes(std::fs::read(testing::GIGA_PAGE)?),
+            )
+            .mount(&server)
+            .await;
+        let url = Url::parse(&format!(
+            "{}/episode/{}",
+            server.uri(),
+            testing::GIGA_EPISODE_ID
+        ))?;
+        let pipe = Pipeline {
+            client: Client::new(
+                ConfigBuilder::custom(server.uri())?
+                    .with_image_host(&server.uri())?
+                    .build(),
+            ),
+            ..Pipeline::default()
+        };
+
+        let pages = pipe.list_pages(&url).await?;
+        assert!(pages
+            .iter()
+            .all(|(_, url)| url.as_str().starts_with(&server.uri())));
+        let episode = pipe.fetch_episode(testing::GIGA_EPISODE_ID).await?;
+        let page = episode.pages().into_iter().next().unwrap();
+        assert!(!pipe.fetch_image(&page).await?.is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_solving_does_not_block_the_runtime() -> Result<()> {
+        let image = Bytes::from(std::fs::read(testing::GIGA_PAGE)?);
+        let pipe = Pipeline::default();
+
+        // on a single worker the ticker only runs while the solve is elsewhere
+        let ticks = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let ticker = tokio::spawn({
+            let ticks = ticks.clone();
+            async move {
+                loop {
+                    tokio::time::sleep(std::time::Duration::from_millis(1)).await;
+                    ticks.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                }
+            }
+        });
+        let solved = pipe.solve_image(image.clone(), None).await?;
+        ticker.abort();
+
+        assert!(ticks.load(std::sync::atomic::Ordering::Relaxed) > 0);
+        assert_eq!(solved, pipe.solver.solve_from_bytes(image)?);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_no_solve_writes_fetched_bytes() -> Result<()> {
+        let server = testing::giga_server().await?;
+        let url = Url::parse(&format!(
+            "{}/episode/{}",
+            server.uri(),
+            testing::GIGA_EPISODE_ID
+        ))?;
+        let pipe = Pipeline {
+            client: Client::new(ConfigBuilder::custom(server.uri())?.build()),
+            ..Pipeline::default()
+        }
+        .set_progress(ProgressConfig::disabled())
+        .set_no_solve(true);
+        let path = Path::new("playground/output/giga_no_solve");
+        let _ = std::fs::remove_dir_all(path);
+
+        pipe.download(&url, path).await?;
+
+        let fetched = std::fs::read(testing::GIGA_PAGE)?;
+        let num_pages = pipe.list_pages(&url).await?.len();
+        assert_eq!(std::fs::read_dir(path)?.count(), num_pages);
+        for i in 0..num_pages {
+            assert_eq!(std::fs::read(path.join(format!("{}.png", i)))?, fetched);
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_download_single_page() -> Result<()> {
+        let server = testing::giga_server().await?;
+        let url = Url::parse(&format!(
+            "{}/episode/{}",
+            server.uri(),
+            testing::GIGA_EPISODE_ID
+        ))?;
+        let pipe = Pipeline {
+            client: Client::new(ConfigBuilder::custom(server.uri())?.build()),
+            ..Pipeline::default()
+        }
+        .set_progress(ProgressConfig::disabled());
+        let path = Path::new("playground/output/giga_single_page/0.png");
+        let _ = std::fs::remove_dir_all("playground/output/giga_single_page");
+
+        pipe.download_page(&url, 0, path).await?;
+
+        let files = std::fs::read_dir("playground/output/giga_single_page")?.count();
+        assert_eq!(files, 1);
+        let image = image::open(path)?;
+        assert_eq!(image.dimensions(), (595, 842));
+        let requests = server.received_requests().await.unwrap_or_default();
+        let fetched = requests
+            .iter()
+            .filter(|request| request.url.path().starts_with("/public/page/"))
+            .count();
+        assert_eq!(fetched, 1);
+
+        assert!(pipe.download_page(&url, 99, path).await.is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sequential_output_matches_concurrent() -> Result<()> {
+        let server = testing::giga_server().await?;
+        let url = Url::parse(&format!(
+            "{}/episode/{}",
+            server.uri(),
+            testing::GIGA_EPISODE_ID
+        ))?;
+        let pipe = Pipeline {
+            client: Client::new(ConfigBuilder::custom(server.uri())?.build()),
+            ..Pipeline::default()
+        }
+        .set_progress(ProgressConfig::disabled())
+        .set_writer_config(WriterConifg::new(
+            SaveFormat::Zip {
+                compression_method: zip::CompressionMethod::Stored,
+                extension: None,
+            },
+            image::ImageFormat::Png,
+        ));
+
+        let mut entries = Vec::new();
+        for sequential in [false, true] {
+            let path = format!("playground/output/giga_sequential_{}.zip", sequential);
+            pipe.clone()
+                .set_sequential(sequential)
+                .download(&url, &path)
+                .await?;
+
+            let mut archive = zip::ZipArchive::new(std::fs::File::open(&path)?)?;
+            let pages = (0..archive.len())
+                .map(|i| {
+                    let mut bytes = Vec::new();
+                    archive
+                        .by_name(&format!("{}.png", i))?
+                        .read_to_end(&mut bytes)?;
+                    Ok(bytes)
+                })
+                .collect::<Result<Vec<_>>>()?;
+            entries.push(pages);
+        }
+        assert_eq!(entries[0].len(), 3);
+        assert_eq!(entries[0], entries[1]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_best_effort_keeps_successful_pages() -> Result<()> {
+        let server = MockServer::start().await;
+        let episode = mock_episode(&server, 4, &[1]).await?;
+
+        let dir = Path::new("playground/output/giga_best_effort");
+        let _ = tokio::fs::remove_dir_all(dir).await;
+        let pipe = Pipeline::default()
+            .set_progress(ProgressConfig::disabled())
+            .set_best_effort(true);
+        pipe.download_episode(&episode, dir).await?;
+
+        let mut written = std::fs::read_dir(dir)?.count();
+        assert_eq!(written, 3);
+        let report = tokio::fs::read_to_string(DownloadReport::report_path(dir)?).await?;
+        assert!(report.contains("page 1:"), "{}", report);
+
+        // the same failure aborts the episode by default
+        let _ = tokio::fs::remove_dir_all(dir).await;
+        let pipe = pipe.set_best_effort(false);
+        assert!(pipe.download_episode(&episode, dir).await.is_err());
+        written = std::fs::read_dir(dir).map(|dir| dir.count()).unwrap_or(0);
+        assert_eq!(written, 0);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_collect_reports_every_failed_page() -> Result<()> {
+        let server = MockServer::start().await;
+        let episode = mock_episode(&server, 5, &[1, 3]).await?;
+
+        let dir = Path::new("playground/output/giga_collect_errors");
+        let _ = tokio::fs::remove_dir_all(dir).await;
+        let pipe = Pipeline::default()
+            .set_progress(ProgressConfig::disabled())
+            .set_error_policy(ErrorPolicy::Collect);
+        let error = pipe
+            .download_episode(&episode, dir)
+            .await
+            .expect_err("two pages are not found");
+
+        let message = error.to_string();
+        assert!(message.starts_with("2 of 5 pages failed"), "{}", message);
+        assert!(message.contains("page 1: "), "{}", message);
+        assert!(message.contains("page 3: "), "{}", message);
+        // nothing is written when the episode fails
+        assert!(!dir.exists());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_failed_page_is_named_in_the_error() -> Result<()> {
+        let server = MockServer::start().await;
+        let episode = mock_episode(&server, 3, &[2]).await?;
+
+        let dir = Path::new("playground/output/giga_failed_page");
+        let pipe = Pipeline::default().set_progress(ProgressConfig::disabled());
+        let error = pipe
+            .download_episode(&episode, dir)
+            .await
+            .expect_err("page 2 is not found");
+
+        let message = format!("{:#}", error);
+        assert!(
+            message.contains(&format!("page 2 ({}/page/2)", server.uri())),
+            "{}",
+            message
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_episodes_merge_into_strips() -> Result<()> {
+        let server = MockServer::start().await;
+        let episodes = vec![
+            mock_episode(&server, 3, &[]).await?,
+            mock_episode(&server, 2, &[]).await?,
+        ];
+
+        let dir = Path::new("playground/output/giga_webtoon");
+        let _ = std::fs::remove_dir_all(dir);
+        let writer =
+            WebtoonWriter::new(ProgressConfig::disabled(), 150, image::ImageFormat::Png, 2);
+        let pipe = Pipeline::default().set_progress(ProgressConfig::disabled());
+        pipe.download_webtoon(&episodes, &writer, dir).await?;
+
+        // five pages of 60 pixels, two to a strip
+        let heights = (0..std::fs::read_dir(dir)?.count())
+            .map(|i| Ok(image::open(dir.join(format!("{}.png", i)))?.height()))
+            .collect::<Result<Vec<_>>>()?;
+        assert_eq!(heights, vec![120, 120, 60]);
+        assert_eq!(heights.iter().sum::<u32>(), 5 * 60);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_transforms_run_before_writing() -> Result<()> {
+        let server = MockServer::start().await;
+        let episode = mock_episode(&server, 2, &[]).await?;
+
+        let path = "playground/output/giga_transforms.zip";
+        let pipe = Pipeline::default()
+            .set_progress(ProgressConfig::disabled())
+            .set_writer_config(WriterConifg::new(
+                SaveFormat::Zip {
+                    compression_method: zip::CompressionMethod::Stored,
+                    extension: None,
+                },
+                image::ImageFormat::Png,
+            ))
+            .set_transforms(vec![
+                Arc::new(transform::Grayscale),
+                Arc::new(transform::Resize::new(20, 30)),
+            ]);
+        pipe.download_episode(&episode, path).await?;
+
+        let mut archive = zip::ZipArchive::new(std::fs::File::open(path)?)?;
+        let mut bytes = Vec::new();
+        archive.by_name("0.png")?.read_to_end(&mut bytes)?;
+        let image = image::load_from_memory(&bytes)?;
+        assert_eq!(image.dimensions(), (20, 30));
+        assert_eq!(image.color(), image::ColorType::L8);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_reverse_pages() -> Result<()> {
+        let pages = vec![
+            Bytes::from_static(b"first"),
+            Bytes::from_static(b"second"),
+            Bytes::from_static(b"third"),
+        ];
+        let path = Path::new("playground/output/giga_reverse_pages");
+        let _ = std::fs::remove_dir_all(path);
+        let pipe = Pipeline::default()
+            .set_progress(ProgressConfig::disabled())
+            .set_writer_config(
+                WriterConifg::new(SaveFormat::Raw, image::ImageFormat::Png)
+                    .with_reverse_pages(true),
+            );
+
+        pipe.write_image_bytes(pages, path).await?;
+
+        assert_eq!(std::fs::read(path.join("0.png"))?, b"third");
+        assert_eq!(std::fs::read(path.join("1.png"))?, b"second");
+        assert_eq!(std::fs::read(path.join("2.png"))?, b"first");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_dotted_title_keeps_its_name() -> Result<()> {
+        let server = MockServer::start().await;
+        let mut episode = serde_json::to_value(mock_episode(&server, 1, &[]).await?)?;
+        episode["readableProduct"]["title"] = serde_json::json!("Vol.2 第3話");
+        let episode: Episode = serde_json::from_value(episode)?;
+        let config = WriterConifg::new(
+            SaveFormat::Zip {
+                compression_method: zip::CompressionMethod::Stored,
+                extension: Some("cbz".to_string()),
+            },
+            image::ImageFormat::Png,
+        );
+        let dir = Path::new("out");
+
+        let path = SeriesLayout::Flat.episode_path(dir, "Series", &episode, &config)?;
+        assert_eq!(path, dir.join("Vol.2 第3話.cbz"));
+        let path = SeriesLayout::Komga.episode_path(dir, "Ser.ies", &episode, &config)?;
+        assert_eq!(path, dir.join("Ser.ies").join("Chapter 001.cbz"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_other_pages_become_placeholders() -> Result<()> {
+        let server = MockServer::start().await;
+        let mut episode = serde_json::to_value(mock_episode(&server, 2, &[]).await?)?;
+        episode["readableProduct"]["pageStructure"]["pages"]
+            .as_array_mut()
+            .context("Pages not found")?
+            .push(serde_json::json!({ "type": "backMatter" }));
+        let episode: Episode = serde_json::from_value(episode)?;
+        assert_eq!(episode.other_page_count(), 1);
+
+        let path = Path::new("playground/output/giga_placeholders");
+        let _ = std::fs::remove_dir_all(path);
+        let pipe = Pipeline::default()
+            .set_progress(ProgressConfig::disabled())
+            .set_other_page_placeholders(true);
+        pipe.download_episode(&episode, path).await?;
+
+        assert_eq!(std::fs::read_dir(path)?.count(), 3);
+        let blank = image::open(path.join("2.png"))?;
+        assert_eq!(blank.dimensions(), (40, 60));
+        assert_eq!(blank.get_pixel(0, 0).0, [255, 255, 255, 255]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_retry_budget_limits_refetches() -> Result<()> {
+        let server = MockServer::start().await;
+        // the first page decodes, the other 9 fail to solve every time
+        let episode = mock_episode(&server, 10, &[]).await?;
+        Mock::given(method("GET"))
+            .and(path_regex("^/page/[1-9]$"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(b"not an image".to_vec()))
+            .with_priority(1)
+            .mount(&server)
+            .await;
+        let path = "playground/output/giga_retry_budget.zip";
+
+        let pipe = Pipeline::default()
+            .set_progress(ProgressConfig::disabled())
+            .set_writer_config(WriterConifg::new(
+                SaveFormat::Zip {
+                    compression_method: zip::CompressionMethod::Stored,
+                    extension: None,
+                },
+                image::ImageFormat::Png,
+            ))
+            .set_best_effort(true)
+            .set_max_solve_retries(3)
+            .set_retry_budget(Some(5));
+        pipe.download_episode(&episode, path).await?;
+
+        // 10 first fetches and at most 5 refetches instead of 9 * 3
+        let requests = server.received_requests().await.unwrap_or_default();
+        assert_eq!(requests.len(), 15);
+        let report = std::fs::read_to_string(DownloadReport::report_path(path)?)?;
+        assert!(report.starts_with("9 of 10 pages failed\n5 retries spent\n"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_output_hook_renames_output() -> Result<()> {
+        let server = MockServer::start().await;
+        let episode = mock_episode(&server, 2, &[]).await?;
+        let dir = Path::new("playground/output/giga_output_hook");
+        let _ = std::fs::remove_dir_all(dir);
+        std::fs::create_dir_all(dir)?;
+
+        let hook = OutputHook::new(|path, info| {
+            let path = path.with_file_name(format!("{:03} {}", info.index, info.id));
+            std::fs::write(path.with_extension("notes.txt"), "translation notes")?;
+            Ok(path)
+        });
+        let pipe = Pipeline::default()
+            .set_progress(ProgressConfig::disabled())
+            .set_writer_config(WriterConifg::new(
+                SaveFormat::Zip {
+                    compression_method: zip::CompressionMethod::Stored,
+                    extension: None,
+                },
+                image::ImageFormat::Png,
+            ))
+            .set_output_hook(Some(hook));
+        pipe.download_episode(&episode, dir.join("mock.zip"))
+            .await?;
+
+        assert!(!dir.join("mock.zip").exists());
+        assert!(dir.join("001 1.zip").exists());
+        assert_eq!(
+            std::fs::read_to_string(dir.join("001 1.notes.txt"))?,
+            "translation notes"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_non_public_episode_is_rejected() -> Result<()> {
+        let server = MockServer::start().await;
+        let mut episode: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(testing::GIGA_EPISODE)?)?;
+        episode["readableProduct"]["isPublic"] = false.into();
+        Mock::given(method("GET"))
+            .and(path(format!("/episode/{}.json", testing::GIGA_EPISODE_ID)))
+            .respond_with(ResponseTemplate::new(200).set_body_json(episode))
+            .mount(&server)
+            .await;
+        let url = Url::parse(&format!(
+            "{}/episode/{}",
+            server.uri(),
+            testing::GIGA_EPISODE_ID
+        ))?;
+        let pipe = Pipeline {
+            client: Client::new(ConfigBuilder::custom(server.uri())?.build()),
+            ..Pipeline::default()
+        }
+        .set_progress(ProgressConfig::disabled());
+
+        let error = pipe
+            .download(&url, "playground/output/giga_not_public")
+            .await
+            .unwrap_err();
+
+        let error = error.downcast_ref::<EpisodeNotPublic>().unwrap();
+        assert_eq!(error.id, testing::GIGA_EPISODE_ID);
+
+        Ok(())
+    }
+
+    #[cfg(feature = "giga")]
+    #[tokio::test]
+    async fn test_komga_series_layout() -> Result<()> {
+        let server = testing::giga_server().await?;
+        Mock::given(method("GET"))
+            .and(path("/rss/series/13933686331624733447"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_raw(std::fs::read(testing::GIGA_SERIES)?, "application/rss+xml"),
+            )
+            .mount(&server)
+            .await;
+        for number in [2, 3] {
+            let id = format!("932410362567641070{}", number - 1);
+            let episode = std::fs::read_to_string(testing::GIGA_EPISODE)?
+                .replace(testing::GIGA_IMAGE_HOST, &server.uri())
+                .replace(testing::GIGA_EPISODE_ID, &id)
+                .replace("\"number\": 1", &format!("\"number\": {}", number));
+            Mock::given(method("GET"))
+                .and(path(format!("/episode/{}.json", id)))
+                .respond_with(ResponseTemplate::new(200).set_body_raw(episode, "application/json"))
+                .mount(&server)
+                .await;
+        }
+
+        let dir = Path::new("playground/output/giga_series_komga");
+        let _ = std::fs::remove_dir_all(dir);
+        let pipe = Pipeline {
+            client: Client::new(ConfigBuilder::custom(server.uri())?.build()),
+            ..Pipeline::default()
+        }
+        .set_progress(ProgressConfig::disabled())
+        .set_writer_config(WriterConifg::new(
+            SaveFormat::Zip {
+                compression_method: zip::CompressionMethod::Stored,
+                extension: Some("cbz".to_string()),
+            },
+            image::ImageFormat::Png,
+        ))
+        .set_series_layout(SeriesLayout::Komga);
+        let url = Url::parse(&format!("{}/series/13933686331624733447", server.uri()))?;
+
+        pipe.download_series_in(&url, dir).await?;
+
+        let mut chapters = std::fs::read_dir(dir.join("Mock Series"))?
+            .map(|entry| Ok(entry?.file_name().to_string_lossy().to_string()))
+            .collect::<Result<Vec<_>>>()?;
+        chapters.sort();
+        assert_eq!(
+            chapters,
+            ["Chapter 001.cbz", "Chapter 002.cbz", "Chapter 003.cbz"]
+        );
+        assert!(dir.join(SeriesState::FILE_NAME).exists());
+
+        Ok(())
+    }
+
+    #[cfg(feature = "giga")]
+    #[tokio::test]
+    async fn test_series_volumes() -> Result<()> {
+        let server = testing::giga_series_server(1..=5).await?;
+        let dir = Path::new("playground/output/giga_series_volumes");
+        let _ = std::fs::remove_dir_all(dir);
+        let pipe = Pipeline {
+            client: Client::new(ConfigBuilder::custom(server.uri())?.build()),
+            ..Pipeline::default()
+        }
+        .set_progress(ProgressConfig::disabled())
+        .set_writer_config(WriterConifg::new(
+            SaveFormat::Zip {
+                compression_method: zip::CompressionMethod::Stored,
+                extension: Some("cbz".to_string()),
+            },
+            image::ImageFormat::Png,
+        ))
+        .set_volume_size(Some(2));
+        let url = Url::parse(&format!(
+            "{}/series/{}",
+            server.uri(),
+            testing::GIGA_SERIES_ID
+        ))?;
+
+        pipe.download_series_in(&url, dir).await?;
+
+        let mut volumes = std::fs::read_dir(dir)?
+            .map(|entry| Ok(entry?.file_name().to_string_lossy().to_string()))
+            .collect::<Result<Vec<_>>>()?;
+        volumes.retain(|name| name != SeriesState::FILE_NAME);
+        volumes.sort();
+        assert_eq!(
+            volumes,
+            [
+                "Mock Series Vol. 01.cbz",
+                "Mock Series Vol. 02.cbz",
+                "Mock Series Vol. 03.cbz"
+            ]
+        );
+        // three pages in every episode, the last volume takes the fifth one alone
+        let pages = volumes
+            .iter()
+            .map(|name| Ok(zip::ZipArchive::new(std::fs::File::open(dir.join(name))?)?.len()))
+            .collect::<Result<Vec<_>>>()?;
+        assert_eq!(pages, [6, 6, 3]);
+
+        Ok(())
+    }
+
+    #[cfg(feature = "giga")]
+    #[tokio::test]
+    async fn test_volumes_keep_their_numbers() -> Result<()> {
+        let dir = Path::new("playground/output/giga_series_volume_numbers");
+        let _ = std::fs::remove_dir_all(dir);
+        let volume_pages = |number: usize| -> Result<usize> {
+            let name = format!("Mock Series Vol. {:02}.cbz", number);
+            Ok(zip::ZipArchive::new(std::fs::File::open(dir.join(name))?)?.len())
+        };
+        // the feed drops the first episode by the time two more are published
+        for numbers in [1..=3, 2..=5] {
+            let server = testing::giga_series_server(numbers).await?;
+            let pipe = Pipeline {
+                client: Client::new(ConfigBuilder::custom(server.uri())?.build()),
+                ..Pipeline::default()
+            }
+            .set_progress(ProgressConfig::disabled())
+            .set_writer_config(WriterConifg::new(
+                SaveFormat::Zip {
+                    compression_method: zip::CompressionMethod::Stored,
+                    extension: Some("cbz".to_string()),
+                },
+                image::ImageFormat::Png,
+            ))
+            .set_volume_size(Some(2));
+            let url = Url::parse(&format!(
+                "{}/series/{}",
+                server.uri(),
+                testing::GIGA_SERIES_ID
+            ))?;
+            pipe.download_series_in(&url, dir).await?;
+        }
+
+        let state = SeriesState::load(dir).await?;
+        assert_eq!(
+            state.volume(2),
+            [
+                testing::giga_series_episode_id(3),
+                testing::giga_series_episode_id(4)
+            ]
+        );
+        assert_eq!(volume_pages(1)?, 6);
+        assert_eq!(volume_pages(2)?, 6);
+        assert_eq!(volume_pages(3)?, 3);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_second_download_reads_the_image_cache() -> Result<()> {
+        let server = testing::giga_server().await?;
+        let cache_dir = Path::new("playground/output/giga_image_cache");
+        let _ = std::fs::remove_dir_all(cache_dir);
+        let pipe = Pipeline {
+            client: Client::new(ConfigBuilder::custom(server.uri())?.build()),
+            ..Pipeline::default()
+        }
+        .set_progress(ProgressConfig::disabled())
+        .set_image_cache(Some(ImageCache::open(cache_dir, 64 * 1024 * 1024)?));
+        let url = Url::parse(&format!(
+            "{}/episode/{}",
+            server.uri(),
+            testing::GIGA_EPISODE_ID
+        ))?;
+        let page_requests = || async {
+            let requests = server.received_requests().await.unwrap_or_default();
+            requests
+                .iter()
+                .filter(|request| request.url.path().starts_with("/public/page/"))
+                .count()
+        };
+
+        pipe.download(&url, "playground/output/giga_cached_1")
+            .await?;
+        assert_eq!(page_requests().await, 3);
+        pipe.download(&url, "playground/output/giga_cached_2")
+            .await?;
+        assert_eq!(page_requests().await, 3);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_titleless_episode_is_named_by_id() -> Result<()> {
+        let server = MockServer::start().await;
+        let episode = std::fs::read_to_string(testing::GIGA_EPISODE)?
+            .replace(testing::GIGA_IMAGE_HOST, &server.uri())
+            .replacen(
+                "\"title\": \"[第1話]ボーイ・ミーツ・マリア\"",
+                "\"title\": \"\"",
+                1,
+            );
+        Mock::given(method("GET"))
+            .and(path(format!("/episode/{}.json", testing::GIGA_EPISODE_ID)))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(episode, "application/json"))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path_regex("^/public/page/"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_bytes(std::fs::read(testing::GIGA_PAGE)?),
+            )
+            .mount(&server)
+            .await;
+
+        let dir = Path::new("playground/output/giga_titleless");
+        let _ = std::fs::remove_dir_all(dir);
+        let pipe = Pipeline {
+            client: Client::new(ConfigBuilder::custom(server.uri())?.build()),
+            ..Pipeline::default()
+        }
+        .set_progress(ProgressConfig::disabled());
+        let url = Url::parse(&format!(
+            "{}/episode/{}",
+            server.uri(),
+            testing::GIGA_EPISODE_ID
+        ))?;
+
+        pipe.download_in(&url, dir).await?;
+        assert_eq!(
+            std::fs::read_dir(dir.join(testing::GIGA_EPISODE_ID))?.count(),
+            3
+        );
+
+        let pipe = pipe.set_writer_config(
+            WriterConifg::new(SaveFormat::Raw, image::ImageFormat::Png)
+                .with_missing_title(MissingTitle::Fail),
+        );
+        assert!(pipe.download_in(&url, dir).await.is_err());
+
+        Ok(())
+    }
+
+    #[cfg(feature = "giga")]
+    #[tokio::test]
+    async fn test_series_skips_completed_episodes() -> Result<()> {
+        let server = testing::giga_server().await?;
+        Mock::given(method("GET"))
+            .and(path("/rss/series/13933686331624733447"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_raw(std::fs::read(testing::GIGA_SERIES)?, "application/rss+xml"),
+            )
+            .mount(&server)
+            .await;
+        // the second episode is done and must not be fetched again
+        Mock::given(method("GET"))
+            .and(path("/episode/9324103625676410701.json"))
+            .respond_with(ResponseTemplate::new(404))
+            .expect(0)
+            .mount(&server)
+            .await;
+        let third = std::fs::read_to_string(testing::GIGA_EPISODE)?
+            .replace(testing::GIGA_IMAGE_HOST, &server.uri())
+            .replace(testing::GIGA_EPISODE_ID, "9324103625676410702")
+            .replace("[第1話]", "[第3話]");
+        Mock::given(method("GET"))
+            .and(path("/episode/9324103625676410702.json"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(third, "application/json"))
+            .mount(&server)
+            .await;
+
+        let dir = Path::new("playground/output/giga_series_state");
+        let _ = std::fs::remove_dir_all(dir);
+        let mut state = SeriesState::default();
+        state.complete("9324103625676410701");
+        state.save(dir).await?;
+        let pipe = Pipeline {
+            client: Client::new(ConfigBuilder::custom(server.uri())?.build()),
+            ..Pipeline::default()
+        }
+        .set_progress(ProgressConfig::disabled());
+        let url = Url::parse(&format!("{}/series/13933686331624733447", server.uri()))?;
+
+        pipe.download_series_in(&url, dir).await?;
+
+        let mut entries = std::fs::read_dir(dir)?
+            .map(|entry| Ok(entry?.file_name().to_string_lossy().to_string()))
+            .collect::<Result<Vec<_>>>()?;
+        entries.sort();
+        assert_eq!(
+            entries,
+            [
+                "[第1話]ボーイ・ミーツ・マリア",
+                "[第3話]ボーイ・ミーツ・マリア",
+                SeriesState::FILE_NAME,
+            ]
+        );
+        let state = SeriesState::load(dir).await?;
+        assert!([
+            "9324103625676410700",
+            "9324103625676410701",
+            "9324103625676410702"
+        ]
+        .iter()
+        .all(|id| state.is_completed(id)));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_list_recorded_pages() -> Result<()> {
+        let server = testing::giga_server().await?;
+        let url = Url::parse(&format!(
+            "{}/episode/{}",
+            server.uri(),
+            testing::GIGA_EPISODE_ID
+        ))?;
+        let pipe = Pipeline {
+            client: Client::new(ConfigBuilder::custom(server.uri())?.build()),
+            ..Pipeline::default()
+        };
+
+        let pages = pipe.list_pages(&url).await?;
+        let episode = pipe.fetch_episode(testing::GIGA_EPISODE_ID).await?;
+
+        let image_pages = episode.pages().into_iter().filter(|page| page.is_image());
+        assert_eq!(pages.len(), image_pages.count());
+        for (i, (index, url)) in pages.iter().enumerate() {
+            assert_eq!(*index, i);
+            assert!(url.as_str().starts_with(&server.uri()));
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_estimate_size_from_content_length() -> Result<()> {
+        let server = MockServer::start().await;
+        let episode = std::fs::read_to_string(testing::GIGA_EPISODE)?
+            .replace(testing::GIGA_IMAGE_HOST, &server.uri());
+        Mock::given(method("GET"))
+            .and(path(format!("/episode/{}.json", testing::GIGA_EPISODE_ID)))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(episode, "application/json"))
+            .mount(&server)
+            .await;
+        Mock::given(method("HEAD"))
+            .and(path_regex("^/public/page/"))
+            .respond_with(ResponseTemplate::new(200).insert_header("content-length", "1048576"))
+            .mount(&server)
+            .await;
+        let url = Url::parse(&format!(
+            "{}/episode/{}",
+            server.uri(),
+            testing::GIGA_EPISODE_ID
+        ))?;
+        let pipe = Pipeline {
+            client: Client::new(ConfigBuilder::custom(server.uri())?.build()),
+            ..Pipeline::default()
+        };
+
+        let estimate = pipe.estimate_size(&url).await?;
+
+        let pages = pipe.list_pages(&url).await?.len();
+        assert_eq!(estimate.pages, pages);
+        assert!(estimate.sampled > 0 && estimate.sampled <= SIZE_SAMPLE_PAGES);
+        assert_eq!(estimate.estimated_bytes, Some(pages as u64 * 1048576));
+        assert_eq!(estimate.estimated_mb(), Some(pages as f64));
+        // no page body was downloaded
+        let requests = server.received_requests().await.unwrap_or_default();
+        assert!(requests
+            .iter()
+            .all(|request| request.method != wiremock::http::Method::GET
+                || !request.url.path().starts_with("/public/page/")));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_download_with_external_client() -> Result<()> {
+        let server = MockServer::start().await;
+        let episode = std::fs::read_to_string(testing::GIGA_EPISODE)?
+            .replace(testing::GIGA_IMAGE_HOST, &server.uri());
+        // only the requests of the logged in client are answered
+        Mock::given(method("GET"))
+            .and(path(format!("/episode/{}.json", testing::GIGA_EPISODE_ID)))
+            .and(header("user-agent", "logged-in/1.0"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(episode, "application/json"))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path_regex("^/public/page/"))
+            .and(header("user-agent", "logged-in/1.0"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_bytes(std::fs::read(testing::GIGA_PAGE)?),
+            )
+            .mount(&server)
+            .await;
+        let client = Client::new(
+            ConfigBuilder::custom(server.uri())?
+                .with_user_agent("logged-in/1.0")
+                .build(),
+        );
+        let url = Url::parse(&format!(
+            "{}/episode/{}",
+            server.uri(),
+            testing::GIGA_EPISODE_ID
+        ))?;
+        let path = Path::new("playground/output/giga_external_client");
+        let _ = std::fs::remove_dir_all(path);
+
+        let pipe = Pipeline::default()
+            .with_client(client)
+            .set_progress(ProgressConfig::disabled());
+        pipe.download(&url, path).await?;
+
+        assert_eq!(std::fs::read_dir(path)?.count(), 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_solve_kind() {
+        let pipe = Pipeline::default();
+        assert_eq!(pipe.solve_kind(), SolveKind::Pixel);
+        assert!(!pipe.solve_kind().is_lossless_passthrough());
+    }
+
+    #[test]
+    fn test_pipelines_share_defaults() -> Result<()> {
+        let defaults = Defaults::default()
+            .with_progress(ProgressConfig::disabled())
+            .with_writer_config(
+                WriterConifg::new(SaveFormat::Raw, image::ImageFormat::Jpeg)
+                    .with_max_file_name_bytes(32),
+            )
+            .with_num_threads(3)
+            .with_num_connections(2)
+            .with_user_agent("my-app/1.0")
+            .with_proxy("http://127.0.0.1:8080")?;
+
+        let jump = Pipeline::from_defaults(Website::ShonenJumpPlus, &defaults)?;
+        let days = {
+            let defaults = defaults.clone();
+            std::thread::spawn(move || Pipeline::from_defaults(Website::ComicDays, &defaults))
+                .join()
+                .unwrap()?
+        };
+
+        for pipe in [&jump, &days] {
+            assert_eq!(pipe.num_threads, 3);
+            assert_eq!(pipe.num_connections(), 2);
+            assert_eq!(pipe.writer_config.image_format(), image::ImageFormat::Jpeg);
+            assert_eq!(pipe.writer_config.file_name(&"a".repeat(64)).len(), 27);
+            let config = pipe.client.config();
+            assert_eq!(
+                config.create_header()?[reqwest::header::USER_AGENT],
+                "my-app/1.0"
+            );
+            assert_eq!(config.proxy(), Some(&Url::parse("http://127.0.0.1:8080")?));
+        }
+        assert_eq!(
+            jump.client.config().base_url(),
+            &Website::ShonenJumpPlus.base_url()
+        );
+        assert_eq!(
+            days.client.config().base_url(),
+            &Website::ComicDays.base_url()
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_download_recorded_episode() -> Result<()> {
+        let server = testing::giga_server().await?;
+        let url = Url::parse(&format!(
+            "{}/episode/{}",
+            server.uri(),
+            testing::GIGA_EPISODE_ID
+        ))?;
+        let path = "playground/output/giga_recorded.zip";
+
+        let pipe = Pipeline {
+            client: Client::new(ConfigBuilder::custom(server.uri())?.build()),
+            ..Pipeline::default()
+        }
+        .set_progress(ProgressConfig::disabled())
+        .set_writer_config(WriterConifg::new(
+            SaveFormat::Zip {
+                compression_method: zip::CompressionMethod::Stored,
+                extension: None,
+            },
+            image::ImageFormat::Png,
+        ));
+        pipe.download(&url, path).await?;
+
+        let mut archive = zip::ZipArchive::new(std::fs::File::open(path)?)?;
+        assert_eq!(archive.len(), 3);
+        for i in 0..3 {
+            let mut bytes = Vec::new();
+            archive
+                .by_name(&format!("{}.png", i))?
+                .read_to_end(&mut bytes)?;
+            assert_eq!(image::load_from_memory(&bytes)?.dimensions(), (595, 842));
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_zip_comment_is_the_episode_url() -> Result<()> {
+        let server = testing::giga_server().await?;
+        let url = Url::parse(&format!(
+            "{}/episode/{}",
+            server.uri(),
+            testing::GIGA_EPISODE_ID
+        ))?;
+        let path = "playground/output/giga_source_url.zip";
+
+        let pipe = Pipeline::default()
+            .with_client(Client::new(ConfigBuilder::custom(server.uri())?.build()))
+            .set_progress(ProgressConfig::disabled())
+            .set_writer_config(
+                WriterConifg::new(
+                    SaveFormat::Zip {
+                        compression_method: zip::CompressionMethod::Stored,
+                        extension: None,
+                    },
+                    image::ImageFormat::Png,
+                )
+                .with_source_url(true),
+            );
+        pipe.download(&url, path).await?;
+
+        // the permalink of the recorded episode
+        let archive = zip::ZipArchive::new(std::fs::File::open(path)?)?;
+        assert_eq!(
+            archive.comment(),
+            format!(
+                "https://shonenjumpplus.com/episode/{}",
+                testing::GIGA_EPISODE_ID
+            )
+            .as_bytes()
+        );
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_pipeline_download_raw() -> Result<()> {
         let url = Url::parse("https://shonenjumpplus.com/episode/16457717013869519536")?;
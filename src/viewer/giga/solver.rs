@@ -1,24 +1,61 @@
-use anyhow::Result;
-use image::{DynamicImage, ImageBuffer, Rgb};
+use anyhow::{bail, Result};
+use image::{DynamicImage, GenericImageView, ImageBuffer, ImageFormat, Rgb};
 
-use crate::{solver::ImageSolver, utils::Bytes};
+use crate::{
+    solver::{ImageSolver, SolvedImage},
+    utils::{self, Bytes},
+};
 
 const NUM_CELLS: u8 = 4;
 const DIVISIBLE_WITH: u8 = 8;
 
+/// Scramble score above which a solved page is taken as still scrambled
+pub const SCRAMBLED_THRESHOLD: f64 = 1.5;
+
 #[derive(Debug, Clone)]
 pub struct Solver {
     num_cells: u32,
     divisible_with: u32,
+    /// Format the solved bytes are encoded in
+    image_format: ImageFormat,
 }
 
 impl Solver {
-    pub fn new() -> Self {
+    /// Solver splitting the image into `num_cells` x `num_cells` tiles
+    /// whose sides are multiples of `divisible_with`
+    pub fn new(num_cells: u32, divisible_with: u32) -> Self {
         Solver {
-            num_cells: u32::from(NUM_CELLS),
-            divisible_with: u32::from(DIVISIBLE_WITH),
+            num_cells,
+            divisible_with,
+            image_format: ImageFormat::Png,
         }
     }
+
+    /// Encode the solved bytes in `image_format` instead of PNG
+    pub fn with_image_format(self, image_format: ImageFormat) -> Self {
+        Self {
+            image_format,
+            ..self
+        }
+    }
+
+    pub fn num_cells(&self) -> u32 {
+        self.num_cells
+    }
+
+    pub fn divisible_with(&self) -> u32 {
+        self.divisible_with
+    }
+
+    pub fn image_format(&self) -> ImageFormat {
+        self.image_format
+    }
+}
+
+impl Default for Solver {
+    fn default() -> Self {
+        Self::new(u32::from(NUM_CELLS), u32::from(DIVISIBLE_WITH))
+    }
 }
 
 impl Solver {
@@ -49,7 +86,7 @@ impl Solver {
 
         for x in 0..width {
             for y in 0..height {
-                let source_pixel = img.get_pixel(source_x + x, source_y + y).clone();
+                let source_pixel = *img.get_pixel(source_x + x, source_y + y);
                 let target_pixel = img.get_pixel(target_x + x, target_y + y);
 
                 img.put_pixel(source_x + x, source_y + y, *target_pixel);
@@ -62,6 +99,13 @@ impl Solver {
         &self,
         buffer: image::ImageBuffer<image::Rgb<u8>, Vec<u8>>,
     ) -> Result<image::ImageBuffer<image::Rgb<u8>, Vec<u8>>> {
+        if self.num_cells == 0 || self.divisible_with == 0 {
+            bail!(
+                "num_cells and divisible_with must be positive, got {} and {}",
+                self.num_cells,
+                self.divisible_with
+            );
+        }
         let (width, height) = buffer.dimensions();
 
         let cell_width = width / (self.num_cells * self.divisible_with) * self.divisible_with;
@@ -86,6 +130,47 @@ impl Solver {
         Ok(img)
     }
 
+    /// How much sharper the edges on the tile boundaries are than the edges next to them.
+    ///
+    /// Around 1 for a correctly solved page, since the picture continues across the
+    /// boundaries, and higher when the tiles are still out of place.
+    pub fn scramble_score(&self, image: &DynamicImage) -> f64 {
+        let gray = image.to_luma8();
+        let (width, height) = gray.dimensions();
+        if self.num_cells < 2 || self.divisible_with == 0 {
+            return 1.0;
+        }
+        let cell_width = width / (self.num_cells * self.divisible_with) * self.divisible_with;
+        let cell_height = height / (self.num_cells * self.divisible_with) * self.divisible_with;
+        if cell_width < 3 || cell_height < 3 {
+            return 1.0;
+        }
+        let (tiled_width, tiled_height) =
+            (cell_width * self.num_cells, cell_height * self.num_cells);
+        let luma = |x: u32, y: u32| gray.get_pixel(x, y).0[0] as f64;
+
+        let (mut boundary, mut inside, mut count) = (0.0, 0.0, 0.0);
+        for k in 1..self.num_cells {
+            let (x, y) = (k * cell_width, k * cell_height);
+            for i in 0..tiled_height {
+                boundary += (luma(x, i) - luma(x - 1, i)).abs();
+                inside += (luma(x - 1, i) - luma(x - 2, i)).abs();
+            }
+            for i in 0..tiled_width {
+                boundary += (luma(i, y) - luma(i, y - 1)).abs();
+                inside += (luma(i, y - 1) - luma(i, y - 2)).abs();
+            }
+            count += (tiled_width + tiled_height) as f64;
+        }
+        // flat pages have no edges either way
+        (boundary / count + 1.0) / (inside / count + 1.0)
+    }
+
+    /// Whether the solved page still looks scrambled, e.g. solved with wrong parameters
+    pub fn looks_scrambled(&self, image: &DynamicImage) -> bool {
+        self.scramble_score(image) > SCRAMBLED_THRESHOLD
+    }
+
     fn solve_image(&self, image: image::DynamicImage) -> Result<image::DynamicImage> {
         let buffer = image.to_rgb8();
         let solved_buffer = self.solve_buffer(buffer)?;
@@ -99,7 +184,7 @@ impl ImageSolver for Solver {
         let image = image::load_from_memory(bytes.as_ref())?;
         let solved_image = self.solve_image(image)?;
 
-        Ok(solved_image.as_bytes().into())
+        utils::encode_image(&solved_image, self.image_format)
     }
 
     fn solve_from_bytes<B: AsRef<[u8]>>(&self, bytes: B) -> Result<DynamicImage> {
@@ -108,19 +193,137 @@ impl ImageSolver for Solver {
 
         Ok(solved_image)
     }
+
+    fn solve_with_dimensions<T: AsRef<[u8]>>(&self, bytes: T) -> Result<SolvedImage> {
+        let image = image::load_from_memory(bytes.as_ref())?;
+        let solved_image = self.solve_image(image)?;
+        let dimensions = solved_image.dimensions();
+
+        Ok(SolvedImage::new(
+            utils::encode_image(&solved_image, self.image_format)?,
+            Some(dimensions),
+        ))
+    }
 }
 
 #[cfg(test)]
 mod test {
+    use crate::testing::{max_pixel_difference, GIGA_SAMPLE, GIGA_SAMPLE_SOLVED};
+
     use super::*;
 
     #[test]
     fn test_solve_sample_image() -> Result<()> {
-        let solver = Solver::new();
-        let img = image::ImageReader::open("./playground/assets/giga-original.jpg")?.decode()?;
+        let solver = Solver::default();
+        let bytes = std::fs::read(GIGA_SAMPLE)?;
+        let img = image::load_from_memory(&bytes)?;
+        let expected = image::open(GIGA_SAMPLE_SOLVED)?;
+
+        // the tiles are only moved, so the pixels match exactly
+        let solved = solver.solve_image(img.clone())?;
+        assert_eq!(max_pixel_difference(&solved, &expected)?, 0);
+        let solved = solver.solve_from_bytes(&bytes)?;
+        assert_eq!(max_pixel_difference(&solved, &expected)?, 0);
+        assert!(max_pixel_difference(&img, &expected)? > 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_solve_non_divisible_dimensions() -> Result<()> {
+        let solver = Solver::default();
+        // the tiles stay 144x208, the pixels right and below them are left as is
+        let crop = |image: DynamicImage| image.crop_imm(0, 0, 590, 839);
+        let img = crop(image::open(GIGA_SAMPLE)?);
+        let expected = crop(image::open(GIGA_SAMPLE_SOLVED)?);
 
         let solved = solver.solve_image(img)?;
-        solved.save("./playground/output/giga-solved.jpg")?;
+        assert_eq!(max_pixel_difference(&solved, &expected)?, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_scramble_score() -> Result<()> {
+        let solver = Solver::default();
+        // a smooth page, so the picture continues across the tile boundaries
+        let page = DynamicImage::ImageRgb8(ImageBuffer::from_fn(595, 842, |x, y| {
+            let value = 128.0 + 60.0 * (x as f64 / 40.0).sin() + 50.0 * (y as f64 / 70.0).cos();
+            Rgb([value as u8, (x / 3) as u8, (y / 4) as u8])
+        }));
+        // swapping the tiles is its own inverse, so solving the page scrambles it
+        let scrambled = solver.solve_image(page.clone())?;
+
+        assert!(!solver.looks_scrambled(&page));
+        assert!(solver.looks_scrambled(&scrambled));
+        assert!(solver.scramble_score(&scrambled) > 2.0 * solver.scramble_score(&page));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_solve_with_dimensions() -> Result<()> {
+        let solver = Solver::default();
+        let bytes = std::fs::read(GIGA_SAMPLE)?;
+        let expected = image::load_from_memory(&bytes)?.dimensions();
+
+        let solved = solver.solve_with_dimensions(&bytes)?;
+        assert_eq!(solved.dimensions(), Some(expected));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_solve_encodes_image() -> Result<()> {
+        let bytes = std::fs::read(GIGA_SAMPLE)?;
+        let expected = image::open(GIGA_SAMPLE_SOLVED)?;
+
+        let solved = Solver::default().solve_with_dimensions(&bytes)?;
+        let decoded = image::load_from_memory(solved.bytes())?;
+        assert_eq!(Some(decoded.dimensions()), solved.dimensions());
+        assert_eq!(max_pixel_difference(&decoded, &expected)?, 0);
+
+        let solver = Solver::default().with_image_format(ImageFormat::Jpeg);
+        let solved = solver.solve(&bytes)?;
+        assert_eq!(image::guess_format(&solved)?, ImageFormat::Jpeg);
+
+        Ok(())
+    }
+
+    #[cfg(feature = "pdf")]
+    #[tokio::test]
+    async fn test_solved_image_writes_pdf() -> Result<()> {
+        use crate::{
+            io::{pdf::PdfWriter, EpisodeWriter},
+            progress::ProgressConfig,
+        };
+
+        let bytes = std::fs::read(GIGA_SAMPLE)?;
+        let solved = Solver::default().solve_with_dimensions(&bytes)?;
+        let (width, height) = image::load_from_memory(&bytes)?.dimensions();
+
+        let path = "playground/output/giga_solved.pdf";
+        let writer = PdfWriter::new(ProgressConfig::disabled(), ImageFormat::Png);
+        writer.write_solved(vec![solved], path).await?;
+
+        let pdf = tokio::fs::read(path).await?;
+        let pdf = String::from_utf8_lossy(&pdf);
+        assert!(pdf.contains(&format!("/Width {width}")));
+        assert!(pdf.contains(&format!("/Height {height}")));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_solve_with_custom_parameters() -> Result<()> {
+        let bytes = std::fs::read(GIGA_SAMPLE)?;
+
+        let default = Solver::default().solve_from_bytes(&bytes)?;
+        let custom = Solver::new(2, 16).solve_from_bytes(&bytes)?;
+        assert_eq!(default.dimensions(), custom.dimensions());
+        assert_ne!(default.as_bytes(), custom.as_bytes());
+
+        assert!(Solver::new(0, 8).solve_from_bytes(&bytes).is_err());
 
         Ok(())
     }
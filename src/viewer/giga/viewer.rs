@@ -1,15 +1,20 @@
 use std::sync::LazyLock;
 
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
 use regex::Regex;
 use reqwest::header::{self, HeaderMap, HeaderValue};
-use reqwest::Response;
+use reqwest::{Response, StatusCode};
 use url::Url;
 
-use crate::auth::EmptyAuth;
-use crate::utils;
+use crate::auth::{Auth, EmptyAuth, Netrc, NetrcAuth};
+use crate::utils::{self, Bytes};
 use crate::viewer::giga::data::Episode;
-use crate::viewer::{ViewerClient, ViewerConfig, ViewerConfigBuilder, ViewerWebsite};
+#[cfg(feature = "giga")]
+use crate::viewer::giga::data::Series;
+use crate::viewer::{
+    authorization, env_var, lookup_host, send_request, ClientOptions, ResponseCache, SitePreset,
+    ViewerClient, ViewerConfig, ViewerConfigBuilder, ViewerWebsite,
+};
 
 /// GigaViewer website family
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -32,6 +37,7 @@ pub enum Website {
     ComicOgyaaa,
     ComicEarthstar,
     Ourfeel,
+    ComicBushi,
     Custom(String),
 }
 
@@ -54,12 +60,17 @@ static HOST_TO_WEBSITE: phf::Map<&str, Website> = phf::phf_map! {
     "comic-ogyaaa.com" => Website::ComicOgyaaa,
     "comic-earthstar.com" => Website::ComicEarthstar,
     "ourfeel.jp" => Website::Ourfeel,
+    "comicbushi-web.com" => Website::ComicBushi,
 };
 
 /// Episode path pattern
 static EPISODE_PATH_PATTERN: LazyLock<Regex> =
     LazyLock::new(|| Regex::new(r#"/episode/(\d+)(?:\.json)?$"#).unwrap());
 
+/// Series path pattern, the id is numeric or a title slug depending on the site
+static SERIES_PATH_PATTERN: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"^(?:/rss)?/series/([^/]+?)(?:\.xml)?/?$"#).unwrap());
+
 impl ViewerWebsite<Website> for Website {
     fn host(&self) -> &str {
         match &self {
@@ -81,6 +92,7 @@ impl ViewerWebsite<Website> for Website {
             Website::ComicOgyaaa => "comic-ogyaaa.com",
             Website::ComicEarthstar => "comic-earthstar.com",
             Website::Ourfeel => "ourfeel.jp",
+            Website::ComicBushi => "comicbushi-web.com",
             Website::Custom(host) => host,
         }
     }
@@ -90,30 +102,69 @@ impl ViewerWebsite<Website> for Website {
     }
 
     fn lookup(host: &str) -> Option<Website> {
-        HOST_TO_WEBSITE.get(host).map(|w| w.clone())
+        lookup_host(&HOST_TO_WEBSITE, host)
+    }
+
+    /// Image CDNs of giga sites check the `Referer`
+    fn preset(&self) -> SitePreset {
+        SitePreset {
+            referer: true,
+            ..SitePreset::default()
+        }
     }
 }
 /// viewer config
 #[derive(Debug, Clone)]
 pub struct Config {
     base_url: Url,
+    /// `Accept` of the image requests
+    image_accept: Option<String>,
+    /// Origin the image urls are rewritten to
+    image_host: Option<Url>,
+    client_options: ClientOptions,
+    /// `Authorization` sent to the site
+    authorization: Option<String>,
 }
 
 impl ViewerConfig for Config {
     fn create_header(&self) -> Result<HeaderMap> {
+        self.client_options
+            .headers(&self.base_url, self.authorization.as_deref())
+    }
+}
+
+impl Config {
+    pub fn base_url(&self) -> &Url {
+        &self.base_url
+    }
+
+    pub fn proxy(&self) -> Option<&Url> {
+        self.client_options.proxy()
+    }
+
+    /// Whether the url is on the site, which gets the `Authorization`
+    fn is_site(&self, url: &Url) -> bool {
+        url.origin() == self.base_url.origin()
+    }
+
+    /// Headers added to the image requests on top of the common ones
+    fn image_header(&self) -> Result<Option<HeaderMap>> {
+        let Some(accept) = &self.image_accept else {
+            return Ok(None);
+        };
         let mut headers = HeaderMap::new();
-        headers.insert(
-            header::USER_AGENT,
-            HeaderValue::from_str(&utils::UserAgent::Bot.value())?,
-        );
-        Ok(headers)
+        headers.insert(header::ACCEPT, HeaderValue::from_str(accept)?);
+        Ok(Some(headers))
     }
 }
 
 #[derive(Debug, Clone)]
-pub struct ConfigBuilder {
+pub struct ConfigBuilder<A: Auth = EmptyAuth> {
     base_url: Url,
-    auth: Option<EmptyAuth>,
+    image_accept: Option<String>,
+    image_host: Option<Url>,
+    client_options: ClientOptions,
+    auth: Option<A>,
 }
 
 impl ConfigBuilder {
@@ -121,28 +172,98 @@ impl ConfigBuilder {
     pub fn new(website: Website) -> Self {
         Self {
             base_url: website.base_url(),
+            image_accept: None,
+            image_host: None,
+            client_options: ClientOptions::new(website.preset()),
             auth: None,
         }
     }
 
+    /// Create a new ConfigBuilder from preset with the overrides in the environment.
+    ///
+    /// Reads `MANGA_PROXY` and `MANGA_USER_AGENT`, unset variables keep the defaults.
+    pub fn from_env(website: Website) -> Result<Self> {
+        Self::from_vars(website, env_var)
+    }
+
+    /// Like [`ConfigBuilder::from_env`], looking the variables up with `vars`
+    pub fn from_vars(website: Website, vars: impl Fn(&str) -> Option<String>) -> Result<Self> {
+        Ok(Self {
+            client_options: ClientOptions::from_vars(website.preset(), vars)?,
+            ..Self::new(website)
+        })
+    }
+
     /// Create a new ConfigBuilder from custom url
     pub fn custom(url: String) -> Result<Self> {
         Ok(Self {
             base_url: Url::parse(&url)?,
+            image_accept: None,
+            image_host: None,
+            client_options: ClientOptions::new(Website::ShonenJumpPlus.preset()),
             auth: None,
         })
     }
 }
 
-impl ViewerConfigBuilder<Config, EmptyAuth> for ConfigBuilder {
-    fn set_auth(&mut self, auth: EmptyAuth) -> &mut Self {
+impl<A: Auth> ConfigBuilder<A> {
+    /// Authenticate every request with this auth, e.g. the credentials of a netrc file
+    pub fn with_auth<B: Auth>(self, auth: B) -> ConfigBuilder<B> {
+        self.replace_auth(Some(auth))
+    }
+
+    /// Authenticate with the credentials of the host of the site in the netrc, if it has any
+    pub fn with_netrc(self, netrc: &Netrc) -> ConfigBuilder<NetrcAuth> {
+        let auth = self.base_url.host_str().and_then(|host| netrc.auth(host));
+        self.replace_auth(auth)
+    }
+
+    fn replace_auth<B: Auth>(self, auth: Option<B>) -> ConfigBuilder<B> {
+        ConfigBuilder {
+            base_url: self.base_url,
+            image_accept: self.image_accept,
+            image_host: self.image_host,
+            client_options: self.client_options,
+            auth,
+        }
+    }
+
+    /// Ask for the images in this format with an `Accept` header, e.g. `image/jpeg`
+    /// where the CDN would serve WebP otherwise
+    pub fn with_image_accept(self, image_accept: &str) -> Self {
+        Self {
+            image_accept: Some(image_accept.to_string()),
+            ..self
+        }
+    }
+
+    /// Fetch the images from this origin instead of the host in the episode, e.g. a
+    /// regional CDN or a mirror. Only the scheme, host and port of `image_host` are used.
+    pub fn with_image_host(self, image_host: &str) -> Result<Self> {
+        Ok(Self {
+            image_host: Some(Url::parse(image_host)?),
+            ..self
+        })
+    }
+}
+
+impl<A: Auth> ViewerConfigBuilder<Config, A> for ConfigBuilder<A> {
+    fn set_auth(&mut self, auth: A) -> &mut Self {
         self.auth = Some(auth);
         self
     }
 
+    fn client_options(&mut self) -> &mut ClientOptions {
+        &mut self.client_options
+    }
+
     fn build(&self) -> Config {
         Config {
             base_url: self.base_url.clone(),
+            image_accept: self.image_accept.clone(),
+            image_host: self.image_host.clone(),
+            client_options: self.client_options.clone(),
+            authorization: authorization(self.auth.as_ref()),
         }
     }
 }
@@ -152,12 +273,17 @@ impl ViewerConfigBuilder<Config, EmptyAuth> for ConfigBuilder {
 pub struct Client {
     client: reqwest::Client,
     config: Config,
+    cache: ResponseCache,
 }
 
 impl ViewerClient<Config> for Client {
     fn new(config: Config) -> Self {
-        let client = reqwest::Client::new();
-        Self { client, config }
+        let client = config.client_options.http_client();
+        Self {
+            client,
+            config,
+            cache: ResponseCache::default(),
+        }
     }
 
     async fn fetch_raw<B: Into<reqwest::Body> + Send>(
@@ -167,18 +293,16 @@ impl ViewerClient<Config> for Client {
         body: Option<B>,
         headers: Option<HeaderMap>,
     ) -> Result<Response> {
-        let mut req = self
-            .client
-            .request(method, url)
-            .headers(self.config.create_header()?);
-        if let Some(headers) = headers {
-            req = req.headers(headers);
-        }
-        if let Some(body) = body {
-            req = req.body(body);
-        }
-        let res = req.send().await?.error_for_status()?;
-        Ok(res)
+        let is_site = self.config.is_site(&url);
+        let request = self.client.request(method, url);
+        send_request(
+            request,
+            self.config.create_header()?,
+            is_site,
+            body,
+            headers,
+        )
+        .await
     }
 
     /// Get episode id from the provided url.
@@ -192,6 +316,60 @@ impl ViewerClient<Config> for Client {
 }
 
 impl Client {
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Get series id from the provided url.
+    /// - https://example.com/series/123456
+    /// - https://example.com/series/title-slug
+    /// - https://example.com/rss/series/123456
+    pub fn parse_series_id(&self, url: &Url) -> Option<String> {
+        let captures = SERIES_PATH_PATTERN.captures(url.path())?;
+        captures.get(1).map(|m| m.as_str().to_string())
+    }
+
+    #[cfg(feature = "giga")]
+    fn compose_series_url(&self, series_id: &str) -> Url {
+        self.config
+            .base_url
+            .join(&format!("/rss/series/{}", series_id))
+            .unwrap()
+    }
+
+    /// Get the series and its episodes from the RSS feed of the series
+    #[cfg(feature = "giga")]
+    pub async fn get_series(&self, series_id: &str) -> Result<Series> {
+        let url = self.compose_series_url(series_id);
+        let bytes = self.fetch_bytes(url).await?;
+        let channel = rss::Channel::read_from(bytes.as_ref())
+            .with_context(|| format!("Failed to parse series {}", series_id))?;
+        Ok(Series::from_channel(series_id, &channel))
+    }
+
+    /// The image url on the configured image host, if there is one
+    pub fn image_url(&self, url: Url) -> Url {
+        let Some(image_host) = &self.config.image_host else {
+            return url;
+        };
+        let mut rewritten = image_host.clone();
+        rewritten.set_path(url.path());
+        rewritten.set_query(url.query());
+        rewritten
+    }
+
+    /// GET an image, asking for the configured format
+    pub async fn get_image(&self, url: Url) -> Result<Response> {
+        let headers = self.config.image_header()?;
+        self.fetch_raw::<reqwest::Body>(url, reqwest::Method::GET, None, headers)
+            .await
+    }
+
+    /// Share the cache of episode responses, e.g. between the clients of several downloads
+    pub fn with_cache(self, cache: ResponseCache) -> Self {
+        Self { cache, ..self }
+    }
+
     fn compose_episode_url(&self, episode_id: &str) -> Url {
         self.config
             .base_url
@@ -202,38 +380,502 @@ impl Client {
     /// Get episode
     pub async fn get_episode(&self, episode_id: &str) -> Result<Episode> {
         let url = self.compose_episode_url(episode_id);
-        let res = self.get(url).await?;
-        let episode: Episode = serde_json::from_slice(&res.bytes().await?)?;
+        let episode: Episode = utils::parse_json(&self.fetch_revalidated(url).await?)
+            .with_context(|| format!("Failed to parse episode {}", episode_id))?;
         Ok(episode)
     }
+
+    /// GET the url, using the cached body when the server answers `304 Not Modified`
+    async fn fetch_revalidated(&self, url: Url) -> Result<Bytes> {
+        let headers = self.cache.conditional_headers(&url)?;
+        let res = self
+            .fetch_raw::<reqwest::Body>(url.clone(), reqwest::Method::GET, None, headers)
+            .await?;
+
+        if res.status() == StatusCode::NOT_MODIFIED {
+            tracing::debug!("Not modified, using the cached response of {}", url);
+            return match self.cache.get(&url)? {
+                Some(body) => Ok(body),
+                None => bail!("Got 304 Not Modified without a cached response: {}", url),
+            };
+        }
+        let headers = res.headers().clone();
+        let body = res.bytes().await?;
+        self.cache.store(url, &headers, body.clone())?;
+        Ok(body)
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use std::sync::Arc;
+    use std::{collections::HashMap, io::Write, sync::Arc};
 
+    use flate2::{write::GzEncoder, Compression};
     use futures::StreamExt as _;
+    use image::GenericImageView;
     use indicatif::ParallelProgressIterator;
     use rayon::{
         iter::{IntoParallelRefIterator, ParallelIterator},
         slice::ParallelSliceMut,
     };
+    use wiremock::{
+        matchers::{header, header_regex, method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
 
     #[cfg(feature = "pdf")]
     use crate::io::pdf::PdfWriter;
     use crate::{
-        data::{MangaEpisode, MangaPage},
+        auth::BearerAuth,
+        data::{MangaEpisode, MangaPage, ScrollDirection},
         io::{raw::RawWriter, zip::ZipWriter, EpisodeWriter},
         progress::ProgressConfig,
         solver::ImageSolver,
-        viewer::giga::solver::Solver,
+        testing,
+        viewer::{giga::solver::Solver, TlsBackend, PROXY_ENV, USER_AGENT_ENV},
     };
 
     use super::*;
 
+    #[test]
+    fn test_lookup_with_and_without_www() {
+        for host in ["shonenjumpplus.com", "www.shonenjumpplus.com"] {
+            assert_eq!(Website::lookup(host), Some(Website::ShonenJumpPlus));
+        }
+        for host in ["www.sunday-webry.com", "sunday-webry.com"] {
+            assert_eq!(Website::lookup(host), Some(Website::SundayWebry));
+        }
+        assert_eq!(Website::lookup("www.example.com"), None);
+    }
+
+    #[test]
+    fn test_preset_sends_referer() -> Result<()> {
+        let website = Website::ComicDays;
+        assert!(website.preset().referer);
+
+        let headers = ConfigBuilder::new(website.clone())
+            .build()
+            .create_header()?;
+        assert_eq!(headers[header::REFERER], website.base_url().as_str());
+
+        // a custom url is some giga viewer as well
+        let headers = ConfigBuilder::custom("https://example.com".to_string())?
+            .build()
+            .create_header()?;
+        assert_eq!(headers[header::REFERER], "https://example.com/");
+
+        let headers = ConfigBuilder::custom("https://example.com".to_string())?
+            .with_referer(false)
+            .build()
+            .create_header()?;
+        assert!(!headers.contains_key(header::REFERER));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_netrc_credentials_of_the_site() -> Result<()> {
+        let netrc = Netrc::parse("machine www.shonenjumpplus.com password token123")?;
+
+        let headers = ConfigBuilder::new(Website::ShonenJumpPlus)
+            .with_netrc(&netrc)
+            .build()
+            .create_header()?;
+        assert_eq!(headers[header::AUTHORIZATION], "Bearer token123");
+
+        let headers = ConfigBuilder::new(Website::ComicDays)
+            .with_netrc(&netrc)
+            .build()
+            .create_header()?;
+        assert!(!headers.contains_key(header::AUTHORIZATION));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_bearer_auth_is_sent() -> Result<()> {
+        let headers = ConfigBuilder::new(Website::ShonenJumpPlus)
+            .with_auth(BearerAuth::new("token123"))
+            .build()
+            .create_header()?;
+        assert_eq!(headers[header::AUTHORIZATION], "Bearer token123");
+
+        // the empty auth sends nothing
+        let mut builder = ConfigBuilder::new(Website::ShonenJumpPlus);
+        builder.set_auth(EmptyAuth {});
+        assert!(!builder
+            .build()
+            .create_header()?
+            .contains_key(header::AUTHORIZATION));
+
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/episode"))
+            .and(header("authorization", "Bearer token123"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&server)
+            .await;
+        let client = Client::new(
+            ConfigBuilder::custom(server.uri())?
+                .with_auth(BearerAuth::new("token123"))
+                .build(),
+        );
+        client
+            .get(Url::parse(&format!("{}/episode", server.uri()))?)
+            .await?
+            .error_for_status()?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_image_host_gets_no_auth() -> Result<()> {
+        let site = MockServer::start().await;
+        let mirror = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/page.jpg"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(b"jpeg".to_vec()))
+            .mount(&mirror)
+            .await;
+        let client = Client::new(
+            ConfigBuilder::custom(site.uri())?
+                .with_auth(BearerAuth::new("token123"))
+                .with_image_host(&mirror.uri())?
+                .build(),
+        );
+
+        let url = client.image_url(Url::parse(&format!("{}/page.jpg", site.uri()))?);
+        client.get_image(url).await?;
+
+        let requests = mirror.received_requests().await.unwrap_or_default();
+        assert_eq!(requests.len(), 1);
+        assert!(!requests[0].headers.contains_key("authorization"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_image_requests_send_accept() -> Result<()> {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/page.jpg"))
+            .and(header("accept", "image/jpeg"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(b"jpeg".to_vec()))
+            .mount(&server)
+            .await;
+        let url = Url::parse(&format!("{}/page.jpg", server.uri()))?;
+
+        let client = Client::new(
+            ConfigBuilder::custom(server.uri())?
+                .with_image_accept("image/jpeg")
+                .build(),
+        );
+        let bytes = client.get_image(url.clone()).await?.bytes().await?;
+        assert_eq!(bytes, &b"jpeg"[..]);
+
+        // no `Accept` is sent unless configured
+        let client = Client::new(ConfigBuilder::custom(server.uri())?.build());
+        assert!(client.get_image(url).await.is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_try_build_rejects_invalid_options() -> Result<()> {
+        let builder = ConfigBuilder::new(Website::ShonenJumpPlus);
+        assert!(builder.clone().with_proxy("not a proxy").is_err());
+        assert!(builder
+            .clone()
+            .with_user_agent("line\nbreak")
+            .try_build()
+            .is_err());
+
+        let config = builder.with_proxy("socks5://127.0.0.1:1080")?.try_build()?;
+        assert_eq!(
+            config.proxy(),
+            Some(&Url::parse("socks5://127.0.0.1:1080")?)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_config_from_vars() -> Result<()> {
+        let vars = HashMap::from([
+            (PROXY_ENV, "http://127.0.0.1:8080"),
+            (USER_AGENT_ENV, "manga-test/0.1"),
+        ]);
+        let lookup = |key: &str| vars.get(key).map(|value| value.to_string());
+
+        let config = ConfigBuilder::from_vars(Website::ShonenJumpPlus, lookup)?.build();
+        assert_eq!(config.proxy(), Some(&Url::parse("http://127.0.0.1:8080")?));
+        assert_eq!(
+            config.create_header()?[header::USER_AGENT],
+            "manga-test/0.1"
+        );
+
+        // unset variables keep the defaults
+        let config = ConfigBuilder::from_vars(Website::ShonenJumpPlus, |_| None)?.build();
+        assert_eq!(config.proxy(), None);
+        assert_eq!(
+            config.create_header()?[header::USER_AGENT],
+            utils::UserAgent::Bot.value().as_str()
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_http1_only() -> Result<()> {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/page.png"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&server)
+            .await;
+        let client = Client::new(
+            ConfigBuilder::new(Website::ShonenJumpPlus)
+                .with_http1_only(true)
+                .build(),
+        );
+
+        let res = client
+            .fetch_raw::<reqwest::Body>(
+                Url::parse(&format!("{}/page.png", server.uri()))?,
+                reqwest::Method::GET,
+                None,
+                None,
+            )
+            .await?;
+        assert_eq!(res.version(), reqwest::Version::HTTP_11);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_fetch_bytes() -> Result<()> {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/page.png"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(b"page bytes".to_vec()))
+            .mount(&server)
+            .await;
+        let client = Client::new(ConfigBuilder::new(Website::ShonenJumpPlus).build());
+
+        let bytes = client
+            .fetch_bytes(Url::parse(&format!("{}/page.png", server.uri()))?)
+            .await?;
+        assert_eq!(bytes, &b"page bytes"[..]);
+
+        // error statuses are reported by fetch_raw
+        let missing = Url::parse(&format!("{}/missing.png", server.uri()))?;
+        assert!(client.fetch_bytes(missing).await.is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_get_gzip_episode() -> Result<()> {
+        let json = serde_json::json!({
+            "readableProduct": {
+                "id": "1",
+                "title": "gzip",
+                "typeName": "episode",
+                "isPublic": true,
+                "number": 1,
+                "permalink": "https://example.com/episode/1",
+            }
+        });
+        let mut gzip = GzEncoder::new(Vec::new(), Compression::default());
+        gzip.write_all(json.to_string().as_bytes())?;
+
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/episode/1.json"))
+            .and(header_regex("accept-encoding", "gzip"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("content-encoding", "gzip")
+                    .set_body_bytes(gzip.finish()?),
+            )
+            .mount(&server)
+            .await;
+        let client = Client::new(ConfigBuilder::custom(server.uri())?.build());
+
+        let episode = client.get_episode("1").await?;
+        assert_eq!(episode.title().as_deref(), Some("gzip"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_get_episode_with_bom() -> Result<()> {
+        let server = MockServer::start().await;
+        let mut body = b"\xEF\xBB\xBF\r\n".to_vec();
+        body.extend(std::fs::read(testing::GIGA_EPISODE)?);
+        Mock::given(method("GET"))
+            .and(path(format!("/episode/{}.json", testing::GIGA_EPISODE_ID)))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(body, "application/json"))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/episode/2.json"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("<html>maintenance</html>"))
+            .mount(&server)
+            .await;
+        let client = Client::new(ConfigBuilder::custom(server.uri())?.build());
+
+        let episode = client.get_episode(testing::GIGA_EPISODE_ID).await?;
+        assert_eq!(episode.id(), testing::GIGA_EPISODE_ID);
+
+        let error = client.get_episode("2").await.unwrap_err();
+        assert!(format!("{:#}", error).contains("<html>maintenance</html>"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_get_recorded_episode() -> Result<()> {
+        let server = testing::giga_server().await?;
+        let client = Client::new(ConfigBuilder::custom(server.uri())?.build());
+
+        let episode = client.get_episode(testing::GIGA_EPISODE_ID).await?;
+        assert_eq!(episode.id(), testing::GIGA_EPISODE_ID);
+        assert_eq!(episode.scroll_direction(), ScrollDirection::RightToLeft);
+
+        let pages = episode.pages();
+        assert_eq!(pages.len(), 3);
+        let bytes = client.fetch_bytes(pages[0].url()?).await?;
+        let image = Solver::default().solve_from_bytes(bytes)?;
+        assert_eq!(image.dimensions(), (595, 842));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_client_connection_options() -> Result<()> {
+        let server = testing::giga_server().await?;
+        let backends = [
+            TlsBackend::Default,
+            #[cfg(feature = "native-tls")]
+            TlsBackend::NativeTls,
+            #[cfg(feature = "rustls-tls")]
+            TlsBackend::Rustls,
+        ];
+
+        for tls_backend in backends {
+            let config = ConfigBuilder::custom(server.uri())?
+                .with_tls_backend(tls_backend)
+                .with_http1_only(true)
+                .build();
+            let client = Client::new(config);
+
+            let episode = client.get_episode(testing::GIGA_EPISODE_ID).await?;
+            assert_eq!(episode.pages().len(), 3);
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_not_modified_episode_uses_cache() -> Result<()> {
+        let server = MockServer::start().await;
+        let episode_path = format!("/episode/{}.json", testing::GIGA_EPISODE_ID);
+        Mock::given(method("GET"))
+            .and(path(episode_path.as_str()))
+            .and(header("if-none-match", "\"v1\""))
+            .respond_with(ResponseTemplate::new(304))
+            .with_priority(1)
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path(episode_path.as_str()))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("etag", "\"v1\"")
+                    .set_body_raw(std::fs::read(testing::GIGA_EPISODE)?, "application/json"),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+        let client = Client::new(ConfigBuilder::custom(server.uri())?.build());
+
+        let fetched = client.get_episode(testing::GIGA_EPISODE_ID).await?;
+        let cached = client.get_episode(testing::GIGA_EPISODE_ID).await?;
+
+        assert_eq!(cached.id(), fetched.id());
+        assert_eq!(cached.pages().len(), fetched.pages().len());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_series_id() -> Result<()> {
+        let client = Client::new(ConfigBuilder::new(Website::ShonenJumpPlus).build());
+        let cases = [
+            (
+                "https://shonenjumpplus.com/series/13933686331623812157",
+                "13933686331623812157",
+            ),
+            ("https://comic-days.com/series/abc-def/", "abc-def"),
+            (
+                "https://shonenjumpplus.com/rss/series/13933686331623812157",
+                "13933686331623812157",
+            ),
+        ];
+        for (url, id) in cases {
+            assert_eq!(
+                client.parse_series_id(&Url::parse(url)?).as_deref(),
+                Some(id)
+            );
+        }
+        let episode = Url::parse("https://shonenjumpplus.com/episode/9324103625676410700")?;
+        assert_eq!(client.parse_series_id(&episode), None);
+
+        Ok(())
+    }
+
+    #[cfg(feature = "giga")]
+    #[tokio::test]
+    async fn test_get_series() -> Result<()> {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/rss/series/13933686331623812157"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_raw(std::fs::read(testing::GIGA_SERIES)?, "application/rss+xml"),
+            )
+            .mount(&server)
+            .await;
+        let client = Client::new(ConfigBuilder::custom(server.uri())?.build());
+        let url = Url::parse(&format!("{}/series/13933686331623812157", server.uri()))?;
+
+        let series_id = client.parse_series_id(&url).context("Not a series url")?;
+        let series = client.get_series(&series_id).await?;
+
+        assert_eq!(series.title(), "Mock Series");
+        let ids = series
+            .episodes()
+            .iter()
+            .map(|episode| episode.id())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            ids,
+            vec![
+                "9324103625676410702",
+                "9324103625676410701",
+                testing::GIGA_EPISODE_ID
+            ]
+        );
+        assert!(series.episodes()[0].published_at().is_some());
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_get_episode() {
-        let episode_ids = vec![
+        let episode_ids = [
             "9324103625676410700",
             "10834108156672080500",
             "16457717013869519536",
@@ -293,7 +935,7 @@ mod test {
 
         println!("Solving {} pages", pages.len());
 
-        let solver = Arc::new(Solver::new());
+        let solver = Arc::new(Solver::default());
         let mut images = pages
             .par_iter()
             .progress_with(progress.build(pages.len())?)
@@ -356,7 +998,7 @@ mod test {
 
         println!("Solving {} pages", pages.len());
 
-        let solver = Arc::new(Solver::new());
+        let solver = Arc::new(Solver::default());
         let images = pages
             .par_iter()
             .progress_with(progress.build(pages.len())?)
@@ -413,7 +1055,7 @@ mod test {
 
         println!("Solving {} pages", pages.len());
 
-        let solver = Arc::new(Solver::new());
+        let solver = Arc::new(Solver::default());
         let images = pages
             .par_iter()
             .progress_with(progress.build(pages.len())?)